@@ -4,7 +4,6 @@ use embedded_tls::blocking::*;
 use embedded_tls::webpki::CertVerifier;
 use rand::rngs::OsRng;
 use std::net::TcpStream;
-use std::time::SystemTime;
 
 fn main() {
     env_logger::init();
@@ -14,16 +13,16 @@ fn main() {
     let mut read_record_buffer = [0; 16384];
     let mut write_record_buffer = [0; 16384];
     let config = TlsConfig::new().with_server_name("localhost");
-    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+    let mut tls: TlsConnection<FromStd<TcpStream>> = TlsConnection::new(
         FromStd::new(stream),
         &mut read_record_buffer,
         &mut write_record_buffer,
     );
     let mut rng = OsRng;
 
-    tls.open::<OsRng, CertVerifier<Aes128GcmSha256, SystemTime, 4096>>(TlsContext::new(
-        &config, &mut rng,
-    ))
+    tls.open::<OsRng, CertVerifier<4096>>(
+        TlsContext::new(&config, &mut rng).with_clock(&SystemClock),
+    )
     .expect("error establishing TLS connection");
 
     tls.write_all(b"ping").expect("error writing data");
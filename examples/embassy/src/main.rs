@@ -5,7 +5,7 @@ use embassy_net::{Config, Ipv4Address, Ipv4Cidr, Stack, StackResources};
 use embassy_net_tuntap::TunTapDevice;
 use embassy_time::Duration;
 use embedded_io_async::Write;
-use embedded_tls::{Aes128GcmSha256, NoVerify, TlsConfig, TlsConnection, TlsContext};
+use embedded_tls::{NoVerify, TlsConfig, TlsConnection, TlsContext};
 use heapless::Vec;
 use log::*;
 use rand::{rngs::OsRng, RngCore};
@@ -83,7 +83,7 @@ async fn main_task(spawner: Spawner) {
     let mut write_record_buffer = [0; 16384];
     let mut rng = OsRng;
     let config = TlsConfig::new().with_server_name("example.com");
-    let mut tls: TlsConnection<TcpSocket, Aes128GcmSha256> =
+    let mut tls: TlsConnection<TcpSocket> =
         TlsConnection::new(socket, &mut read_record_buffer, &mut write_record_buffer);
 
     tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut rng))
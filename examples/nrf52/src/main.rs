@@ -22,7 +22,7 @@ fn main() -> ! {
     let mut read_record_buffer = [0; 16384];
     let mut write_record_buffer = [0; 16384];
     let config = TlsConfig::new().with_server_name("example.com");
-    let mut tls: TlsConnection<Dummy, Aes128GcmSha256> =
+    let mut tls: TlsConnection<Dummy> =
         TlsConnection::new(Dummy {}, &mut read_record_buffer, &mut write_record_buffer);
 
     tls.open::<Rng, NoVerify>(TlsContext::new(&config, &mut rng))
@@ -20,7 +20,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .with_server_name("localhost")
         .with_psk(&[0xaa, 0xbb, 0xcc, 0xdd], &[b"vader"]);
     let mut rng = OsRng;
-    let mut tls: TlsConnection<FromTokio<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
         FromTokio::new(stream),
         &mut read_record_buffer,
         &mut write_record_buffer,
@@ -0,0 +1,848 @@
+use crate::alert::{AlertDescription, AlertLevel};
+use crate::config::{TlsCipherSuite, TlsConfig};
+use crate::connection::{
+    certificate_verify, client_finished_finalize, decrypt_record, finished,
+    handle_processing_error, handle_processing_error_blocking, respond, respond_blocking,
+};
+use crate::crypto_provider::{CryptoProvider, TlsKeyExchange};
+use crate::extensions::extension_data::alpn::ProtocolNameList;
+use crate::extensions::extension_data::certificate_type::{
+    CertificateType, CertificateTypeResponse,
+};
+use crate::extensions::extension_data::key_share::{KeyShareEntry, KeyShareServerHello};
+use crate::extensions::extension_data::pre_shared_key::PreSharedKeyServerHello;
+use crate::extensions::extension_data::supported_groups::NamedGroup;
+use crate::extensions::extension_data::supported_versions::{SupportedVersionsServerHello, TLS13};
+use crate::extensions::messages::{EncryptedExtensionsExtension, ServerHelloExtension};
+use crate::handshake::certificate::CertificateRef;
+use crate::handshake::client_hello::ClientHelloRef;
+use crate::handshake::encrypted_extensions::EncryptedExtensions;
+use crate::handshake::hello_retry_request::HelloRetryRequest;
+use crate::handshake::server_hello::ServerHello;
+use crate::handshake::{ClientHandshake, HandshakeType, ServerHandshake};
+use crate::key_schedule::{dispatch, AnyKeySchedule, KeySchedule};
+use crate::record::{ClientRecord, ServerRecord};
+use crate::record_reader::RecordReader;
+use crate::write_buffer::WriteBuffer;
+use crate::TlsError;
+use digest::Digest;
+use embedded_io::{Read as BlockingRead, Write as BlockingWrite};
+use embedded_io_async::{Read as AsyncRead, Write as AsyncWrite};
+use heapless::Vec;
+use rand_core::{CryptoRng, RngCore};
+
+type SharedSecret<Provider> =
+    <<Provider as CryptoProvider>::KeyExchange as TlsKeyExchange>::SharedSecret;
+
+/// The parameters negotiated by a server during the handshake.
+pub struct AcceptorHandshake {
+    legacy_session_id: Vec<u8, 32>,
+    /// The group of the key share asked for with a HelloRetryRequest.
+    hello_retry_request: Option<NamedGroup>,
+    selected_psk: Option<u16>,
+    server_certificate_type: CertificateType,
+    alpn_protocol: Option<usize>,
+    /// Whether the client sends early data, which is skipped as it is never accepted.
+    early_data: bool,
+}
+
+impl AcceptorHandshake {
+    pub fn new() -> Self {
+        Self {
+            legacy_session_id: Vec::new(),
+            hello_retry_request: None,
+            selected_psk: None,
+            server_certificate_type: CertificateType::X509,
+            alpn_protocol: None,
+            early_data: false,
+        }
+    }
+
+    /// The application protocol selected among those of `config`.
+    pub fn alpn_protocol<'c>(&self, config: &TlsConfig<'c>) -> Option<&'c [u8]> {
+        self.alpn_protocol
+            .and_then(|index| config.alpn_protocols.get(index).copied())
+    }
+}
+
+impl Default for AcceptorHandshake {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AcceptorState {
+    ClientHello,
+    EncryptedExtensions,
+    ServerCert,
+    ServerCertVerify,
+    ServerFinished,
+    ClientFinished,
+    ApplicationData,
+}
+
+impl<'a> AcceptorState {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn process<Transport, RNG, Provider>(
+        self,
+        transport: &mut Transport,
+        handshake: &mut AcceptorHandshake,
+        record_reader: &mut RecordReader<'_>,
+        tx_buf: &mut WriteBuffer<'_>,
+        key_schedule: &mut AnyKeySchedule<Provider>,
+        config: &TlsConfig<'a>,
+        rng: &mut RNG,
+    ) -> Result<AcceptorState, TlsError>
+    where
+        Transport: AsyncRead + AsyncWrite + 'a,
+        RNG: CryptoRng + RngCore + 'a,
+        Provider: CryptoProvider,
+    {
+        match self {
+            AcceptorState::ClientHello => {
+                let result = dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    record_reader
+                        .read(transport, key_schedule.read_state())
+                        .await
+                        .and_then(client_hello)
+                });
+
+                let result = match result {
+                    Ok(ClientHelloRecord::ClientHello(client_hello)) => {
+                        match process_client_hello(
+                            handshake,
+                            key_schedule,
+                            config,
+                            rng,
+                            tx_buf,
+                            &client_hello,
+                        ) {
+                            Ok((tx, shared)) => {
+                                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                                    respond(tx, transport, key_schedule).await?;
+                                    initialize_handshake_secret(
+                                        handshake,
+                                        key_schedule,
+                                        config,
+                                        shared,
+                                    )
+                                })
+                            }
+                            Err(e) => Err(e),
+                        }
+                    }
+                    Ok(record) => skip_before_client_hello(handshake, record),
+                    Err(e) => Err(e),
+                };
+
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    handle_processing_error(result, transport, key_schedule, tx_buf).await
+                })
+            }
+            AcceptorState::EncryptedExtensions => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let (state, tx) =
+                        encrypted_extensions(handshake, key_schedule, config, tx_buf)?;
+
+                    respond(tx, transport, key_schedule).await?;
+
+                    Ok(state)
+                })
+            }
+            AcceptorState::ServerCert => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let tx = server_cert(key_schedule, config, tx_buf)?;
+
+                    respond(tx, transport, key_schedule).await?;
+
+                    Ok(AcceptorState::ServerCertVerify)
+                })
+            }
+            AcceptorState::ServerCertVerify => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let tx = server_cert_verify(key_schedule, config, tx_buf)?;
+
+                    respond(tx, transport, key_schedule).await?;
+
+                    Ok(AcceptorState::ServerFinished)
+                })
+            }
+            AcceptorState::ServerFinished => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let tx = finished(key_schedule, tx_buf)?;
+
+                    respond(tx, transport, key_schedule).await?;
+                    key_schedule.save_traffic_hash();
+
+                    Ok(AcceptorState::ClientFinished)
+                })
+            }
+            AcceptorState::ClientFinished => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let result = match record_reader
+                        .read(transport, key_schedule.read_state())
+                        .await
+                    {
+                        Ok(record) => process_client_finished(handshake, key_schedule, record),
+                        Err(e) => Err(e),
+                    };
+
+                    handle_processing_error(result, transport, key_schedule, tx_buf).await
+                })
+            }
+            AcceptorState::ApplicationData => Ok(AcceptorState::ApplicationData),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_blocking<Transport, RNG, Provider>(
+        self,
+        transport: &mut Transport,
+        handshake: &mut AcceptorHandshake,
+        record_reader: &mut RecordReader<'_>,
+        tx_buf: &mut WriteBuffer,
+        key_schedule: &mut AnyKeySchedule<Provider>,
+        config: &TlsConfig<'a>,
+        rng: &mut RNG,
+    ) -> Result<AcceptorState, TlsError>
+    where
+        Transport: BlockingRead + BlockingWrite + 'a,
+        RNG: CryptoRng + RngCore + 'a,
+        Provider: CryptoProvider,
+    {
+        match self {
+            AcceptorState::ClientHello => {
+                let result = dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    record_reader
+                        .read_blocking(transport, key_schedule.read_state())
+                        .and_then(client_hello)
+                });
+
+                let result = match result {
+                    Ok(ClientHelloRecord::ClientHello(client_hello)) => {
+                        match process_client_hello(
+                            handshake,
+                            key_schedule,
+                            config,
+                            rng,
+                            tx_buf,
+                            &client_hello,
+                        ) {
+                            Ok((tx, shared)) => {
+                                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                                    respond_blocking(tx, transport, key_schedule)?;
+                                    initialize_handshake_secret(
+                                        handshake,
+                                        key_schedule,
+                                        config,
+                                        shared,
+                                    )
+                                })
+                            }
+                            Err(e) => Err(e),
+                        }
+                    }
+                    Ok(record) => skip_before_client_hello(handshake, record),
+                    Err(e) => Err(e),
+                };
+
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    handle_processing_error_blocking(result, transport, key_schedule, tx_buf)
+                })
+            }
+            AcceptorState::EncryptedExtensions => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let (state, tx) =
+                        encrypted_extensions(handshake, key_schedule, config, tx_buf)?;
+
+                    respond_blocking(tx, transport, key_schedule)?;
+
+                    Ok(state)
+                })
+            }
+            AcceptorState::ServerCert => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let tx = server_cert(key_schedule, config, tx_buf)?;
+
+                    respond_blocking(tx, transport, key_schedule)?;
+
+                    Ok(AcceptorState::ServerCertVerify)
+                })
+            }
+            AcceptorState::ServerCertVerify => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let tx = server_cert_verify(key_schedule, config, tx_buf)?;
+
+                    respond_blocking(tx, transport, key_schedule)?;
+
+                    Ok(AcceptorState::ServerFinished)
+                })
+            }
+            AcceptorState::ServerFinished => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let tx = finished(key_schedule, tx_buf)?;
+
+                    respond_blocking(tx, transport, key_schedule)?;
+                    key_schedule.save_traffic_hash();
+
+                    Ok(AcceptorState::ClientFinished)
+                })
+            }
+            AcceptorState::ClientFinished => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let result = match record_reader
+                        .read_blocking(transport, key_schedule.read_state())
+                    {
+                        Ok(record) => process_client_finished(handshake, key_schedule, record),
+                        Err(e) => Err(e),
+                    };
+
+                    handle_processing_error_blocking(result, transport, key_schedule, tx_buf)
+                })
+            }
+            AcceptorState::ApplicationData => Ok(AcceptorState::ApplicationData),
+        }
+    }
+}
+
+/// The records accepted while waiting for the ClientHello, independent of the cipher suite.
+enum ClientHelloRecord<'a> {
+    ClientHello(ClientHelloRef<'a>),
+    ChangeCipherSpec,
+    EarlyData,
+}
+
+fn client_hello<CipherSuite>(
+    record: ServerRecord<'_, CipherSuite>,
+) -> Result<ClientHelloRecord<'_>, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    match record {
+        ServerRecord::Handshake(ServerHandshake::ClientHello(client_hello)) => {
+            Ok(ClientHelloRecord::ClientHello(client_hello))
+        }
+        ServerRecord::ChangeCipherSpec(_) => Ok(ClientHelloRecord::ChangeCipherSpec),
+        ServerRecord::ApplicationData(_) => Ok(ClientHelloRecord::EarlyData),
+        ServerRecord::Alert(alert) => {
+            Err(TlsError::HandshakeAborted(alert.level, alert.description))
+        }
+        ServerRecord::Handshake(_) => Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::UnexpectedMessage,
+        )),
+    }
+}
+
+/// Skips the records a client may send between a HelloRetryRequest and its second ClientHello.
+fn skip_before_client_hello(
+    handshake: &AcceptorHandshake,
+    record: ClientHelloRecord<'_>,
+) -> Result<AcceptorState, TlsError> {
+    let skip = match record {
+        // Sent by clients in middlebox compatibility mode (Appendix D.4)
+        ClientHelloRecord::ChangeCipherSpec => handshake.hello_retry_request.is_some(),
+        // Section 4.2.10: early data sent with the first ClientHello is rejected by the
+        // HelloRetryRequest
+        ClientHelloRecord::EarlyData => handshake.early_data,
+        ClientHelloRecord::ClientHello(_) => false,
+    };
+    if skip {
+        Ok(AcceptorState::ClientHello)
+    } else {
+        Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::UnexpectedMessage,
+        ))
+    }
+}
+
+/// Negotiates the parameters of the handshake, and encodes either the ServerHello, returned with
+/// the shared secret of the key exchange, or a HelloRetryRequest.
+fn process_client_hello<'r, RNG, Provider>(
+    handshake: &mut AcceptorHandshake,
+    key_schedule: &mut AnyKeySchedule<Provider>,
+    config: &TlsConfig,
+    rng: &mut RNG,
+    tx_buf: &'r mut WriteBuffer,
+    client_hello: &ClientHelloRef<'_>,
+) -> Result<(&'r [u8], Option<SharedSecret<Provider>>), TlsError>
+where
+    RNG: CryptoRng + RngCore,
+    Provider: CryptoProvider,
+{
+    trace!("********* ClientHello");
+    let illegal_parameter =
+        TlsError::AbortHandshake(AlertLevel::Fatal, AlertDescription::IllegalParameter);
+    let handshake_failure =
+        TlsError::AbortHandshake(AlertLevel::Fatal, AlertDescription::HandshakeFailure);
+
+    // Section 4.2.1
+    // If this extension is not present, servers which are compliant with this specification
+    // and which also support TLS 1.2 MUST negotiate TLS 1.2 or prior [...]. A server which
+    // only supports TLS 1.3 aborts with a "protocol_version" alert.
+    if !client_hello.supports_tls13() {
+        return Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::ProtocolVersion,
+        ));
+    }
+    if !client_hello.has_null_compression() {
+        return Err(illegal_parameter);
+    }
+
+    let cipher_suite = match handshake.hello_retry_request {
+        // Section 4.1.4: the second ClientHello must still offer the suite selected before
+        Some(_) => {
+            let cipher_suite = key_schedule.cipher_suite();
+            if !client_hello
+                .cipher_suites()
+                .any(|offered| offered == cipher_suite)
+            {
+                return Err(illegal_parameter);
+            }
+            cipher_suite
+        }
+        None => {
+            let cipher_suite = config
+                .cipher_suites
+                .iter()
+                .copied()
+                .find(|suite| {
+                    client_hello
+                        .cipher_suites()
+                        .any(|offered| offered == *suite)
+                })
+                .ok_or(handshake_failure)?;
+            *key_schedule =
+                AnyKeySchedule::new_server(cipher_suite).ok_or(TlsError::InvalidCipherSuite)?;
+            handshake.early_data = client_hello.early_data();
+            cipher_suite
+        }
+    };
+    handshake.legacy_session_id = Vec::from_slice(client_hello.legacy_session_id)
+        .map_err(|_| TlsError::InvalidSessionIdLength)?;
+
+    handshake.selected_psk = dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+        select_psk(key_schedule, config, client_hello)?
+    });
+
+    // Section 4.4.2.2: the certificate must use a signature scheme offered by the client
+    if handshake.selected_psk.is_none() {
+        let (Some(cert), Some(signer)) = (&config.cert, config.signer) else {
+            warn!("No certificate and signer configured to authenticate with");
+            return Err(handshake_failure);
+        };
+        if !client_hello
+            .signature_schemes()
+            .contains(&signer.signature_scheme())
+        {
+            return Err(handshake_failure);
+        }
+        let certificate_type = cert.certificate_type();
+        if !client_hello
+            .server_certificate_types()
+            .contains(&certificate_type)
+        {
+            return Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnsupportedCertificate,
+            ));
+        }
+        handshake.server_certificate_type = certificate_type;
+    }
+
+    handshake.alpn_protocol = select_alpn_protocol(&client_hello.alpn_protocols(), config)?;
+
+    let group = match handshake.hello_retry_request {
+        Some(group) => group,
+        None => {
+            let mut groups = config
+                .named_groups
+                .iter()
+                .copied()
+                .filter(|group| Provider::KeyExchange::is_supported(*group) && !group.is_kem());
+            let supported_groups = client_hello.supported_groups();
+            match groups
+                .clone()
+                .find(|group| client_hello.key_share(*group).is_some())
+            {
+                Some(group) => group,
+                None => {
+                    // Section 4.1.1: ask for a key share for a group supported by both
+                    let group = groups
+                        .find(|group| supported_groups.contains(group))
+                        .ok_or(handshake_failure)?;
+                    let tx = write_hello_retry_request(
+                        handshake,
+                        key_schedule,
+                        tx_buf,
+                        client_hello,
+                        cipher_suite,
+                        group,
+                    )?;
+                    return Ok((tx, None));
+                }
+            }
+        }
+    };
+    // Section 4.2.8: the second ClientHello must contain the key share asked for
+    let client_share = client_hello.key_share(group).ok_or(illegal_parameter)?;
+
+    let secret = Provider::KeyExchange::generate(group, rng)?;
+    let mut random = [0; 32];
+    rng.fill_bytes(&mut random);
+
+    let mut extensions = Vec::new();
+    unwrap!(extensions
+        .push(ServerHelloExtension::SupportedVersions(
+            SupportedVersionsServerHello {
+                selected_version: TLS13,
+            }
+        ))
+        .ok());
+    unwrap!(extensions
+        .push(ServerHelloExtension::KeyShare(KeyShareServerHello(
+            KeyShareEntry {
+                group,
+                opaque: secret.public_key(),
+            }
+        )))
+        .ok());
+    if let Some(selected_identity) = handshake.selected_psk {
+        unwrap!(extensions
+            .push(ServerHelloExtension::PreSharedKey(
+                PreSharedKeyServerHello { selected_identity }
+            ))
+            .ok());
+    }
+    let server_hello = ServerHello::new(
+        random,
+        &handshake.legacy_session_id,
+        cipher_suite,
+        extensions,
+    );
+
+    let tx = dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+        key_schedule.transcript_hash().update(client_hello.raw);
+
+        let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+        tx_buf.write_record(
+            &ClientRecord::Handshake(ClientHandshake::ServerHello(server_hello), false),
+            write_key_schedule,
+            Some(read_key_schedule),
+        )?
+    });
+
+    let shared = secret.complete(client_share)?;
+    Ok((tx, Some(shared)))
+}
+
+/// Selects the first offered identity of the configured PSK, if the client allows using it
+/// with a key exchange, after verifying its binder.
+fn select_psk<CipherSuite>(
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    config: &TlsConfig,
+    client_hello: &ClientHelloRef<'_>,
+) -> Result<Option<u16>, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let (Some((psk, identities)), Some(offered)) = (&config.psk, client_hello.pre_shared_key())
+    else {
+        return Ok(None);
+    };
+    // Section 4.2.9: only psk_dhe_ke is supported, as the key exchange provides forward secrecy
+    if !client_hello.allows_psk_dhe_ke() {
+        return Ok(None);
+    }
+    let Some(selected) = offered
+        .identities
+        .iter()
+        .position(|offered| identities.contains(&offered.identity))
+    else {
+        return Ok(None);
+    };
+
+    // Section 4.2.11.2: the binder covers the transcript up to the ClientHello without the
+    // binders, which are at its end
+    let truncated_len = client_hello
+        .raw
+        .len()
+        .checked_sub(offered.binders_len())
+        .ok_or(TlsError::InvalidHandshake)?;
+    let transcript = key_schedule
+        .transcript_hash()
+        .clone()
+        .chain_update(&client_hello.raw[..truncated_len]);
+    if !KeySchedule::<CipherSuite>::verify_psk_binder(
+        psk,
+        false,
+        &transcript,
+        offered.binders[selected],
+    )? {
+        return Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::DecryptError,
+        ));
+    }
+
+    Ok(Some(selected as u16))
+}
+
+/// Selects the first configured application protocol offered by the client, returning its
+/// index among the configured ones.
+fn select_alpn_protocol(offered: &[&[u8]], config: &TlsConfig) -> Result<Option<usize>, TlsError> {
+    if offered.is_empty() || config.alpn_protocols.is_empty() {
+        return Ok(None);
+    }
+    // RFC 7301, Section 3.2: without a protocol in common, the server aborts with a
+    // "no_application_protocol" alert
+    config
+        .alpn_protocols
+        .iter()
+        .position(|protocol| offered.contains(protocol))
+        .map(Some)
+        .ok_or(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::NoApplicationProtocol,
+        ))
+}
+
+fn write_hello_retry_request<'r, Provider>(
+    handshake: &mut AcceptorHandshake,
+    key_schedule: &mut AnyKeySchedule<Provider>,
+    tx_buf: &'r mut WriteBuffer,
+    client_hello: &ClientHelloRef<'_>,
+    cipher_suite: crate::cipher_suites::CipherSuite,
+    group: NamedGroup,
+) -> Result<&'r [u8], TlsError>
+where
+    Provider: CryptoProvider,
+{
+    trace!("********* HelloRetryRequest");
+    handshake.hello_retry_request = Some(group);
+    let hello_retry_request =
+        HelloRetryRequest::new(&handshake.legacy_session_id, cipher_suite, group);
+
+    dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+        // Section 4.4.1
+        // When the server responds to a ClientHello with a HelloRetryRequest, the value of
+        // ClientHello1 is replaced with a special synthetic handshake message of handshake
+        // type "message_hash" containing Hash(ClientHello1).
+        let transcript = key_schedule.transcript_hash();
+        let client_hello_hash = transcript.clone().chain_update(client_hello.raw).finalize();
+        transcript.update([
+            HandshakeType::MessageHash as u8,
+            0,
+            0,
+            client_hello_hash.len() as u8,
+        ]);
+        transcript.update(client_hello_hash);
+
+        let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+        tx_buf.write_record(
+            &ClientRecord::Handshake(
+                ClientHandshake::HelloRetryRequest(hello_retry_request),
+                false,
+            ),
+            write_key_schedule,
+            Some(read_key_schedule),
+        )
+    })
+}
+
+/// Derives the handshake traffic secrets once the ServerHello is sent, or waits for the second
+/// ClientHello after a HelloRetryRequest.
+fn initialize_handshake_secret<CipherSuite, Secret>(
+    handshake: &AcceptorHandshake,
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    config: &TlsConfig,
+    shared: Option<Secret>,
+) -> Result<AcceptorState, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Secret: AsRef<[u8]>,
+{
+    let Some(shared) = shared else {
+        return Ok(AcceptorState::ClientHello);
+    };
+    let psk = handshake
+        .selected_psk
+        .and(config.psk.as_ref())
+        .map(|(psk, _)| *psk);
+    key_schedule.initialize_early_secret(psk)?;
+    key_schedule.initialize_handshake_secret(shared.as_ref())?;
+    Ok(AcceptorState::EncryptedExtensions)
+}
+
+fn encrypted_extensions<'r, CipherSuite>(
+    handshake: &AcceptorHandshake,
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    config: &TlsConfig,
+    tx_buf: &'r mut WriteBuffer,
+) -> Result<(AcceptorState, &'r [u8]), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let mut extensions = Vec::new();
+    if let Some(protocol) = handshake.alpn_protocol(config) {
+        unwrap!(extensions
+            .push(
+                EncryptedExtensionsExtension::ApplicationLayerProtocolNegotiation(
+                    ProtocolNameList {
+                        protocols: unwrap!(Vec::from_slice(&[protocol]).ok()),
+                    }
+                )
+            )
+            .ok());
+    }
+    // RFC 7250, Section 4.2: X.509 certificates are used if the extension is omitted
+    if handshake.server_certificate_type != CertificateType::X509 {
+        unwrap!(extensions
+            .push(EncryptedExtensionsExtension::ServerCertificateType(
+                CertificateTypeResponse {
+                    certificate_type: handshake.server_certificate_type,
+                }
+            ))
+            .ok());
+    }
+
+    // Section 4.2.11: the certificate is not sent when authenticating with a PSK
+    let next_state = if handshake.selected_psk.is_some() {
+        AcceptorState::ServerFinished
+    } else {
+        AcceptorState::ServerCert
+    };
+
+    let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+    tx_buf
+        .write_record(
+            &ClientRecord::Handshake(
+                ClientHandshake::EncryptedExtensions(EncryptedExtensions::new(extensions)),
+                true,
+            ),
+            write_key_schedule,
+            Some(read_key_schedule),
+        )
+        .map(|slice| (next_state, slice))
+}
+
+fn server_cert<'r, CipherSuite>(
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    config: &TlsConfig,
+    tx_buf: &'r mut WriteBuffer,
+) -> Result<&'r [u8], TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let cert = config.cert.as_ref().ok_or(TlsError::InvalidHandshake)?;
+    let mut certificate = CertificateRef::with_context(&[]);
+    certificate.add(cert.try_into()?)?;
+
+    let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+    tx_buf.write_record(
+        &ClientRecord::Handshake(ClientHandshake::Certificate(certificate), true),
+        write_key_schedule,
+        Some(read_key_schedule),
+    )
+}
+
+fn server_cert_verify<'r, CipherSuite>(
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    config: &TlsConfig,
+    tx_buf: &'r mut WriteBuffer,
+) -> Result<&'r [u8], TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let signer = config.signer.ok_or(TlsError::InvalidHandshake)?;
+
+    certificate_verify(
+        key_schedule,
+        signer,
+        b"TLS 1.3, server CertificateVerify\0",
+        tx_buf,
+    )
+}
+
+fn process_client_finished<CipherSuite>(
+    handshake: &mut AcceptorHandshake,
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    record: ServerRecord<'_, CipherSuite>,
+) -> Result<AcceptorState, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let mut state = AcceptorState::ClientFinished;
+    let result = decrypt_record(key_schedule.read_state(), record, |key_schedule, record| {
+        match record {
+            ServerRecord::Handshake(ServerHandshake::Finished(finished)) => {
+                trace!("********* Finished");
+                if !key_schedule.verify_server_finished(&finished)? {
+                    return Err(TlsError::AbortHandshake(
+                        AlertLevel::Fatal,
+                        AlertDescription::DecryptError,
+                    ));
+                }
+                state = AcceptorState::ApplicationData;
+                Ok(())
+            }
+            // Sent by clients in middlebox compatibility mode (Appendix D.4)
+            ServerRecord::ChangeCipherSpec(_) => Ok(()),
+            ServerRecord::Alert(alert) => {
+                Err(TlsError::HandshakeAborted(alert.level, alert.description))
+            }
+            _ => Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnexpectedMessage,
+            )),
+        }
+    });
+
+    match result {
+        // Section 4.2.10: the server skips the early data it rejected, which it fails to
+        // decrypt, until it receives the client handshake messages
+        Err(TlsError::CryptoError) if handshake.early_data => {
+            return Ok(AcceptorState::ClientFinished);
+        }
+        Err(e) => return Err(e),
+        Ok(()) => handshake.early_data = false,
+    }
+
+    match state {
+        AcceptorState::ApplicationData => {
+            client_finished_finalize(key_schedule).map(|_| AcceptorState::ApplicationData)
+        }
+        state => Ok(state),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_alpn_protocol() {
+        let config = TlsConfig::new().with_alpn_protocols(&[b"h2", b"http/1.1"]);
+
+        assert!(matches!(select_alpn_protocol(&[], &config), Ok(None)));
+        assert!(matches!(
+            select_alpn_protocol(&[b"http/1.1", b"h2"], &config),
+            Ok(Some(0))
+        ));
+        assert!(matches!(
+            select_alpn_protocol(&[b"h3"], &config),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::NoApplicationProtocol
+            ))
+        ));
+        assert!(matches!(
+            select_alpn_protocol(&[b"h3"], &TlsConfig::new()),
+            Ok(None)
+        ));
+    }
+}
@@ -1,43 +1,60 @@
+use crate::acceptor::{AcceptorHandshake, AcceptorState};
 use crate::common::decrypted_buffer_info::DecryptedBufferInfo;
 use crate::common::decrypted_read_handler::DecryptedReadHandler;
 use crate::connection::*;
-use crate::key_schedule::KeySchedule;
-use crate::key_schedule::{ReadKeySchedule, SharedState, WriteKeySchedule};
+use crate::handshake::certificate_request::CertificateRequest;
+use crate::key_schedule::{
+    dispatch, AnyKeySchedule, AnyReadKeySchedule, AnySharedState, AnyWriteKeySchedule,
+};
+use crate::peer_certificates::PeerCertificateBuffer;
 use crate::read_buffer::ReadBuffer;
 use crate::record::{ClientRecord, ClientRecordHeader};
 use crate::record_reader::RecordReader;
 use crate::split::{SplitState, SplitStateContainer};
+use crate::ticket::Tickets;
 use crate::write_buffer::WriteBuffer;
 use embedded_io::Error as _;
 use embedded_io::{BufRead, ErrorType, Read, Write};
+use heapless::Vec;
 use rand_core::{CryptoRng, RngCore};
 
 pub use crate::config::*;
+pub use crate::peer_certificates::PeerCertificates;
 #[cfg(feature = "std")]
 pub use crate::split::ManagedSplitState;
 pub use crate::split::SplitConnectionState;
+pub use crate::ticket::{EarlyDataStatus, SessionTicket, SingleTicketStorage, TicketStorage};
 pub use crate::TlsError;
 
 /// Type representing a TLS connection. An instance of this type can
 /// be used to establish a TLS connection, write and read encrypted data over this connection,
 /// and closing to free up the underlying resources.
-pub struct TlsConnection<'a, Socket, CipherSuite>
+pub struct TlsConnection<'a, Socket, Provider = RustCryptoProvider>
 where
     Socket: Read + Write + 'a,
-    CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     delegate: Socket,
     opened: bool,
-    key_schedule: KeySchedule<CipherSuite>,
-    record_reader: RecordReader<'a, CipherSuite>,
+    is_server: bool,
+    key_schedule: AnyKeySchedule<Provider>,
+    record_reader: RecordReader<'a>,
     record_write_buf: WriteBuffer<'a>,
     decrypted: DecryptedBufferInfo,
+    peer_certificates: PeerCertificateBuffer<'a>,
+    alpn_protocol: Option<Vec<u8, 255>>,
+    tickets: Tickets<'a>,
+    early_data_status: EarlyDataStatus,
+    key_update_threshold: u64,
+    key_update_pending: bool,
+    client_auth: Option<(Certificate<'a>, &'a (dyn TlsSigner + Sync))>,
+    post_handshake_auth: Option<CertificateType>,
 }
 
-impl<'a, Socket, CipherSuite> TlsConnection<'a, Socket, CipherSuite>
+impl<'a, Socket, Provider> TlsConnection<'a, Socket, Provider>
 where
     Socket: Read + Write + 'a,
-    CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     /// Create a new TLS connection with the provided context and a blocking I/O implementation
     ///
@@ -50,6 +67,7 @@ where
     /// records if depending on the size of the write buffer.
     /// The largest of the two buffers will be used to encode the TLS handshake record, hence either of the
     /// buffers must at least be large enough to encode a handshake.
+    /// [`TlsConfig::min_write_buffer_len`] returns the size needed to encode the ClientHello.
     pub fn new(
         delegate: Socket,
         record_read_buf: &'a mut [u8],
@@ -58,34 +76,91 @@ where
         Self {
             delegate,
             opened: false,
-            key_schedule: KeySchedule::new(),
+            is_server: false,
+            key_schedule: AnyKeySchedule::default(),
             record_reader: RecordReader::new(record_read_buf),
             record_write_buf: WriteBuffer::new(record_write_buf),
             decrypted: DecryptedBufferInfo::default(),
+            peer_certificates: PeerCertificateBuffer::default(),
+            alpn_protocol: None,
+            tickets: Tickets::default(),
+            early_data_status: EarlyDataStatus::NotSent,
+            key_update_threshold: DEFAULT_KEY_UPDATE_THRESHOLD,
+            key_update_pending: false,
+            client_auth: None,
+            post_handshake_auth: None,
         }
     }
 
+    /// Keep the certificate chain of the server in `buf` during the handshake, to be returned by
+    /// [`Self::peer_certificates()`] once the connection is opened.
+    ///
+    /// The chain takes 4 bytes per certificate in addition to the certificates themselves.
+    /// Certificates that do not fit in the buffer are dropped, starting with the last one, so a
+    /// buffer large enough for the leaf certificate keeps at least the leaf.
+    pub fn with_peer_certificates_buffer(mut self, buf: &'a mut [u8]) -> Self {
+        self.peer_certificates = PeerCertificateBuffer::new(buf);
+        self
+    }
+
+    /// Resume the session with the ticket in `storage` when opening the connection, and store
+    /// the tickets sent by the server afterwards.
+    ///
+    /// Tickets are received while reading from the connection, and are only offered to a server
+    /// with the same server name. Their age is measured with `clock`, with [`NoClock`] tickets
+    /// are assumed not to expire.
+    pub fn with_ticket_storage(
+        mut self,
+        storage: &'a mut (dyn TicketStorage + Send),
+        clock: &'a (dyn TlsClock + Sync),
+    ) -> Self {
+        self.tickets = Tickets::new(storage, clock);
+        self
+    }
+
+    /// Authenticate with `cert` and `signer` when the server requests a client certificate
+    /// after the handshake. The request is answered while reading from the connection.
+    ///
+    /// The client offers to authenticate after the handshake if it is opened with a
+    /// [`TlsConfig`] that has a client certificate, usually the same one. Without this, such
+    /// requests are answered without a certificate, and the server decides whether to continue.
+    pub fn with_post_handshake_auth(
+        mut self,
+        cert: Certificate<'a>,
+        signer: &'a (dyn TlsSigner + Sync),
+    ) -> Self {
+        self.client_auth = Some((cert, signer));
+        self
+    }
+
     /// Open a TLS connection, performing the handshake with the configuration provided when
     /// creating the connection instance.
     ///
     /// Returns an error if the handshake does not proceed. If an error occurs, the connection
     /// instance must be recreated.
-    pub fn open<'v, RNG, Verifier>(
-        &mut self,
-        context: TlsContext<'v, CipherSuite, RNG>,
-    ) -> Result<(), TlsError>
+    pub fn open<'v, RNG, Verifier>(&mut self, context: TlsContext<'v, RNG>) -> Result<(), TlsError>
     where
         RNG: CryptoRng + RngCore,
-        Verifier: TlsVerifier<'v, CipherSuite>,
+        Verifier: TlsVerifier<'v>,
     {
-        let mut handshake: Handshake<CipherSuite, Verifier> =
-            Handshake::new(Verifier::new(context.config.server_name));
+        let mut handshake: Handshake<'_, Provider, Verifier> =
+            Handshake::new(Verifier::new(context.config.server_name, context.clock));
+        self.tickets.set_server_name(context.config.server_name);
+        let ticket = self.tickets.load(context.config);
+        if let Some(data) = context.early_data {
+            handshake.offer_early_data(data, ticket.as_ref().map(|(ticket, _)| ticket));
+        }
+        handshake.offer_psks(context.config, ticket);
+        self.early_data_status = EarlyDataStatus::NotSent;
+        self.key_update_threshold = context.config.key_update_threshold;
+        self.peer_certificates.clear();
         let mut state = State::ClientHello;
 
         while state != State::ApplicationData {
             let next_state = state.process_blocking(
                 &mut self.delegate,
                 &mut handshake,
+                &mut self.peer_certificates,
                 &mut self.record_reader,
                 &mut self.record_write_buf,
                 &mut self.key_schedule,
@@ -96,10 +171,45 @@ where
             state = next_state;
         }
         self.opened = true;
+        self.early_data_status = handshake.early_data_status();
+        self.post_handshake_auth = handshake.post_handshake_auth(context.config);
+        self.alpn_protocol = handshake
+            .alpn_protocol(context.config)
+            .and_then(|protocol| Vec::from_slice(protocol).ok());
 
         Ok(())
     }
 
+    /// Returns the cipher suite selected by the server, once the connection is opened.
+    pub fn cipher_suite(&self) -> Option<CipherSuite> {
+        self.opened.then(|| self.key_schedule.cipher_suite())
+    }
+
+    /// Returns whether the server accepted the early data set with
+    /// [`TlsContext::with_early_data()`], once the connection is opened.
+    pub fn early_data_status(&self) -> EarlyDataStatus {
+        self.early_data_status
+    }
+
+    /// Returns the certificate chain sent by the server, starting with the leaf certificate, once
+    /// the connection is opened.
+    ///
+    /// The chain is only kept with [`Self::with_peer_certificates_buffer()`], and is empty if the
+    /// server authenticated with a pre-shared key.
+    pub fn peer_certificates(&self) -> PeerCertificates<'_> {
+        if self.opened {
+            self.peer_certificates.iter()
+        } else {
+            PeerCertificates::empty()
+        }
+    }
+
+    /// Returns the application protocol selected by the server among those configured with
+    /// [`TlsConfig::with_alpn_protocols`], once the connection is opened.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_deref().filter(|_| self.opened)
+    }
+
     /// Encrypt and send the provided slice over the connection. The connection
     /// must be opened before writing.
     ///
@@ -135,21 +245,88 @@ where
     /// to the connection.
     pub fn flush(&mut self) -> Result<(), TlsError> {
         if !self.record_write_buf.is_empty() {
-            let key_schedule = self.key_schedule.write_state();
-            let slice = self.record_write_buf.close_record(key_schedule)?;
+            dispatch!(AnyKeySchedule, &mut self.key_schedule, key_schedule => {
+                let key_schedule = key_schedule.write_state();
+                let slice = self.record_write_buf.close_record(key_schedule)?;
 
-            self.delegate
-                .write_all(slice)
-                .map_err(|e| TlsError::Io(e.kind()))?;
+                self.delegate
+                    .write_all(slice)
+                    .map_err(|e| TlsError::Io(e.kind()))?;
 
-            key_schedule.increment_counter();
+                key_schedule.increment_counter()?;
+                // Section 5.5: the key is updated before too many records are protected with it
+                if key_schedule.sequence_number() >= self.key_update_threshold {
+                    self.key_update_pending = true;
+                }
+            });
 
             self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))?;
         }
 
+        if self.key_update_pending {
+            self.key_update_pending = false;
+            self.send_key_update()?;
+        }
+
         Ok(())
     }
 
+    /// Responds to a KeyUpdate requested by the server, switching to the next write keys.
+    fn send_key_update(&mut self) -> Result<(), TlsError> {
+        dispatch!(AnyKeySchedule, &mut self.key_schedule, key_schedule => {
+            let key_schedule = key_schedule.write_state();
+            let slice = key_update(key_schedule, &mut self.record_write_buf)?;
+
+            self.delegate
+                .write_all(slice)
+                .map_err(|e| TlsError::Io(e.kind()))?;
+
+            key_schedule.increment_counter()?;
+            key_schedule.update_traffic_secret()?;
+        });
+
+        self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))
+    }
+
+    /// Authenticates with the client certificate, as requested by the server after the
+    /// handshake. Buffered application data is sent first.
+    fn answer_certificate_request(&mut self, request: &CertificateRequest) -> Result<(), TlsError> {
+        // The server may only send the request if the client offered post_handshake_auth
+        let certificate_type = self.post_handshake_auth.ok_or(TlsError::InvalidHandshake)?;
+        self.flush()?;
+
+        dispatch!(AnyKeySchedule, &mut self.key_schedule, key_schedule => {
+            let transcript = key_schedule
+                .read_state()
+                .take_certificate_request_hash()
+                .ok_or(TlsError::InternalError)?;
+            key_schedule.replace_transcript_hash(transcript);
+
+            let mut state = State::ClientCert;
+            while state != State::ApplicationData {
+                let (next_state, slice) = post_handshake_auth(
+                    state,
+                    request,
+                    certificate_type,
+                    self.client_auth.as_ref().map(|(cert, _)| cert),
+                    self.client_auth.as_ref().map(|(_, signer)| *signer),
+                    key_schedule,
+                    &mut self.record_write_buf,
+                )?;
+
+                self.delegate
+                    .write_all(slice)
+
+                    .map_err(|e| TlsError::Io(e.kind()))?;
+
+                key_schedule.write_state().increment_counter()?;
+                state = next_state;
+            }
+        });
+
+        self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))
+    }
+
     fn create_read_buffer(&mut self) -> ReadBuffer {
         self.decrypted.create_read_buffer(self.record_reader.buf)
     }
@@ -182,19 +359,54 @@ where
 
     fn read_application_data(&mut self) -> Result<(), TlsError> {
         let buf_ptr_range = self.record_reader.buf.as_ptr_range();
-        let key_schedule = self.key_schedule.read_state();
-        let record = self
-            .record_reader
-            .read_blocking(&mut self.delegate, key_schedule)?;
-
-        let mut handler = DecryptedReadHandler {
-            source_buffer: buf_ptr_range,
-            buffer_info: &mut self.decrypted,
-            is_open: &mut self.opened,
-        };
-        decrypt_record(key_schedule, record, |_key_schedule, record| {
-            handler.handle(record)
-        })?;
+        let mut certificate_request = None;
+        let result = dispatch!(AnyKeySchedule, &mut self.key_schedule, key_schedule => {
+            let key_schedule = key_schedule.read_state();
+            match self
+                .record_reader
+                .read_blocking(&mut self.delegate, key_schedule)
+            {
+                Ok(record) => {
+                    let mut handler = DecryptedReadHandler {
+                        source_buffer: buf_ptr_range,
+                        buffer_info: &mut self.decrypted,
+                        is_open: &mut self.opened,
+                        tickets: &mut self.tickets,
+                        is_server: self.is_server,
+                        key_update_requested: &mut self.key_update_pending,
+                        certificate_request: Some(&mut certificate_request),
+                    };
+                    decrypt_record(key_schedule, record, |key_schedule, record| {
+                        handler.handle(key_schedule, record)
+                    })
+                }
+                Err(e) => Err(e),
+            }
+        });
+
+        if let Err(TlsError::AbortHandshake(..)) = result {
+            // The alert is sent after the buffered application data
+            self.flush()?;
+            dispatch!(AnyKeySchedule, &mut self.key_schedule, key_schedule => {
+                handle_processing_error_blocking(
+                    result,
+                    &mut self.delegate,
+                    key_schedule,
+                    &mut self.record_write_buf,
+                )?;
+            });
+        }
+        result?;
+
+        if let Some(request) = certificate_request {
+            self.answer_certificate_request(&request)?;
+        }
+
+        // Section 4.6.3: a requested KeyUpdate is answered right away, after the buffered
+        // application data, so that a connection that only reads responds as well
+        if self.key_update_pending {
+            self.flush()?;
+        }
 
         Ok(())
     }
@@ -202,18 +414,20 @@ where
     fn close_internal(&mut self) -> Result<(), TlsError> {
         self.flush()?;
 
-        let (write_key_schedule, read_key_schedule) = self.key_schedule.as_split();
-        let slice = self.record_write_buf.write_record(
-            &ClientRecord::close_notify(self.opened),
-            write_key_schedule,
-            Some(read_key_schedule),
-        )?;
+        dispatch!(AnyKeySchedule, &mut self.key_schedule, key_schedule => {
+            let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+            let slice = self.record_write_buf.write_record(
+                &ClientRecord::close_notify(self.opened),
+                write_key_schedule,
+                Some(read_key_schedule),
+            )?;
 
-        self.delegate
-            .write_all(slice)
-            .map_err(|e| TlsError::Io(e.kind()))?;
+            self.delegate
+                .write_all(slice)
+                .map_err(|e| TlsError::Io(e.kind()))?;
 
-        self.key_schedule.write_state().increment_counter();
+            key_schedule.write_state().increment_counter()?;
+        });
 
         self.flush()?;
 
@@ -232,8 +446,8 @@ where
     pub fn split(
         self,
     ) -> (
-        TlsReader<'a, Socket, CipherSuite, ManagedSplitState>,
-        TlsWriter<'a, Socket, CipherSuite, ManagedSplitState>,
+        TlsReader<'a, Socket, ManagedSplitState, Provider>,
+        TlsWriter<'a, Socket, ManagedSplitState, Provider>,
     )
     where
         Socket: Clone,
@@ -241,12 +455,13 @@ where
         self.split_with(ManagedSplitState::new())
     }
 
+    #[allow(clippy::type_complexity)]
     pub fn split_with<StateContainer>(
         self,
         state: StateContainer,
     ) -> (
-        TlsReader<'a, Socket, CipherSuite, StateContainer::State>,
-        TlsWriter<'a, Socket, CipherSuite, StateContainer::State>,
+        TlsReader<'a, Socket, StateContainer::State, Provider>,
+        TlsWriter<'a, Socket, StateContainer::State, Provider>,
     )
     where
         Socket: Clone,
@@ -254,6 +469,9 @@ where
     {
         let state = state.state();
         state.set_open(self.opened);
+        if self.key_update_pending {
+            state.request_key_update();
+        }
 
         let (shared, wks, rks) = self.key_schedule.split();
 
@@ -263,6 +481,10 @@ where
             key_schedule: rks,
             record_reader: self.record_reader,
             decrypted: self.decrypted,
+            peer_certificates: self.peer_certificates,
+            alpn_protocol: self.alpn_protocol,
+            tickets: self.tickets,
+            is_server: self.is_server,
         };
         let writer = TlsWriter {
             state,
@@ -270,14 +492,17 @@ where
             key_schedule_shared: shared,
             key_schedule: wks,
             record_write_buf: self.record_write_buf,
+            key_update_threshold: self.key_update_threshold,
+            client_auth: self.client_auth,
+            post_handshake_auth: self.post_handshake_auth,
         };
 
         (reader, writer)
     }
 
     pub fn unsplit<State>(
-        reader: TlsReader<'a, Socket, CipherSuite, State>,
-        writer: TlsWriter<'a, Socket, CipherSuite, State>,
+        reader: TlsReader<'a, Socket, State, Provider>,
+        writer: TlsWriter<'a, Socket, State, Provider>,
     ) -> Self
     where
         Socket: Clone,
@@ -288,7 +513,8 @@ where
         TlsConnection {
             delegate: writer.delegate,
             opened: writer.state.is_open(),
-            key_schedule: KeySchedule::unsplit(
+            is_server: reader.is_server,
+            key_schedule: AnyKeySchedule::unsplit(
                 writer.key_schedule_shared,
                 writer.key_schedule,
                 reader.key_schedule,
@@ -296,32 +522,40 @@ where
             record_reader: reader.record_reader,
             record_write_buf: writer.record_write_buf,
             decrypted: reader.decrypted,
+            peer_certificates: reader.peer_certificates,
+            alpn_protocol: reader.alpn_protocol,
+            tickets: reader.tickets,
+            early_data_status: EarlyDataStatus::NotSent,
+            key_update_threshold: writer.key_update_threshold,
+            key_update_pending: writer.state.take_key_update_request(),
+            client_auth: writer.client_auth,
+            post_handshake_auth: writer.post_handshake_auth,
         }
     }
 }
 
-impl<'a, Socket, CipherSuite> ErrorType for TlsConnection<'a, Socket, CipherSuite>
+impl<'a, Socket, Provider> ErrorType for TlsConnection<'a, Socket, Provider>
 where
     Socket: Read + Write + 'a,
-    CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     type Error = TlsError;
 }
 
-impl<'a, Socket, CipherSuite> Read for TlsConnection<'a, Socket, CipherSuite>
+impl<'a, Socket, Provider> Read for TlsConnection<'a, Socket, Provider>
 where
     Socket: Read + Write + 'a,
-    CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
         TlsConnection::read(self, buf)
     }
 }
 
-impl<'a, Socket, CipherSuite> BufRead for TlsConnection<'a, Socket, CipherSuite>
+impl<'a, Socket, Provider> BufRead for TlsConnection<'a, Socket, Provider>
 where
     Socket: Read + Write + 'a,
-    CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
         self.read_buffered().map(|mut buf| buf.peek_all())
@@ -332,10 +566,10 @@ where
     }
 }
 
-impl<'a, Socket, CipherSuite> Write for TlsConnection<'a, Socket, CipherSuite>
+impl<'a, Socket, Provider> Write for TlsConnection<'a, Socket, Provider>
 where
     Socket: Read + Write + 'a,
-    CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
         TlsConnection::write(self, buf)
@@ -346,31 +580,117 @@ where
     }
 }
 
-pub struct TlsReader<'a, Socket, CipherSuite, State>
+/// Type representing the server side of a TLS connection. An instance of this type accepts a
+/// TLS handshake from a client, and turns into a [`TlsConnection`] to write and read encrypted
+/// data over the connection.
+pub struct TlsAcceptor<'a, Socket, Provider = RustCryptoProvider>
 where
-    CipherSuite: TlsCipherSuite + 'static,
+    Socket: Read + Write + 'a,
+    Provider: CryptoProvider,
+{
+    connection: TlsConnection<'a, Socket, Provider>,
+}
+
+impl<'a, Socket, Provider> TlsAcceptor<'a, Socket, Provider>
+where
+    Socket: Read + Write + 'a,
+    Provider: CryptoProvider,
+{
+    /// Create a new TLS acceptor with a blocking I/O implementation.
+    ///
+    /// The buffers are used as in [`TlsConnection::new()`]. Either of them must be large enough to
+    /// encode the server certificate.
+    pub fn new(
+        delegate: Socket,
+        record_read_buf: &'a mut [u8],
+        record_write_buf: &'a mut [u8],
+    ) -> Self {
+        Self {
+            connection: TlsConnection::new(delegate, record_read_buf, record_write_buf),
+        }
+    }
+
+    /// Accept a TLS connection, performing the handshake with the configuration of `context`.
+    ///
+    /// The server authenticates with the certificate and signer of the configuration, or with its
+    /// pre-shared key if the client offers it. The client cannot authenticate, and early data is
+    /// rejected.
+    ///
+    /// Returns the ownership of the I/O provider if the handshake does not proceed.
+    pub fn accept<RNG>(
+        mut self,
+        context: TlsContext<'_, RNG>,
+    ) -> Result<TlsConnection<'a, Socket, Provider>, (Socket, TlsError)>
+    where
+        RNG: CryptoRng + RngCore,
+    {
+        match self.accept_internal(context) {
+            Ok(()) => Ok(self.connection),
+            Err(e) => Err((self.connection.delegate, e)),
+        }
+    }
+
+    fn accept_internal<RNG>(&mut self, context: TlsContext<'_, RNG>) -> Result<(), TlsError>
+    where
+        RNG: CryptoRng + RngCore,
+    {
+        let connection = &mut self.connection;
+        let mut handshake = AcceptorHandshake::new();
+        connection.key_update_threshold = context.config.key_update_threshold;
+        let mut state = AcceptorState::ClientHello;
+
+        while state != AcceptorState::ApplicationData {
+            let next_state = state.process_blocking(
+                &mut connection.delegate,
+                &mut handshake,
+                &mut connection.record_reader,
+                &mut connection.record_write_buf,
+                &mut connection.key_schedule,
+                context.config,
+                context.rng,
+            )?;
+            trace!("State {:?} -> {:?}", state, next_state);
+            state = next_state;
+        }
+        connection.opened = true;
+        connection.is_server = true;
+        connection.alpn_protocol = handshake
+            .alpn_protocol(context.config)
+            .and_then(|protocol| Vec::from_slice(protocol).ok());
+
+        Ok(())
+    }
+}
+
+pub struct TlsReader<'a, Socket, State, Provider = RustCryptoProvider>
+where
+    Provider: CryptoProvider,
 {
     state: State,
     delegate: Socket,
-    key_schedule: ReadKeySchedule<CipherSuite>,
-    record_reader: RecordReader<'a, CipherSuite>,
+    key_schedule: AnyReadKeySchedule<Provider>,
+    record_reader: RecordReader<'a>,
     decrypted: DecryptedBufferInfo,
+    peer_certificates: PeerCertificateBuffer<'a>,
+    alpn_protocol: Option<Vec<u8, 255>>,
+    tickets: Tickets<'a>,
+    is_server: bool,
 }
 
-impl<'a, Socket, CipherSuite, State> AsRef<Socket> for TlsReader<'a, Socket, CipherSuite, State>
+impl<'a, Socket, State, Provider> AsRef<Socket> for TlsReader<'a, Socket, State, Provider>
 where
-    CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     fn as_ref(&self) -> &Socket {
         &self.delegate
     }
 }
 
-impl<'a, Socket, CipherSuite, State> TlsReader<'a, Socket, CipherSuite, State>
+impl<'a, Socket, State, Provider> TlsReader<'a, Socket, State, Provider>
 where
     Socket: Read + 'a,
-    CipherSuite: TlsCipherSuite + 'static,
     State: SplitState,
+    Provider: CryptoProvider,
 {
     fn create_read_buffer(&mut self) -> ReadBuffer {
         self.decrypted.create_read_buffer(self.record_reader.buf)
@@ -391,66 +711,81 @@ where
 
     fn read_application_data(&mut self) -> Result<(), TlsError> {
         let buf_ptr_range = self.record_reader.buf.as_ptr_range();
-        let record = self
-            .record_reader
-            .read_blocking(&mut self.delegate, &mut self.key_schedule)?;
-
         let mut opened = self.state.is_open();
-        let mut handler = DecryptedReadHandler {
-            source_buffer: buf_ptr_range,
-            buffer_info: &mut self.decrypted,
-            is_open: &mut opened,
-        };
-        let result = decrypt_record(&mut self.key_schedule, record, |_key_schedule, record| {
-            handler.handle(record)
+        let mut key_update_requested = false;
+        let result = dispatch!(AnyReadKeySchedule, &mut self.key_schedule, key_schedule => {
+            let record = self
+                .record_reader
+                .read_blocking(&mut self.delegate, key_schedule)?;
+
+            let mut handler = DecryptedReadHandler {
+                source_buffer: buf_ptr_range,
+                buffer_info: &mut self.decrypted,
+                is_open: &mut opened,
+                tickets: &mut self.tickets,
+                is_server: self.is_server,
+                key_update_requested: &mut key_update_requested,
+                certificate_request: None,
+            };
+            decrypt_record(key_schedule, record, |key_schedule, record| {
+                handler.handle(key_schedule, record)
+            })
         });
 
         if !opened {
             self.state.set_open(false);
         }
+        // The reader cannot write, so the KeyUpdate is answered by the writer with its next
+        // flush
+        if key_update_requested {
+            self.state.request_key_update();
+        }
         result
     }
 }
 
-pub struct TlsWriter<'a, Socket, CipherSuite, State>
+pub struct TlsWriter<'a, Socket, State, Provider = RustCryptoProvider>
 where
-    CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     state: State,
     delegate: Socket,
-    key_schedule_shared: SharedState<CipherSuite>,
-    key_schedule: WriteKeySchedule<CipherSuite>,
+    key_schedule_shared: AnySharedState<Provider>,
+    key_schedule: AnyWriteKeySchedule<Provider>,
     record_write_buf: WriteBuffer<'a>,
+    key_update_threshold: u64,
+    client_auth: Option<(Certificate<'a>, &'a (dyn TlsSigner + Sync))>,
+    post_handshake_auth: Option<CertificateType>,
 }
 
-impl<'a, Socket, CipherSuite, State> AsRef<Socket> for TlsWriter<'a, Socket, CipherSuite, State>
+impl<'a, Socket, State, Provider> AsRef<Socket> for TlsWriter<'a, Socket, State, Provider>
 where
-    CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     fn as_ref(&self) -> &Socket {
         &self.delegate
     }
 }
 
-impl<'a, Socket, CipherSuite, State> ErrorType for TlsWriter<'a, Socket, CipherSuite, State>
+impl<'a, Socket, State, Provider> ErrorType for TlsWriter<'a, Socket, State, Provider>
 where
-    CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     type Error = TlsError;
 }
 
-impl<'a, Socket, CipherSuite, State> ErrorType for TlsReader<'a, Socket, CipherSuite, State>
+impl<'a, Socket, State, Provider> ErrorType for TlsReader<'a, Socket, State, Provider>
 where
-    CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     type Error = TlsError;
 }
 
-impl<'a, Socket, CipherSuite, State> Read for TlsReader<'a, Socket, CipherSuite, State>
+impl<'a, Socket, State, Provider> Read for TlsReader<'a, Socket, State, Provider>
 where
     Socket: Read + 'a,
-    CipherSuite: TlsCipherSuite + 'static,
     State: SplitState,
+    Provider: CryptoProvider,
 {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
         if buf.is_empty() {
@@ -465,11 +800,11 @@ where
     }
 }
 
-impl<'a, Socket, CipherSuite, State> BufRead for TlsReader<'a, Socket, CipherSuite, State>
+impl<'a, Socket, State, Provider> BufRead for TlsReader<'a, Socket, State, Provider>
 where
     Socket: Read + 'a,
-    CipherSuite: TlsCipherSuite + 'static,
     State: SplitState,
+    Provider: CryptoProvider,
 {
     fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
         self.read_buffered().map(|mut buf| buf.peek_all())
@@ -480,11 +815,33 @@ where
     }
 }
 
-impl<'a, Socket, CipherSuite, State> Write for TlsWriter<'a, Socket, CipherSuite, State>
+impl<'a, Socket, State, Provider> TlsWriter<'a, Socket, State, Provider>
+where
+    Socket: Write + 'a,
+    Provider: CryptoProvider,
+{
+    /// Responds to a KeyUpdate requested by the server, switching to the next write keys.
+    fn send_key_update(&mut self) -> Result<(), TlsError> {
+        dispatch!(AnyWriteKeySchedule, &mut self.key_schedule, key_schedule => {
+            let slice = key_update(key_schedule, &mut self.record_write_buf)?;
+
+            self.delegate
+                .write_all(slice)
+                .map_err(|e| TlsError::Io(e.kind()))?;
+
+            key_schedule.increment_counter()?;
+            key_schedule.update_traffic_secret()?;
+        });
+
+        self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))
+    }
+}
+
+impl<'a, Socket, State, Provider> Write for TlsWriter<'a, Socket, State, Provider>
 where
     Socket: Write + 'a,
-    CipherSuite: TlsCipherSuite + 'static,
     State: SplitState,
+    Provider: CryptoProvider,
 {
     fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
         if self.state.is_open() {
@@ -511,17 +868,27 @@ where
 
     fn flush(&mut self) -> Result<(), Self::Error> {
         if !self.record_write_buf.is_empty() {
-            let slice = self.record_write_buf.close_record(&mut self.key_schedule)?;
+            dispatch!(AnyWriteKeySchedule, &mut self.key_schedule, key_schedule => {
+                let slice = self.record_write_buf.close_record(key_schedule)?;
 
-            self.delegate
-                .write_all(slice)
-                .map_err(|e| TlsError::Io(e.kind()))?;
+                self.delegate
+                    .write_all(slice)
+                    .map_err(|e| TlsError::Io(e.kind()))?;
 
-            self.key_schedule.increment_counter();
+                key_schedule.increment_counter()?;
+                // Section 5.5: the key is updated before too many records are protected with it
+                if key_schedule.sequence_number() >= self.key_update_threshold {
+                    self.state.request_key_update();
+                }
+            });
 
             self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))?;
         }
 
+        if self.state.take_key_update_request() {
+            self.send_key_update()?;
+        }
+
         Ok(())
     }
 }
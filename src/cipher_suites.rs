@@ -1,6 +1,6 @@
 use crate::parse_buffer::{ParseBuffer, ParseError};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum CipherSuite {
     TlsAes128GcmSha256 = 0x1301,
@@ -13,7 +13,15 @@ pub enum CipherSuite {
 
 impl CipherSuite {
     pub fn parse(buf: &mut ParseBuffer) -> Result<Self, ParseError> {
-        match buf.read_u16()? {
+        buf.read_u16()?.try_into()
+    }
+}
+
+impl TryFrom<u16> for CipherSuite {
+    type Error = ParseError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
             v if v == Self::TlsAes128GcmSha256 as u16 => Ok(Self::TlsAes128GcmSha256),
             v if v == Self::TlsAes256GcmSha384 as u16 => Ok(Self::TlsAes256GcmSha384),
             v if v == Self::TlsChacha20Poly1305Sha256 as u16 => Ok(Self::TlsChacha20Poly1305Sha256),
@@ -1,19 +1,34 @@
 use core::ops::Range;
 
 use crate::{
-    alert::AlertDescription, common::decrypted_buffer_info::DecryptedBufferInfo,
-    config::TlsCipherSuite, handshake::ServerHandshake, record::ServerRecord, TlsError,
+    alert::{AlertDescription, AlertLevel},
+    common::decrypted_buffer_info::DecryptedBufferInfo,
+    config::TlsCipherSuite,
+    handshake::{certificate_request::CertificateRequest, ServerHandshake},
+    key_schedule::ReadKeySchedule,
+    record::ServerRecord,
+    ticket::Tickets,
+    TlsError,
 };
 
-pub struct DecryptedReadHandler<'a> {
+pub struct DecryptedReadHandler<'a, 't> {
     pub source_buffer: Range<*const u8>,
     pub buffer_info: &'a mut DecryptedBufferInfo,
     pub is_open: &'a mut bool,
+    pub tickets: &'a mut Tickets<'t>,
+    /// Whether the records are received from a client, which does not send the messages of a
+    /// server.
+    pub is_server: bool,
+    pub key_update_requested: &'a mut bool,
+    /// Where a post-handshake CertificateRequest is kept until it is answered, or `None` if the
+    /// connection cannot answer it.
+    pub certificate_request: Option<&'a mut Option<CertificateRequest>>,
 }
 
-impl DecryptedReadHandler<'_> {
+impl DecryptedReadHandler<'_, '_> {
     pub fn handle<CipherSuite: TlsCipherSuite>(
         &mut self,
+        key_schedule: &mut ReadKeySchedule<CipherSuite>,
         record: ServerRecord<'_, CipherSuite>,
     ) -> Result<(), TlsError> {
         match record {
@@ -50,15 +65,38 @@ impl DecryptedReadHandler<'_> {
                 }
             }
             ServerRecord::ChangeCipherSpec(_) => Err(TlsError::InternalError),
-            ServerRecord::Handshake(ServerHandshake::NewSessionTicket(_)) => {
+            ServerRecord::Handshake(
+                ServerHandshake::NewSessionTicket(_) | ServerHandshake::CertificateRequest(_),
+            ) if self.is_server => Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnexpectedMessage,
+            )),
+            ServerRecord::Handshake(ServerHandshake::NewSessionTicket(ticket)) => {
                 // TODO: we should validate extensions and abort. We can do this automatically
                 // as long as the connection is unsplit, however, split connections must be aborted
                 // by the user.
-                Ok(())
+                self.tickets.store(key_schedule, &ticket)
+            }
+            ServerRecord::Handshake(ServerHandshake::KeyUpdate(key_update)) => {
+                // Section 4.6.3: the response is sent by the caller, before any further
+                // application data
+                if key_update.update_requested {
+                    *self.key_update_requested = true;
+                }
+                key_schedule.update_traffic_secret()
             }
-            _ => {
-                unimplemented!()
+            ServerRecord::Handshake(ServerHandshake::CertificateRequest(request)) => {
+                // The request is answered after the record is handled, with the transcript up to
+                // and including it
+                let pending = self
+                    .certificate_request
+                    .as_mut()
+                    .ok_or(TlsError::Unimplemented)?;
+                key_schedule.save_certificate_request_hash()?;
+                pending.replace(request.try_into()?);
+                Ok(())
             }
+            _ => Err(TlsError::InvalidHandshake),
         }
     }
 }
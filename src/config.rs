@@ -1,25 +1,48 @@
-use crate::cipher_suites::CipherSuite;
-use crate::extensions::extension_data::signature_algorithms::SignatureScheme;
-use crate::extensions::extension_data::supported_groups::NamedGroup;
 use crate::handshake::certificate::CertificateRef;
 use crate::handshake::certificate_verify::CertificateVerify;
 use crate::TlsError;
+use aes_gcm::aes::Aes128;
 use aes_gcm::{AeadInPlace, Aes128Gcm, Aes256Gcm, KeyInit};
-use core::marker::PhantomData;
+use ccm::consts::U8;
+use ccm::Ccm;
+use chacha20poly1305::ChaCha20Poly1305;
 use digest::core_api::BlockSizeUser;
 use digest::{Digest, FixedOutput, OutputSizeUser, Reset};
 use generic_array::ArrayLength;
 use heapless::Vec;
+use hmac::{Mac, SimpleHmac};
 use rand_core::{CryptoRng, RngCore};
 pub use sha2::Sha256;
 pub use sha2::Sha384;
 use typenum::{Sum, U10, U12, U16, U32};
 
+pub use crate::cipher_suites::CipherSuite;
+pub use crate::crypto_provider::{
+    CryptoProvider, P256Signer, RustCryptoKeyExchange, RustCryptoProvider, RustCryptoSharedSecret,
+    TlsKeyExchange,
+};
+pub use crate::extensions::extension_data::certificate_type::CertificateType;
 pub use crate::extensions::extension_data::max_fragment_length::MaxFragmentLength;
+pub use crate::extensions::extension_data::signature_algorithms::SignatureScheme;
+pub use crate::extensions::extension_data::supported_groups::NamedGroup;
 
 pub(crate) const TLS_RECORD_MAX: usize = 16384;
 pub const TLS_RECORD_OVERHEAD: usize = 128;
 
+/// The maximum number of trust anchors in a [`TlsConfig`].
+pub const MAX_TRUST_ANCHORS: usize = 8;
+
+/// The maximum number of certificate revocation lists in a [`TlsConfig`].
+pub const MAX_CRLS: usize = 4;
+
+/// The maximum number of application protocols offered with
+/// [`TlsConfig::with_alpn_protocols`].
+pub const MAX_ALPN_PROTOCOLS: usize = 4;
+
+/// The default number of records the client sends with a traffic key before updating it,
+/// below the limit of 2^24.5 records for AES-GCM (Section 5.5).
+pub const DEFAULT_KEY_UPDATE_THRESHOLD: u64 = 1 << 24;
+
 // longest label is 12b -> buf <= 2 + 1 + 6 + longest + 1 + hash_out = hash_out + 22
 type LongestLabel = U12;
 type LabelOverhead = U10;
@@ -36,6 +59,8 @@ pub trait TlsCipherSuite {
     type IvLen: ArrayLength<u8>;
 
     type Hash: Digest + Reset + Clone + OutputSizeUser + BlockSizeUser + FixedOutput;
+    /// HMAC using [`Self::Hash`], which is also used for the HKDF key derivation.
+    type Hmac: Mac<OutputSize = <Self::Hash as OutputSizeUser>::OutputSize> + KeyInit + Clone;
     type LabelBufferSize: ArrayLength<u8>;
 }
 
@@ -47,6 +72,7 @@ impl TlsCipherSuite for Aes128GcmSha256 {
     type IvLen = U12;
 
     type Hash = Sha256;
+    type Hmac = SimpleHmac<Sha256>;
     type LabelBufferSize = LabelBuffer<Self>;
 }
 
@@ -58,6 +84,44 @@ impl TlsCipherSuite for Aes256GcmSha384 {
     type IvLen = U12;
 
     type Hash = Sha384;
+    type Hmac = SimpleHmac<Sha384>;
+    type LabelBufferSize = LabelBuffer<Self>;
+}
+
+pub struct Chacha20Poly1305Sha256;
+impl TlsCipherSuite for Chacha20Poly1305Sha256 {
+    const CODE_POINT: u16 = CipherSuite::TlsChacha20Poly1305Sha256 as u16;
+    type Cipher = ChaCha20Poly1305;
+    type KeyLen = U32;
+    type IvLen = U12;
+
+    type Hash = Sha256;
+    type Hmac = SimpleHmac<Sha256>;
+    type LabelBufferSize = LabelBuffer<Self>;
+}
+
+pub struct Aes128CcmSha256;
+impl TlsCipherSuite for Aes128CcmSha256 {
+    const CODE_POINT: u16 = CipherSuite::TlsAes128CcmSha256 as u16;
+    type Cipher = Ccm<Aes128, U16, U12>;
+    type KeyLen = U16;
+    type IvLen = U12;
+
+    type Hash = Sha256;
+    type Hmac = SimpleHmac<Sha256>;
+    type LabelBufferSize = LabelBuffer<Self>;
+}
+
+/// AES-128-CCM with a truncated 8 byte authentication tag.
+pub struct Aes128Ccm8Sha256;
+impl TlsCipherSuite for Aes128Ccm8Sha256 {
+    const CODE_POINT: u16 = CipherSuite::TlsAes128Ccm8Sha256 as u16;
+    type Cipher = Ccm<Aes128, U8, U12>;
+    type KeyLen = U16;
+    type IvLen = U12;
+
+    type Hash = Sha256;
+    type Hmac = SimpleHmac<Sha256>;
     type LabelBufferSize = LabelBuffer<Self>;
 }
 
@@ -66,26 +130,29 @@ impl TlsCipherSuite for Aes256GcmSha384 {
 /// The verifier is responsible for verifying certificates and signatures. Since certificate verification is
 /// an expensive process, this trait allows clients to choose how much verification should take place,
 /// and also to skip the verification if the server is verified through other means (I.e. a pre-shared key).
-pub trait TlsVerifier<'a, CipherSuite>
-where
-    CipherSuite: TlsCipherSuite,
-{
+pub trait TlsVerifier<'a> {
     /// Create a new verification instance.
     ///
     /// This method is called for every TLS handshake.
     ///
-    /// Host verification is enabled by passing a server hostname.
-    fn new(host: Option<&'a str>) -> Self;
+    /// Host verification is enabled by passing a server hostname. `clock` is the clock of the
+    /// [`TlsContext`], for checking the validity period of certificates.
+    fn new(host: Option<&'a str>, clock: &'a (dyn TlsClock + Sync)) -> Self;
 
     /// Verify a certificate.
     ///
-    /// The handshake transcript up to this point and the server certificate is provided
-    /// for the implementation
-    /// to use.
+    /// The hash of the handshake transcript up to and including the certificate message, using
+    /// the hash function of the negotiated cipher suite, and the server certificate is provided
+    /// for the implementation to use. The certificate is trusted if it is authenticated by any
+    /// of the trust anchors in `ca`, and must be rejected if it is revoked by any of the DER
+    /// encoded certificate revocation lists in `crls`. `ocsp_stapling` is set if a stapled OCSP
+    /// response was requested with [`TlsConfig::enable_ocsp_stapling`].
     fn verify_certificate(
         &mut self,
-        transcript: &CipherSuite::Hash,
-        ca: &Option<Certificate>,
+        transcript: &[u8],
+        ca: &[Certificate],
+        crls: &[&[u8]],
+        ocsp_stapling: bool,
         cert: CertificateRef,
     ) -> Result<(), TlsError>;
 
@@ -97,18 +164,17 @@ where
 
 pub struct NoVerify;
 
-impl<'a, CipherSuite> TlsVerifier<'a, CipherSuite> for NoVerify
-where
-    CipherSuite: TlsCipherSuite,
-{
-    fn new(_host: Option<&str>) -> Self {
+impl<'a> TlsVerifier<'a> for NoVerify {
+    fn new(_host: Option<&str>, _clock: &(dyn TlsClock + Sync)) -> Self {
         Self
     }
 
     fn verify_certificate(
         &mut self,
-        _transcript: &CipherSuite::Hash,
-        _ca: &Option<Certificate>,
+        _transcript: &[u8],
+        _ca: &[Certificate],
+        _crls: &[&[u8]],
+        _ocsp_stapling: bool,
         _cert: CertificateRef,
     ) -> Result<(), TlsError> {
         Ok(())
@@ -119,75 +185,175 @@ where
     }
 }
 
+/// Signs the client CertificateVerify message, proving possession of the private key of the
+/// client certificate.
+///
+/// [`P256Signer`] signs with an ECDSA secp256r1 key held in memory. Implement this trait to sign
+/// with other key types, or with a key stored in a secure element.
+pub trait TlsSigner {
+    /// The signature scheme, which must match the key of the client certificate.
+    fn signature_scheme(&self) -> SignatureScheme;
+
+    /// Signs `message` with [`Self::signature_scheme`], writing the signature to `signature`
+    /// and returning its length.
+    ///
+    /// `signature` is [`MAX_SIGNATURE_LEN`] bytes long.
+    fn sign(&self, message: &[u8], signature: &mut [u8]) -> Result<usize, TlsError>;
+}
+
+/// The largest signature a [`TlsSigner`] can produce, a 4096 bit RSA signature.
+pub const MAX_SIGNATURE_LEN: usize = 512;
+
+impl<'a> core::fmt::Debug for dyn TlsSigner + Sync + 'a {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TlsSigner")
+            .field("signature_scheme", &self.signature_scheme())
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<'a> defmt::Format for dyn TlsSigner + Sync + 'a {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "TlsSigner({})", self.signature_scheme())
+    }
+}
+
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-pub struct TlsConfig<'a, CipherSuite>
-where
-    CipherSuite: TlsCipherSuite,
-{
-    //pub(crate) cipher_suites: Vec<CipherSuite, U16>,
+pub struct TlsConfig<'a> {
     pub(crate) server_name: Option<&'a str>,
     pub(crate) psk: Option<(&'a [u8], Vec<&'a [u8], 4>)>,
-    pub(crate) cipher_suite: PhantomData<CipherSuite>,
+    pub(crate) cipher_suites: Vec<CipherSuite, 5>,
     pub(crate) signature_schemes: Vec<SignatureScheme, 16>,
     pub(crate) named_groups: Vec<NamedGroup, 16>,
     pub(crate) max_fragment_length: Option<MaxFragmentLength>,
-    pub(crate) ca: Option<Certificate<'a>>,
+    pub(crate) ocsp_stapling: bool,
+    pub(crate) ca: Vec<Certificate<'a>, MAX_TRUST_ANCHORS>,
+    pub(crate) crls: Vec<&'a [u8], MAX_CRLS>,
     pub(crate) cert: Option<Certificate<'a>>,
+    pub(crate) signer: Option<&'a (dyn TlsSigner + Sync)>,
+    pub(crate) key_update_threshold: u64,
+    pub(crate) alpn_protocols: Vec<&'a [u8], MAX_ALPN_PROTOCOLS>,
 }
 
+/// A source of the current time, for checking the validity period of certificates.
+///
+/// The clock is passed to [`TlsContext::with_clock`], so it can hold state such as an RTC
+/// driver or the last time received over NTP.
 pub trait TlsClock {
-    fn now() -> Option<u64>;
+    /// Returns the number of seconds since the Unix epoch, or `None` if the time is unknown.
+    fn now(&self) -> Option<u64>;
 }
 
+/// A clock that does not know the time. Certificates are checked as if it were the Unix epoch,
+/// so their validation fails.
 pub struct NoClock;
 
 impl TlsClock for NoClock {
-    fn now() -> Option<u64> {
+    fn now(&self) -> Option<u64> {
         None
     }
 }
 
+impl<'a> core::fmt::Debug for dyn TlsClock + Sync + 'a {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // Reading the clock may have side effects, such as accessing an RTC
+        f.write_str("TlsClock")
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<'a> defmt::Format for dyn TlsClock + Sync + 'a {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "TlsClock")
+    }
+}
+
+/// The system clock, read with [`std::time::SystemTime`].
+///
+/// `SystemTime` implements [`TlsClock`] as well, reading the current time in the same way.
+#[cfg(feature = "std")]
+pub struct SystemClock;
+
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-pub struct TlsContext<'a, CipherSuite, RNG>
+pub struct TlsContext<'a, RNG>
 where
-    CipherSuite: TlsCipherSuite,
     RNG: CryptoRng + RngCore + 'a,
 {
-    pub(crate) config: &'a TlsConfig<'a, CipherSuite>,
+    pub(crate) config: &'a TlsConfig<'a>,
     pub(crate) rng: &'a mut RNG,
+    pub(crate) clock: &'a (dyn TlsClock + Sync),
+    pub(crate) early_data: Option<&'a [u8]>,
 }
 
-impl<'a, CipherSuite, RNG> TlsContext<'a, CipherSuite, RNG>
+impl<'a, RNG> TlsContext<'a, RNG>
 where
-    CipherSuite: TlsCipherSuite,
     RNG: CryptoRng + RngCore + 'a,
 {
     /// Create a new context with a given config and random number generator reference.
-    pub fn new(config: &'a TlsConfig<'a, CipherSuite>, rng: &'a mut RNG) -> Self {
-        Self { config, rng }
+    pub fn new(config: &'a TlsConfig<'a>, rng: &'a mut RNG) -> Self {
+        Self {
+            config,
+            rng,
+            clock: &NoClock,
+            early_data: None,
+        }
+    }
+
+    /// Sets the clock used by the verifier to check the validity period of certificates.
+    /// Defaults to [`NoClock`].
+    ///
+    /// The clock is `Sync`, so that the futures of the connection remain `Send`.
+    pub fn with_clock(mut self, clock: &'a (dyn TlsClock + Sync)) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sends `data` in the first flight of the handshake (0-RTT), when resuming a session with
+    /// a ticket that allows that much early data. Whether it was sent and accepted is returned by
+    /// `TlsConnection::early_data_status`.
+    ///
+    /// Early data is not protected against replay by the TLS protocol, so it should only
+    /// contain requests that can be safely repeated.
+    pub fn with_early_data(mut self, data: &'a [u8]) -> Self {
+        self.early_data = Some(data);
+        self
     }
 }
 
-impl<'a, CipherSuite> TlsConfig<'a, CipherSuite>
-where
-    CipherSuite: TlsCipherSuite,
-{
+impl<'a> TlsConfig<'a> {
     pub fn new() -> Self {
         let mut config = Self {
-            cipher_suite: PhantomData,
+            cipher_suites: Vec::new(),
             signature_schemes: Vec::new(),
             named_groups: Vec::new(),
             max_fragment_length: None,
+            ocsp_stapling: false,
             psk: None,
             server_name: None,
-            ca: None,
+            ca: Vec::new(),
+            crls: Vec::new(),
             cert: None,
+            signer: None,
+            key_update_threshold: DEFAULT_KEY_UPDATE_THRESHOLD,
+            alpn_protocols: Vec::new(),
         };
 
-        //config.cipher_suites.push(CipherSuite::TlsAes128GcmSha256);
-        //
+        unwrap!(config
+            .cipher_suites
+            .push(CipherSuite::TlsAes128GcmSha256)
+            .ok());
+        unwrap!(config
+            .cipher_suites
+            .push(CipherSuite::TlsAes256GcmSha384)
+            .ok());
+        unwrap!(config
+            .cipher_suites
+            .push(CipherSuite::TlsChacha20Poly1305Sha256)
+            .ok());
+
         if cfg!(feature = "alloc") {
             config = config.enable_rsa_signatures();
         }
@@ -236,17 +402,153 @@ where
         self
     }
 
+    /// Configures the cipher suites offered to the server, in order of preference.
+    ///
+    /// The handshake continues with whichever of these suites the server selects, which can be
+    /// queried once the connection is opened. Defaults to TLS_AES_128_GCM_SHA256,
+    /// TLS_AES_256_GCM_SHA384 and TLS_CHACHA20_POLY1305_SHA256. When using a pre-shared key,
+    /// the key is assumed to be associated with the hash of the first suite.
+    ///
+    /// Suites that are not supported by embedded-tls are ignored. A `TlsAcceptor` selects the
+    /// first of these suites offered by the client.
+    pub fn with_cipher_suites(mut self, cipher_suites: &[CipherSuite]) -> Self {
+        self.cipher_suites.clear();
+        for cipher_suite in cipher_suites {
+            if *cipher_suite != CipherSuite::TlsPskAes128GcmSha256
+                && !self.cipher_suites.contains(cipher_suite)
+            {
+                unwrap!(self.cipher_suites.push(*cipher_suite).ok());
+            }
+        }
+        self
+    }
+
+    /// Configures the key exchange groups offered to the server, in order of preference.
+    ///
+    /// A key share is sent for each of the groups, so every additional group costs one
+    /// extra key generation during the handshake. Defaults to secp256r1. X25519MLKEM768
+    /// requires the `mlkem` feature, and its key share needs a larger write buffer, see
+    /// [`Self::min_write_buffer_len`].
+    ///
+    /// Groups that are not supported by the [`CryptoProvider`] of the connection are ignored.
+    /// A `TlsAcceptor` selects the first group the client sent a key share for, and otherwise
+    /// asks for a key share of the first group the client supports. KEM groups are not
+    /// supported by the acceptor.
+    pub fn with_named_groups(mut self, named_groups: &[NamedGroup]) -> Self {
+        self.named_groups.clear();
+        for group in named_groups {
+            if !self.named_groups.contains(group) {
+                unwrap!(self.named_groups.push(*group).ok());
+            }
+        }
+        self
+    }
+
+    /// Configures the number of records sent with a traffic key after which the client sends a
+    /// KeyUpdate and switches to the next key, so long-lived connections stay within the limits
+    /// of the cipher (Section 5.5). Defaults to [`DEFAULT_KEY_UPDATE_THRESHOLD`].
+    ///
+    /// The KeyUpdate is sent when flushing the record that reaches the threshold. With
+    /// `u64::MAX` the keys are never updated, and writing fails with
+    /// `TlsError::SequenceNumberExhausted` once all sequence numbers are used.
+    pub fn with_key_update_threshold(mut self, records: u64) -> Self {
+        self.key_update_threshold = records;
+        self
+    }
+
+    /// Returns the write record buffer size needed to send the ClientHello for this
+    /// configuration.
+    ///
+    /// This is an upper bound assuming a key share is sent for every configured group, which
+    /// helps sizing the buffer when offering groups with large key shares such as
+    /// X25519MLKEM768. It does not include the cookie a server may ask to be echoed, nor a
+    /// session ticket offered to resume a session and the early_data extension sent with it.
+    pub fn min_write_buffer_len(&self) -> usize {
+        // Record and handshake headers, version, random, session id, cipher suites,
+        // compression methods and the extensions length
+        let mut len = 5 + 4 + 2 + 32 + 1 + 2 + 2 * self.cipher_suites.len() + 2 + 2;
+        // supported_versions, signature_algorithms, supported_groups and psk_key_exchange_modes
+        len += 4 + 1 + 2;
+        len += 4 + 2 + 2 * self.signature_schemes.len();
+        len += 4 + 2 + 2 * self.named_groups.len();
+        len += 4 + 1 + 1;
+        if self.max_fragment_length.is_some() {
+            len += 4 + 1;
+        }
+        if self.ocsp_stapling {
+            len += 4 + 1 + 2 + 2;
+        }
+        len += 4 + 2;
+        for group in self.named_groups.iter() {
+            len += 4 + group.public_key_len();
+        }
+        if let Some(server_name) = self.server_name {
+            len += 4 + 2 + 1 + 2 + server_name.len();
+        }
+        if !self.alpn_protocols.is_empty() {
+            len += 4 + 2;
+            for protocol in self.alpn_protocols.iter() {
+                len += 1 + protocol.len();
+            }
+        }
+        if let Some(cert) = &self.cert {
+            // post_handshake_auth, and client_certificate_type for a raw public key
+            len += 4;
+            if let Certificate::RawPublicKey(_) = cert {
+                len += 4 + 1 + 1;
+            }
+        }
+        let server_certificate_types = self.server_certificate_types();
+        if server_certificate_types.contains(&CertificateType::RawPublicKey) {
+            len += 4 + 1 + server_certificate_types.len();
+        }
+        if let Some((_, identities)) = &self.psk {
+            // Identities with their ticket age, and binders for the longest hash
+            len += 4 + 2 + 2;
+            for identity in identities.iter() {
+                len += 2 + identity.len() + 4 + 1 + 48;
+            }
+        }
+        len.max(TLS_RECORD_OVERHEAD + 1)
+    }
+
     pub fn with_server_name(mut self, server_name: &'a str) -> Self {
         self.server_name = Some(server_name);
         self
     }
 
+    /// Configures the application protocols offered to the server with ALPN (RFC 7301), in
+    /// order of preference, such as `b"h2"` and `b"http/1.1"`. Up to [`MAX_ALPN_PROTOCOLS`]
+    /// protocols can be offered, the least preferred ones beyond it are ignored with a warning.
+    /// Their names must be 1 to 255 bytes long.
+    ///
+    /// The protocol selected by the server is returned by `TlsConnection::alpn_protocol` once
+    /// the connection is opened. The handshake fails if the server selects a protocol that was
+    /// not offered, but the server may also select none.
+    ///
+    /// A `TlsAcceptor` selects the first of these protocols offered by the client, and aborts
+    /// the handshake if the client offers none of them.
+    pub fn with_alpn_protocols(mut self, protocols: &[&'a [u8]]) -> Self {
+        if protocols.len() > MAX_ALPN_PROTOCOLS {
+            warn!("Ignoring ALPN protocols beyond {}", MAX_ALPN_PROTOCOLS);
+        }
+        let protocols = &protocols[..protocols.len().min(MAX_ALPN_PROTOCOLS)];
+        self.alpn_protocols = unwrap!(Vec::from_slice(protocols).ok());
+        self
+    }
+
     /// Configures the maximum plaintext fragment size.
     ///
     /// This option may help reduce memory size, as smaller fragment lengths require smaller
-    /// read/write buffers. Note that embedded-tls does not currently use this option to fragment
-    /// writes. Note that the buffers need to include some overhead over the configured fragment
-    /// length.
+    /// read/write buffers. Once the server echoes the length, written records are fragmented to
+    /// it, and received records exceeding it are rejected with a `record_overflow` alert. The
+    /// handshake fails if the server echoes a different length, while a server that ignores the
+    /// extension leaves the default of 2^14 bytes.
+    ///
+    /// Note that the buffers need to include some overhead over the configured fragment length,
+    /// up to 256 bytes plus the record header for received records. Handshake messages of the
+    /// server split over several records are not supported, so a small length is mostly usable
+    /// with a pre-shared key, as a certificate chain rarely fits in a single record.
     ///
     /// From [RFC 6066, Section 4.  Maximum Fragment Length Negotiation](https://www.rfc-editor.org/rfc/rfc6066#page-8):
     ///
@@ -264,22 +566,100 @@ where
         self
     }
 
+    /// Asks the server to staple the OCSP response for its certificate (RFC 6066, Section 8).
+    ///
+    /// `webpki::CertVerifier` checks the stapled response, rejecting revoked certificates.
+    /// The response is sent with the server certificate, so the read buffer must be large
+    /// enough to hold both.
+    pub fn enable_ocsp_stapling(mut self) -> Self {
+        self.ocsp_stapling = true;
+        self
+    }
+
     /// Resets the max fragment length to 14 bits (16384).
     pub fn reset_max_fragment_length(mut self) -> Self {
         self.max_fragment_length = None;
         self
     }
 
+    /// Adds a trust anchor used by the verifier to authenticate the server.
+    ///
+    /// The server is trusted if it is authenticated by any of the trust anchors, which allows
+    /// trusting both a private and a public CA, or rolling over to a new CA. Up to
+    /// [`MAX_TRUST_ANCHORS`] trust anchors can be added, further ones are ignored with a
+    /// warning.
+    ///
+    /// A [`Certificate::TrustAnchor`] avoids parsing an X.509 CA certificate on every handshake.
+    /// A [`Certificate::RawPublicKey`] asks the server to authenticate with its raw public key
+    /// (RFC 7250) instead of an X.509 certificate, see [`crate::rpk::RpkVerifier`]. When both
+    /// kinds are configured, the server selects which one to authenticate with. A
+    /// [`Certificate::SpkiSha256`] pins the public key of the server, see
+    /// [`crate::pin::PinVerifier`].
     pub fn with_ca(mut self, ca: Certificate<'a>) -> Self {
-        self.ca = Some(ca);
+        if self.ca.push(ca).is_err() {
+            warn!("Ignoring trust anchor beyond {}", MAX_TRUST_ANCHORS);
+        }
+        self
+    }
+
+    /// Adds a DER encoded certificate revocation list (RFC 5280, Section 5).
+    ///
+    /// `webpki::CertVerifier` rejects certificate chains containing a certificate revoked by
+    /// a CRL of its issuer with [`TlsError::CertificateRevoked`]. The CRL is only trusted if it
+    /// is signed by the issuer, but it is used regardless of its next update time, as it is
+    /// expected to be distributed with firmware updates. Up to [`MAX_CRLS`] CRLs can be added,
+    /// further ones are ignored with a warning.
+    pub fn with_crl(mut self, crl: &'a [u8]) -> Self {
+        if self.crls.push(crl).is_err() {
+            warn!("Ignoring CRL beyond {}", MAX_CRLS);
+        }
         self
     }
 
+    /// The certificate types of the trust anchors, in the order they were configured.
+    pub(crate) fn server_certificate_types(&self) -> Vec<CertificateType, 2> {
+        let mut types = Vec::new();
+        for ca in self.ca.iter() {
+            let certificate_type = ca.certificate_type();
+            if !types.contains(&certificate_type) {
+                unwrap!(types.push(certificate_type).ok());
+            }
+        }
+        types
+    }
+
+    /// Configures the client certificate, sent when the server requests client authentication.
+    ///
+    /// A [`Certificate::RawPublicKey`] is only sent if the server accepts raw public keys
+    /// (RFC 7250).
+    ///
+    /// A [`TlsSigner`] for the private key of the certificate must be configured with
+    /// [`Self::with_signer`] as well, otherwise no certificate is sent.
+    ///
+    /// The client also offers to authenticate after the handshake, which requires passing the
+    /// certificate and signer to `TlsConnection::with_post_handshake_auth` as well. A split
+    /// connection cannot answer such a request, and reading fails with `TlsError::Unimplemented`
+    /// instead.
+    ///
+    /// A `TlsAcceptor` authenticates with this certificate and signer instead, unless the client
+    /// offers the pre-shared key of [`Self::with_psk`].
     pub fn with_cert(mut self, cert: Certificate<'a>) -> Self {
         self.cert = Some(cert);
         self
     }
 
+    /// Configures the signer for the private key of the client certificate, or of the server
+    /// certificate of a `TlsAcceptor`. The signer is `Sync`, so that the futures of the
+    /// connection remain `Send`.
+    pub fn with_signer(mut self, signer: &'a (dyn TlsSigner + Sync)) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Configures a pre-shared key and the identities it is offered with.
+    ///
+    /// A `TlsAcceptor` authenticates with the key instead of its certificate when the client
+    /// offers one of the identities with a key exchange.
     pub fn with_psk(mut self, psk: &'a [u8], identities: &[&'a [u8]]) -> Self {
         // TODO: Remove potential panic
         self.psk = Some((psk, unwrap!(Vec::from_slice(identities).ok())));
@@ -287,10 +667,7 @@ where
     }
 }
 
-impl<'a, CipherSuite> Default for TlsConfig<'a, CipherSuite>
-where
-    CipherSuite: TlsCipherSuite,
-{
+impl<'a> Default for TlsConfig<'a> {
     fn default() -> Self {
         TlsConfig::new()
     }
@@ -299,6 +676,78 @@ where
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Certificate<'a> {
+    /// A DER encoded X.509 certificate.
     X509(&'a [u8]),
+    /// A DER encoded SubjectPublicKeyInfo.
     RawPublicKey(&'a [u8]),
+    /// An X.509 CA certificate parsed in advance, which can only be used as a trust anchor.
+    TrustAnchor(TrustAnchor<'a>),
+    /// The SHA-256 hash of a DER encoded SubjectPublicKeyInfo, which can only be used as a trust
+    /// anchor to pin the key of the server, see [`crate::pin::PinVerifier`].
+    SpkiSha256([u8; 32]),
+}
+
+impl<'a> Certificate<'a> {
+    pub(crate) fn certificate_type(&self) -> CertificateType {
+        match self {
+            Certificate::X509(_) | Certificate::TrustAnchor(_) | Certificate::SpkiSha256(_) => {
+                CertificateType::X509
+            }
+            Certificate::RawPublicKey(_) => CertificateType::RawPublicKey,
+        }
+    }
+}
+
+/// The parts of an X.509 CA certificate needed to verify a certificate chain.
+///
+/// With the `webpki` feature, `webpki::TrustAnchor::from_cert_der` extracts them from a DER encoded
+/// certificate, so that it is parsed once when the configuration is created rather than on
+/// every handshake.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TrustAnchor<'a> {
+    /// The contents of the DER encoded subject of the certificate.
+    pub subject: &'a [u8],
+    /// The contents of the DER encoded SubjectPublicKeyInfo of the certificate.
+    pub spki: &'a [u8],
+    /// The DER encoded name constraints of the certificate, if any.
+    pub name_constraints: Option<&'a [u8]>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_ca_beyond_limit() {
+        let mut config = TlsConfig::new();
+        for _ in 0..MAX_TRUST_ANCHORS + 1 {
+            config = config.with_ca(Certificate::X509(&[]));
+        }
+        assert_eq!(MAX_TRUST_ANCHORS, config.ca.len());
+    }
+
+    #[test]
+    fn test_with_crl_beyond_limit() {
+        let mut config = TlsConfig::new();
+        for _ in 0..MAX_CRLS + 1 {
+            config = config.with_crl(&[]);
+        }
+        assert_eq!(MAX_CRLS, config.crls.len());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_system_time_clock() {
+        let before = SystemClock.now().unwrap();
+        let now = std::time::SystemTime::UNIX_EPOCH.now().unwrap();
+        assert!(now >= before && now <= SystemClock.now().unwrap());
+    }
+
+    #[test]
+    fn test_with_alpn_protocols_beyond_limit() {
+        let protocols: [&[u8]; MAX_ALPN_PROTOCOLS + 1] = [b"h2"; MAX_ALPN_PROTOCOLS + 1];
+        let config = TlsConfig::new().with_alpn_protocols(&protocols);
+        assert_eq!(MAX_ALPN_PROTOCOLS, config.alpn_protocols.len());
+    }
 }
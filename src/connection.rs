@@ -1,18 +1,38 @@
-use crate::config::{TlsCipherSuite, TlsConfig, TlsVerifier};
-use crate::handshake::{ClientHandshake, ServerHandshake};
-use crate::key_schedule::{KeySchedule, ReadKeySchedule, WriteKeySchedule};
-use crate::record::{ClientRecord, ServerRecord};
+use crate::config::{
+    Certificate, MaxFragmentLength, TlsCipherSuite, TlsConfig, TlsSigner, TlsVerifier,
+    MAX_SIGNATURE_LEN,
+};
+use crate::crypto_provider::{CryptoProvider, TlsKeyExchange};
+use crate::extensions::extension_data::certificate_type::CertificateType;
+use crate::extensions::extension_data::key_share::MAX_KEY_SHARES;
+use crate::extensions::extension_data::pre_shared_key::{PskIdentity, MAX_PSK_IDENTITIES};
+use crate::handshake::client_hello::{ClientHello, OfferedPsk};
+use crate::handshake::encrypted_extensions::EncryptedExtensions;
+use crate::handshake::hello_retry_request::HelloRetryRequest;
+use crate::handshake::key_update::KeyUpdate;
+use crate::handshake::server_hello::ServerHello;
+use crate::handshake::{ClientHandshake, HandshakeType, ServerHandshake};
+use crate::key_schedule::{
+    dispatch, AnyKeySchedule, KeySchedule, ReadKeySchedule, WriteKeySchedule,
+};
+use crate::peer_certificates::PeerCertificateBuffer;
+use crate::record::{ClientRecord, ClientRecordHeader, ServerRecord};
 use crate::record_reader::RecordReader;
+use crate::ticket::{EarlyDataStatus, SessionTicket};
 use crate::write_buffer::WriteBuffer;
 use crate::TlsError;
 use crate::{
     alert::*,
-    handshake::{certificate::CertificateRef, certificate_request::CertificateRequest},
+    handshake::{
+        certificate::CertificateRef, certificate_request::CertificateRequest,
+        certificate_verify::CertificateVerify,
+    },
 };
 use core::fmt::Debug;
 use embedded_io::Error as _;
 use embedded_io::{Read as BlockingRead, Write as BlockingWrite};
 use embedded_io_async::{Read as AsyncRead, Write as AsyncWrite};
+use heapless::Vec;
 use rand_core::{CryptoRng, RngCore};
 
 use crate::application_data::ApplicationData;
@@ -24,7 +44,7 @@ use crate::application_data::ApplicationData;
 // use crate::handshake::server_hello::ServerHello;
 use crate::buffer::CryptoBuffer;
 use digest::generic_array::typenum::Unsigned;
-use p256::ecdh::EphemeralSecret;
+use digest::Digest;
 
 use crate::content_types::ContentType;
 // use crate::handshake::certificate_request::CertificateRequest;
@@ -77,12 +97,20 @@ where
         // Remove the content type
         app_data.truncate(app_data.len() - 1);
 
+        // The record is counted before it is handled, which may update the traffic secret
+        key_schedule.increment_counter()?;
+
         let mut buf = ParseBuffer::new(app_data.as_slice());
         match content_type {
             ContentType::Handshake => {
                 // Decode potentially coalesced handshake messages
                 while buf.remaining() > 0 {
-                    let inner = ServerHandshake::read(&mut buf, key_schedule.transcript_hash())?;
+                    let inner =
+                        ServerHandshake::read(&mut buf, key_schedule.message_transcript_hash())?;
+                    // Section 5.1: a KeyUpdate must be the last message of its record
+                    if matches!(inner, ServerHandshake::KeyUpdate(_)) && buf.remaining() > 0 {
+                        return Err(TlsError::InvalidHandshake);
+                    }
                     cb(key_schedule, ServerRecord::Handshake(inner))?;
                 }
             }
@@ -96,7 +124,6 @@ where
             }
             _ => return Err(TlsError::Unimplemented),
         }
-        key_schedule.increment_counter();
     } else {
         trace!("Not decrypting: content_type = {:?}", record.content_type());
         cb(key_schedule, record)?;
@@ -139,175 +166,525 @@ where
         .map_err(|_| TlsError::InvalidApplicationData)
 }
 
-pub struct Handshake<CipherSuite, Verifier>
+/// The transcript of the last ClientHello with the hash function of each cipher suite, to
+/// start the transcript over once the server selects a cipher suite. The ClientHello is not
+/// kept in the write buffer, as early data is written to it afterwards.
+struct ClientHelloTranscript<Provider>
 where
-    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    aes128_gcm_sha256: <Provider::Aes128GcmSha256 as TlsCipherSuite>::Hash,
+    aes256_gcm_sha384: <Provider::Aes256GcmSha384 as TlsCipherSuite>::Hash,
+    chacha20_poly1305_sha256: <Provider::Chacha20Poly1305Sha256 as TlsCipherSuite>::Hash,
+    aes128_ccm_sha256: <Provider::Aes128CcmSha256 as TlsCipherSuite>::Hash,
+    aes128_ccm8_sha256: <Provider::Aes128Ccm8Sha256 as TlsCipherSuite>::Hash,
+}
+
+impl<Provider> ClientHelloTranscript<Provider>
+where
+    Provider: CryptoProvider,
+{
+    fn new(client_hello: &[u8]) -> Self {
+        Self {
+            aes128_gcm_sha256: Digest::new_with_prefix(client_hello),
+            aes256_gcm_sha384: Digest::new_with_prefix(client_hello),
+            chacha20_poly1305_sha256: Digest::new_with_prefix(client_hello),
+            aes128_ccm_sha256: Digest::new_with_prefix(client_hello),
+            aes128_ccm8_sha256: Digest::new_with_prefix(client_hello),
+        }
+    }
+
+    /// Replaces the transcript of `key_schedule` with the ClientHello.
+    fn restart(&self, key_schedule: &mut AnyKeySchedule<Provider>) {
+        match key_schedule {
+            AnyKeySchedule::Aes128GcmSha256(key_schedule) => {
+                *key_schedule.transcript_hash() = self.aes128_gcm_sha256.clone();
+            }
+            AnyKeySchedule::Aes256GcmSha384(key_schedule) => {
+                *key_schedule.transcript_hash() = self.aes256_gcm_sha384.clone();
+            }
+            AnyKeySchedule::Chacha20Poly1305Sha256(key_schedule) => {
+                *key_schedule.transcript_hash() = self.chacha20_poly1305_sha256.clone();
+            }
+            AnyKeySchedule::Aes128CcmSha256(key_schedule) => {
+                *key_schedule.transcript_hash() = self.aes128_ccm_sha256.clone();
+            }
+            AnyKeySchedule::Aes128Ccm8Sha256(key_schedule) => {
+                *key_schedule.transcript_hash() = self.aes128_ccm8_sha256.clone();
+            }
+        }
+    }
+}
+
+pub struct Handshake<'h, Provider, Verifier>
+where
+    Provider: CryptoProvider,
 {
-    traffic_hash: Option<CipherSuite::Hash>,
-    secret: Option<EphemeralSecret>,
+    random: [u8; 32],
+    secrets: Vec<Provider::KeyExchange, MAX_KEY_SHARES>,
+    psks: Vec<OfferedPsk<'h>, MAX_PSK_IDENTITIES>,
+    selected_psk: Option<u16>,
+    early_data: Option<&'h [u8]>,
+    early_data_status: EarlyDataStatus,
+    certificate_verified: bool,
+    hello_retry_request: bool,
+    client_hello: ClientHelloTranscript<Provider>,
     certificate_request: Option<CertificateRequest>,
+    client_certificate_type: CertificateType,
+    server_certificate_type: CertificateType,
+    alpn_protocol: Option<usize>,
+    max_fragment_length: Option<MaxFragmentLength>,
     verifier: Verifier,
 }
 
-impl<'v, CipherSuite, Verifier> Handshake<CipherSuite, Verifier>
+impl<'h, 'v, Provider, Verifier> Handshake<'h, Provider, Verifier>
 where
-    CipherSuite: TlsCipherSuite,
-    Verifier: TlsVerifier<'v, CipherSuite>,
+    Provider: CryptoProvider,
+    Verifier: TlsVerifier<'v>,
 {
-    pub fn new(verifier: Verifier) -> Handshake<CipherSuite, Verifier> {
+    pub fn new(verifier: Verifier) -> Handshake<'h, Provider, Verifier> {
         Handshake {
-            traffic_hash: None,
-            secret: None,
+            random: [0; 32],
+            secrets: Vec::new(),
+            psks: Vec::new(),
+            selected_psk: None,
+            early_data: None,
+            early_data_status: EarlyDataStatus::NotSent,
+            certificate_verified: false,
+            hello_retry_request: false,
+            client_hello: ClientHelloTranscript::new(&[]),
             certificate_request: None,
+            client_certificate_type: CertificateType::X509,
+            server_certificate_type: CertificateType::X509,
+            alpn_protocol: None,
+            max_fragment_length: None,
             verifier,
         }
     }
+
+    /// Offers the session ticket with its obfuscated age, if any, followed by the identities of
+    /// the configured PSK.
+    pub fn offer_psks(&mut self, config: &TlsConfig<'h>, ticket: Option<(SessionTicket<'h>, u32)>) {
+        self.psks.clear();
+        if let Some((ticket, obfuscated_ticket_age)) = ticket {
+            unwrap!(self
+                .psks
+                .push(OfferedPsk {
+                    identity: PskIdentity {
+                        identity: ticket.ticket,
+                        obfuscated_ticket_age,
+                    },
+                    secret: ticket.psk,
+                    cipher_suite: Some(ticket.cipher_suite),
+                })
+                .ok());
+        }
+        if let Some((psk, identities)) = &config.psk {
+            for identity in identities.iter() {
+                unwrap!(self
+                    .psks
+                    .push(OfferedPsk {
+                        identity: PskIdentity {
+                            identity,
+                            obfuscated_ticket_age: 0,
+                        },
+                        secret: psk,
+                        cipher_suite: None,
+                    })
+                    .ok());
+            }
+        }
+    }
+
+    /// Sends `data` as early data if the offered session ticket allows that much early data.
+    pub fn offer_early_data(&mut self, data: &'h [u8], ticket: Option<&SessionTicket>) {
+        match ticket {
+            Some(ticket)
+                if !data.is_empty() && data.len() <= ticket.max_early_data_size as usize =>
+            {
+                self.early_data = Some(data);
+            }
+            Some(_) => warn!("The session ticket does not allow the early data"),
+            None => {}
+        }
+    }
+
+    pub fn early_data_status(&self) -> EarlyDataStatus {
+        self.early_data_status
+    }
+
+    /// The application protocol selected by the server among those of `config`.
+    pub fn alpn_protocol<'c>(&self, config: &TlsConfig<'c>) -> Option<&'c [u8]> {
+        self.alpn_protocol
+            .and_then(|index| config.alpn_protocols.get(index).copied())
+    }
+
+    /// The client certificate type negotiated during the handshake, if the client offered to
+    /// authenticate after the handshake.
+    pub fn post_handshake_auth(&self, config: &TlsConfig) -> Option<CertificateType> {
+        config.cert.as_ref().map(|_| self.client_certificate_type)
+    }
+
+    /// The state after the server Finished message, or after the EndOfEarlyData message if the
+    /// server accepted early data.
+    fn client_flight(&self) -> State {
+        if self.certificate_request.is_some() {
+            State::ClientCert
+        } else {
+            State::ClientFinished
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum State {
     ClientHello,
+    EarlyData,
     ServerHello,
     ServerVerify,
+    EndOfEarlyData,
     ClientCert,
+    ClientCertVerify,
     ClientFinished,
     ApplicationData,
 }
 
 impl<'a> State {
     #[allow(clippy::too_many_arguments)]
-    pub async fn process<'v, Transport, CipherSuite, RNG, Verifier>(
+    pub async fn process<'v, Transport, RNG, Provider, Verifier>(
         self,
         transport: &mut Transport,
-        handshake: &mut Handshake<CipherSuite, Verifier>,
-        record_reader: &mut RecordReader<'_, CipherSuite>,
+        handshake: &mut Handshake<'_, Provider, Verifier>,
+        peer_certificates: &mut PeerCertificateBuffer<'_>,
+        record_reader: &mut RecordReader<'_>,
         tx_buf: &mut WriteBuffer<'_>,
-        key_schedule: &mut KeySchedule<CipherSuite>,
-        config: &TlsConfig<'a, CipherSuite>,
+        key_schedule: &mut AnyKeySchedule<Provider>,
+        config: &TlsConfig<'a>,
         rng: &mut RNG,
     ) -> Result<State, TlsError>
     where
         Transport: AsyncRead + AsyncWrite + 'a,
         RNG: CryptoRng + RngCore + 'a,
-        CipherSuite: TlsCipherSuite,
-        Verifier: TlsVerifier<'v, CipherSuite>,
+        Provider: CryptoProvider,
+        Verifier: TlsVerifier<'v>,
     {
         match self {
             State::ClientHello => {
-                let (state, tx) = client_hello(key_schedule, config, rng, tx_buf, handshake)?;
+                *key_schedule = preferred_key_schedule(config, handshake)?;
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let (state, tx) = client_hello(key_schedule, config, rng, tx_buf, handshake)?;
 
-                respond(tx, transport, key_schedule).await?;
+                    respond(tx, transport, key_schedule).await?;
 
-                Ok(state)
+                    Ok(state)
+                })
             }
-            State::ServerHello => {
-                let record = record_reader
-                    .read(transport, key_schedule.read_state())
-                    .await?;
+            State::EarlyData => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    key_schedule.initialize_early_traffic_secret()?;
+                    key_schedule.swap_early_state();
+                    let mut data = handshake.early_data.unwrap_or_default();
+                    while !data.is_empty() {
+                        let (tx, len) = early_data(key_schedule, tx_buf, data)?;
+                        data = &data[len..];
+
+                        respond(tx, transport, key_schedule).await?;
+                    }
+                    key_schedule.swap_early_state();
 
-                let result = process_server_hello(handshake, key_schedule, record);
+                    Ok(State::ServerHello)
+                })
+            }
+            State::ServerHello => {
+                let result = dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    record_reader
+                        .read(transport, key_schedule.read_state())
+                        .await
+                        .and_then(server_hello)
+                });
+
+                let result = match result {
+                    Ok(ServerHelloRecord::ServerHello(server_hello)) => {
+                        process_server_hello(handshake, key_schedule, config, server_hello)
+                    }
+                    Ok(ServerHelloRecord::HelloRetryRequest(hello_retry_request)) => {
+                        match process_hello_retry_request(
+                            handshake,
+                            key_schedule,
+                            config,
+                            rng,
+                            tx_buf,
+                            hello_retry_request,
+                        ) {
+                            Ok(tx) => dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                                respond(tx, transport, key_schedule).await?;
+                                Ok(State::ServerHello)
+                            }),
+                            Err(e) => Err(e),
+                        }
+                    }
+                    Ok(ServerHelloRecord::ChangeCipherSpec) => Ok(State::ServerHello),
+                    Err(e) => Err(e),
+                };
 
-                handle_processing_error(result, transport, key_schedule, tx_buf).await
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    handle_processing_error(result, transport, key_schedule, tx_buf).await
+                })
             }
             State::ServerVerify => {
-                let record = record_reader
-                    .read(transport, key_schedule.read_state())
-                    .await?;
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let result = match record_reader
+                        .read(transport, key_schedule.read_state())
+                        .await
+                    {
+                        Ok(record) => process_server_verify(
+                            handshake,
+                            peer_certificates,
+                            key_schedule,
+                            config,
+                            record,
+                        ),
+                        Err(e) => Err(e),
+                    };
+                    record_reader.set_max_fragment_length(handshake.max_fragment_length);
+                    tx_buf.set_max_fragment_length(handshake.max_fragment_length);
+
+                    handle_processing_error(result, transport, key_schedule, tx_buf).await
+                })
+            }
+            State::EndOfEarlyData => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    key_schedule.swap_early_state();
+                    let tx = end_of_early_data(key_schedule, tx_buf)?;
 
-                let result = process_server_verify(handshake, key_schedule, config, record);
+                    respond(tx, transport, key_schedule).await?;
+                    key_schedule.swap_early_state();
 
-                handle_processing_error(result, transport, key_schedule, tx_buf).await
+                    Ok(handshake.client_flight())
+                })
             }
             State::ClientCert => {
-                let (state, tx) = client_cert(handshake, key_schedule, config, tx_buf)?;
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let request = handshake
+                        .certificate_request
+                        .as_ref()
+                        .ok_or(TlsError::InvalidHandshake)?;
+                    let (state, tx) = client_cert(
+                        request,
+                        handshake.client_certificate_type,
+                        config.cert.as_ref(),
+                        config.signer,
+                        key_schedule,
+                        tx_buf,
+                    )?;
+
+                    respond(tx, transport, key_schedule).await?;
+
+                    Ok(state)
+                })
+            }
+            State::ClientCertVerify => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let tx = client_cert_verify(key_schedule, config.signer, tx_buf)?;
 
-                respond(tx, transport, key_schedule).await?;
+                    respond(tx, transport, key_schedule).await?;
 
-                Ok(state)
+                    Ok(State::ClientFinished)
+                })
             }
             State::ClientFinished => {
-                let tx = client_finished(key_schedule, tx_buf)?;
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let tx = finished(key_schedule, tx_buf)?;
 
-                respond(tx, transport, key_schedule).await?;
+                    respond(tx, transport, key_schedule).await?;
 
-                client_finished_finalize(key_schedule, handshake)
+                    client_finished_finalize(key_schedule)
+                })
             }
             State::ApplicationData => Ok(State::ApplicationData),
         }
     }
 
     #[allow(clippy::too_many_arguments)]
-    pub fn process_blocking<'v, Transport, CipherSuite, RNG, Verifier>(
+    pub fn process_blocking<'v, Transport, RNG, Provider, Verifier>(
         self,
         transport: &mut Transport,
-        handshake: &mut Handshake<CipherSuite, Verifier>,
-        record_reader: &mut RecordReader<'_, CipherSuite>,
+        handshake: &mut Handshake<'_, Provider, Verifier>,
+        peer_certificates: &mut PeerCertificateBuffer<'_>,
+        record_reader: &mut RecordReader<'_>,
         tx_buf: &mut WriteBuffer,
-        key_schedule: &mut KeySchedule<CipherSuite>,
-        config: &TlsConfig<'a, CipherSuite>,
+        key_schedule: &mut AnyKeySchedule<Provider>,
+        config: &TlsConfig<'a>,
         rng: &mut RNG,
     ) -> Result<State, TlsError>
     where
         Transport: BlockingRead + BlockingWrite + 'a,
         RNG: CryptoRng + RngCore,
-        CipherSuite: TlsCipherSuite + 'static,
-        Verifier: TlsVerifier<'v, CipherSuite>,
+        Provider: CryptoProvider,
+        Verifier: TlsVerifier<'v>,
     {
         match self {
             State::ClientHello => {
-                let (state, tx) = client_hello(key_schedule, config, rng, tx_buf, handshake)?;
+                *key_schedule = preferred_key_schedule(config, handshake)?;
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let (state, tx) = client_hello(key_schedule, config, rng, tx_buf, handshake)?;
 
-                respond_blocking(tx, transport, key_schedule)?;
+                    respond_blocking(tx, transport, key_schedule)?;
 
-                Ok(state)
+                    Ok(state)
+                })
             }
-            State::ServerHello => {
-                let record = record_reader.read_blocking(transport, key_schedule.read_state())?;
+            State::EarlyData => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    key_schedule.initialize_early_traffic_secret()?;
+                    key_schedule.swap_early_state();
+                    let mut data = handshake.early_data.unwrap_or_default();
+                    while !data.is_empty() {
+                        let (tx, len) = early_data(key_schedule, tx_buf, data)?;
+                        data = &data[len..];
+
+                        respond_blocking(tx, transport, key_schedule)?;
+                    }
+                    key_schedule.swap_early_state();
 
-                let result = process_server_hello(handshake, key_schedule, record);
+                    Ok(State::ServerHello)
+                })
+            }
+            State::ServerHello => {
+                let result = dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    record_reader
+                        .read_blocking(transport, key_schedule.read_state())
+                        .and_then(server_hello)
+                });
+
+                let result = match result {
+                    Ok(ServerHelloRecord::ServerHello(server_hello)) => {
+                        process_server_hello(handshake, key_schedule, config, server_hello)
+                    }
+                    Ok(ServerHelloRecord::HelloRetryRequest(hello_retry_request)) => {
+                        match process_hello_retry_request(
+                            handshake,
+                            key_schedule,
+                            config,
+                            rng,
+                            tx_buf,
+                            hello_retry_request,
+                        ) {
+                            Ok(tx) => dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                                respond_blocking(tx, transport, key_schedule)?;
+                                Ok(State::ServerHello)
+                            }),
+                            Err(e) => Err(e),
+                        }
+                    }
+                    Ok(ServerHelloRecord::ChangeCipherSpec) => Ok(State::ServerHello),
+                    Err(e) => Err(e),
+                };
 
-                handle_processing_error_blocking(result, transport, key_schedule, tx_buf)
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    handle_processing_error_blocking(result, transport, key_schedule, tx_buf)
+                })
             }
             State::ServerVerify => {
-                let record = record_reader.read_blocking(transport, key_schedule.read_state())?;
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let result = match record_reader
+                        .read_blocking(transport, key_schedule.read_state())
+                    {
+                        Ok(record) => process_server_verify(
+                            handshake,
+                            peer_certificates,
+                            key_schedule,
+                            config,
+                            record,
+                        ),
+                        Err(e) => Err(e),
+                    };
+                    record_reader.set_max_fragment_length(handshake.max_fragment_length);
+                    tx_buf.set_max_fragment_length(handshake.max_fragment_length);
+
+                    handle_processing_error_blocking(result, transport, key_schedule, tx_buf)
+                })
+            }
+            State::EndOfEarlyData => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    key_schedule.swap_early_state();
+                    let tx = end_of_early_data(key_schedule, tx_buf)?;
 
-                let result = process_server_verify(handshake, key_schedule, config, record);
+                    respond_blocking(tx, transport, key_schedule)?;
+                    key_schedule.swap_early_state();
 
-                handle_processing_error_blocking(result, transport, key_schedule, tx_buf)
+                    Ok(handshake.client_flight())
+                })
             }
             State::ClientCert => {
-                let (state, tx) = client_cert(handshake, key_schedule, config, tx_buf)?;
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let request = handshake
+                        .certificate_request
+                        .as_ref()
+                        .ok_or(TlsError::InvalidHandshake)?;
+                    let (state, tx) = client_cert(
+                        request,
+                        handshake.client_certificate_type,
+                        config.cert.as_ref(),
+                        config.signer,
+                        key_schedule,
+                        tx_buf,
+                    )?;
+
+                    respond_blocking(tx, transport, key_schedule)?;
+
+                    Ok(state)
+                })
+            }
+            State::ClientCertVerify => {
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let tx = client_cert_verify(key_schedule, config.signer, tx_buf)?;
 
-                respond_blocking(tx, transport, key_schedule)?;
+                    respond_blocking(tx, transport, key_schedule)?;
 
-                Ok(state)
+                    Ok(State::ClientFinished)
+                })
             }
             State::ClientFinished => {
-                let tx = client_finished(key_schedule, tx_buf)?;
+                dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+                    let tx = finished(key_schedule, tx_buf)?;
 
-                respond_blocking(tx, transport, key_schedule)?;
+                    respond_blocking(tx, transport, key_schedule)?;
 
-                client_finished_finalize(key_schedule, handshake)
+                    client_finished_finalize(key_schedule)
+                })
             }
             State::ApplicationData => Ok(State::ApplicationData),
         }
     }
 }
 
-fn handle_processing_error_blocking<CipherSuite>(
-    result: Result<State, TlsError>,
+/// The alert to send to the peer when processing a record fails.
+fn alert_for<S>(result: &Result<S, TlsError>) -> Option<(AlertLevel, AlertDescription)> {
+    match result {
+        Err(TlsError::AbortHandshake(level, description)) => Some((*level, *description)),
+        Err(TlsError::CertificateRevoked) => {
+            Some((AlertLevel::Fatal, AlertDescription::CertificateRevoked))
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn handle_processing_error_blocking<S, CipherSuite>(
+    result: Result<S, TlsError>,
     transport: &mut impl BlockingWrite,
     key_schedule: &mut KeySchedule<CipherSuite>,
     tx_buf: &mut WriteBuffer,
-) -> Result<State, TlsError>
+) -> Result<S, TlsError>
 where
     CipherSuite: TlsCipherSuite,
 {
-    if let Err(TlsError::AbortHandshake(level, description)) = result {
+    if let Some((level, description)) = alert_for(&result) {
         let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+        let encrypted = write_key_schedule.is_encrypting();
         let tx = tx_buf.write_record(
-            &ClientRecord::Alert(Alert { level, description }, false),
+            &ClientRecord::Alert(Alert { level, description }, encrypted),
             write_key_schedule,
             Some(read_key_schedule),
         )?;
@@ -318,7 +695,7 @@ where
     result
 }
 
-fn respond_blocking<CipherSuite>(
+pub(crate) fn respond_blocking<CipherSuite>(
     tx: &[u8],
     transport: &mut impl BlockingWrite,
     key_schedule: &mut KeySchedule<CipherSuite>,
@@ -330,26 +707,27 @@ where
         .write_all(tx)
         .map_err(|e| TlsError::Io(e.kind()))?;
 
-    key_schedule.write_state().increment_counter();
+    key_schedule.write_state().increment_counter()?;
 
     transport.flush().map_err(|e| TlsError::Io(e.kind()))?;
 
     Ok(())
 }
 
-async fn handle_processing_error<'a, CipherSuite>(
-    result: Result<State, TlsError>,
+pub(crate) async fn handle_processing_error<'a, S, CipherSuite>(
+    result: Result<S, TlsError>,
     transport: &mut impl AsyncWrite,
     key_schedule: &mut KeySchedule<CipherSuite>,
     tx_buf: &mut WriteBuffer<'a>,
-) -> Result<State, TlsError>
+) -> Result<S, TlsError>
 where
     CipherSuite: TlsCipherSuite,
 {
-    if let Err(TlsError::AbortHandshake(level, description)) = result {
+    if let Some((level, description)) = alert_for(&result) {
         let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+        let encrypted = write_key_schedule.is_encrypting();
         let tx = tx_buf.write_record(
-            &ClientRecord::Alert(Alert { level, description }, false),
+            &ClientRecord::Alert(Alert { level, description }, encrypted),
             write_key_schedule,
             Some(read_key_schedule),
         )?;
@@ -360,7 +738,7 @@ where
     result
 }
 
-async fn respond<CipherSuite>(
+pub(crate) async fn respond<CipherSuite>(
     tx: &[u8],
     transport: &mut impl AsyncWrite,
     key_schedule: &mut KeySchedule<CipherSuite>,
@@ -373,7 +751,7 @@ where
         .await
         .map_err(|e| TlsError::Io(e.kind()))?;
 
-    key_schedule.write_state().increment_counter();
+    key_schedule.write_state().increment_counter()?;
 
     transport
         .flush()
@@ -383,51 +761,138 @@ where
     Ok(())
 }
 
-fn client_hello<'r, CipherSuite, RNG, Verifier>(
+/// The key schedule of the cipher suite of the resumed session, or else of the first configured
+/// cipher suite.
+fn preferred_key_schedule<Provider, Verifier>(
+    config: &TlsConfig,
+    handshake: &Handshake<'_, Provider, Verifier>,
+) -> Result<AnyKeySchedule<Provider>, TlsError>
+where
+    Provider: CryptoProvider,
+{
+    handshake
+        .psks
+        .first()
+        .and_then(|psk| psk.cipher_suite)
+        .or(config.cipher_suites.first().copied())
+        .and_then(AnyKeySchedule::new)
+        .ok_or(TlsError::InvalidCipherSuite)
+}
+
+fn client_hello<'r, CipherSuite, RNG, Provider, Verifier>(
     key_schedule: &mut KeySchedule<CipherSuite>,
-    config: &TlsConfig<CipherSuite>,
+    config: &TlsConfig,
     rng: &mut RNG,
     tx_buf: &'r mut WriteBuffer,
-    handshake: &mut Handshake<CipherSuite, Verifier>,
+    handshake: &mut Handshake<'_, Provider, Verifier>,
 ) -> Result<(State, &'r [u8]), TlsError>
 where
     RNG: CryptoRng + RngCore,
     CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
 {
-    key_schedule.initialize_early_secret(config.psk.as_ref().map(|p| p.0))?;
-    let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
-    let client_hello = ClientRecord::client_hello(config, rng);
-    let slice = tx_buf.write_record(&client_hello, write_key_schedule, Some(read_key_schedule))?;
+    key_schedule.initialize_early_secret(handshake.psks.first().map(|psk| psk.secret))?;
+
+    rng.fill_bytes(&mut handshake.random);
+
+    // Send a key share for every configured group, so that the server does not
+    // need to request another one.
+    handshake.secrets.clear();
+    for group in config
+        .named_groups
+        .iter()
+        .filter(|group| Provider::KeyExchange::is_supported(**group))
+        .take(MAX_KEY_SHARES)
+    {
+        let secret = Provider::KeyExchange::generate(*group, rng)?;
+        unwrap!(handshake.secrets.push(secret).ok());
+    }
 
-    if let ClientRecord::Handshake(ClientHandshake::ClientHello(client_hello), _) = client_hello {
-        handshake.secret.replace(client_hello.secret);
-        Ok((State::ServerHello, slice))
+    let slice = write_client_hello(key_schedule, config, tx_buf, handshake, None)?;
+    let state = if handshake.early_data.is_some() {
+        State::EarlyData
     } else {
-        Err(TlsError::EncodeError)
-    }
+        State::ServerHello
+    };
+    Ok((state, slice))
+}
+
+/// Encrypts as much of `data` as fits in a record with the client early traffic secret,
+/// returning the record and the length of the data it contains.
+fn early_data<'r, CipherSuite>(
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    tx_buf: &'r mut WriteBuffer,
+    data: &[u8],
+) -> Result<(&'r [u8], usize), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    tx_buf.start_record(ClientRecordHeader::ApplicationData)?;
+    let len = tx_buf.append(data);
+    let slice = tx_buf.close_record(key_schedule.write_state())?;
+    Ok((slice, len))
 }
 
-fn process_server_hello<CipherSuite, Verifier>(
-    handshake: &mut Handshake<CipherSuite, Verifier>,
+fn write_client_hello<'r, CipherSuite, Provider, Verifier>(
     key_schedule: &mut KeySchedule<CipherSuite>,
+    config: &TlsConfig,
+    tx_buf: &'r mut WriteBuffer,
+    handshake: &mut Handshake<'_, Provider, Verifier>,
+    cookie: Option<&[u8]>,
+) -> Result<&'r [u8], TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    // Section 4.1.4: PSKs that are not compatible with the cipher suite selected by a
+    // HelloRetryRequest are dropped from the second ClientHello
+    handshake
+        .psks
+        .retain(|psk| psk.is_compatible::<CipherSuite>());
+
+    let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+    let client_hello = ClientHello::new(
+        config,
+        handshake.random,
+        &handshake.secrets,
+        cookie,
+        &handshake.psks,
+        handshake.early_data.is_some(),
+    );
+    tx_buf.write_record(
+        &ClientRecord::Handshake(ClientHandshake::ClientHello(client_hello), false),
+        write_key_schedule,
+        Some(read_key_schedule),
+    )?;
+    handshake.client_hello = ClientHelloTranscript::new(tx_buf.last_record_payload());
+    Ok(tx_buf.last_record())
+}
+
+/// The records accepted while waiting for the ServerHello, independent of the cipher suite.
+enum ServerHelloRecord<'a> {
+    ServerHello(ServerHello<'a>),
+    HelloRetryRequest(HelloRetryRequest<'a>),
+    ChangeCipherSpec,
+}
+
+fn server_hello<CipherSuite>(
     record: ServerRecord<'_, CipherSuite>,
-) -> Result<State, TlsError>
+) -> Result<ServerHelloRecord<'_>, TlsError>
 where
     CipherSuite: TlsCipherSuite,
 {
     match record {
         ServerRecord::Handshake(server_handshake) => match server_handshake {
             ServerHandshake::ServerHello(server_hello) => {
-                trace!("********* ServerHello");
-                let secret = handshake.secret.take().ok_or(TlsError::InvalidHandshake)?;
-                let shared = server_hello
-                    .calculate_shared_secret(&secret)
-                    .ok_or(TlsError::InvalidKeyShare)?;
-                key_schedule.initialize_handshake_secret(shared.raw_secret_bytes())?;
-                Ok(State::ServerVerify)
+                Ok(ServerHelloRecord::ServerHello(server_hello))
+            }
+            ServerHandshake::HelloRetryRequest(hello_retry_request) => {
+                Ok(ServerHelloRecord::HelloRetryRequest(hello_retry_request))
             }
             _ => Err(TlsError::InvalidHandshake),
         },
+        // Sent by servers in middlebox compatibility mode
+        ServerRecord::ChangeCipherSpec(_) => Ok(ServerHelloRecord::ChangeCipherSpec),
         ServerRecord::Alert(alert) => {
             Err(TlsError::HandshakeAborted(alert.level, alert.description))
         }
@@ -435,52 +900,252 @@ where
     }
 }
 
-fn process_server_verify<'a, 'v, CipherSuite, Verifier>(
-    handshake: &mut Handshake<CipherSuite, Verifier>,
+fn process_hello_retry_request<'r, RNG, Provider, Verifier>(
+    handshake: &mut Handshake<'_, Provider, Verifier>,
+    key_schedule: &mut AnyKeySchedule<Provider>,
+    config: &TlsConfig,
+    rng: &mut RNG,
+    tx_buf: &'r mut WriteBuffer,
+    hello_retry_request: HelloRetryRequest<'_>,
+) -> Result<&'r [u8], TlsError>
+where
+    RNG: CryptoRng + RngCore,
+    Provider: CryptoProvider,
+{
+    trace!("********* HelloRetryRequest");
+    let illegal_parameter =
+        TlsError::AbortHandshake(AlertLevel::Fatal, AlertDescription::IllegalParameter);
+
+    // Section 4.1.4
+    // If a client receives a second HelloRetryRequest in the same connection, it MUST abort
+    // the handshake with an "unexpected_message" alert.
+    if handshake.hello_retry_request {
+        return Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::UnexpectedMessage,
+        ));
+    }
+    handshake.hello_retry_request = true;
+
+    // Section 4.2.10: early data is not sent again after a HelloRetryRequest
+    if handshake.early_data.take().is_some() {
+        handshake.early_data_status = EarlyDataStatus::Rejected;
+    }
+
+    let cipher_suite = hello_retry_request.cipher_suite();
+    if !config.cipher_suites.contains(&cipher_suite) {
+        return Err(illegal_parameter);
+    }
+
+    let secrets = match hello_retry_request.selected_group() {
+        Some(group) => {
+            // Section 4.2.8
+            // Clients MUST verify that the selected_group field corresponds to a group which
+            // was provided in the "supported_groups" extension in the original ClientHello and
+            // that the selected_group field does not correspond to a group which was provided
+            // in the "key_share" extension in the original ClientHello.
+            if !config.named_groups.contains(&group)
+                || !Provider::KeyExchange::is_supported(group)
+                || handshake.secrets.iter().any(|s| s.group() == group)
+            {
+                return Err(illegal_parameter);
+            }
+            let mut secrets = Vec::new();
+            let secret = Provider::KeyExchange::generate(group, rng)?;
+            unwrap!(secrets.push(secret).ok());
+            secrets
+        }
+        // The server only asks for the cookie to be echoed, keep the key shares.
+        None if hello_retry_request.cookie().is_some() => core::mem::take(&mut handshake.secrets),
+        // Section 4.1.4
+        // Clients MUST abort the handshake with an "illegal_parameter" alert if the
+        // HelloRetryRequest would not result in any change in the ClientHello.
+        None => return Err(illegal_parameter),
+    };
+
+    // Section 4.4.1
+    // When the server responds to a ClientHello with a HelloRetryRequest, the value of
+    // ClientHello1 is replaced with a special synthetic handshake message of handshake type
+    // "message_hash" containing Hash(ClientHello1).
+    let mut selected =
+        AnyKeySchedule::<Provider>::new(cipher_suite).ok_or(TlsError::InvalidCipherSuite)?;
+    handshake.client_hello.restart(&mut selected);
+    dispatch!(AnyKeySchedule, &mut selected, key_schedule => {
+        let transcript = key_schedule.transcript_hash();
+        let client_hello_hash = transcript.clone().finalize();
+        Digest::reset(transcript);
+        transcript.update([
+            HandshakeType::MessageHash as u8,
+            0,
+            0,
+            client_hello_hash.len() as u8,
+        ]);
+        transcript.update(client_hello_hash);
+        transcript.update(hello_retry_request.raw);
+    });
+    *key_schedule = selected;
+
+    // The second client hello must be identical to the first one except for the key shares
+    // and the cookie.
+    handshake.secrets = secrets;
+    dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+        write_client_hello(key_schedule, config, tx_buf, handshake, hello_retry_request.cookie())
+    })
+}
+
+fn process_server_hello<Provider, Verifier>(
+    handshake: &mut Handshake<'_, Provider, Verifier>,
+    key_schedule: &mut AnyKeySchedule<Provider>,
+    config: &TlsConfig,
+    server_hello: ServerHello<'_>,
+) -> Result<State, TlsError>
+where
+    Provider: CryptoProvider,
+{
+    trace!("********* ServerHello");
+    // Section 4.2.11
+    // Clients MUST verify that the server's selected_identity is within the range supplied by
+    // the client.
+    handshake.selected_psk = server_hello.pre_shared_key();
+    let psk = match handshake.selected_psk {
+        Some(selected) => Some(*handshake.psks.get(selected as usize).ok_or(
+            TlsError::AbortHandshake(AlertLevel::Fatal, AlertDescription::IllegalParameter),
+        )?),
+        None => None,
+    };
+    let cipher_suite = server_hello.cipher_suite();
+    if cipher_suite != key_schedule.cipher_suite() {
+        // Section 4.1.4
+        // Upon receiving the ServerHello, clients MUST check that the cipher suite supplied in
+        // the ServerHello is the same as that in the HelloRetryRequest.
+        if handshake.hello_retry_request || !config.cipher_suites.contains(&cipher_suite) {
+            return Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::IllegalParameter,
+            ));
+        }
+
+        // The transcript so far uses the hash of the preferred cipher suite, so start
+        // over with the suite selected by the server.
+        let mut selected =
+            AnyKeySchedule::<Provider>::new(cipher_suite).ok_or(TlsError::InvalidCipherSuite)?;
+        handshake.client_hello.restart(&mut selected);
+        dispatch!(AnyKeySchedule, &mut selected, key_schedule => {
+            let transcript = key_schedule.transcript_hash();
+            transcript.update(server_hello.raw);
+        });
+        *key_schedule = selected;
+    }
+
+    let secrets = core::mem::take(&mut handshake.secrets);
+    let shared = server_hello.calculate_shared_secret(secrets)?;
+    dispatch!(AnyKeySchedule, key_schedule, key_schedule => {
+        initialize_early_secret(key_schedule, psk.as_ref())?;
+        key_schedule.initialize_handshake_secret(shared.as_ref())?;
+    });
+    Ok(State::ServerVerify)
+}
+
+/// Initializes the early secret with the PSK selected by the server, which must be compatible
+/// with the selected cipher suite.
+fn initialize_early_secret<CipherSuite>(
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    psk: Option<&OfferedPsk>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    match psk {
+        Some(psk) if !psk.is_compatible::<CipherSuite>() => Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::IllegalParameter,
+        )),
+        psk => key_schedule.initialize_early_secret(psk.map(|psk| psk.secret)),
+    }
+}
+
+fn process_server_verify<'a, 'v, CipherSuite, Provider, Verifier>(
+    handshake: &mut Handshake<'_, Provider, Verifier>,
+    peer_certificates: &mut PeerCertificateBuffer<'_>,
     key_schedule: &mut KeySchedule<CipherSuite>,
-    config: &TlsConfig<'a, CipherSuite>,
+    config: &TlsConfig<'a>,
     record: ServerRecord<'_, CipherSuite>,
 ) -> Result<State, TlsError>
 where
     CipherSuite: TlsCipherSuite,
-    Verifier: TlsVerifier<'v, CipherSuite>,
+    Provider: CryptoProvider,
+    Verifier: TlsVerifier<'v>,
 {
     let mut state = State::ServerVerify;
     decrypt_record(key_schedule.read_state(), record, |key_schedule, record| {
         match record {
             ServerRecord::Handshake(server_handshake) => {
                 match server_handshake {
-                    ServerHandshake::EncryptedExtensions(_) => {}
+                    ServerHandshake::EncryptedExtensions(extensions) => {
+                        let client_certificate_types: Vec<_, 1> = config
+                            .cert
+                            .iter()
+                            .map(Certificate::certificate_type)
+                            .collect();
+                        handshake.client_certificate_type = negotiated_certificate_type(
+                            extensions.client_certificate_type(),
+                            &client_certificate_types,
+                        )?;
+                        handshake.server_certificate_type = negotiated_certificate_type(
+                            extensions.server_certificate_type(),
+                            &config.server_certificate_types(),
+                        )?;
+                        handshake.early_data_status = early_data_status(handshake, &extensions)?;
+                        handshake.alpn_protocol = negotiated_alpn_protocol(
+                            extensions.alpn_protocol(),
+                            &config.alpn_protocols,
+                        )?;
+                        handshake.max_fragment_length = negotiated_max_fragment_length(
+                            extensions.max_fragment_length(),
+                            config.max_fragment_length,
+                        )?;
+                    }
                     ServerHandshake::Certificate(certificate) => {
-                        let transcript = key_schedule.transcript_hash();
+                        let certificate =
+                            certificate.with_certificate_type(handshake.server_certificate_type)?;
+                        let transcript = key_schedule.transcript_hash().clone().finalize();
+                        peer_certificates.store(&certificate);
                         handshake.verifier.verify_certificate(
-                            transcript,
+                            &transcript,
                             &config.ca,
+                            &config.crls,
+                            config.ocsp_stapling,
                             certificate,
                         )?;
                         debug!("Certificate verified!");
                     }
                     ServerHandshake::CertificateVerify(verify) => {
                         handshake.verifier.verify_signature(verify)?;
+                        handshake.certificate_verified = true;
                         debug!("Signature verified!");
                     }
                     ServerHandshake::CertificateRequest(request) => {
                         handshake.certificate_request.replace(request.try_into()?);
                     }
                     ServerHandshake::Finished(finished) => {
+                        // Section 4.4: the server authenticates with a certificate, unless it
+                        // accepted a PSK
+                        if handshake.selected_psk.is_none() && !handshake.certificate_verified {
+                            return Err(TlsError::AbortHandshake(
+                                AlertLevel::Fatal,
+                                AlertDescription::UnexpectedMessage,
+                            ));
+                        }
                         if !key_schedule.verify_server_finished(&finished)? {
                             warn!("Server signature verification failed");
                             return Err(TlsError::InvalidSignature);
                         }
 
                         // trace!("server verified {}", verified);
-                        state = if handshake.certificate_request.is_some() {
-                            State::ClientCert
+                        state = if handshake.early_data_status == EarlyDataStatus::Accepted {
+                            State::EndOfEarlyData
                         } else {
-                            handshake
-                                .traffic_hash
-                                .replace(key_schedule.transcript_hash().clone());
-                            State::ClientFinished
+                            handshake.client_flight()
                         };
                     }
                     _ => return Err(TlsError::InvalidHandshake),
@@ -492,77 +1157,372 @@ where
 
         Ok(())
     })?;
+
+    if state != State::ServerVerify {
+        // The server Finished message is the last one of the server's flight
+        key_schedule.save_traffic_hash();
+    }
     Ok(state)
 }
 
-fn client_cert<'r, CipherSuite, Verifier>(
-    handshake: &mut Handshake<CipherSuite, Verifier>,
+/// Checks whether the server accepted the early data. It can only be accepted if it was sent,
+/// and with the first PSK offered.
+fn early_data_status<Provider, Verifier>(
+    handshake: &Handshake<'_, Provider, Verifier>,
+    extensions: &EncryptedExtensions,
+) -> Result<EarlyDataStatus, TlsError>
+where
+    Provider: CryptoProvider,
+{
+    match (handshake.early_data, extensions.early_data()) {
+        (Some(_), true) if handshake.selected_psk == Some(0) => Ok(EarlyDataStatus::Accepted),
+        (Some(_), true) => Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::IllegalParameter,
+        )),
+        (None, true) => Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::UnsupportedExtension,
+        )),
+        (Some(_), false) => Ok(EarlyDataStatus::Rejected),
+        (None, false) => Ok(handshake.early_data_status),
+    }
+}
+
+/// Checks the application protocol selected by the server, returning its index among the
+/// offered ones.
+fn negotiated_alpn_protocol(
+    selected: Option<&[u8]>,
+    offered: &[&[u8]],
+) -> Result<Option<usize>, TlsError> {
+    match selected {
+        None => Ok(None),
+        Some(_) if offered.is_empty() => Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::UnsupportedExtension,
+        )),
+        Some(selected) => offered
+            .iter()
+            .position(|protocol| *protocol == selected)
+            .map(Some)
+            .ok_or(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::IllegalParameter,
+            )),
+    }
+}
+
+/// Checks the maximum fragment length echoed by the server, which must be the requested one
+/// (RFC 6066, Section 4).
+fn negotiated_max_fragment_length(
+    echoed: Option<MaxFragmentLength>,
+    requested: Option<MaxFragmentLength>,
+) -> Result<Option<MaxFragmentLength>, TlsError> {
+    match (echoed, requested) {
+        (None, _) => Ok(None),
+        (Some(_), None) => Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::UnsupportedExtension,
+        )),
+        (Some(echoed), Some(requested)) if echoed == requested => Ok(Some(echoed)),
+        (Some(_), Some(_)) => Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::IllegalParameter,
+        )),
+    }
+}
+
+/// Checks the certificate type selected by the server, which can only differ from X.509 if
+/// the client asked for raw public keys by configuring one.
+fn negotiated_certificate_type(
+    selected: Option<CertificateType>,
+    configured: &[CertificateType],
+) -> Result<CertificateType, TlsError> {
+    // The extension is only sent when raw public keys are configured
+    let offered = configured.contains(&CertificateType::RawPublicKey);
+    match selected {
+        None => Ok(CertificateType::X509),
+        Some(selected) if offered && configured.contains(&selected) => Ok(selected),
+        Some(_) => Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::UnsupportedExtension,
+        )),
+    }
+}
+
+fn client_cert<'r, CipherSuite>(
+    request: &CertificateRequest,
+    certificate_type: CertificateType,
+    cert: Option<&Certificate>,
+    signer: Option<&(dyn TlsSigner + Sync)>,
     key_schedule: &mut KeySchedule<CipherSuite>,
-    config: &TlsConfig<CipherSuite>,
     buffer: &'r mut WriteBuffer,
 ) -> Result<(State, &'r [u8]), TlsError>
 where
     CipherSuite: TlsCipherSuite,
 {
-    handshake
-        .traffic_hash
-        .replace(key_schedule.transcript_hash().clone());
-
-    let request_context = &handshake
-        .certificate_request
-        .as_ref()
-        .ok_or(TlsError::InvalidHandshake)?
-        .request_context;
-
-    let mut certificate = CertificateRef::with_context(request_context);
-    if let Some(cert) = &config.cert {
-        certificate.add(cert.into())?;
-    }
+    // Section 4.4.2: without a suitable certificate, a certificate message without any
+    // certificates is sent, and the server decides whether to continue the handshake
+    let signer = signer.filter(|signer| {
+        request
+            .signature_schemes
+            .contains(&signer.signature_scheme())
+    });
+    let mut certificate = CertificateRef::with_context(&request.request_context);
+    let accepted_cert = cert.filter(|cert| cert.certificate_type() == certificate_type);
+    let next_state = match (accepted_cert, signer) {
+        (Some(cert), Some(_)) => {
+            certificate.add(cert.try_into()?)?;
+            State::ClientCertVerify
+        }
+        (Some(_), None) => {
+            warn!("No signer for the client certificate that is accepted by the server");
+            State::ClientFinished
+        }
+        (None, _) => {
+            if cert.is_some() {
+                warn!("The server does not accept the type of the client certificate");
+            }
+            State::ClientFinished
+        }
+    };
     let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
 
     buffer
         .write_record(
-            &ClientRecord::Handshake(ClientHandshake::ClientCert(certificate), true),
+            &ClientRecord::Handshake(ClientHandshake::Certificate(certificate), true),
             write_key_schedule,
             Some(read_key_schedule),
         )
-        .map(|slice| (State::ClientFinished, slice))
+        .map(|slice| (next_state, slice))
 }
 
-fn client_finished<'r, CipherSuite>(
+fn client_cert_verify<'r, CipherSuite>(
     key_schedule: &mut KeySchedule<CipherSuite>,
+    signer: Option<&(dyn TlsSigner + Sync)>,
     buffer: &'r mut WriteBuffer,
 ) -> Result<&'r [u8], TlsError>
 where
     CipherSuite: TlsCipherSuite,
 {
-    let client_finished = key_schedule
+    let signer = signer.ok_or(TlsError::InvalidHandshake)?;
+
+    certificate_verify(
+        key_schedule,
+        signer,
+        b"TLS 1.3, client CertificateVerify\0",
+        buffer,
+    )
+}
+
+/// Encodes the CertificateVerify signing the transcript up to and including the certificate of
+/// this endpoint, with the context string of the client or the server.
+pub(crate) fn certificate_verify<'r, CipherSuite>(
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    signer: &(dyn TlsSigner + Sync),
+    context_string: &[u8],
+    buffer: &'r mut WriteBuffer,
+) -> Result<&'r [u8], TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    // Section 4.4.3: the signature covers 64 spaces, the context string, a zero byte and the
+    // transcript hash up to and including the certificate
+    let transcript = key_schedule.transcript_hash().clone().finalize();
+    let mut message: Vec<u8, { 64 + 34 + 64 }> = Vec::new();
+    message
+        .extend_from_slice(&[0x20; 64])
+        .and_then(|_| message.extend_from_slice(context_string))
+        .and_then(|_| message.extend_from_slice(&transcript))
+        .map_err(|_| TlsError::InternalError)?;
+
+    let mut signature = [0; MAX_SIGNATURE_LEN];
+    let len = signer.sign(&message, &mut signature)?;
+    let record = ClientRecord::Handshake(
+        ClientHandshake::CertificateVerify(CertificateVerify {
+            signature_scheme: signer.signature_scheme(),
+            signature: signature.get(..len).ok_or(TlsError::InvalidSignature)?,
+        }),
+        true,
+    );
+
+    let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+
+    buffer.write_record(&record, write_key_schedule, Some(read_key_schedule))
+}
+
+fn end_of_early_data<'r, CipherSuite>(
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    buffer: &'r mut WriteBuffer,
+) -> Result<&'r [u8], TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+
+    buffer.write_record(
+        &ClientRecord::Handshake(ClientHandshake::EndOfEarlyData, true),
+        write_key_schedule,
+        Some(read_key_schedule),
+    )
+}
+
+/// Encodes the message answering a post-handshake CertificateRequest in `state`, starting with
+/// `State::ClientCert`, and returns the state of the next message. The answer is complete once
+/// the state is `State::ApplicationData`.
+///
+/// The transcript of the key schedule must be the one up to and including the request.
+pub(crate) fn post_handshake_auth<'r, CipherSuite>(
+    state: State,
+    request: &CertificateRequest,
+    certificate_type: CertificateType,
+    cert: Option<&Certificate>,
+    signer: Option<&(dyn TlsSigner + Sync)>,
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    buffer: &'r mut WriteBuffer,
+) -> Result<(State, &'r [u8]), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    match state {
+        State::ClientCert => client_cert(
+            request,
+            certificate_type,
+            cert,
+            signer,
+            key_schedule,
+            buffer,
+        ),
+        State::ClientCertVerify => {
+            client_cert_verify(key_schedule, signer, buffer).map(|tx| (State::ClientFinished, tx))
+        }
+        // The Finished key is derived from the current application traffic secret
+        State::ClientFinished => {
+            finished(key_schedule, buffer).map(|tx| (State::ApplicationData, tx))
+        }
+        _ => Err(TlsError::InternalError),
+    }
+}
+
+/// Encodes the KeyUpdate sent in response to one from the server with `update_requested` set.
+/// The write keys must be updated once it is sent.
+pub(crate) fn key_update<'r, CipherSuite>(
+    key_schedule: &mut WriteKeySchedule<CipherSuite>,
+    buffer: &'r mut WriteBuffer,
+) -> Result<&'r [u8], TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let key_update = KeyUpdate {
+        update_requested: false,
+    };
+    buffer.write_record(
+        &ClientRecord::Handshake(ClientHandshake::KeyUpdate(key_update), true),
+        key_schedule,
+        None,
+    )
+}
+
+/// Encodes the Finished message of this endpoint, keyed with its current traffic secret.
+pub(crate) fn finished<'r, CipherSuite>(
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    buffer: &'r mut WriteBuffer,
+) -> Result<&'r [u8], TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let finished = key_schedule
         .create_client_finished()
         .map_err(|_| TlsError::InvalidHandshake)?;
 
     let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
 
     buffer.write_record(
-        &ClientRecord::Handshake(ClientHandshake::Finished(client_finished), true),
+        &ClientRecord::Handshake(ClientHandshake::Finished(finished), true),
         write_key_schedule,
         Some(read_key_schedule),
     )
 }
 
-fn client_finished_finalize<CipherSuite, Verifier>(
+/// Derives the application traffic secrets once the client Finished message is part of the
+/// transcript.
+pub(crate) fn client_finished_finalize<CipherSuite>(
     key_schedule: &mut KeySchedule<CipherSuite>,
-    handshake: &mut Handshake<CipherSuite, Verifier>,
 ) -> Result<State, TlsError>
 where
     CipherSuite: TlsCipherSuite,
 {
-    key_schedule.replace_transcript_hash(
-        handshake
-            .traffic_hash
-            .take()
-            .ok_or(TlsError::InvalidHandshake)?,
-    );
+    let traffic_hash = key_schedule
+        .take_traffic_hash()
+        .ok_or(TlsError::InvalidHandshake)?;
+    // The transcript up to the client Finished message, for the resumption master secret
+    let transcript = key_schedule.transcript_hash().clone();
+    key_schedule.replace_transcript_hash(traffic_hash);
     key_schedule.initialize_master_secret()?;
+    key_schedule.initialize_resumption_secret(&transcript)?;
+    key_schedule.read_state().finish_handshake(transcript);
 
     Ok(State::ApplicationData)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiated_alpn_protocol() {
+        let offered: [&[u8]; 2] = [b"h2", b"http/1.1"];
+
+        assert!(matches!(negotiated_alpn_protocol(None, &offered), Ok(None)));
+        assert!(matches!(
+            negotiated_alpn_protocol(Some(b"http/1.1"), &offered),
+            Ok(Some(1))
+        ));
+        assert!(matches!(
+            negotiated_alpn_protocol(Some(b"h3"), &offered),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::IllegalParameter
+            ))
+        ));
+        assert!(matches!(
+            negotiated_alpn_protocol(Some(b"h2"), &[]),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnsupportedExtension
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_negotiated_max_fragment_length() {
+        assert!(matches!(
+            negotiated_max_fragment_length(None, Some(MaxFragmentLength::Bits9)),
+            Ok(None)
+        ));
+        assert!(matches!(
+            negotiated_max_fragment_length(
+                Some(MaxFragmentLength::Bits9),
+                Some(MaxFragmentLength::Bits9)
+            ),
+            Ok(Some(MaxFragmentLength::Bits9))
+        ));
+        assert!(matches!(
+            negotiated_max_fragment_length(
+                Some(MaxFragmentLength::Bits10),
+                Some(MaxFragmentLength::Bits9)
+            ),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::IllegalParameter
+            ))
+        ));
+        assert!(matches!(
+            negotiated_max_fragment_length(Some(MaxFragmentLength::Bits9), None),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnsupportedExtension
+            ))
+        ));
+    }
+}
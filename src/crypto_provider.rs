@@ -0,0 +1,461 @@
+use heapless::Vec;
+use p256::EncodedPoint;
+use rand_core::{CryptoRng, RngCore};
+
+use crate::alert::{AlertDescription, AlertLevel};
+use crate::config::{
+    Aes128Ccm8Sha256, Aes128CcmSha256, Aes128GcmSha256, Aes256GcmSha384, Chacha20Poly1305Sha256,
+    TlsCipherSuite, TlsSigner,
+};
+use crate::extensions::extension_data::signature_algorithms::SignatureScheme;
+use crate::extensions::extension_data::supported_groups::NamedGroup;
+use crate::TlsError;
+#[cfg(feature = "mlkem")]
+use ml_kem::{kem::Decapsulate, EncodedSizeUser, KemCore, MlKem768, B32};
+
+/// The cryptographic primitives used by a TLS connection.
+///
+/// A provider supplies one [`TlsCipherSuite`] per cipher suite supported by embedded-tls, which
+/// determine the AEAD, hash, HMAC and HKDF implementations, and the key exchange implementation.
+/// The `CODE_POINT` of each cipher suite type must match the suite it is used for.
+///
+/// [`RustCryptoProvider`] is used by default. Implement this trait to use other implementations,
+/// for example one backed by hardware accelerators, reusing the RustCrypto types for anything
+/// that is not accelerated.
+pub trait CryptoProvider {
+    type Aes128GcmSha256: TlsCipherSuite;
+    type Aes256GcmSha384: TlsCipherSuite;
+    type Chacha20Poly1305Sha256: TlsCipherSuite;
+    type Aes128CcmSha256: TlsCipherSuite;
+    type Aes128Ccm8Sha256: TlsCipherSuite;
+
+    type KeyExchange: TlsKeyExchange;
+}
+
+/// An ephemeral (EC)DHE key exchange.
+///
+/// A `TlsAcceptor` generates its key share for the group of the client key share, except for
+/// key encapsulation groups such as X25519MLKEM768, which are only supported by clients.
+pub trait TlsKeyExchange: Sized {
+    type SharedSecret: AsRef<[u8]>;
+
+    /// Whether key shares can be generated for `group`.
+    ///
+    /// Configured groups that are not supported are not offered to the server.
+    fn is_supported(group: NamedGroup) -> bool;
+
+    /// Generates an ephemeral key pair for `group`.
+    fn generate<RNG>(group: NamedGroup, rng: &mut RNG) -> Result<Self, TlsError>
+    where
+        RNG: CryptoRng + RngCore;
+
+    fn group(&self) -> NamedGroup;
+
+    /// The public key, encoded as the key_exchange field of a key share entry.
+    fn public_key(&self) -> &[u8];
+
+    /// Computes the shared secret with the key_exchange field of the key share of the peer.
+    fn complete(self, peer_public_key: &[u8]) -> Result<Self::SharedSecret, TlsError>;
+}
+
+/// The default provider, using the pure Rust implementations of the RustCrypto project.
+pub struct RustCryptoProvider;
+
+impl CryptoProvider for RustCryptoProvider {
+    type Aes128GcmSha256 = Aes128GcmSha256;
+    type Aes256GcmSha384 = Aes256GcmSha384;
+    type Chacha20Poly1305Sha256 = Chacha20Poly1305Sha256;
+    type Aes128CcmSha256 = Aes128CcmSha256;
+    type Aes128Ccm8Sha256 = Aes128Ccm8Sha256;
+
+    type KeyExchange = RustCryptoKeyExchange;
+}
+
+/// The longest public key, an uncompressed secp384r1 point.
+#[cfg(not(feature = "mlkem"))]
+const MAX_PUBLIC_KEY_LEN: usize = 97;
+/// The longest public key, an ML-KEM-768 encapsulation key followed by an x25519 public key.
+#[cfg(feature = "mlkem")]
+const MAX_PUBLIC_KEY_LEN: usize = MLKEM768_ENCAPSULATION_KEY_LEN + 32;
+
+/// The lengths of the ML-KEM-768 encapsulation key, ciphertext and shared secret (FIPS 203,
+/// Table 3).
+#[cfg(feature = "mlkem")]
+const MLKEM768_ENCAPSULATION_KEY_LEN: usize = 1184;
+#[cfg(feature = "mlkem")]
+const MLKEM768_CIPHERTEXT_LEN: usize = 1088;
+#[cfg(feature = "mlkem")]
+const MLKEM768_SHARED_SECRET_LEN: usize = 32;
+
+/// Key exchange for secp256r1, secp384r1 and x25519, and X25519MLKEM768 with the `mlkem`
+/// feature.
+pub struct RustCryptoKeyExchange {
+    secret: EphemeralSecret,
+    public_key: Vec<u8, MAX_PUBLIC_KEY_LEN>,
+}
+
+enum EphemeralSecret {
+    Secp256r1(p256::ecdh::EphemeralSecret),
+    Secp384r1(p384::ecdh::EphemeralSecret),
+    X25519(x25519_dalek::EphemeralSecret),
+    #[cfg(feature = "mlkem")]
+    X25519MlKem768(MlKem768Seed, x25519_dalek::EphemeralSecret),
+}
+
+/// An ML-KEM-768 decapsulation key, kept as the 64 byte seed it is generated from and expanded
+/// when it is used, as the expanded key takes several kilobytes.
+#[cfg(feature = "mlkem")]
+struct MlKem768Seed([u8; 64]);
+
+#[cfg(feature = "mlkem")]
+impl MlKem768Seed {
+    fn generate<RNG>(rng: &mut RNG) -> Self
+    where
+        RNG: CryptoRng + RngCore,
+    {
+        let mut seed = [0; 64];
+        rng.fill_bytes(&mut seed);
+        Self(seed)
+    }
+
+    /// ML-KEM.KeyGen_internal with the seeds d and z.
+    fn expand(
+        &self,
+    ) -> (
+        <MlKem768 as KemCore>::DecapsulationKey,
+        <MlKem768 as KemCore>::EncapsulationKey,
+    ) {
+        let (d, z) = self.0.split_at(32);
+        let d = B32::from_fn(|i| d[i]);
+        let z = B32::from_fn(|i| z[i]);
+        MlKem768::generate_deterministic(&d, &z)
+    }
+
+    fn encapsulation_key(&self) -> impl AsRef<[u8]> {
+        self.expand().1.as_bytes()
+    }
+
+    fn decapsulate(&self, ciphertext: &[u8]) -> Result<[u8; MLKEM768_SHARED_SECRET_LEN], TlsError> {
+        let ciphertext = ml_kem::Ciphertext::<MlKem768>::try_from(ciphertext)
+            .map_err(|_| TlsError::InvalidKeyShare)?;
+        let mut shared = self
+            .expand()
+            .0
+            .decapsulate(&ciphertext)
+            .map_err(|_| TlsError::InvalidKeyShare)?;
+        let mut result = [0; MLKEM768_SHARED_SECRET_LEN];
+        result.copy_from_slice(&shared);
+        zeroize::Zeroize::zeroize(shared.as_mut_slice());
+        Ok(result)
+    }
+}
+
+#[cfg(feature = "mlkem")]
+impl Drop for MlKem768Seed {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(&mut self.0);
+    }
+}
+
+impl TlsKeyExchange for RustCryptoKeyExchange {
+    type SharedSecret = RustCryptoSharedSecret;
+
+    fn is_supported(group: NamedGroup) -> bool {
+        match group {
+            NamedGroup::Secp256r1 | NamedGroup::Secp384r1 | NamedGroup::X25519 => true,
+            #[cfg(feature = "mlkem")]
+            NamedGroup::X25519MlKem768 => true,
+            _ => false,
+        }
+    }
+
+    fn generate<RNG>(group: NamedGroup, rng: &mut RNG) -> Result<Self, TlsError>
+    where
+        RNG: CryptoRng + RngCore,
+    {
+        let (secret, public_key) = match group {
+            NamedGroup::Secp256r1 => {
+                let secret = p256::ecdh::EphemeralSecret::random(rng);
+                let public_key =
+                    Vec::from_slice(EncodedPoint::from(secret.public_key()).as_bytes());
+                (EphemeralSecret::Secp256r1(secret), public_key)
+            }
+            NamedGroup::Secp384r1 => {
+                let secret = p384::ecdh::EphemeralSecret::random(rng);
+                let public_key =
+                    Vec::from_slice(p384::EncodedPoint::from(secret.public_key()).as_bytes());
+                (EphemeralSecret::Secp384r1(secret), public_key)
+            }
+            NamedGroup::X25519 => {
+                let secret = x25519_dalek::EphemeralSecret::random_from_rng(rng);
+                let public_key = Vec::from_slice(x25519_dalek::PublicKey::from(&secret).as_bytes());
+                (EphemeralSecret::X25519(secret), public_key)
+            }
+            #[cfg(feature = "mlkem")]
+            NamedGroup::X25519MlKem768 => {
+                // The ML-KEM encapsulation key comes first, followed by the x25519 public key
+                let mlkem = MlKem768Seed::generate(rng);
+                let x25519 = x25519_dalek::EphemeralSecret::random_from_rng(rng);
+                let mut public_key = Vec::new();
+                let result = public_key
+                    .extend_from_slice(mlkem.encapsulation_key().as_ref())
+                    .and_then(|_| {
+                        public_key
+                            .extend_from_slice(x25519_dalek::PublicKey::from(&x25519).as_bytes())
+                    })
+                    .map(|_| public_key);
+                (EphemeralSecret::X25519MlKem768(mlkem, x25519), result)
+            }
+            _ => return Err(TlsError::InvalidKeyShare),
+        };
+
+        Ok(Self {
+            secret,
+            public_key: public_key.map_err(|_| TlsError::InternalError)?,
+        })
+    }
+
+    fn group(&self) -> NamedGroup {
+        match self.secret {
+            EphemeralSecret::Secp256r1(_) => NamedGroup::Secp256r1,
+            EphemeralSecret::Secp384r1(_) => NamedGroup::Secp384r1,
+            EphemeralSecret::X25519(_) => NamedGroup::X25519,
+            #[cfg(feature = "mlkem")]
+            EphemeralSecret::X25519MlKem768(..) => NamedGroup::X25519MlKem768,
+        }
+    }
+
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    fn complete(self, peer_public_key: &[u8]) -> Result<RustCryptoSharedSecret, TlsError> {
+        match self.secret {
+            EphemeralSecret::Secp256r1(secret) => {
+                let public_key = p256::PublicKey::from_sec1_bytes(peer_public_key)
+                    .map_err(|_| TlsError::InvalidKeyShare)?;
+                Ok(RustCryptoSharedSecret::Secp256r1(
+                    secret.diffie_hellman(&public_key),
+                ))
+            }
+            EphemeralSecret::Secp384r1(secret) => {
+                let public_key = p384::PublicKey::from_sec1_bytes(peer_public_key)
+                    .map_err(|_| TlsError::InvalidKeyShare)?;
+                Ok(RustCryptoSharedSecret::Secp384r1(
+                    secret.diffie_hellman(&public_key),
+                ))
+            }
+            EphemeralSecret::X25519(secret) => Ok(RustCryptoSharedSecret::X25519(
+                x25519_diffie_hellman(secret, peer_public_key)?,
+            )),
+            #[cfg(feature = "mlkem")]
+            EphemeralSecret::X25519MlKem768(mlkem, x25519) => {
+                // The server sends the ML-KEM ciphertext followed by its x25519 public key,
+                // and the shared secrets are concatenated in the same order.
+                if peer_public_key.len() != MLKEM768_CIPHERTEXT_LEN + 32 {
+                    return Err(TlsError::InvalidKeyShare);
+                }
+                let (ciphertext, x25519_public_key) =
+                    peer_public_key.split_at(MLKEM768_CIPHERTEXT_LEN);
+
+                let mut shared = [0; MLKEM768_SHARED_SECRET_LEN + 32];
+                shared[..MLKEM768_SHARED_SECRET_LEN]
+                    .copy_from_slice(&mlkem.decapsulate(ciphertext)?);
+                shared[MLKEM768_SHARED_SECRET_LEN..]
+                    .copy_from_slice(x25519_diffie_hellman(x25519, x25519_public_key)?.as_bytes());
+                Ok(RustCryptoSharedSecret::X25519MlKem768(shared))
+            }
+        }
+    }
+}
+
+fn x25519_diffie_hellman(
+    secret: x25519_dalek::EphemeralSecret,
+    peer_public_key: &[u8],
+) -> Result<x25519_dalek::SharedSecret, TlsError> {
+    let public_key: [u8; 32] = peer_public_key
+        .try_into()
+        .map_err(|_| TlsError::InvalidKeyShare)?;
+    let shared = secret.diffie_hellman(&public_key.into());
+    // Section 7.4.2: check for the all-zero value and abort if so
+    if !shared.was_contributory() {
+        return Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::IllegalParameter,
+        ));
+    }
+    Ok(shared)
+}
+
+/// Shared secret resulting from a [`RustCryptoKeyExchange`].
+pub enum RustCryptoSharedSecret {
+    Secp256r1(p256::ecdh::SharedSecret),
+    Secp384r1(p384::ecdh::SharedSecret),
+    X25519(x25519_dalek::SharedSecret),
+    #[cfg(feature = "mlkem")]
+    X25519MlKem768([u8; MLKEM768_SHARED_SECRET_LEN + 32]),
+}
+
+impl AsRef<[u8]> for RustCryptoSharedSecret {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            Self::Secp256r1(shared) => shared.raw_secret_bytes(),
+            Self::Secp384r1(shared) => shared.raw_secret_bytes(),
+            Self::X25519(shared) => shared.as_bytes(),
+            #[cfg(feature = "mlkem")]
+            Self::X25519MlKem768(shared) => shared,
+        }
+    }
+}
+
+#[cfg(feature = "mlkem")]
+impl Drop for RustCryptoSharedSecret {
+    fn drop(&mut self) {
+        if let Self::X25519MlKem768(shared) = self {
+            zeroize::Zeroize::zeroize(shared);
+        }
+    }
+}
+
+/// Signs with an ECDSA secp256r1 private key held in memory.
+pub struct P256Signer {
+    key: p256::ecdsa::SigningKey,
+}
+
+impl P256Signer {
+    /// Creates a signer from the big endian encoded private scalar.
+    pub fn from_bytes(key: &[u8]) -> Result<Self, TlsError> {
+        let key =
+            p256::ecdsa::SigningKey::from_slice(key).map_err(|_| TlsError::InvalidPrivateKey)?;
+        Ok(Self { key })
+    }
+
+    /// Creates a signer from a DER encoded PKCS#8 private key.
+    pub fn from_pkcs8_der(key: &[u8]) -> Result<Self, TlsError> {
+        use p256::pkcs8::DecodePrivateKey;
+        let key = p256::ecdsa::SigningKey::from_pkcs8_der(key)
+            .map_err(|_| TlsError::InvalidPrivateKey)?;
+        Ok(Self { key })
+    }
+}
+
+impl TlsSigner for P256Signer {
+    fn signature_scheme(&self) -> SignatureScheme {
+        SignatureScheme::EcdsaSecp256r1Sha256
+    }
+
+    fn sign(&self, message: &[u8], signature: &mut [u8]) -> Result<usize, TlsError> {
+        use p256::ecdsa::signature::Signer;
+        let encoded: p256::ecdsa::DerSignature = self
+            .key
+            .try_sign(message)
+            .map_err(|_| TlsError::CryptoError)?;
+        let encoded = encoded.as_bytes();
+        signature
+            .get_mut(..encoded.len())
+            .ok_or(TlsError::InsufficientSpace)?
+            .copy_from_slice(encoded);
+        Ok(encoded.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_x25519_rejects_non_contributory() {
+        // A low order point results in an all-zero shared secret
+        let low_order = [0; 32];
+        let key_exchange =
+            RustCryptoKeyExchange::generate(NamedGroup::X25519, &mut rand::rngs::OsRng).unwrap();
+        let result = key_exchange.complete(&low_order);
+        assert!(matches!(
+            result,
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::IllegalParameter
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_public_key() {
+        let key_exchange =
+            RustCryptoKeyExchange::generate(NamedGroup::Secp256r1, &mut rand::rngs::OsRng).unwrap();
+        let public_key = [9; 32];
+        let result = key_exchange.complete(&public_key);
+        assert!(matches!(result, Err(TlsError::InvalidKeyShare)));
+    }
+
+    #[test]
+    fn test_unsupported_group() {
+        assert!(!RustCryptoKeyExchange::is_supported(NamedGroup::Ffdhe2048));
+        let result = RustCryptoKeyExchange::generate(NamedGroup::Ffdhe2048, &mut rand::rngs::OsRng);
+        assert!(matches!(result, Err(TlsError::InvalidKeyShare)));
+    }
+
+    #[cfg(feature = "mlkem")]
+    #[test]
+    fn test_mlkem768_known_answer() {
+        fn from_hex(hex: &str) -> std::vec::Vec<u8> {
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect()
+        }
+
+        // Generated with OpenSSL 3.5 from the seed d || z
+        let seed = MlKem768Seed(
+            from_hex(include_str!("../tests/data/mlkem768/seed.hex").trim())
+                .try_into()
+                .unwrap(),
+        );
+        let ek = from_hex(include_str!("../tests/data/mlkem768/ek.hex").trim());
+        let ciphertext = from_hex(include_str!("../tests/data/mlkem768/ciphertext.hex").trim());
+        let shared = from_hex(include_str!("../tests/data/mlkem768/shared.hex").trim());
+
+        assert_eq!(&ek[..], seed.encapsulation_key().as_ref());
+        assert_eq!(&shared[..], &seed.decapsulate(&ciphertext).unwrap()[..]);
+    }
+
+    #[cfg(feature = "mlkem")]
+    #[test]
+    fn test_mlkem768_invalid_key_share() {
+        let key_exchange =
+            RustCryptoKeyExchange::generate(NamedGroup::X25519MlKem768, &mut rand::rngs::OsRng)
+                .unwrap();
+        assert_eq!(
+            MLKEM768_ENCAPSULATION_KEY_LEN + 32,
+            key_exchange.public_key().len()
+        );
+        let result = key_exchange.complete(&[0; MLKEM768_CIPHERTEXT_LEN]);
+        assert!(matches!(result, Err(TlsError::InvalidKeyShare)));
+    }
+
+    #[test]
+    fn test_p256_signer() {
+        use p256::ecdsa::signature::Verifier;
+
+        let signer = P256Signer::from_bytes(&[7; 32]).unwrap();
+        let mut signature = [0; crate::config::MAX_SIGNATURE_LEN];
+        let len = signer.sign(b"message", &mut signature).unwrap();
+
+        let signature = p256::ecdsa::DerSignature::from_bytes(&signature[..len]).unwrap();
+        let verifying_key = p256::ecdsa::VerifyingKey::from(&signer.key);
+        assert!(verifying_key.verify(b"message", &signature).is_ok());
+        assert!(verifying_key.verify(b"other", &signature).is_err());
+    }
+
+    #[test]
+    fn test_p256_signer_invalid_key() {
+        assert!(matches!(
+            P256Signer::from_bytes(&[0; 32]),
+            Err(TlsError::InvalidPrivateKey)
+        ));
+        assert!(matches!(
+            P256Signer::from_pkcs8_der(&[0; 32]),
+            Err(TlsError::InvalidPrivateKey)
+        ));
+    }
+}
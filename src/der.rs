@@ -0,0 +1,274 @@
+//! Just enough DER decoding to read the fields of certificates and OCSP responses without
+//! allocating.
+
+use crate::parse_buffer::{ParseBuffer, ParseError};
+
+pub(crate) const BOOLEAN: u8 = 0x01;
+pub(crate) const INTEGER: u8 = 0x02;
+pub(crate) const BIT_STRING: u8 = 0x03;
+pub(crate) const OCTET_STRING: u8 = 0x04;
+pub(crate) const OID: u8 = 0x06;
+pub(crate) const ENUMERATED: u8 = 0x0a;
+pub(crate) const UTC_TIME: u8 = 0x17;
+pub(crate) const GENERALIZED_TIME: u8 = 0x18;
+pub(crate) const SEQUENCE: u8 = 0x30;
+
+/// The tag of a constructed context specific element, such as `[0] EXPLICIT`.
+pub(crate) const fn context(number: u8) -> u8 {
+    0xa0 | number
+}
+
+/// The tag of a primitive context specific element, such as `[0] IMPLICIT NULL`.
+pub(crate) const fn context_primitive(number: u8) -> u8 {
+    0x80 | number
+}
+
+/// Returns the tag of the next element, without consuming it.
+pub(crate) fn peek_tag(buf: &ParseBuffer) -> Option<u8> {
+    buf.as_slice().get(buf.offset()).copied()
+}
+
+/// Reads an element with any tag, returning the tag and the contents.
+pub(crate) fn read_any<'a>(buf: &mut ParseBuffer<'a>) -> Result<(u8, ParseBuffer<'a>), ParseError> {
+    let tag = buf.read_u8()?;
+    let len = match buf.read_u8()? {
+        len if len < 0x80 => len as usize,
+        0x81 => buf.read_u8()? as usize,
+        0x82 => buf.read_u16()? as usize,
+        0x83 => buf.read_u24()? as usize,
+        _ => return Err(ParseError::InvalidData),
+    };
+    Ok((tag, buf.slice(len)?))
+}
+
+/// Reads an element with the given tag, returning its contents.
+pub(crate) fn read_element<'a>(
+    buf: &mut ParseBuffer<'a>,
+    tag: u8,
+) -> Result<ParseBuffer<'a>, ParseError> {
+    match read_any(buf)? {
+        (actual, contents) if actual == tag => Ok(contents),
+        _ => Err(ParseError::InvalidData),
+    }
+}
+
+/// Reads an element with the given tag if it is the next one.
+pub(crate) fn read_optional_element<'a>(
+    buf: &mut ParseBuffer<'a>,
+    tag: u8,
+) -> Result<Option<ParseBuffer<'a>>, ParseError> {
+    if peek_tag(buf) == Some(tag) {
+        read_element(buf, tag).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Reads an element with the given tag, returning its encoding including the tag and length.
+pub(crate) fn read_encoded<'a>(buf: &mut ParseBuffer<'a>, tag: u8) -> Result<&'a [u8], ParseError> {
+    let start = buf.offset();
+    read_element(buf, tag)?;
+    Ok(&buf.as_slice()[start..buf.offset()])
+}
+
+/// Reads a BIT STRING without unused bits, such as a public key or a signature.
+pub(crate) fn read_bit_string<'a>(buf: &mut ParseBuffer<'a>) -> Result<&'a [u8], ParseError> {
+    let mut bits = read_element(buf, BIT_STRING)?;
+    if bits.read_u8()? != 0 {
+        return Err(ParseError::InvalidData);
+    }
+    Ok(&bits.as_slice()[bits.offset()..])
+}
+
+/// Reads a UTCTime or GeneralizedTime in the forms required by RFC 5280, Section 4.1.2.5,
+/// returning the seconds since the Unix epoch.
+pub(crate) fn read_time(buf: &mut ParseBuffer) -> Result<u64, ParseError> {
+    let (tag, time) = read_any(buf)?;
+    let time = time.as_slice();
+    let (year, time) = match (tag, time.len()) {
+        (UTC_TIME, 13) => {
+            // Years from 1950 to 2049
+            let year = digits(&time[..2])?;
+            (
+                if year < 50 { 2000 + year } else { 1900 + year },
+                &time[2..],
+            )
+        }
+        (GENERALIZED_TIME, 15) => (digits(&time[..4])?, &time[4..]),
+        _ => return Err(ParseError::InvalidData),
+    };
+    if time[10] != b'Z' {
+        return Err(ParseError::InvalidData);
+    }
+    let month = digits(&time[0..2])?;
+    let day = digits(&time[2..4])?;
+    let hours = digits(&time[4..6])?;
+    let minutes = digits(&time[6..8])?;
+    let seconds = digits(&time[8..10])?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hours > 23 || minutes > 59 {
+        return Err(ParseError::InvalidData);
+    }
+    if year < 1970 || seconds > 59 {
+        return Err(ParseError::InvalidData);
+    }
+
+    // Days since the epoch of the civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    Ok(((days * 24 + hours) * 60 + minutes) * 60 + seconds)
+}
+
+fn digits(digits: &[u8]) -> Result<u64, ParseError> {
+    digits.iter().try_fold(0, |value, digit| match digit {
+        b'0'..=b'9' => Ok(value * 10 + u64::from(digit - b'0')),
+        _ => Err(ParseError::InvalidData),
+    })
+}
+
+/// Returns the subjectPublicKey of the contents of a SubjectPublicKeyInfo.
+///
+/// ```text
+/// SubjectPublicKeyInfo ::= SEQUENCE {
+///     algorithm AlgorithmIdentifier,
+///     subjectPublicKey BIT STRING
+/// }
+/// ```
+pub(crate) fn subject_public_key(spki: &[u8]) -> Result<&[u8], ParseError> {
+    let mut spki = ParseBuffer::new(spki);
+    read_element(&mut spki, SEQUENCE)?;
+    read_bit_string(&mut spki)
+}
+
+/// The fields of a DER encoded X.509 certificate needed to check its key and revocation status.
+///
+/// ```text
+/// Certificate ::= SEQUENCE {
+///     tbsCertificate TBSCertificate,
+///     ...
+/// }
+///
+/// TBSCertificate ::= SEQUENCE {
+///     version [0] EXPLICIT Version DEFAULT v1,
+///     serialNumber CertificateSerialNumber,
+///     signature AlgorithmIdentifier,
+///     issuer Name,
+///     validity Validity,
+///     subject Name,
+///     subjectPublicKeyInfo SubjectPublicKeyInfo,
+///     issuerUniqueID [1] IMPLICIT UniqueIdentifier OPTIONAL,
+///     subjectUniqueID [2] IMPLICIT UniqueIdentifier OPTIONAL,
+///     extensions [3] EXPLICIT Extensions OPTIONAL
+/// }
+/// ```
+pub(crate) struct X509Certificate<'a> {
+    /// The contents of the serial number.
+    pub serial_number: &'a [u8],
+    /// The contents of the issuer name.
+    pub issuer: &'a [u8],
+    /// The encoded issuer name.
+    pub encoded_issuer: &'a [u8],
+    /// The contents of the subject name.
+    pub subject: &'a [u8],
+    /// The encoded SubjectPublicKeyInfo.
+    pub subject_public_key_info: &'a [u8],
+    extensions: Option<&'a [u8]>,
+}
+
+impl<'a> X509Certificate<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ParseError> {
+        let mut buf = ParseBuffer::new(data);
+        let mut certificate = read_element(&mut buf, SEQUENCE)?;
+        let mut tbs_certificate = read_element(&mut certificate, SEQUENCE)?;
+
+        read_optional_element(&mut tbs_certificate, context(0))?;
+        let serial_number = read_element(&mut tbs_certificate, INTEGER)?;
+        read_element(&mut tbs_certificate, SEQUENCE)?;
+        let encoded_issuer = read_encoded(&mut tbs_certificate, SEQUENCE)?;
+        let issuer = read_element(&mut ParseBuffer::new(encoded_issuer), SEQUENCE)?;
+        read_element(&mut tbs_certificate, SEQUENCE)?;
+        let subject = read_element(&mut tbs_certificate, SEQUENCE)?;
+        let subject_public_key_info = read_encoded(&mut tbs_certificate, SEQUENCE)?;
+        read_optional_element(&mut tbs_certificate, context_primitive(1))?;
+        read_optional_element(&mut tbs_certificate, context_primitive(2))?;
+        let extensions = read_optional_element(&mut tbs_certificate, context(3))?
+            .map(|mut extensions| read_element(&mut extensions, SEQUENCE))
+            .transpose()?
+            .map(|extensions| extensions.as_slice());
+
+        Ok(Self {
+            serial_number: serial_number.as_slice(),
+            issuer: issuer.as_slice(),
+            encoded_issuer,
+            subject: subject.as_slice(),
+            subject_public_key_info,
+            extensions,
+        })
+    }
+
+    /// Returns the subjectPublicKey of the SubjectPublicKeyInfo.
+    pub fn subject_public_key(&self) -> Result<&'a [u8], ParseError> {
+        let mut buf = ParseBuffer::new(self.subject_public_key_info);
+        let spki = read_element(&mut buf, SEQUENCE)?;
+        subject_public_key(spki.as_slice())
+    }
+
+    /// Returns the contents of the value of the extension with the given OID.
+    ///
+    /// ```text
+    /// Extension ::= SEQUENCE {
+    ///     extnID OBJECT IDENTIFIER,
+    ///     critical BOOLEAN DEFAULT FALSE,
+    ///     extnValue OCTET STRING
+    /// }
+    /// ```
+    pub fn extension(&self, oid: &[u8]) -> Result<Option<ParseBuffer<'a>>, ParseError> {
+        let Some(extensions) = self.extensions else {
+            return Ok(None);
+        };
+        let mut extensions = ParseBuffer::new(extensions);
+        while !extensions.is_empty() {
+            let mut extension = read_element(&mut extensions, SEQUENCE)?;
+            let id = read_element(&mut extension, OID)?;
+            read_optional_element(&mut extension, BOOLEAN)?;
+            let value = read_element(&mut extension, OCTET_STRING)?;
+            if id.as_slice() == oid {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(tag: u8, time: &str) -> Result<u64, ParseError> {
+        let mut encoded = std::vec![tag, time.len() as u8];
+        encoded.extend_from_slice(time.as_bytes());
+        read_time(&mut ParseBuffer::new(&encoded))
+    }
+
+    #[test]
+    fn test_read_time() {
+        assert_eq!(0, time(UTC_TIME, "700101000000Z").unwrap());
+        assert_eq!(951_782_400, time(UTC_TIME, "000229000000Z").unwrap());
+        assert_eq!(2_524_607_999, time(UTC_TIME, "491231235959Z").unwrap());
+        assert_eq!(
+            4_102_444_800,
+            time(GENERALIZED_TIME, "21000101000000Z").unwrap()
+        );
+        assert!(time(GENERALIZED_TIME, "20240101000000+0100").is_err());
+        assert!(time(UTC_TIME, "241301000000Z").is_err());
+        assert!(time(OCTET_STRING, "700101000000Z").is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use heapless::Vec;
+
+use crate::{
+    buffer::CryptoBuffer,
+    parse_buffer::{ParseBuffer, ParseError},
+    TlsError,
+};
+
+/// The application_layer_protocol_negotiation extension (RFC 7301). The ClientHello lists the
+/// protocols offered by the client, and the EncryptedExtensions the one selected by the server.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProtocolNameList<'a, const N: usize> {
+    pub protocols: Vec<&'a [u8], N>,
+}
+
+impl<'a, const N: usize> ProtocolNameList<'a, N> {
+    pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<Self, ParseError> {
+        let data_length = buf.read_u16()? as usize;
+        let protocols = buf.read_list::<_, N>(data_length, |buf| {
+            let len = buf.read_u8()? as usize;
+            Ok(buf.slice(len)?.as_slice())
+        })?;
+
+        // RFC 7301, Section 3.1: the list and the protocol names must not be empty
+        if protocols.is_empty() || protocols.iter().any(|protocol| protocol.is_empty()) {
+            return Err(ParseError::InvalidData);
+        }
+
+        Ok(Self { protocols })
+    }
+
+    pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        buf.with_u16_length(|buf| {
+            for protocol in self.protocols.iter() {
+                if protocol.is_empty() || protocol.len() > u8::MAX as usize {
+                    return Err(TlsError::EncodeError);
+                }
+                buf.with_u8_length(|buf| buf.extend_from_slice(protocol))?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_selected_protocol() {
+        let buffer = [
+            0x00, 0x03, // protocol_name_list length = 3 bytes
+            0x02, b'h', b'2',
+        ];
+        let result = ProtocolNameList::<1>::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+
+        assert_eq!(&[b"h2"], result.protocols.as_slice());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        // The server selects exactly one protocol, with a name that is not empty
+        let empty_list = [0x00, 0x00];
+        let empty_name = [0x00, 0x01, 0x00];
+        let two_protocols = [0x00, 0x06, 0x02, b'h', b'2', 0x02, b'h', b'3'];
+
+        for buffer in [&empty_list[..], &empty_name, &two_protocols] {
+            assert!(ProtocolNameList::<1>::parse(&mut ParseBuffer::new(buffer)).is_err());
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::buffer::CryptoBuffer;
+
+use crate::parse_buffer::{ParseBuffer, ParseError};
+use crate::TlsError;
+
+use heapless::Vec;
+
+/// Certificate types of the client_certificate_type and server_certificate_type extensions
+///
+/// RFC 7250, Section 3.  Structure of the Raw Public Key Extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CertificateType {
+    X509 = 0,
+    RawPublicKey = 2,
+}
+
+impl CertificateType {
+    pub fn parse(buf: &mut ParseBuffer) -> Result<Self, ParseError> {
+        match buf.read_u8()? {
+            0 => Ok(Self::X509),
+            2 => Ok(Self::RawPublicKey),
+            other => {
+                warn!("Read unknown CertificateType: {}", other);
+                Err(ParseError::InvalidData)
+            }
+        }
+    }
+
+    pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        buf.push(*self as u8).map_err(|_| TlsError::EncodeError)
+    }
+}
+
+/// The certificate types supported by the client, in order of preference.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CertificateTypeRequest<const N: usize> {
+    pub certificate_types: Vec<CertificateType, N>,
+}
+
+impl<const N: usize> CertificateTypeRequest<N> {
+    pub fn single(certificate_type: CertificateType) -> Self {
+        Self {
+            certificate_types: unwrap!(Vec::from_slice(&[certificate_type]).ok()),
+        }
+    }
+
+    pub fn parse(buf: &mut ParseBuffer) -> Result<Self, ParseError> {
+        let data_length = buf.read_u8()? as usize;
+
+        Ok(Self {
+            certificate_types: buf.read_list::<_, N>(data_length, CertificateType::parse)?,
+        })
+    }
+
+    pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        buf.with_u8_length(|buf| {
+            for certificate_type in self.certificate_types.iter() {
+                certificate_type.encode(buf)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// The certificate type selected by the server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CertificateTypeResponse {
+    pub certificate_type: CertificateType,
+}
+
+impl CertificateTypeResponse {
+    pub fn parse(buf: &mut ParseBuffer) -> Result<Self, ParseError> {
+        Ok(Self {
+            certificate_type: CertificateType::parse(buf)?,
+        })
+    }
+
+    pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        self.certificate_type.encode(buf)
+    }
+}
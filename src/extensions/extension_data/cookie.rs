@@ -0,0 +1,32 @@
+use crate::{
+    buffer::CryptoBuffer,
+    parse_buffer::{ParseBuffer, ParseError},
+    TlsError,
+};
+
+/// Opaque cookie sent by the server in a HelloRetryRequest, which the client echoes back in
+/// its second ClientHello.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Cookie<'a> {
+    pub cookie: &'a [u8],
+}
+
+impl<'a> Cookie<'a> {
+    pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<Self, ParseError> {
+        let len = buf.read_u16()? as usize;
+        if len == 0 {
+            return Err(ParseError::InvalidData);
+        }
+        let cookie = buf.slice(len)?;
+
+        Ok(Self {
+            cookie: cookie.as_slice(),
+        })
+    }
+
+    pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        buf.with_u16_length(|buf| buf.extend_from_slice(self.cookie))
+            .map_err(|_| TlsError::EncodeError)
+    }
+}
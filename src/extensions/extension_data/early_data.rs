@@ -0,0 +1,45 @@
+use crate::{
+    buffer::CryptoBuffer,
+    parse_buffer::{ParseBuffer, ParseError},
+    TlsError,
+};
+
+/// The early_data extension of the ClientHello and EncryptedExtensions, which is empty.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EarlyDataIndication;
+
+impl EarlyDataIndication {
+    pub fn parse(buf: &mut ParseBuffer) -> Result<Self, ParseError> {
+        if buf.is_empty() {
+            Ok(Self)
+        } else {
+            Err(ParseError::InvalidData)
+        }
+    }
+
+    pub fn encode(&self, _buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        Ok(())
+    }
+}
+
+/// The early_data extension of the NewSessionTicket, with the most early data the server
+/// accepts when resuming with the ticket.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MaxEarlyDataSize {
+    pub max_early_data_size: u32,
+}
+
+impl MaxEarlyDataSize {
+    pub fn parse(buf: &mut ParseBuffer) -> Result<Self, ParseError> {
+        Ok(Self {
+            max_early_data_size: buf.read_u32()?,
+        })
+    }
+
+    pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        buf.push_u32(self.max_early_data_size)
+            .map_err(|_| TlsError::EncodeError)
+    }
+}
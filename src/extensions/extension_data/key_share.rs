@@ -6,6 +6,9 @@ use crate::extensions::extension_data::supported_groups::NamedGroup;
 use crate::parse_buffer::{ParseBuffer, ParseError};
 use crate::TlsError;
 
+/// The maximum number of key shares sent in a client hello.
+pub(crate) const MAX_KEY_SHARES: usize = 3;
+
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct KeyShareServerHello<'a>(pub KeyShareEntry<'a>);
@@ -29,9 +32,28 @@ pub struct KeyShareClientHello<'a, const N: usize> {
 impl<'a, const N: usize> KeyShareClientHello<'a, N> {
     pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<Self, ParseError> {
         let len = buf.read_u16()? as usize;
-        Ok(KeyShareClientHello {
-            client_shares: buf.read_list(len, KeyShareEntry::parse)?,
-        })
+        let mut data = buf.slice(len)?;
+
+        let mut client_shares = Vec::new();
+        while !data.is_empty() {
+            match KeyShareEntry::parse(&mut data) {
+                // Ignore the key shares that do not fit, a HelloRetryRequest can still ask for
+                // one of them
+                Ok(entry) => {
+                    if client_shares.push(entry).is_err() {
+                        trace!("Ignoring key share exceeding {} shares", N);
+                    }
+                }
+                // Ignore key shares for groups we do not know about
+                Err(ParseError::InvalidData) => {
+                    let opaque_len = data.read_u16()?;
+                    data.slice(opaque_len as usize)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(KeyShareClientHello { client_shares })
     }
 
     pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
@@ -131,4 +153,45 @@ mod tests {
         assert_eq!(2, result.opaque.len());
         assert_eq!([0xAA, 0xBB], result.opaque);
     }
+
+    #[test]
+    fn test_parse_client_hello_unknown_group() {
+        setup();
+        let buffer = [
+            0x00, 0x0b, // client_shares length = 11 bytes
+            0x2a, 0x2a, // unknown group
+            0x00, 0x01, // key_exchange length = 1 byte
+            0x00, //
+            0x00, 0x1d, // X25519
+            0x00, 0x02, // key_exchange length = 2 bytes
+            0xAA, 0xBB,
+        ];
+        let result = KeyShareClientHello::<2>::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+
+        assert_eq!(1, result.client_shares.len());
+        assert_eq!(NamedGroup::X25519, result.client_shares[0].group);
+        assert_eq!([0xAA, 0xBB], result.client_shares[0].opaque);
+    }
+
+    #[test]
+    fn test_parse_client_hello_too_many_shares() {
+        setup();
+        let buffer = [
+            0x00, 0x0f, // client_shares length = 15 bytes
+            0x00, 0x1d, // X25519
+            0x00, 0x01, // key_exchange length = 1 byte
+            0xAA, //
+            0x00, 0x17, // Secp256r1
+            0x00, 0x01, // key_exchange length = 1 byte
+            0xBB, //
+            0x00, 0x18, // Secp384r1
+            0x00, 0x01, // key_exchange length = 1 byte
+            0xCC,
+        ];
+        let result = KeyShareClientHello::<2>::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+
+        assert_eq!(2, result.client_shares.len());
+        assert_eq!(NamedGroup::X25519, result.client_shares[0].group);
+        assert_eq!(NamedGroup::Secp256r1, result.client_shares[1].group);
+    }
 }
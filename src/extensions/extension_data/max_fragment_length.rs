@@ -41,4 +41,9 @@ impl MaxFragmentLength {
     pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
         buf.push(*self as u8).map_err(|_| TlsError::EncodeError)
     }
+
+    /// The maximum plaintext fragment length in bytes.
+    pub fn size(&self) -> usize {
+        1 << (8 + *self as usize)
+    }
 }
@@ -1,10 +1,16 @@
+pub mod alpn;
+pub mod certificate_type;
+pub mod cookie;
+pub mod early_data;
 pub mod key_share;
 pub mod max_fragment_length;
+pub mod post_handshake_auth;
 pub mod pre_shared_key;
 pub mod psk_key_exchange_modes;
 pub mod server_name;
 pub mod signature_algorithms;
 pub mod signature_algorithms_cert;
+pub mod status_request;
 pub mod supported_groups;
 pub mod supported_versions;
 pub mod unimplemented;
@@ -0,0 +1,24 @@
+use crate::{
+    buffer::CryptoBuffer,
+    parse_buffer::{ParseBuffer, ParseError},
+    TlsError,
+};
+
+/// The post_handshake_auth extension of the ClientHello, which is empty.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PostHandshakeAuth;
+
+impl PostHandshakeAuth {
+    pub fn parse(buf: &mut ParseBuffer) -> Result<Self, ParseError> {
+        if buf.is_empty() {
+            Ok(Self)
+        } else {
+            Err(ParseError::InvalidData)
+        }
+    }
+
+    pub fn encode(&self, _buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        Ok(())
+    }
+}
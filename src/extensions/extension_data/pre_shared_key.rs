@@ -5,26 +5,76 @@ use crate::TlsError;
 
 use heapless::Vec;
 
+/// The identities of the configured PSK, and a session ticket.
+pub const MAX_PSK_IDENTITIES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PskIdentity<'a> {
+    pub identity: &'a [u8],
+    /// The age of a session ticket in milliseconds plus its `ticket_age_add`, or 0 for external
+    /// PSKs.
+    pub obfuscated_ticket_age: u32,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct PreSharedKeyClientHello<'a, const N: usize> {
-    pub identities: Vec<&'a [u8], N>,
+    pub identities: Vec<PskIdentity<'a>, N>,
+    /// The binders received with the identities. When encoding, they are computed once the
+    /// rest of the ClientHello is known, see `ClientHello::finalize`.
+    pub binders: Vec<&'a [u8], N>,
+    /// The length of each binder, which is the length of the hash of the cipher suite.
     pub hash_size: usize,
 }
 
-impl<const N: usize> PreSharedKeyClientHello<'_, N> {
-    pub fn parse(_buf: &mut ParseBuffer) -> Result<Self, ParseError> {
-        unimplemented!()
+impl<'a, const N: usize> PreSharedKeyClientHello<'a, N> {
+    pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<Self, ParseError> {
+        let identities_len = buf.read_u16()? as usize;
+        let identities = buf.read_list::<_, N>(identities_len, |buf| {
+            let identity_len = buf.read_u16()? as usize;
+            Ok(PskIdentity {
+                identity: buf.slice(identity_len)?.as_slice(),
+                obfuscated_ticket_age: buf.read_u32()?,
+            })
+        })?;
+
+        let binders_len = buf.read_u16()? as usize;
+        let binders = buf.read_list::<_, N>(binders_len, |buf| {
+            let binder_len = buf.read_u8()? as usize;
+            Ok(buf.slice(binder_len)?.as_slice())
+        })?;
+
+        // Section 4.2.11: there is one binder for each of the offered identities
+        if identities.is_empty() || identities.len() != binders.len() {
+            return Err(ParseError::InvalidData);
+        }
+
+        Ok(Self {
+            identities,
+            hash_size: binders[0].len(),
+            binders,
+        })
+    }
+
+    /// The length of the encoded binders, including their length prefix. The binders are at
+    /// the end of the ClientHello, and the transcript they are computed over excludes them.
+    pub fn binders_len(&self) -> usize {
+        2 + self
+            .binders
+            .iter()
+            .map(|binder| 1 + binder.len())
+            .sum::<usize>()
     }
 
     pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
         buf.with_u16_length(|buf| {
             for identity in self.identities.iter() {
-                buf.with_u16_length(|buf| buf.extend_from_slice(identity))
+                buf.with_u16_length(|buf| buf.extend_from_slice(identity.identity))
                     .map_err(|_| TlsError::EncodeError)?;
 
-                // NOTE: No support for ticket age, set to 0 as recommended by RFC
-                buf.push_u32(0).map_err(|_| TlsError::EncodeError)?;
+                buf.push_u32(identity.obfuscated_ticket_age)
+                    .map_err(|_| TlsError::EncodeError)?;
             }
             Ok(())
         })
@@ -61,3 +111,36 @@ impl PreSharedKeyServerHello {
             .map_err(|_| TlsError::EncodeError)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_client_hello() {
+        let buffer = [
+            0x00, 0x0b, // identities length = 11 bytes
+            0x00, 0x05, b'v', b'a', b'd', b'e', b'r', // identity
+            0x00, 0x00, 0x00, 0x00, // obfuscated_ticket_age
+            0x00, 0x05, // binders length = 5 bytes
+            0x04, 0xaa, 0xbb, 0xcc, 0xdd,
+        ];
+        let result = PreSharedKeyClientHello::<1>::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+
+        assert_eq!(b"vader", result.identities[0].identity);
+        assert_eq!(&[&[0xaa, 0xbb, 0xcc, 0xdd][..]], result.binders.as_slice());
+        assert_eq!(7, result.binders_len());
+    }
+
+    #[test]
+    fn test_parse_missing_binder() {
+        let buffer = [
+            0x00, 0x0b, // identities length = 11 bytes
+            0x00, 0x05, b'v', b'a', b'd', b'e', b'r', // identity
+            0x00, 0x00, 0x00, 0x00, // obfuscated_ticket_age
+            0x00, 0x00, // binders length = 0 bytes
+        ];
+
+        assert!(PreSharedKeyClientHello::<1>::parse(&mut ParseBuffer::new(&buffer)).is_err());
+    }
+}
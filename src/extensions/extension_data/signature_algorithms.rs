@@ -79,10 +79,23 @@ pub struct SignatureAlgorithms<const N: usize> {
 impl<const N: usize> SignatureAlgorithms<N> {
     pub fn parse(buf: &mut ParseBuffer) -> Result<Self, ParseError> {
         let data_length = buf.read_u16()? as usize;
+        let mut data = buf.slice(data_length)?;
+
+        let mut supported_signature_algorithms = Vec::new();
+        while !data.is_empty() {
+            match SignatureScheme::parse(&mut data) {
+                Ok(scheme) => supported_signature_algorithms.push(scheme).map_err(|_| {
+                    error!("Failed to store parse result");
+                    ParseError::InsufficientSpace
+                })?,
+                // Ignore schemes we do not know about
+                Err(ParseError::InvalidData) => {}
+                Err(e) => return Err(e),
+            }
+        }
 
         Ok(Self {
-            supported_signature_algorithms: buf
-                .read_list::<_, N>(data_length, SignatureScheme::parse)?,
+            supported_signature_algorithms,
         })
     }
 
@@ -0,0 +1,93 @@
+use crate::{
+    buffer::CryptoBuffer,
+    parse_buffer::{ParseBuffer, ParseError},
+    TlsError,
+};
+
+/// RFC 6066, Section 8.  Certificate Status Request
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CertificateStatusType {
+    Ocsp = 1,
+}
+
+impl CertificateStatusType {
+    pub fn parse(buf: &mut ParseBuffer) -> Result<Self, ParseError> {
+        match buf.read_u8()? {
+            1 => Ok(Self::Ocsp),
+            other => {
+                warn!("Read unknown CertificateStatusType: {}", other);
+                Err(ParseError::InvalidData)
+            }
+        }
+    }
+
+    pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        buf.push(*self as u8).map_err(|_| TlsError::EncodeError)
+    }
+}
+
+/// Asks the server to staple the OCSP response for its certificate.
+///
+/// The responder list and request extensions are left empty, meaning the responders are known
+/// to the server.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CertificateStatusRequest {
+    pub status_type: CertificateStatusType,
+}
+
+impl CertificateStatusRequest {
+    pub fn ocsp() -> Self {
+        Self {
+            status_type: CertificateStatusType::Ocsp,
+        }
+    }
+
+    pub fn parse(buf: &mut ParseBuffer) -> Result<Self, ParseError> {
+        let status_type = CertificateStatusType::parse(buf)?;
+
+        // responder_id_list and request_extensions
+        let responder_ids_len = buf.read_u16()?;
+        buf.slice(responder_ids_len as usize)?;
+        let extensions_len = buf.read_u16()?;
+        buf.slice(extensions_len as usize)?;
+
+        Ok(Self { status_type })
+    }
+
+    pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        self.status_type.encode(buf)?;
+        buf.push_u16(0)?;
+        buf.push_u16(0)
+    }
+}
+
+/// The OCSP response stapled to a certificate entry.
+///
+/// RFC 8446, Section 4.4.2.1.  OCSP Status and SCT Extensions
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CertificateStatus<'a> {
+    pub status_type: CertificateStatusType,
+    /// The DER encoded OCSP response.
+    pub ocsp_response: &'a [u8],
+}
+
+impl<'a> CertificateStatus<'a> {
+    pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<Self, ParseError> {
+        let status_type = CertificateStatusType::parse(buf)?;
+        let response_len = buf.read_u24()?;
+        let ocsp_response = buf.slice(response_len as usize)?.as_slice();
+
+        Ok(Self {
+            status_type,
+            ocsp_response,
+        })
+    }
+
+    pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        self.status_type.encode(buf)?;
+        buf.with_u24_length(|buf| buf.extend_from_slice(self.ocsp_response))
+    }
+}
@@ -22,6 +22,9 @@ pub enum NamedGroup {
     Ffdhe4096 = 0x0102,
     Ffdhe6144 = 0x0103,
     Ffdhe8192 = 0x0104,
+
+    /* Hybrid Post-Quantum Groups */
+    X25519MlKem768 = 0x11EC,
 }
 
 impl NamedGroup {
@@ -37,6 +40,7 @@ impl NamedGroup {
             v if v == Self::Ffdhe4096 as u16 => Ok(Self::Ffdhe4096),
             v if v == Self::Ffdhe6144 as u16 => Ok(Self::Ffdhe6144),
             v if v == Self::Ffdhe8192 as u16 => Ok(Self::Ffdhe8192),
+            v if v == Self::X25519MlKem768 as u16 => Ok(Self::X25519MlKem768),
             _ => Err(ParseError::InvalidData),
         }
     }
@@ -45,6 +49,29 @@ impl NamedGroup {
         buf.push_u16(*self as u16)
             .map_err(|_| TlsError::EncodeError)
     }
+
+    /// Whether the group is a key encapsulation mechanism, where only the client can generate
+    /// the key share, and the server encapsulates a secret to it.
+    pub(crate) fn is_kem(&self) -> bool {
+        matches!(self, Self::X25519MlKem768)
+    }
+
+    /// The length of the key_exchange field of a client key share for this group.
+    pub(crate) fn public_key_len(&self) -> usize {
+        match self {
+            Self::Secp256r1 => 65,
+            Self::Secp384r1 => 97,
+            Self::Secp521r1 => 133,
+            Self::X25519 => 32,
+            Self::X448 => 56,
+            Self::Ffdhe2048 => 256,
+            Self::Ffdhe3072 => 384,
+            Self::Ffdhe4096 => 512,
+            Self::Ffdhe6144 => 768,
+            Self::Ffdhe8192 => 1024,
+            Self::X25519MlKem768 => 1216,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -56,10 +83,22 @@ pub struct SupportedGroups<const N: usize> {
 impl<const N: usize> SupportedGroups<N> {
     pub fn parse(buf: &mut ParseBuffer) -> Result<Self, ParseError> {
         let data_length = buf.read_u16()? as usize;
+        let mut data = buf.slice(data_length)?;
 
-        Ok(Self {
-            supported_groups: buf.read_list::<_, N>(data_length, NamedGroup::parse)?,
-        })
+        let mut supported_groups = Vec::new();
+        while !data.is_empty() {
+            match NamedGroup::parse(&mut data) {
+                Ok(group) => supported_groups.push(group).map_err(|_| {
+                    error!("Failed to store parse result");
+                    ParseError::InsufficientSpace
+                })?,
+                // Ignore groups we do not know about
+                Err(ParseError::InvalidData) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Self { supported_groups })
     }
 
     pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
@@ -1,12 +1,21 @@
+use crate::config::MAX_ALPN_PROTOCOLS;
 use crate::extensions::{
     extension_data::{
-        key_share::{KeyShareClientHello, KeyShareServerHello},
+        alpn::ProtocolNameList,
+        certificate_type::{CertificateTypeRequest, CertificateTypeResponse},
+        cookie::Cookie,
+        early_data::{EarlyDataIndication, MaxEarlyDataSize},
+        key_share::{
+            KeyShareClientHello, KeyShareHelloRetryRequest, KeyShareServerHello, MAX_KEY_SHARES,
+        },
         max_fragment_length::MaxFragmentLength,
-        pre_shared_key::{PreSharedKeyClientHello, PreSharedKeyServerHello},
+        post_handshake_auth::PostHandshakeAuth,
+        pre_shared_key::{PreSharedKeyClientHello, PreSharedKeyServerHello, MAX_PSK_IDENTITIES},
         psk_key_exchange_modes::PskKeyExchangeModes,
         server_name::{ServerNameList, ServerNameResponse},
         signature_algorithms::SignatureAlgorithms,
         signature_algorithms_cert::SignatureAlgorithmsCert,
+        status_request::{CertificateStatus, CertificateStatusRequest},
         supported_groups::SupportedGroups,
         supported_versions::{SupportedVersionsClientHello, SupportedVersionsServerHello},
         unimplemented::Unimplemented,
@@ -21,24 +30,24 @@ extension_group! {
         SupportedVersions(SupportedVersionsClientHello<16>),
         SignatureAlgorithms(SignatureAlgorithms<16>),
         SupportedGroups(SupportedGroups<16>),
-        KeyShare(KeyShareClientHello<'a, 1>),
-        PreSharedKey(PreSharedKeyClientHello<'a, 4>),
+        KeyShare(KeyShareClientHello<'a, MAX_KEY_SHARES>),
+        PreSharedKey(PreSharedKeyClientHello<'a, MAX_PSK_IDENTITIES>),
         PskKeyExchangeModes(PskKeyExchangeModes<4>),
         SignatureAlgorithmsCert(SignatureAlgorithmsCert<16>),
         MaxFragmentLength(MaxFragmentLength),
-        StatusRequest(Unimplemented<'a>),
+        StatusRequest(CertificateStatusRequest),
         UseSrtp(Unimplemented<'a>),
         Heartbeat(Unimplemented<'a>),
-        ApplicationLayerProtocolNegotiation(Unimplemented<'a>),
+        ApplicationLayerProtocolNegotiation(ProtocolNameList<'a, MAX_ALPN_PROTOCOLS>),
         SignedCertificateTimestamp(Unimplemented<'a>),
-        ClientCertificateType(Unimplemented<'a>),
-        ServerCertificateType(Unimplemented<'a>),
+        ClientCertificateType(CertificateTypeRequest<2>),
+        ServerCertificateType(CertificateTypeRequest<2>),
         Padding(Unimplemented<'a>),
-        EarlyData(Unimplemented<'a>),
-        Cookie(Unimplemented<'a>),
+        EarlyData(EarlyDataIndication),
+        Cookie(Cookie<'a>),
         CertificateAuthorities(Unimplemented<'a>),
         OidFilters(Unimplemented<'a>),
-        PostHandshakeAuth(Unimplemented<'a>)
+        PostHandshakeAuth(PostHandshakeAuth)
     }
 }
 
@@ -47,7 +56,6 @@ extension_group! {
     pub enum ServerHelloExtension<'a> {
         KeyShare(KeyShareServerHello<'a>),
         PreSharedKey(PreSharedKeyServerHello),
-        Cookie(Unimplemented<'a>), // temporary so we don't trip up on HelloRetryRequests
         SupportedVersions(SupportedVersionsServerHello)
     }
 }
@@ -60,10 +68,10 @@ extension_group! {
         SupportedGroups(SupportedGroups<10>),
         UseSrtp(Unimplemented<'a>),
         Heartbeat(Unimplemented<'a>),
-        ApplicationLayerProtocolNegotiation(Unimplemented<'a>),
-        ClientCertificateType(Unimplemented<'a>),
-        ServerCertificateType(Unimplemented<'a>),
-        EarlyData(Unimplemented<'a>)
+        ApplicationLayerProtocolNegotiation(ProtocolNameList<'a, 1>),
+        ClientCertificateType(CertificateTypeResponse),
+        ServerCertificateType(CertificateTypeResponse),
+        EarlyData(EarlyDataIndication)
     }
 }
 
@@ -71,7 +79,7 @@ extension_group! {
 extension_group! {
     pub enum CertificateRequestExtension<'a> {
         StatusRequest(Unimplemented<'a>),
-        SignatureAlgorithms(SignatureAlgorithms<4>),
+        SignatureAlgorithms(SignatureAlgorithms<16>),
         SignedCertificateTimestamp(Unimplemented<'a>),
         CertificateAuthorities(Unimplemented<'a>),
         OidFilters(Unimplemented<'a>),
@@ -82,23 +90,23 @@ extension_group! {
 // Source: https://www.rfc-editor.org/rfc/rfc8446#section-4.2 table, rows marked with CT
 extension_group! {
     pub enum CertificateExtension<'a> {
-        StatusRequest(Unimplemented<'a>),
+        StatusRequest(CertificateStatus<'a>),
         SignedCertificateTimestamp(Unimplemented<'a>)
     }
 }
 
 // Source: https://www.rfc-editor.org/rfc/rfc8446#section-4.2 table, rows marked with NST
 extension_group! {
-    pub enum NewSessionTicketExtension<'a> {
-        EarlyData(Unimplemented<'a>)
+    pub enum NewSessionTicketExtension {
+        EarlyData(MaxEarlyDataSize)
     }
 }
 
 // Source: https://www.rfc-editor.org/rfc/rfc8446#section-4.2 table, rows marked with HRR
 extension_group! {
     pub enum HelloRetryRequestExtension<'a> {
-        KeyShare(Unimplemented<'a>),
-        Cookie(Unimplemented<'a>),
-        SupportedVersions(Unimplemented<'a>)
+        KeyShare(KeyShareHelloRetryRequest),
+        Cookie(Cookie<'a>),
+        SupportedVersions(SupportedVersionsServerHello)
     }
 }
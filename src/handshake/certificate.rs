@@ -1,16 +1,22 @@
 use crate::buffer::CryptoBuffer;
+use crate::der::{self, X509Certificate};
+use crate::extensions::extension_data::certificate_type::CertificateType;
 use crate::extensions::messages::CertificateExtension;
 use crate::parse_buffer::ParseBuffer;
 use crate::TlsError;
 use heapless::Vec;
 
+/// The maximum number of certificates in a chain, including the leaf.
+pub const MAX_CERTIFICATE_CHAIN_LEN: usize = 16;
+
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct CertificateRef<'a> {
     raw_entries: &'a [u8],
     request_context: &'a [u8],
+    ocsp_response: Option<&'a [u8]>,
 
-    pub(crate) entries: Vec<CertificateEntryRef<'a>, 16>,
+    pub(crate) entries: Vec<CertificateEntryRef<'a>, MAX_CERTIFICATE_CHAIN_LEN>,
 }
 
 impl<'a> CertificateRef<'a> {
@@ -18,6 +24,7 @@ impl<'a> CertificateRef<'a> {
         Self {
             raw_entries: &[],
             request_context,
+            ocsp_response: None,
             entries: Vec::new(),
         }
     }
@@ -39,26 +46,55 @@ impl<'a> CertificateRef<'a> {
             .slice(entries_len as usize)
             .map_err(|_| TlsError::InvalidCertificate)?;
 
-        let entries = CertificateEntryRef::parse_vector(&mut raw_entries)?;
+        let (entries, ocsp_response) = CertificateEntryRef::parse_vector(&mut raw_entries)?;
 
         Ok(Self {
             raw_entries: raw_entries.as_slice(),
             request_context: request_context.as_slice(),
+            ocsp_response,
             entries,
         })
     }
 
+    /// The DER encoded OCSP response stapled to the leaf certificate, if any.
+    pub fn ocsp_response(&self) -> Option<&'a [u8]> {
+        self.ocsp_response
+    }
+
+    /// Interprets the entries according to the certificate type negotiated for the peer.
+    ///
+    /// Entries are parsed as X.509 certificates. With raw public keys (RFC 7250), the single
+    /// entry is a DER encoded SubjectPublicKeyInfo instead.
+    pub(crate) fn with_certificate_type(
+        mut self,
+        certificate_type: CertificateType,
+    ) -> Result<Self, TlsError> {
+        if certificate_type == CertificateType::RawPublicKey {
+            if self.entries.len() != 1 {
+                return Err(TlsError::InvalidCertificate);
+            }
+            for entry in self.entries.iter_mut() {
+                if let CertificateEntryRef::X509(data) = *entry {
+                    parse_subject_public_key_info(data)?;
+                    *entry = CertificateEntryRef::RawPublicKey(data);
+                }
+            }
+        }
+        Ok(self)
+    }
+
     pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
         buf.push(self.request_context.len() as u8)
             .map_err(|_| TlsError::EncodeError)?;
         buf.extend_from_slice(self.request_context)
             .map_err(|_| TlsError::EncodeError)?;
 
-        buf.push_u24(self.entries.len() as u32)?;
-        for entry in self.entries.iter() {
-            entry.encode(buf)?;
-        }
-        Ok(())
+        buf.with_u24_length(|buf| {
+            for entry in self.entries.iter() {
+                entry.encode(buf)?;
+            }
+            Ok(())
+        })
     }
 }
 
@@ -70,7 +106,8 @@ pub enum CertificateEntryRef<'a> {
 }
 
 impl<'a> CertificateEntryRef<'a> {
-    pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<Self, TlsError> {
+    /// Parses an entry, returning the OCSP response stapled to it, if any.
+    pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<(Self, Option<&'a [u8]>), TlsError> {
         let entry_len = buf
             .read_u24()
             .map_err(|_| TlsError::InvalidCertificateEntry)?;
@@ -78,50 +115,98 @@ impl<'a> CertificateEntryRef<'a> {
             .slice(entry_len as usize)
             .map_err(|_| TlsError::InvalidCertificateEntry)?;
 
+        // Raw public keys are told apart by the negotiated certificate type, see
+        // `CertificateRef::with_certificate_type`
         let entry = CertificateEntryRef::X509(cert.as_slice());
 
-        // Validate extensions
-        CertificateExtension::parse_vector::<2>(buf)?;
+        let ocsp_response = CertificateExtension::parse_vector::<2>(buf)?
+            .iter()
+            .find_map(|extension| match extension {
+                CertificateExtension::StatusRequest(status) => Some(status.ocsp_response),
+                _ => None,
+            });
 
-        Ok(entry)
+        Ok((entry, ocsp_response))
     }
 
+    /// Parses the entries of a certificate chain, returning the OCSP response stapled to the
+    /// leaf certificate, if any.
     pub fn parse_vector<const N: usize>(
         buf: &mut ParseBuffer<'a>,
-    ) -> Result<Vec<Self, N>, TlsError> {
+    ) -> Result<(Vec<Self, N>, Option<&'a [u8]>), TlsError> {
         let mut result = Vec::new();
+        let mut leaf_ocsp_response = None;
 
         while !buf.is_empty() {
-            result
-                .push(Self::parse(buf)?)
-                .map_err(|_| TlsError::DecodeError)?;
+            let (entry, ocsp_response) = Self::parse(buf)?;
+            if result.is_empty() {
+                leaf_ocsp_response = ocsp_response;
+            }
+            result.push(entry).map_err(|_| {
+                warn!("Certificate chain is longer than {} entries", N);
+                TlsError::CertificateChainTooLong
+            })?;
         }
 
-        Ok(result)
+        Ok((result, leaf_ocsp_response))
     }
 
-    pub(crate) fn encode(&self, _buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
-        todo!("not implemented");
-        /*
-        match self {
-            CertificateEntry::RawPublicKey(key) => {
-                let entry_len = (key.len() as u32).to_be_bytes();
-            }
-            CertificateEntry::X509(cert) => {
-                let entry_len = (cert.len() as u32).to_be_bytes();
+    pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
+        match *self {
+            // The raw public key is encoded as a DER SubjectPublicKeyInfo, see RFC 7250
+            CertificateEntryRef::RawPublicKey(data) | CertificateEntryRef::X509(data) => {
+                buf.with_u24_length(|buf| buf.extend_from_slice(data))?;
             }
         }
+
+        // Zero extensions for now
+        buf.push_u16(0)?;
         Ok(())
-        */
     }
 }
 
-impl<'a> From<&crate::config::Certificate<'a>> for CertificateEntryRef<'a> {
-    fn from(cert: &crate::config::Certificate<'a>) -> Self {
+/// Checks the structure of a DER encoded SubjectPublicKeyInfo, leaving the key itself to the
+/// verifier.
+///
+/// ```text
+/// SubjectPublicKeyInfo ::= SEQUENCE {
+///     algorithm AlgorithmIdentifier,
+///     subjectPublicKey BIT STRING
+/// }
+/// ```
+fn parse_subject_public_key_info(data: &[u8]) -> Result<(), TlsError> {
+    let mut buf = ParseBuffer::new(data);
+    let mut spki = der::read_element(&mut buf, der::SEQUENCE)
+        .map_err(|_| TlsError::InvalidCertificateEntry)?;
+    der::read_element(&mut spki, der::SEQUENCE).map_err(|_| TlsError::InvalidCertificateEntry)?;
+    der::read_element(&mut spki, der::BIT_STRING).map_err(|_| TlsError::InvalidCertificateEntry)?;
+    if !buf.is_empty() || !spki.is_empty() {
+        return Err(TlsError::InvalidCertificateEntry);
+    }
+    Ok(())
+}
+
+/// Returns the DER encoded SubjectPublicKeyInfo of a DER encoded X.509 certificate.
+pub(crate) fn x509_subject_public_key_info(data: &[u8]) -> Result<&[u8], TlsError> {
+    let certificate =
+        X509Certificate::parse(data).map_err(|_| TlsError::InvalidCertificateEntry)?;
+    parse_subject_public_key_info(certificate.subject_public_key_info)?;
+    Ok(certificate.subject_public_key_info)
+}
+
+impl<'a> TryFrom<&crate::config::Certificate<'a>> for CertificateEntryRef<'a> {
+    type Error = TlsError;
+
+    fn try_from(cert: &crate::config::Certificate<'a>) -> Result<Self, Self::Error> {
         match cert {
-            crate::config::Certificate::X509(data) => CertificateEntryRef::X509(data),
+            crate::config::Certificate::X509(data) => Ok(CertificateEntryRef::X509(data)),
             crate::config::Certificate::RawPublicKey(data) => {
-                CertificateEntryRef::RawPublicKey(data)
+                Ok(CertificateEntryRef::RawPublicKey(data))
+            }
+            crate::config::Certificate::TrustAnchor(_)
+            | crate::config::Certificate::SpkiSha256(_) => {
+                warn!("A trust anchor cannot be sent as a certificate");
+                Err(TlsError::InvalidCertificate)
             }
         }
     }
@@ -131,6 +216,7 @@ impl<'a> From<&crate::config::Certificate<'a>> for CertificateEntryRef<'a> {
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Certificate<const N: usize> {
     request_context: Vec<u8, 256>,
+    certificate_type: CertificateType,
     num_entries: usize,
     entries_data: Vec<u8, N>,
 }
@@ -153,8 +239,14 @@ impl<'a, const N: usize> TryFrom<CertificateRef<'a>> for Certificate<N> {
             .extend_from_slice(cert.raw_entries)
             .map_err(|_| TlsError::OutOfMemory)?;
 
+        let certificate_type = match cert.entries.first() {
+            Some(CertificateEntryRef::RawPublicKey(_)) => CertificateType::RawPublicKey,
+            _ => CertificateType::X509,
+        };
+
         Ok(Self {
             request_context,
+            certificate_type,
             num_entries: cert.entries.len(),
             entries_data,
         })
@@ -165,12 +257,14 @@ impl<'a, const N: usize> TryFrom<&'a Certificate<N>> for CertificateRef<'a> {
     type Error = TlsError;
     fn try_from(cert: &'a Certificate<N>) -> Result<Self, Self::Error> {
         let request_context = cert.request_context();
-        let entries =
+        let (entries, ocsp_response) =
             CertificateEntryRef::parse_vector(&mut ParseBuffer::from(&cert.entries_data[..]))?;
-        Ok(Self {
+        Self {
             raw_entries: &cert.entries_data[..],
             request_context,
+            ocsp_response,
             entries,
-        })
+        }
+        .with_certificate_type(cert.certificate_type)
     }
 }
@@ -1,3 +1,4 @@
+use crate::extensions::extension_data::signature_algorithms::SignatureScheme;
 use crate::extensions::messages::CertificateRequestExtension;
 use crate::parse_buffer::ParseBuffer;
 use crate::TlsError;
@@ -7,6 +8,7 @@ use heapless::Vec;
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct CertificateRequestRef<'a> {
     pub(crate) request_context: &'a [u8],
+    pub(crate) signature_schemes: Vec<SignatureScheme, 16>,
 }
 
 impl<'a> CertificateRequestRef<'a> {
@@ -18,11 +20,20 @@ impl<'a> CertificateRequestRef<'a> {
             .slice(request_context_len as usize)
             .map_err(|_| TlsError::InvalidCertificateRequest)?;
 
-        // Validate extensions
-        CertificateRequestExtension::parse_vector::<6>(buf)?;
+        let extensions = CertificateRequestExtension::parse_vector::<6>(buf)?;
+        let signature_schemes = extensions
+            .into_iter()
+            .find_map(|extension| match extension {
+                CertificateRequestExtension::SignatureAlgorithms(algorithms) => {
+                    Some(algorithms.supported_signature_algorithms)
+                }
+                _ => None,
+            })
+            .unwrap_or_default();
 
         Ok(Self {
             request_context: request_context.as_slice(),
+            signature_schemes,
         })
     }
 }
@@ -31,6 +42,7 @@ impl<'a> CertificateRequestRef<'a> {
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct CertificateRequest {
     pub(crate) request_context: Vec<u8, 256>,
+    pub(crate) signature_schemes: Vec<SignatureScheme, 16>,
 }
 
 impl<'a> TryFrom<CertificateRequestRef<'a>> for CertificateRequest {
@@ -43,6 +55,9 @@ impl<'a> TryFrom<CertificateRequestRef<'a>> for CertificateRequest {
                 error!("CertificateRequest: InsufficientSpace");
                 TlsError::InsufficientSpace
             })?;
-        Ok(Self { request_context })
+        Ok(Self {
+            request_context,
+            signature_schemes: cert.signature_schemes,
+        })
     }
 }
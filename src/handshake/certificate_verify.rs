@@ -1,3 +1,4 @@
+use crate::buffer::CryptoBuffer;
 use crate::extensions::extension_data::signature_algorithms::SignatureScheme;
 use crate::parse_buffer::ParseBuffer;
 use crate::TlsError;
@@ -24,4 +25,9 @@ impl<'a> CertificateVerify<'a> {
             signature: signature.as_slice(),
         })
     }
+
+    pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
+        buf.push_u16(self.signature_scheme as u16)?;
+        buf.with_u16_length(|buf| buf.extend_from_slice(self.signature))
+    }
 }
@@ -1,57 +1,118 @@
+use core::marker::PhantomData;
 use digest::{Digest, OutputSizeUser};
 use heapless::Vec;
-use p256::ecdh::EphemeralSecret;
-use p256::elliptic_curve::rand_core::{CryptoRng, RngCore};
-use p256::EncodedPoint;
 use typenum::Unsigned;
 
+use crate::alert::{AlertDescription, AlertLevel};
 use crate::buffer::*;
-use crate::config::{TlsCipherSuite, TlsConfig};
-use crate::extensions::extension_data::key_share::{KeyShareClientHello, KeyShareEntry};
-use crate::extensions::extension_data::pre_shared_key::PreSharedKeyClientHello;
+use crate::cipher_suites::CipherSuite;
+use crate::config::{Certificate, TlsCipherSuite, TlsConfig, MAX_ALPN_PROTOCOLS};
+use crate::crypto_provider::TlsKeyExchange;
+use crate::extensions::extension_data::alpn::ProtocolNameList;
+use crate::extensions::extension_data::certificate_type::{
+    CertificateType, CertificateTypeRequest,
+};
+use crate::extensions::extension_data::cookie::Cookie;
+use crate::extensions::extension_data::early_data::EarlyDataIndication;
+use crate::extensions::extension_data::key_share::{
+    KeyShareClientHello, KeyShareEntry, MAX_KEY_SHARES,
+};
+use crate::extensions::extension_data::post_handshake_auth::PostHandshakeAuth;
+use crate::extensions::extension_data::pre_shared_key::{
+    PreSharedKeyClientHello, PskIdentity, MAX_PSK_IDENTITIES,
+};
 use crate::extensions::extension_data::psk_key_exchange_modes::{
     PskKeyExchangeMode, PskKeyExchangeModes,
 };
 use crate::extensions::extension_data::server_name::ServerNameList;
-use crate::extensions::extension_data::signature_algorithms::SignatureAlgorithms;
+use crate::extensions::extension_data::signature_algorithms::{
+    SignatureAlgorithms, SignatureScheme,
+};
+use crate::extensions::extension_data::status_request::CertificateStatusRequest;
 use crate::extensions::extension_data::supported_groups::{NamedGroup, SupportedGroups};
 use crate::extensions::extension_data::supported_versions::{SupportedVersionsClientHello, TLS13};
 use crate::extensions::messages::ClientHelloExtension;
 use crate::handshake::{Random, LEGACY_VERSION};
-use crate::key_schedule::{HashOutputSize, WriteKeySchedule};
+use crate::key_schedule::{HashOutputSize, KeySchedule};
+use crate::parse_buffer::ParseBuffer;
 use crate::TlsError;
 
+/// A pre-shared key offered in the ClientHello, either an identity of the configured PSK or a
+/// session ticket.
+#[derive(Debug, Clone, Copy)]
+pub struct OfferedPsk<'a> {
+    pub identity: PskIdentity<'a>,
+    pub secret: &'a [u8],
+    /// The cipher suite of the resumed session, for session tickets.
+    pub cipher_suite: Option<CipherSuite>,
+}
+
+impl OfferedPsk<'_> {
+    /// Section 4.2.11: a resumption PSK can only be used with the hash of the session it was
+    /// established in, which is also the length of the PSK.
+    pub fn is_compatible<CipherSuite: TlsCipherSuite>(&self) -> bool {
+        self.cipher_suite.is_none()
+            || self.secret.len() == HashOutputSize::<CipherSuite>::to_usize()
+    }
+}
+
 pub struct ClientHello<'config, CipherSuite>
 where
     CipherSuite: TlsCipherSuite,
 {
-    pub(crate) config: &'config TlsConfig<'config, CipherSuite>,
-    random: Random,
-    pub(crate) secret: EphemeralSecret,
+    pub(crate) config: &'config TlsConfig<'config>,
+    pub(crate) random: Random,
+    supported_groups: Vec<NamedGroup, 16>,
+    key_shares: Vec<KeyShareEntry<'config>, MAX_KEY_SHARES>,
+    cookie: Option<&'config [u8]>,
+    psks: &'config [OfferedPsk<'config>],
+    early_data: bool,
+    cipher_suite: PhantomData<CipherSuite>,
 }
 
 impl<'config, CipherSuite> ClientHello<'config, CipherSuite>
 where
     CipherSuite: TlsCipherSuite,
 {
-    pub fn new<RNG>(config: &'config TlsConfig<'config, CipherSuite>, rng: &mut RNG) -> Self
+    /// Creates a client hello offering the configured groups supported by `KeyExchange`, with
+    /// a key share for each of the `secrets`. The cookie is only set when responding to a
+    /// HelloRetryRequest. The `psks` must be compatible with `CipherSuite`, and `early_data`
+    /// indicates that early data is sent with the first of them.
+    pub fn new<KeyExchange>(
+        config: &'config TlsConfig<'config>,
+        random: Random,
+        secrets: &'config [KeyExchange],
+        cookie: Option<&'config [u8]>,
+        psks: &'config [OfferedPsk<'config>],
+        early_data: bool,
+    ) -> Self
     where
-        RNG: CryptoRng + RngCore,
+        KeyExchange: TlsKeyExchange,
     {
-        let mut random = [0; 32];
-        rng.fill_bytes(&mut random);
-
         Self {
             config,
             random,
-            secret: EphemeralSecret::random(rng),
+            supported_groups: config
+                .named_groups
+                .iter()
+                .copied()
+                .filter(|group| KeyExchange::is_supported(*group))
+                .collect(),
+            key_shares: secrets
+                .iter()
+                .map(|secret| KeyShareEntry {
+                    group: secret.group(),
+                    opaque: secret.public_key(),
+                })
+                .collect(),
+            cookie,
+            psks,
+            early_data,
+            cipher_suite: PhantomData,
         }
     }
 
     pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
-        let public_key = EncodedPoint::from(&self.secret.public_key());
-        let public_key = public_key.as_ref();
-
         buf.push_u16(LEGACY_VERSION)
             .map_err(|_| TlsError::EncodeError)?;
         buf.extend_from_slice(&self.random)
@@ -61,13 +122,11 @@ where
         buf.push(0).map_err(|_| TlsError::EncodeError)?;
 
         // cipher suites (2+)
-        //buf.extend_from_slice(&((self.config.cipher_suites.len() * 2) as u16).to_be_bytes());
-        //for c in self.config.cipher_suites.iter() {
-        //buf.extend_from_slice(&(*c as u16).to_be_bytes());
-        //}
-        buf.push_u16(2).map_err(|_| TlsError::EncodeError)?;
-        buf.push_u16(CipherSuite::CODE_POINT)
+        buf.push_u16((self.config.cipher_suites.len() * 2) as u16)
             .map_err(|_| TlsError::EncodeError)?;
+        for c in self.config.cipher_suites.iter() {
+            buf.push_u16(*c as u16).map_err(|_| TlsError::EncodeError)?;
+        }
 
         // compression methods, 1 byte of 0
         buf.push(1).map_err(|_| TlsError::EncodeError)?;
@@ -93,8 +152,13 @@ where
                 ClientHelloExtension::MaxFragmentLength(max_fragment_length).encode(buf)?;
             }
 
+            if self.config.ocsp_stapling {
+                ClientHelloExtension::StatusRequest(CertificateStatusRequest::ocsp())
+                    .encode(buf)?;
+            }
+
             ClientHelloExtension::SupportedGroups(SupportedGroups {
-                supported_groups: self.config.named_groups.clone(),
+                supported_groups: self.supported_groups.clone(),
             })
             .encode(buf)?;
 
@@ -104,11 +168,7 @@ where
             .encode(buf)?;
 
             ClientHelloExtension::KeyShare(KeyShareClientHello {
-                client_shares: Vec::from_slice(&[KeyShareEntry {
-                    group: NamedGroup::Secp256r1,
-                    opaque: public_key,
-                }])
-                .unwrap(),
+                client_shares: self.key_shares.clone(),
             })
             .encode(buf)?;
 
@@ -117,14 +177,52 @@ where
                     .encode(buf)?;
             }
 
+            if !self.config.alpn_protocols.is_empty() {
+                ClientHelloExtension::ApplicationLayerProtocolNegotiation(ProtocolNameList {
+                    protocols: self.config.alpn_protocols.clone(),
+                })
+                .encode(buf)?;
+            }
+
+            // RFC 7250, Section 4.1: only send the extensions when raw public keys are used,
+            // as X.509 certificates are the default
+            if let Some(Certificate::RawPublicKey(_)) = self.config.cert {
+                ClientHelloExtension::ClientCertificateType(CertificateTypeRequest::single(
+                    CertificateType::RawPublicKey,
+                ))
+                .encode(buf)?;
+            }
+
+            let server_certificate_types = self.config.server_certificate_types();
+            if server_certificate_types.contains(&CertificateType::RawPublicKey) {
+                ClientHelloExtension::ServerCertificateType(CertificateTypeRequest {
+                    certificate_types: server_certificate_types,
+                })
+                .encode(buf)?;
+            }
+
+            // Section 4.6.2: the server may request the client certificate after the handshake
+            if self.config.cert.is_some() {
+                ClientHelloExtension::PostHandshakeAuth(PostHandshakeAuth).encode(buf)?;
+            }
+
+            if let Some(cookie) = self.cookie {
+                ClientHelloExtension::Cookie(Cookie { cookie }).encode(buf)?;
+            }
+
+            if self.early_data {
+                ClientHelloExtension::EarlyData(EarlyDataIndication).encode(buf)?;
+            }
+
             // Section 4.2
             // When multiple extensions of different types are present, the
             // extensions MAY appear in any order, with the exception of
             // "pre_shared_key" which MUST be the last extension in
             // the ClientHello.
-            if let Some((_, identities)) = &self.config.psk {
+            if !self.psks.is_empty() {
                 ClientHelloExtension::PreSharedKey(PreSharedKeyClientHello {
-                    identities: identities.clone(),
+                    identities: self.psks.iter().map(|psk| psk.identity).collect(),
+                    binders: Vec::new(),
                     hash_size: <CipherSuite::Hash as OutputSizeUser>::output_size(),
                 })
                 .encode(buf)?;
@@ -140,7 +238,6 @@ where
         &self,
         enc_buf: &mut [u8],
         transcript: &mut CipherSuite::Hash,
-        write_key_schedule: &mut WriteKeySchedule<CipherSuite>,
     ) -> Result<(), TlsError> {
         // Special case for PSK which needs to:
         //
@@ -150,8 +247,8 @@ where
         //
         // This causes a few issues since lengths must be correctly inside the payload,
         // but won't actually be added to the record buffer until the end.
-        if let Some((_, identities)) = &self.config.psk {
-            let binders_len = identities.len() * (1 + HashOutputSize::<CipherSuite>::to_usize());
+        if !self.psks.is_empty() {
+            let binders_len = self.psks.len() * (1 + HashOutputSize::<CipherSuite>::to_usize());
 
             let binders_pos = enc_buf.len() - binders_len;
 
@@ -161,8 +258,12 @@ where
             // Append after the client hello data. Sizes have already been set.
             let mut buf = CryptoBuffer::wrap(&mut enc_buf[binders_pos..]);
             // Create a binder and encode for each identity
-            for _id in identities {
-                let binder = write_key_schedule.create_psk_binder(transcript)?;
+            for psk in self.psks {
+                let binder = KeySchedule::<CipherSuite>::create_psk_binder(
+                    psk.secret,
+                    psk.cipher_suite.is_some(),
+                    transcript,
+                )?;
                 binder.encode(&mut buf)?;
             }
 
@@ -174,3 +275,205 @@ where
         Ok(())
     }
 }
+
+/// A ClientHello received by a server.
+///
+/// The extensions are checked when parsing, and only decoded when looked up, so that a large
+/// ClientHello does not need to be kept decoded in memory.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ClientHelloRef<'a> {
+    pub(crate) legacy_session_id: &'a [u8],
+    cipher_suites: &'a [u8],
+    compression_methods: &'a [u8],
+    extensions: &'a [u8],
+    /// The encoded handshake message, including its header.
+    pub(crate) raw: &'a [u8],
+}
+
+impl<'a> ClientHelloRef<'a> {
+    pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<ClientHelloRef<'a>, TlsError> {
+        let _version = buf.read_u16().map_err(|_| TlsError::InvalidHandshake)?;
+
+        let mut random = [0; 32];
+        buf.fill(&mut random)?;
+
+        let session_id_length = buf
+            .read_u8()
+            .map_err(|_| TlsError::InvalidSessionIdLength)?;
+        if session_id_length > 32 {
+            return Err(TlsError::InvalidSessionIdLength);
+        }
+        let legacy_session_id = buf
+            .slice(session_id_length as usize)
+            .map_err(|_| TlsError::InvalidSessionIdLength)?;
+
+        let cipher_suites_length = buf.read_u16().map_err(|_| TlsError::InvalidCipherSuite)?;
+        if cipher_suites_length % 2 != 0 {
+            return Err(TlsError::InvalidCipherSuite);
+        }
+        let cipher_suites = buf
+            .slice(cipher_suites_length as usize)
+            .map_err(|_| TlsError::InvalidCipherSuite)?;
+
+        let compression_methods_length = buf.read_u8()?;
+        let compression_methods = buf.slice(compression_methods_length as usize)?;
+
+        let extensions_length = buf
+            .read_u16()
+            .map_err(|_| TlsError::InvalidExtensionsLength)?;
+        let extensions = buf
+            .slice(extensions_length as usize)
+            .map_err(|_| TlsError::InvalidExtensionsLength)?;
+
+        // Section 4.2
+        // The "pre_shared_key" extension MUST be the last extension in the ClientHello.
+        // Servers MUST check that it is the last extension and otherwise fail the handshake
+        // with an "illegal_parameter" alert.
+        let mut ext_buf = ParseBuffer::new(extensions.as_slice());
+        while !ext_buf.is_empty() {
+            match ClientHelloExtension::parse(&mut ext_buf) {
+                Ok(ClientHelloExtension::PreSharedKey(_)) if !ext_buf.is_empty() => {
+                    return Err(TlsError::AbortHandshake(
+                        AlertLevel::Fatal,
+                        AlertDescription::IllegalParameter,
+                    ));
+                }
+                Ok(_) | Err(TlsError::UnknownExtensionType) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Self {
+            legacy_session_id: legacy_session_id.as_slice(),
+            cipher_suites: cipher_suites.as_slice(),
+            compression_methods: compression_methods.as_slice(),
+            extensions: extensions.as_slice(),
+            raw: &[],
+        })
+    }
+
+    /// Returns the first extension for which `f` returns a value.
+    fn find_extension<T>(
+        &self,
+        mut f: impl FnMut(ClientHelloExtension<'a>) -> Option<T>,
+    ) -> Option<T> {
+        let mut buf = ParseBuffer::new(self.extensions);
+        while !buf.is_empty() {
+            match ClientHelloExtension::parse(&mut buf) {
+                Ok(extension) => {
+                    if let Some(value) = f(extension) {
+                        return Some(value);
+                    }
+                }
+                Err(TlsError::UnknownExtensionType) => {}
+                // The extensions were checked when parsing
+                Err(_) => return None,
+            }
+        }
+        None
+    }
+
+    /// The offered cipher suites known to embedded-tls, in order of client preference.
+    pub fn cipher_suites(&self) -> impl Iterator<Item = CipherSuite> + 'a {
+        self.cipher_suites.chunks_exact(2).filter_map(|suite| {
+            CipherSuite::try_from(u16::from_be_bytes([suite[0], suite[1]])).ok()
+        })
+    }
+
+    /// Section 4.1.2: a TLS 1.3 ClientHello only offers the null compression method.
+    pub fn has_null_compression(&self) -> bool {
+        self.compression_methods == [0]
+    }
+
+    /// Whether the client offers TLS 1.3 in the supported_versions extension.
+    pub fn supports_tls13(&self) -> bool {
+        self.find_extension(|e| match e {
+            ClientHelloExtension::SupportedVersions(versions) => {
+                Some(versions.versions.contains(&TLS13))
+            }
+            _ => None,
+        })
+        .unwrap_or(false)
+    }
+
+    /// The signature schemes the server can authenticate with, in order of client preference.
+    pub fn signature_schemes(&self) -> Vec<SignatureScheme, 16> {
+        self.find_extension(|e| match e {
+            ClientHelloExtension::SignatureAlgorithms(algorithms) => {
+                Some(algorithms.supported_signature_algorithms)
+            }
+            _ => None,
+        })
+        .unwrap_or_default()
+    }
+
+    /// The key exchange groups supported by the client, in order of client preference.
+    pub fn supported_groups(&self) -> Vec<NamedGroup, 16> {
+        self.find_extension(|e| match e {
+            ClientHelloExtension::SupportedGroups(groups) => Some(groups.supported_groups),
+            _ => None,
+        })
+        .unwrap_or_default()
+    }
+
+    /// The public key of the client key share for `group`, if any.
+    pub fn key_share(&self, group: NamedGroup) -> Option<&'a [u8]> {
+        self.find_extension(|e| match e {
+            ClientHelloExtension::KeyShare(key_share) => key_share
+                .client_shares
+                .iter()
+                .find(|share| share.group == group)
+                .map(|share| share.opaque),
+            _ => None,
+        })
+    }
+
+    /// The offered PSK identities and their binders, if any.
+    pub fn pre_shared_key(&self) -> Option<PreSharedKeyClientHello<'a, MAX_PSK_IDENTITIES>> {
+        self.find_extension(|e| match e {
+            ClientHelloExtension::PreSharedKey(psk) => Some(psk),
+            _ => None,
+        })
+    }
+
+    /// Whether the client allows PSKs to be used with an (EC)DHE key exchange.
+    pub fn allows_psk_dhe_ke(&self) -> bool {
+        self.find_extension(|e| match e {
+            ClientHelloExtension::PskKeyExchangeModes(modes) => {
+                Some(modes.modes.contains(&PskKeyExchangeMode::PskDheKe))
+            }
+            _ => None,
+        })
+        .unwrap_or(false)
+    }
+
+    /// The application protocols offered by the client (RFC 7301), which is empty if it did not
+    /// send the extension.
+    pub fn alpn_protocols(&self) -> Vec<&'a [u8], MAX_ALPN_PROTOCOLS> {
+        self.find_extension(|e| match e {
+            ClientHelloExtension::ApplicationLayerProtocolNegotiation(list) => Some(list.protocols),
+            _ => None,
+        })
+        .unwrap_or_default()
+    }
+
+    /// The certificate types the client accepts for the server certificate (RFC 7250), or only
+    /// X.509 if it did not send the extension.
+    pub fn server_certificate_types(&self) -> Vec<CertificateType, 2> {
+        self.find_extension(|e| match e {
+            ClientHelloExtension::ServerCertificateType(request) => Some(request.certificate_types),
+            _ => None,
+        })
+        .unwrap_or_else(|| unwrap!(Vec::from_slice(&[CertificateType::X509]).ok()))
+    }
+
+    /// Whether the client sends early data.
+    pub fn early_data(&self) -> bool {
+        self.find_extension(|e| match e {
+            ClientHelloExtension::EarlyData(_) => Some(()),
+            _ => None,
+        })
+        .is_some()
+    }
+}
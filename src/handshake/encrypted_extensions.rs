@@ -1,3 +1,6 @@
+use crate::buffer::CryptoBuffer;
+use crate::extensions::extension_data::certificate_type::CertificateType;
+use crate::extensions::extension_data::max_fragment_length::MaxFragmentLength;
 use crate::extensions::messages::EncryptedExtensionsExtension;
 
 use crate::parse_buffer::ParseBuffer;
@@ -11,7 +14,72 @@ pub struct EncryptedExtensions<'a> {
 }
 
 impl<'a> EncryptedExtensions<'a> {
+    /// Creates the EncryptedExtensions sent by a server.
+    pub fn new(extensions: Vec<EncryptedExtensionsExtension<'a>, 16>) -> Self {
+        Self { extensions }
+    }
+
     pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<EncryptedExtensions<'a>, TlsError> {
         EncryptedExtensionsExtension::parse_vector(buf).map(|extensions| Self { extensions })
     }
+
+    pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
+        buf.with_u16_length(|buf| {
+            for extension in self.extensions.iter() {
+                extension.encode(buf)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// The application protocol selected by the server (RFC 7301).
+    pub fn alpn_protocol(&self) -> Option<&'a [u8]> {
+        self.extensions.iter().find_map(|e| {
+            if let EncryptedExtensionsExtension::ApplicationLayerProtocolNegotiation(list) = e {
+                list.protocols.first().copied()
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The maximum fragment length echoed by the server (RFC 6066, Section 4).
+    pub fn max_fragment_length(&self) -> Option<MaxFragmentLength> {
+        self.extensions.iter().find_map(|e| {
+            if let EncryptedExtensionsExtension::MaxFragmentLength(max_fragment_length) = e {
+                Some(*max_fragment_length)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The certificate type the client has to authenticate with, if selected by the server.
+    pub fn client_certificate_type(&self) -> Option<CertificateType> {
+        self.extensions.iter().find_map(|e| {
+            if let EncryptedExtensionsExtension::ClientCertificateType(response) = e {
+                Some(response.certificate_type)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Whether the server accepted the early data of the client.
+    pub fn early_data(&self) -> bool {
+        self.extensions
+            .iter()
+            .any(|e| matches!(e, EncryptedExtensionsExtension::EarlyData(_)))
+    }
+
+    /// The certificate type the server authenticates with, if selected by the server.
+    pub fn server_certificate_type(&self) -> Option<CertificateType> {
+        self.extensions.iter().find_map(|e| {
+            if let EncryptedExtensionsExtension::ServerCertificateType(response) = e {
+                Some(response.certificate_type)
+            } else {
+                None
+            }
+        })
+    }
 }
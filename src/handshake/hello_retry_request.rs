@@ -0,0 +1,143 @@
+use heapless::Vec;
+
+use crate::buffer::CryptoBuffer;
+use crate::cipher_suites::CipherSuite;
+use crate::extensions::extension_data::key_share::KeyShareHelloRetryRequest;
+use crate::extensions::extension_data::supported_groups::NamedGroup;
+use crate::extensions::extension_data::supported_versions::{SupportedVersionsServerHello, TLS13};
+use crate::extensions::messages::HelloRetryRequestExtension;
+use crate::handshake::{Random, LEGACY_VERSION};
+use crate::parse_buffer::ParseBuffer;
+use crate::TlsError;
+
+/// The `random` of a ServerHello that is actually a HelloRetryRequest, which is the SHA-256 of
+/// "HelloRetryRequest".
+const HELLO_RETRY_REQUEST_RANDOM: Random = [
+    0xCF, 0x21, 0xAD, 0x74, 0xE5, 0x9A, 0x61, 0x11, 0xBE, 0x1D, 0x8C, 0x02, 0x1E, 0x65, 0xB8, 0x91,
+    0xC2, 0xA2, 0x11, 0x16, 0x7A, 0xBB, 0x8C, 0x5E, 0x07, 0x9E, 0x09, 0xE2, 0xC8, 0xA8, 0x33, 0x9C,
+];
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HelloRetryRequest<'a> {
+    legacy_session_id_echo: &'a [u8],
+    cipher_suite: CipherSuite,
+    extensions: Vec<HelloRetryRequestExtension<'a>, 3>,
+    /// The encoded handshake message, including its header.
+    pub(crate) raw: &'a [u8],
+}
+
+impl<'a> HelloRetryRequest<'a> {
+    /// Creates the HelloRetryRequest sent by a server asking for a key share for `group`,
+    /// echoing the legacy session id of the ClientHello.
+    pub fn new(
+        legacy_session_id_echo: &'a [u8],
+        cipher_suite: CipherSuite,
+        group: NamedGroup,
+    ) -> Self {
+        let mut extensions = Vec::new();
+        unwrap!(extensions
+            .push(HelloRetryRequestExtension::SupportedVersions(
+                SupportedVersionsServerHello {
+                    selected_version: TLS13,
+                }
+            ))
+            .ok());
+        unwrap!(extensions
+            .push(HelloRetryRequestExtension::KeyShare(
+                KeyShareHelloRetryRequest {
+                    selected_group: group,
+                }
+            ))
+            .ok());
+        Self {
+            legacy_session_id_echo,
+            cipher_suite,
+            extensions,
+            raw: &[],
+        }
+    }
+
+    /// Returns whether the ServerHello message in `buf` is a HelloRetryRequest, without
+    /// consuming any data.
+    pub fn is_hello_retry_request(buf: &ParseBuffer<'a>) -> bool {
+        let mut buf = ParseBuffer::new(&buf.as_slice()[buf.offset()..]);
+        let mut random = [0; 32];
+        buf.read_u16().is_ok()
+            && buf.fill(&mut random).is_ok()
+            && random == HELLO_RETRY_REQUEST_RANDOM
+    }
+
+    pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<HelloRetryRequest<'a>, TlsError> {
+        let _version = buf.read_u16().map_err(|_| TlsError::InvalidHandshake)?;
+
+        let mut random = [0; 32];
+        buf.fill(&mut random)?;
+
+        let session_id_length = buf
+            .read_u8()
+            .map_err(|_| TlsError::InvalidSessionIdLength)?;
+        let session_id = buf
+            .slice(session_id_length as usize)
+            .map_err(|_| TlsError::InvalidSessionIdLength)?;
+
+        let cipher_suite = CipherSuite::parse(buf).map_err(|_| TlsError::InvalidCipherSuite)?;
+
+        // skip compression method, it's 0.
+        buf.read_u8()?;
+
+        let extensions = HelloRetryRequestExtension::parse_vector(buf)?;
+
+        debug!("retry cipher_suite {:?}", cipher_suite);
+        debug!("retry extensions {:?}", extensions);
+
+        Ok(Self {
+            legacy_session_id_echo: session_id.as_slice(),
+            cipher_suite,
+            extensions,
+            raw: &[],
+        })
+    }
+
+    /// Encodes the HelloRetryRequest, which is a ServerHello with a special random.
+    pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
+        buf.push_u16(LEGACY_VERSION)?;
+        buf.extend_from_slice(&HELLO_RETRY_REQUEST_RANDOM)?;
+        buf.with_u8_length(|buf| buf.extend_from_slice(self.legacy_session_id_echo))?;
+        buf.push_u16(self.cipher_suite as u16)?;
+        // legacy_compression_method
+        buf.push(0)?;
+        buf.with_u16_length(|buf| {
+            for extension in self.extensions.iter() {
+                extension.encode(buf)?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn cipher_suite(&self) -> CipherSuite {
+        self.cipher_suite
+    }
+
+    /// The group the server wants a key share for, if any.
+    pub fn selected_group(&self) -> Option<NamedGroup> {
+        self.extensions.iter().find_map(|e| {
+            if let HelloRetryRequestExtension::KeyShare(key_share) = e {
+                Some(key_share.selected_group)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The cookie to echo in the second ClientHello, if any.
+    pub fn cookie(&self) -> Option<&'a [u8]> {
+        self.extensions.iter().find_map(|e| {
+            if let HelloRetryRequestExtension::Cookie(cookie) = e {
+                Some(cookie.cookie)
+            } else {
+                None
+            }
+        })
+    }
+}
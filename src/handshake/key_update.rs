@@ -0,0 +1,27 @@
+use crate::buffer::CryptoBuffer;
+use crate::parse_buffer::ParseBuffer;
+use crate::TlsError;
+
+/// Signals that the sender is updating its traffic keys (Section 4.6.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KeyUpdate {
+    /// Whether the receiver should respond with its own KeyUpdate.
+    pub update_requested: bool,
+}
+
+impl KeyUpdate {
+    pub fn parse(buf: &mut ParseBuffer) -> Result<Self, TlsError> {
+        let update_requested = match buf.read_u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(TlsError::InvalidHandshake),
+        };
+        Ok(Self { update_requested })
+    }
+
+    pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
+        buf.push(u8::from(self.update_requested))
+            .map_err(|_| TlsError::EncodeError)
+    }
+}
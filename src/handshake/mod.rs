@@ -1,17 +1,19 @@
 //use p256::elliptic_curve::AffinePoint;
+use crate::buffer::*;
 use crate::config::TlsCipherSuite;
 use crate::handshake::certificate::CertificateRef;
 use crate::handshake::certificate_request::CertificateRequestRef;
 use crate::handshake::certificate_verify::CertificateVerify;
-use crate::handshake::client_hello::ClientHello;
+use crate::handshake::client_hello::{ClientHello, ClientHelloRef};
 use crate::handshake::encrypted_extensions::EncryptedExtensions;
 use crate::handshake::finished::Finished;
+use crate::handshake::hello_retry_request::HelloRetryRequest;
+use crate::handshake::key_update::KeyUpdate;
 use crate::handshake::new_session_ticket::NewSessionTicket;
 use crate::handshake::server_hello::ServerHello;
 use crate::key_schedule::HashOutputSize;
 use crate::parse_buffer::{ParseBuffer, ParseError};
 use crate::TlsError;
-use crate::{buffer::*, key_schedule::WriteKeySchedule};
 use core::fmt::{Debug, Formatter};
 use sha2::Digest;
 
@@ -22,6 +24,8 @@ pub mod certificate_verify;
 pub mod client_hello;
 pub mod encrypted_extensions;
 pub mod finished;
+pub mod hello_retry_request;
+pub mod key_update;
 pub mod new_session_ticket;
 pub mod server_hello;
 
@@ -64,14 +68,22 @@ impl HandshakeType {
     }
 }
 
+/// The handshake messages sent by this endpoint, which are client messages for a connection
+/// and server messages for an acceptor.
 #[allow(clippy::large_enum_variant)]
 pub enum ClientHandshake<'config, 'a, CipherSuite>
 where
     CipherSuite: TlsCipherSuite,
 {
-    ClientCert(CertificateRef<'a>),
+    Certificate(CertificateRef<'a>),
+    CertificateVerify(CertificateVerify<'a>),
     ClientHello(ClientHello<'config, CipherSuite>),
+    EncryptedExtensions(EncryptedExtensions<'a>),
+    EndOfEarlyData,
     Finished(Finished<HashOutputSize<CipherSuite>>),
+    HelloRetryRequest(HelloRetryRequest<'a>),
+    KeyUpdate(KeyUpdate),
+    ServerHello(ServerHello<'a>),
 }
 
 impl<'config, 'a, CipherSuite> ClientHandshake<'config, 'a, CipherSuite>
@@ -82,7 +94,13 @@ where
         match self {
             ClientHandshake::ClientHello(_) => HandshakeType::ClientHello,
             ClientHandshake::Finished(_) => HandshakeType::Finished,
-            ClientHandshake::ClientCert(_) => HandshakeType::Certificate,
+            ClientHandshake::Certificate(_) => HandshakeType::Certificate,
+            ClientHandshake::CertificateVerify(_) => HandshakeType::CertificateVerify,
+            ClientHandshake::EncryptedExtensions(_) => HandshakeType::EncryptedExtensions,
+            ClientHandshake::EndOfEarlyData => HandshakeType::EndOfEarlyData,
+            ClientHandshake::HelloRetryRequest(_) => HandshakeType::ServerHello,
+            ClientHandshake::KeyUpdate(_) => HandshakeType::KeyUpdate,
+            ClientHandshake::ServerHello(_) => HandshakeType::ServerHello,
         }
     }
 
@@ -90,7 +108,13 @@ where
         match self {
             ClientHandshake::ClientHello(inner) => inner.encode(buf),
             ClientHandshake::Finished(inner) => inner.encode(buf),
-            ClientHandshake::ClientCert(inner) => inner.encode(buf),
+            ClientHandshake::Certificate(inner) => inner.encode(buf),
+            ClientHandshake::CertificateVerify(inner) => inner.encode(buf),
+            ClientHandshake::EncryptedExtensions(inner) => inner.encode(buf),
+            ClientHandshake::EndOfEarlyData => Ok(()),
+            ClientHandshake::HelloRetryRequest(inner) => inner.encode(buf),
+            ClientHandshake::KeyUpdate(inner) => inner.encode(buf),
+            ClientHandshake::ServerHello(inner) => inner.encode(buf),
         }
     }
 
@@ -105,11 +129,10 @@ where
         &self,
         buf: &mut CryptoBuffer,
         transcript: &mut CipherSuite::Hash,
-        write_key_schedule: &mut WriteKeySchedule<CipherSuite>,
     ) -> Result<(), TlsError> {
         let enc_buf = buf.as_mut_slice();
         if let ClientHandshake::ClientHello(hello) = self {
-            hello.finalize(enc_buf, transcript, write_key_schedule)
+            hello.finalize(enc_buf, transcript)
         } else {
             transcript.update(enc_buf);
             Ok(())
@@ -121,34 +144,41 @@ where
         buf: &mut CryptoBuffer,
         transcript: &mut CipherSuite::Hash,
     ) -> Result<(), TlsError> {
-        let enc_buf = buf.as_slice();
-        let end = enc_buf.len();
-        // Don't include the content type in the slice
-        transcript.update(&enc_buf[0..end - 1]);
+        // The content type is only appended when the record is closed
+        transcript.update(buf.as_slice());
         Ok(())
     }
 }
 
+/// The handshake messages received by this endpoint, which are server messages for a
+/// connection and client messages for an acceptor.
+#[allow(clippy::large_enum_variant)]
 pub enum ServerHandshake<'a, CipherSuite: TlsCipherSuite> {
+    ClientHello(ClientHelloRef<'a>),
     ServerHello(ServerHello<'a>),
+    HelloRetryRequest(HelloRetryRequest<'a>),
     EncryptedExtensions(EncryptedExtensions<'a>),
     NewSessionTicket(NewSessionTicket<'a>),
     Certificate(CertificateRef<'a>),
     CertificateRequest(CertificateRequestRef<'a>),
     CertificateVerify(CertificateVerify<'a>),
     Finished(Finished<HashOutputSize<CipherSuite>>),
+    KeyUpdate(KeyUpdate),
 }
 
 impl<'a, CipherSuite: TlsCipherSuite> ServerHandshake<'a, CipherSuite> {
     pub fn handshake_type(&self) -> HandshakeType {
         match self {
+            ServerHandshake::ClientHello(_) => HandshakeType::ClientHello,
             ServerHandshake::ServerHello(_) => HandshakeType::ServerHello,
+            ServerHandshake::HelloRetryRequest(_) => HandshakeType::ServerHello,
             ServerHandshake::EncryptedExtensions(_) => HandshakeType::EncryptedExtensions,
             ServerHandshake::NewSessionTicket(_) => HandshakeType::NewSessionTicket,
             ServerHandshake::Certificate(_) => HandshakeType::Certificate,
             ServerHandshake::CertificateRequest(_) => HandshakeType::CertificateRequest,
             ServerHandshake::CertificateVerify(_) => HandshakeType::CertificateVerify,
             ServerHandshake::Finished(_) => HandshakeType::Finished,
+            ServerHandshake::KeyUpdate(_) => HandshakeType::KeyUpdate,
         }
     }
 }
@@ -156,12 +186,15 @@ impl<'a, CipherSuite: TlsCipherSuite> ServerHandshake<'a, CipherSuite> {
 impl<'a, CipherSuite: TlsCipherSuite> Debug for ServerHandshake<'a, CipherSuite> {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
+            ServerHandshake::ClientHello(inner) => Debug::fmt(inner, f),
             ServerHandshake::ServerHello(inner) => Debug::fmt(inner, f),
+            ServerHandshake::HelloRetryRequest(inner) => Debug::fmt(inner, f),
             ServerHandshake::EncryptedExtensions(inner) => Debug::fmt(inner, f),
             ServerHandshake::Certificate(inner) => Debug::fmt(inner, f),
             ServerHandshake::CertificateRequest(inner) => Debug::fmt(inner, f),
             ServerHandshake::CertificateVerify(inner) => Debug::fmt(inner, f),
             ServerHandshake::Finished(inner) => Debug::fmt(inner, f),
+            ServerHandshake::KeyUpdate(inner) => Debug::fmt(inner, f),
             ServerHandshake::NewSessionTicket(inner) => Debug::fmt(inner, f),
         }
     }
@@ -171,12 +204,15 @@ impl<'a, CipherSuite: TlsCipherSuite> Debug for ServerHandshake<'a, CipherSuite>
 impl<'a, CipherSuite: TlsCipherSuite> defmt::Format for ServerHandshake<'a, CipherSuite> {
     fn format(&self, f: defmt::Formatter<'_>) {
         match self {
+            ServerHandshake::ClientHello(inner) => defmt::write!(f, "{}", inner),
             ServerHandshake::ServerHello(inner) => defmt::write!(f, "{}", inner),
+            ServerHandshake::HelloRetryRequest(inner) => defmt::write!(f, "{}", inner),
             ServerHandshake::EncryptedExtensions(inner) => defmt::write!(f, "{}", inner),
             ServerHandshake::Certificate(inner) => defmt::write!(f, "{}", inner),
             ServerHandshake::CertificateRequest(inner) => defmt::write!(f, "{}", inner),
             ServerHandshake::CertificateVerify(inner) => defmt::write!(f, "{}", inner),
             ServerHandshake::Finished(inner) => defmt::write!(f, "{}", inner),
+            ServerHandshake::KeyUpdate(inner) => defmt::write!(f, "{}", inner),
             ServerHandshake::NewSessionTicket(inner) => defmt::write!(f, "{}", inner),
         }
     }
@@ -191,8 +227,24 @@ impl<'a, CipherSuite: TlsCipherSuite> ServerHandshake<'a, CipherSuite> {
         let mut handshake = Self::parse(buf)?;
         let handshake_end = buf.offset();
 
-        if let ServerHandshake::Finished(finished) = &mut handshake {
-            finished.hash.replace(digest.clone().finalize());
+        match &mut handshake {
+            ServerHandshake::Finished(finished) => {
+                finished.hash.replace(digest.clone().finalize());
+            }
+            ServerHandshake::ServerHello(server_hello) => {
+                server_hello.raw = &buf.as_slice()[handshake_start..handshake_end];
+            }
+            ServerHandshake::HelloRetryRequest(hello_retry_request) => {
+                hello_retry_request.raw = &buf.as_slice()[handshake_start..handshake_end];
+            }
+            ServerHandshake::ClientHello(client_hello) => {
+                client_hello.raw = &buf.as_slice()[handshake_start..handshake_end];
+                // The ClientHello is only added to the transcript once the server has selected
+                // the cipher suite, which determines the hash, and verified the PSK binders,
+                // which are computed over the transcript without the binders.
+                return Ok(handshake);
+            }
+            _ => {}
         }
 
         digest.update(&buf.as_slice()[handshake_start..handshake_end]);
@@ -208,7 +260,10 @@ impl<'a, CipherSuite: TlsCipherSuite> ServerHandshake<'a, CipherSuite> {
         let content_len = buf.read_u24().map_err(|_| TlsError::InvalidHandshake)?;
 
         let handshake = match handshake_type {
-            //HandshakeType::ClientHello => {}
+            HandshakeType::ClientHello => ServerHandshake::ClientHello(ClientHelloRef::parse(buf)?),
+            HandshakeType::ServerHello if HelloRetryRequest::is_hello_retry_request(buf) => {
+                ServerHandshake::HelloRetryRequest(HelloRetryRequest::parse(buf)?)
+            }
             HandshakeType::ServerHello => ServerHandshake::ServerHello(ServerHello::parse(buf)?),
             HandshakeType::NewSessionTicket => {
                 ServerHandshake::NewSessionTicket(NewSessionTicket::parse(buf)?)
@@ -229,7 +284,7 @@ impl<'a, CipherSuite: TlsCipherSuite> ServerHandshake<'a, CipherSuite> {
             HandshakeType::Finished => {
                 ServerHandshake::Finished(Finished::parse(buf, content_len)?)
             }
-            //HandshakeType::KeyUpdate => {}
+            HandshakeType::KeyUpdate => ServerHandshake::KeyUpdate(KeyUpdate::parse(buf)?),
             //HandshakeType::MessageHash => {}
             t => {
                 warn!("Unimplemented handshake type: {:?}", t);
@@ -11,7 +11,7 @@ pub struct NewSessionTicket<'a> {
     age_add: u32,
     nonce: &'a [u8],
     ticket: &'a [u8],
-    extensions: Vec<NewSessionTicketExtension<'a>, 1>,
+    extensions: Vec<NewSessionTicketExtension, 1>,
 }
 
 impl<'a> NewSessionTicket<'a> {
@@ -39,4 +39,32 @@ impl<'a> NewSessionTicket<'a> {
             extensions,
         })
     }
+
+    pub(crate) fn lifetime(&self) -> u32 {
+        self.lifetime
+    }
+
+    pub(crate) fn age_add(&self) -> u32 {
+        self.age_add
+    }
+
+    pub(crate) fn nonce(&self) -> &'a [u8] {
+        self.nonce
+    }
+
+    pub(crate) fn ticket(&self) -> &'a [u8] {
+        self.ticket
+    }
+
+    /// The most early data the server accepts when resuming with this ticket, 0 if it does not
+    /// accept early data.
+    pub(crate) fn max_early_data_size(&self) -> u32 {
+        self.extensions
+            .iter()
+            .map(|e| match e {
+                NewSessionTicketExtension::EarlyData(early_data) => early_data.max_early_data_size,
+            })
+            .next()
+            .unwrap_or(0)
+    }
 }
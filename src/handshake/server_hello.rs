@@ -1,14 +1,14 @@
 use heapless::Vec;
 
+use crate::alert::{AlertDescription, AlertLevel};
+use crate::buffer::CryptoBuffer;
 use crate::cipher_suites::CipherSuite;
-use crate::crypto_engine::CryptoEngine;
+use crate::crypto_provider::TlsKeyExchange;
 use crate::extensions::extension_data::key_share::KeyShareEntry;
 use crate::extensions::messages::ServerHelloExtension;
-use crate::handshake::Random;
+use crate::handshake::{Random, LEGACY_VERSION};
 use crate::parse_buffer::ParseBuffer;
 use crate::TlsError;
-use p256::ecdh::{EphemeralSecret, SharedSecret};
-use p256::PublicKey;
 
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -17,9 +17,28 @@ pub struct ServerHello<'a> {
     legacy_session_id_echo: &'a [u8],
     cipher_suite: CipherSuite,
     extensions: Vec<ServerHelloExtension<'a>, 4>,
+    /// The encoded handshake message, including its header.
+    pub(crate) raw: &'a [u8],
 }
 
 impl<'a> ServerHello<'a> {
+    /// Creates the ServerHello sent by a server, echoing the legacy session id of the
+    /// ClientHello.
+    pub fn new(
+        random: Random,
+        legacy_session_id_echo: &'a [u8],
+        cipher_suite: CipherSuite,
+        extensions: Vec<ServerHelloExtension<'a>, 4>,
+    ) -> Self {
+        Self {
+            random,
+            legacy_session_id_echo,
+            cipher_suite,
+            extensions,
+            raw: &[],
+        }
+    }
+
     pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<ServerHello<'a>, TlsError> {
         //let mut buf = ParseBuffer::new(&buf[0..content_length]);
         //let mut buf = ParseBuffer::new(&buf);
@@ -58,9 +77,29 @@ impl<'a> ServerHello<'a> {
             legacy_session_id_echo: session_id.as_slice(),
             cipher_suite,
             extensions,
+            raw: &[],
         })
     }
 
+    pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
+        buf.push_u16(LEGACY_VERSION)?;
+        buf.extend_from_slice(&self.random)?;
+        buf.with_u8_length(|buf| buf.extend_from_slice(self.legacy_session_id_echo))?;
+        buf.push_u16(self.cipher_suite as u16)?;
+        // legacy_compression_method
+        buf.push(0)?;
+        buf.with_u16_length(|buf| {
+            for extension in self.extensions.iter() {
+                extension.encode(buf)?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn cipher_suite(&self) -> CipherSuite {
+        self.cipher_suite
+    }
+
     pub fn key_share(&self) -> Option<&KeyShareEntry> {
         self.extensions.iter().find_map(|e| {
             if let ServerHelloExtension::KeyShare(entry) = e {
@@ -71,20 +110,35 @@ impl<'a> ServerHello<'a> {
         })
     }
 
-    pub fn calculate_shared_secret(&self, secret: &EphemeralSecret) -> Option<SharedSecret> {
-        let server_key_share = self.key_share()?;
-        let server_public_key = PublicKey::from_sec1_bytes(server_key_share.opaque).ok()?;
-        Some(secret.diffie_hellman(&server_public_key))
+    /// Returns the index of the PSK identity selected by the server, if it accepted one.
+    pub fn pre_shared_key(&self) -> Option<u16> {
+        self.extensions.iter().find_map(|e| {
+            if let ServerHelloExtension::PreSharedKey(psk) = e {
+                Some(psk.selected_identity)
+            } else {
+                None
+            }
+        })
     }
 
-    pub fn initialize_crypto_engine(&self, secret: EphemeralSecret) -> Option<CryptoEngine> {
-        let server_key_share = self.key_share()?;
-
-        let group = server_key_share.group;
-
-        let server_public_key = PublicKey::from_sec1_bytes(server_key_share.opaque).ok()?;
-        let shared = secret.diffie_hellman(&server_public_key);
-
-        Some(CryptoEngine::new(group, shared))
+    /// Computes the shared secret using whichever of the client's secrets matches the group
+    /// of the server's key share.
+    pub(crate) fn calculate_shared_secret<KeyExchange, I>(
+        &self,
+        secrets: I,
+    ) -> Result<KeyExchange::SharedSecret, TlsError>
+    where
+        KeyExchange: TlsKeyExchange,
+        I: IntoIterator<Item = KeyExchange>,
+    {
+        let server_key_share = self.key_share().ok_or(TlsError::InvalidKeyShare)?;
+        let secret = secrets
+            .into_iter()
+            .find(|s| s.group() == server_key_share.group)
+            .ok_or(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::IllegalParameter,
+            ))?;
+        secret.complete(server_key_share.opaque)
     }
 }
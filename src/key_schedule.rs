@@ -1,9 +1,12 @@
+use crate::cipher_suites::CipherSuite;
+use crate::crypto_provider::CryptoProvider;
 use crate::handshake::binder::PskBinder;
 use crate::handshake::finished::Finished;
 use crate::{config::TlsCipherSuite, TlsError};
 use digest::generic_array::ArrayLength;
 use digest::OutputSizeUser;
-use hmac::{Mac, SimpleHmac};
+use hmac::digest::KeyInit;
+use hmac::Mac;
 use sha2::digest::generic_array::{typenum::Unsigned, GenericArray};
 use sha2::Digest;
 
@@ -15,10 +18,63 @@ pub type IvArray<CipherSuite> = GenericArray<u8, <CipherSuite as TlsCipherSuite>
 pub type KeyArray<CipherSuite> = GenericArray<u8, <CipherSuite as TlsCipherSuite>::KeyLen>;
 pub type HashArray<CipherSuite> = GenericArray<u8, HashOutputSize<CipherSuite>>;
 
-type Hkdf<CipherSuite> = hkdf::Hkdf<
-    <CipherSuite as TlsCipherSuite>::Hash,
-    SimpleHmac<<CipherSuite as TlsCipherSuite>::Hash>,
->;
+/// HKDF (RFC 5869) using the HMAC implementation of the cipher suite.
+struct Hkdf<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    /// HMAC keyed with the pseudorandom key.
+    prk: CipherSuite::Hmac,
+}
+
+impl<CipherSuite> Hkdf<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    fn extract(
+        salt: Option<&[u8]>,
+        ikm: &[u8],
+    ) -> Result<(HashArray<CipherSuite>, Self), TlsError> {
+        let zeros = HashArray::<CipherSuite>::default();
+        let mut hmac = <CipherSuite::Hmac as KeyInit>::new_from_slice(salt.unwrap_or(&zeros))
+            .map_err(|_| TlsError::CryptoError)?;
+        Mac::update(&mut hmac, ikm);
+        let prk = hmac.finalize().into_bytes();
+        let hkdf = Self::from_prk(&prk)?;
+        Ok((prk, hkdf))
+    }
+
+    fn from_prk(prk: &[u8]) -> Result<Self, TlsError> {
+        if prk.len() < HashOutputSize::<CipherSuite>::to_usize() {
+            return Err(TlsError::InternalError);
+        }
+        Ok(Self {
+            prk: <CipherSuite::Hmac as KeyInit>::new_from_slice(prk)
+                .map_err(|_| TlsError::CryptoError)?,
+        })
+    }
+
+    fn expand(&self, info: &[u8], okm: &mut [u8]) -> Result<(), TlsError> {
+        let hash_len = HashOutputSize::<CipherSuite>::to_usize();
+        if okm.len() > 255 * hash_len {
+            return Err(TlsError::CryptoError);
+        }
+
+        let mut previous: Option<HashArray<CipherSuite>> = None;
+        for (i, chunk) in okm.chunks_mut(hash_len).enumerate() {
+            let mut hmac = self.prk.clone();
+            if let Some(previous) = &previous {
+                Mac::update(&mut hmac, previous);
+            }
+            Mac::update(&mut hmac, info);
+            Mac::update(&mut hmac, &[i as u8 + 1]);
+            let block = hmac.finalize().into_bytes();
+            chunk.copy_from_slice(&block[..chunk.len()]);
+            previous = Some(block);
+        }
+        Ok(())
+    }
+}
 
 enum Secret<CipherSuite>
 where
@@ -36,6 +92,10 @@ where
         *self = Self::Initialized(secret);
     }
 
+    fn is_initialized(&self) -> bool {
+        matches!(self, Secret::Initialized(_))
+    }
+
     fn as_ref(&self) -> Result<&Hkdf<CipherSuite>, TlsError> {
         match self {
             Secret::Initialized(ref secret) => Ok(secret),
@@ -47,6 +107,19 @@ where
         &self,
         label: &[u8],
         context_type: ContextType<CipherSuite>,
+    ) -> Result<GenericArray<u8, N>, TlsError> {
+        match context_type {
+            ContextType::None => self.expand_label(label, &[]),
+            ContextType::Hash(context) => self.expand_label(label, &context),
+        }
+    }
+
+    /// HKDF-Expand-Label (Section 7.1) with an arbitrary context, which must not be longer than
+    /// the hash.
+    fn expand_label<N: ArrayLength<u8>>(
+        &self,
+        label: &[u8],
+        context: &[u8],
     ) -> Result<GenericArray<u8, N>, TlsError> {
         //info!("make label {:?} {}", label, len);
         let mut hkdf_label = heapless_typenum::Vec::<u8, LabelBufferSize<CipherSuite>>::new();
@@ -65,19 +138,12 @@ where
             .extend_from_slice(label)
             .map_err(|_| TlsError::InternalError)?;
 
-        match context_type {
-            ContextType::None => {
-                hkdf_label.push(0).map_err(|_| TlsError::InternalError)?;
-            }
-            ContextType::Hash(context) => {
-                hkdf_label
-                    .extend_from_slice(&(context.len() as u8).to_be_bytes())
-                    .map_err(|_| TlsError::InternalError)?;
-                hkdf_label
-                    .extend_from_slice(&context)
-                    .map_err(|_| TlsError::InternalError)?;
-            }
-        }
+        hkdf_label
+            .extend_from_slice(&(context.len() as u8).to_be_bytes())
+            .map_err(|_| TlsError::InternalError)?;
+        hkdf_label
+            .extend_from_slice(context)
+            .map_err(|_| TlsError::InternalError)?;
 
         let mut okm = GenericArray::default();
         //info!("label {:x?}", label);
@@ -108,10 +174,11 @@ where
         }
     }
 
-    fn initialize(&mut self, ikm: &[u8]) {
-        let (secret, hkdf) = Hkdf::<CipherSuite>::extract(Some(self.secret.as_ref()), ikm);
+    fn initialize(&mut self, ikm: &[u8]) -> Result<(), TlsError> {
+        let (secret, hkdf) = Hkdf::<CipherSuite>::extract(Some(self.secret.as_ref()), ikm)?;
         self.hkdf.replace(hkdf);
         self.secret = secret;
+        Ok(())
     }
 
     fn derive_secret(
@@ -178,8 +245,24 @@ where
         Ok(())
     }
 
-    pub fn increment_counter(&mut self) {
-        self.counter = unwrap!(self.counter.checked_add(1));
+    /// Replaces the traffic secret with the next generation after a KeyUpdate (Section 7.2).
+    fn update_traffic_secret(&mut self) -> Result<(), TlsError> {
+        let secret: HashArray<CipherSuite> =
+            self.traffic_secret.expand_label(b"traffic upd", &[])?;
+        let traffic_secret =
+            Hkdf::<CipherSuite>::from_prk(&secret).map_err(|_| TlsError::InternalError)?;
+
+        self.traffic_secret.replace(traffic_secret);
+        self.counter = 0;
+        Ok(())
+    }
+
+    pub fn increment_counter(&mut self) -> Result<(), TlsError> {
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or(TlsError::SequenceNumberExhausted)?;
+        Ok(())
     }
 }
 
@@ -215,6 +298,13 @@ where
     shared: SharedState<CipherSuite>,
     client_state: WriteKeySchedule<CipherSuite>,
     server_state: ReadKeySchedule<CipherSuite>,
+    /// The client early traffic secret, while it is not used by `client_state`.
+    early_state: WriteKeySchedule<CipherSuite>,
+    /// The transcript up to and including the server Finished message.
+    traffic_hash: Option<CipherSuite::Hash>,
+    /// Whether the handshake is performed as the server, which writes with the server traffic
+    /// secrets and reads with the client ones.
+    server: bool,
 }
 
 impl<CipherSuite> KeySchedule<CipherSuite>
@@ -226,12 +316,27 @@ where
             shared: SharedState::new(),
             client_state: WriteKeySchedule {
                 state: KeyScheduleState::new(),
-                binder_key: Secret::Uninitialized,
             },
             server_state: ReadKeySchedule {
                 state: KeyScheduleState::new(),
                 transcript_hash: <CipherSuite::Hash as Digest>::new(),
+                resumption_secret: Secret::Uninitialized,
+                handshake_hash: None,
+                certificate_request_hash: None,
+            },
+            early_state: WriteKeySchedule {
+                state: KeyScheduleState::new(),
             },
+            traffic_hash: None,
+            server: false,
+        }
+    }
+
+    /// Creates the key schedule of a server.
+    pub fn new_server() -> Self {
+        Self {
+            server: true,
+            ..Self::new()
         }
     }
 
@@ -243,6 +348,16 @@ where
         self.server_state.transcript_hash = hash;
     }
 
+    /// Remembers the current transcript, which the application traffic secrets are derived from.
+    pub(crate) fn save_traffic_hash(&mut self) {
+        self.traffic_hash
+            .replace(self.server_state.transcript_hash.clone());
+    }
+
+    pub(crate) fn take_traffic_hash(&mut self) -> Option<CipherSuite::Hash> {
+        self.traffic_hash.take()
+    }
+
     pub fn as_split(
         &mut self,
     ) -> (
@@ -264,6 +379,9 @@ where
 
     /// Re-creates a KeySchedule from its split parts. Make sure to only pass in
     /// parts coming from the same original KeySchedule.
+    ///
+    /// The role is only needed to derive the traffic secrets during the handshake, so it is not
+    /// kept.
     pub fn unsplit(
         shared: SharedState<CipherSuite>,
         write: WriteKeySchedule<CipherSuite>,
@@ -273,6 +391,11 @@ where
             shared,
             client_state: write,
             server_state: read,
+            early_state: WriteKeySchedule {
+                state: KeyScheduleState::new(),
+            },
+            traffic_hash: None,
+            server: false,
         }
     }
 
@@ -284,6 +407,8 @@ where
         &mut self.server_state
     }
 
+    /// Creates the Finished message sent by this endpoint, which is the server Finished for the
+    /// key schedule of a server.
     pub fn create_client_finished(
         &self,
     ) -> Result<Finished<HashOutputSize<CipherSuite>>, TlsError> {
@@ -296,7 +421,7 @@ where
                 ContextType::None,
             )?;
 
-        let mut hmac = SimpleHmac::<CipherSuite::Hash>::new_from_slice(&key)
+        let mut hmac = <CipherSuite::Hmac as KeyInit>::new_from_slice(&key)
             .map_err(|_| TlsError::CryptoError)?;
         Mac::update(
             &mut hmac,
@@ -348,31 +473,93 @@ where
         GenericArray::default()
     }
 
-    // Initializes the early secrets with a callback for any PSK binders
+    /// Initializes the early secret with the PSK selected by the server, replacing any early
+    /// secret derived before.
     pub fn initialize_early_secret(&mut self, psk: Option<&[u8]>) -> Result<(), TlsError> {
+        self.shared = SharedState::new();
         self.shared.initialize(
             #[allow(clippy::or_fun_call)]
             psk.unwrap_or(Self::zero().as_slice()),
-        );
+        )?;
+        self.shared.derived()
+    }
 
-        let binder_key = self
-            .shared
-            .derive_secret(b"ext binder", ContextType::empty_hash())?;
-        self.client_state.binder_key.replace(
+    /// Derives the client early traffic secret from the early secret of the first offered PSK
+    /// and the ClientHello.
+    pub fn initialize_early_traffic_secret(&mut self) -> Result<(), TlsError> {
+        self.early_state.state.calculate_traffic_secret(
+            b"c e traffic",
+            &mut self.shared,
+            &self.server_state.transcript_hash,
+        )
+    }
+
+    /// Switches the records written by the client between the early traffic secret, used for
+    /// early data and the EndOfEarlyData message, and its current traffic secret.
+    pub(crate) fn swap_early_state(&mut self) {
+        core::mem::swap(&mut self.client_state, &mut self.early_state);
+    }
+
+    /// Computes the binder of a PSK offered in the ClientHello, with the transcript up to the
+    /// binders (Section 4.2.11.2). Resumption PSKs use a different binder key than external
+    /// PSKs.
+    pub fn create_psk_binder(
+        psk: &[u8],
+        resumption: bool,
+        transcript_hash: &CipherSuite::Hash,
+    ) -> Result<PskBinder<HashOutputSize<CipherSuite>>, TlsError> {
+        let hmac = Self::psk_binder_hmac(psk, resumption, transcript_hash)?;
+        let verify = hmac.finalize().into_bytes();
+        Ok(PskBinder { verify })
+    }
+
+    /// Verifies the binder of a PSK offered to a server, see [`Self::create_psk_binder`].
+    pub fn verify_psk_binder(
+        psk: &[u8],
+        resumption: bool,
+        transcript_hash: &CipherSuite::Hash,
+        binder: &[u8],
+    ) -> Result<bool, TlsError> {
+        let hmac = Self::psk_binder_hmac(psk, resumption, transcript_hash)?;
+        Ok(hmac.verify_slice(binder).is_ok())
+    }
+
+    fn psk_binder_hmac(
+        psk: &[u8],
+        resumption: bool,
+        transcript_hash: &CipherSuite::Hash,
+    ) -> Result<CipherSuite::Hmac, TlsError> {
+        let mut early_secret = SharedState::<CipherSuite>::new();
+        early_secret.initialize(psk)?;
+        let label: &[u8] = if resumption {
+            b"res binder"
+        } else {
+            b"ext binder"
+        };
+        let binder_key = early_secret.derive_secret(label, ContextType::empty_hash())?;
+        let binder_key = Secret::Initialized(
             Hkdf::<CipherSuite>::from_prk(&binder_key).map_err(|_| TlsError::InternalError)?,
         );
-        self.shared.derived()
+        let key = binder_key.make_expanded_hkdf_label::<HashOutputSize<CipherSuite>>(
+            b"finished",
+            ContextType::None,
+        )?;
+
+        let mut hmac = <CipherSuite::Hmac as KeyInit>::new_from_slice(&key)
+            .map_err(|_| TlsError::CryptoError)?;
+        Mac::update(&mut hmac, &transcript_hash.clone().finalize());
+        Ok(hmac)
     }
 
     pub fn initialize_handshake_secret(&mut self, ikm: &[u8]) -> Result<(), TlsError> {
-        self.shared.initialize(ikm);
+        self.shared.initialize(ikm)?;
 
         self.calculate_traffic_secrets(b"c hs traffic", b"s hs traffic")?;
         self.shared.derived()
     }
 
     pub fn initialize_master_secret(&mut self) -> Result<(), TlsError> {
-        self.shared.initialize(Self::zero().as_slice());
+        self.shared.initialize(Self::zero().as_slice())?;
 
         //let context = self.transcript_hash.as_ref().unwrap().clone().finalize();
         //info!("Derive keys, hash: {:x?}", context);
@@ -381,19 +568,41 @@ where
         self.shared.derived()
     }
 
+    /// Derives the resumption master secret, which the PSKs of session tickets are derived
+    /// from, with the transcript up to and including the client Finished message. Must be called
+    /// after [`Self::initialize_master_secret`].
+    pub fn initialize_resumption_secret(
+        &mut self,
+        transcript_hash: &CipherSuite::Hash,
+    ) -> Result<(), TlsError> {
+        let secret = self
+            .shared
+            .derive_secret(b"res master", ContextType::transcript_hash(transcript_hash))?;
+        self.server_state
+            .resumption_secret
+            .replace(Hkdf::<CipherSuite>::from_prk(&secret).map_err(|_| TlsError::InternalError)?);
+        Ok(())
+    }
+
     fn calculate_traffic_secrets(
         &mut self,
         client_label: &[u8],
         server_label: &[u8],
     ) -> Result<(), TlsError> {
+        let (write_label, read_label) = if self.server {
+            (server_label, client_label)
+        } else {
+            (client_label, server_label)
+        };
+
         self.client_state.state.calculate_traffic_secret(
-            client_label,
+            write_label,
             &mut self.shared,
             &self.server_state.transcript_hash,
         )?;
 
         self.server_state.state.calculate_traffic_secret(
-            server_label,
+            read_label,
             &mut self.shared,
             &self.server_state.transcript_hash,
         )?;
@@ -416,16 +625,30 @@ where
     CipherSuite: TlsCipherSuite,
 {
     state: KeyScheduleState<CipherSuite>,
-    binder_key: Secret<CipherSuite>,
 }
 impl<CipherSuite> WriteKeySchedule<CipherSuite>
 where
     CipherSuite: TlsCipherSuite,
 {
-    pub(crate) fn increment_counter(&mut self) {
+    pub(crate) fn increment_counter(&mut self) -> Result<(), TlsError> {
         self.state.increment_counter()
     }
 
+    /// The number of records sent with the current traffic secret.
+    pub(crate) fn sequence_number(&self) -> u64 {
+        self.state.counter
+    }
+
+    /// Switches to the next application traffic secret, after sending a KeyUpdate.
+    pub(crate) fn update_traffic_secret(&mut self) -> Result<(), TlsError> {
+        self.state.update_traffic_secret()
+    }
+
+    /// Whether records are protected, i.e. the handshake traffic secret has been derived.
+    pub(crate) fn is_encrypting(&self) -> bool {
+        self.state.traffic_secret.is_initialized()
+    }
+
     pub(crate) fn get_key(&self) -> Result<KeyArray<CipherSuite>, TlsError> {
         self.state.get_key()
     }
@@ -433,24 +656,6 @@ where
     pub(crate) fn get_nonce(&self) -> Result<IvArray<CipherSuite>, TlsError> {
         self.state.get_nonce()
     }
-
-    pub fn create_psk_binder(
-        &self,
-        transcript_hash: &CipherSuite::Hash,
-    ) -> Result<PskBinder<HashOutputSize<CipherSuite>>, TlsError> {
-        let key = self
-            .binder_key
-            .make_expanded_hkdf_label::<HashOutputSize<CipherSuite>>(
-                b"finished",
-                ContextType::None,
-            )?;
-
-        let mut hmac = SimpleHmac::<CipherSuite::Hash>::new_from_slice(&key)
-            .map_err(|_| TlsError::CryptoError)?;
-        Mac::update(&mut hmac, &transcript_hash.clone().finalize());
-        let verify = hmac.finalize().into_bytes();
-        Ok(PskBinder { verify })
-    }
 }
 
 pub struct ReadKeySchedule<CipherSuite>
@@ -459,13 +664,19 @@ where
 {
     state: KeyScheduleState<CipherSuite>,
     transcript_hash: CipherSuite::Hash,
+    resumption_secret: Secret<CipherSuite>,
+    /// The transcript up to and including the client Finished message, once the handshake is
+    /// complete.
+    handshake_hash: Option<CipherSuite::Hash>,
+    /// The transcript up to and including a post-handshake CertificateRequest to be answered.
+    certificate_request_hash: Option<CipherSuite::Hash>,
 }
 
 impl<CipherSuite> ReadKeySchedule<CipherSuite>
 where
     CipherSuite: TlsCipherSuite,
 {
-    pub(crate) fn increment_counter(&mut self) {
+    pub(crate) fn increment_counter(&mut self) -> Result<(), TlsError> {
         self.state.increment_counter()
     }
 
@@ -473,6 +684,45 @@ where
         &mut self.transcript_hash
     }
 
+    /// Completes the transcript of the handshake with the client Finished message.
+    pub(crate) fn finish_handshake(&mut self, transcript_hash: CipherSuite::Hash) {
+        self.handshake_hash.replace(transcript_hash);
+    }
+
+    /// The transcript to add the next received handshake message to. After the handshake, each
+    /// message continues the transcript of the handshake on its own (Section 4.4.1).
+    pub(crate) fn message_transcript_hash(&mut self) -> &mut CipherSuite::Hash {
+        if let Some(handshake_hash) = &self.handshake_hash {
+            self.transcript_hash = handshake_hash.clone();
+        }
+        &mut self.transcript_hash
+    }
+
+    /// Keeps the transcript of the post-handshake CertificateRequest just received, until it is
+    /// answered. Only one request can be pending at a time.
+    pub(crate) fn save_certificate_request_hash(&mut self) -> Result<(), TlsError> {
+        if self.certificate_request_hash.is_some() {
+            warn!("Only one post-handshake CertificateRequest can be answered at a time");
+            return Err(TlsError::Unimplemented);
+        }
+        self.certificate_request_hash = Some(self.transcript_hash.clone());
+        Ok(())
+    }
+
+    pub(crate) fn take_certificate_request_hash(&mut self) -> Option<CipherSuite::Hash> {
+        self.certificate_request_hash.take()
+    }
+
+    /// Switches to the next application traffic secret, after receiving a KeyUpdate.
+    pub(crate) fn update_traffic_secret(&mut self) -> Result<(), TlsError> {
+        self.state.update_traffic_secret()
+    }
+
+    /// Derives the PSK of a session ticket from its nonce (Section 4.6.1).
+    pub(crate) fn ticket_psk(&self, nonce: &[u8]) -> Result<HashArray<CipherSuite>, TlsError> {
+        self.resumption_secret.expand_label(b"resumption", nonce)
+    }
+
     pub(crate) fn get_key(&self) -> Result<KeyArray<CipherSuite>, TlsError> {
         self.state.get_key()
     }
@@ -481,6 +731,8 @@ where
         self.state.get_nonce()
     }
 
+    /// Verifies the Finished message received from the peer, which is the client Finished for
+    /// the key schedule of a server.
     pub fn verify_server_finished(
         &self,
         finished: &Finished<HashOutputSize<CipherSuite>>,
@@ -496,7 +748,7 @@ where
                 ContextType::None,
             )?;
         // info!("hmac sign key {:x?}", key);
-        let mut hmac = SimpleHmac::<CipherSuite::Hash>::new_from_slice(&key)
+        let mut hmac = <CipherSuite::Hmac as KeyInit>::new_from_slice(&key)
             .map_err(|_| TlsError::InternalError)?;
         Mac::update(
             &mut hmac,
@@ -511,3 +763,167 @@ where
         //unimplemented!()
     }
 }
+
+/// Declares an enum with one variant per built-in cipher suite, each wrapping `$inner<Suite>`
+/// with the suite implementation of the crypto provider.
+macro_rules! cipher_suite_enum {
+    ($(#[$meta:meta])* $name:ident($inner:ident)) => {
+        $(#[$meta])*
+        #[allow(clippy::large_enum_variant)]
+        pub(crate) enum $name<Provider>
+        where
+            Provider: CryptoProvider,
+        {
+            Aes128GcmSha256($inner<Provider::Aes128GcmSha256>),
+            Aes256GcmSha384($inner<Provider::Aes256GcmSha384>),
+            Chacha20Poly1305Sha256($inner<Provider::Chacha20Poly1305Sha256>),
+            Aes128CcmSha256($inner<Provider::Aes128CcmSha256>),
+            Aes128Ccm8Sha256($inner<Provider::Aes128Ccm8Sha256>),
+        }
+    };
+}
+
+/// Runs `$body` with `$name` bound to the key schedule of whichever cipher suite `$value` holds.
+macro_rules! dispatch {
+    ($enum:ident, $value:expr, $name:ident => $body:expr) => {
+        match $value {
+            $enum::Aes128GcmSha256($name) => $body,
+            $enum::Aes256GcmSha384($name) => $body,
+            $enum::Chacha20Poly1305Sha256($name) => $body,
+            $enum::Aes128CcmSha256($name) => $body,
+            $enum::Aes128Ccm8Sha256($name) => $body,
+        }
+    };
+}
+pub(crate) use dispatch;
+
+cipher_suite_enum!(
+    /// Key schedule for the cipher suite selected at runtime.
+    AnyKeySchedule(KeySchedule)
+);
+cipher_suite_enum!(AnySharedState(SharedState));
+cipher_suite_enum!(AnyWriteKeySchedule(WriteKeySchedule));
+cipher_suite_enum!(AnyReadKeySchedule(ReadKeySchedule));
+
+impl<Provider> AnyKeySchedule<Provider>
+where
+    Provider: CryptoProvider,
+{
+    /// Creates an empty key schedule for `cipher_suite`, or `None` if the suite is not supported.
+    pub fn new(cipher_suite: CipherSuite) -> Option<Self> {
+        Some(match cipher_suite {
+            CipherSuite::TlsAes128GcmSha256 => Self::Aes128GcmSha256(KeySchedule::new()),
+            CipherSuite::TlsAes256GcmSha384 => Self::Aes256GcmSha384(KeySchedule::new()),
+            CipherSuite::TlsChacha20Poly1305Sha256 => {
+                Self::Chacha20Poly1305Sha256(KeySchedule::new())
+            }
+            CipherSuite::TlsAes128CcmSha256 => Self::Aes128CcmSha256(KeySchedule::new()),
+            CipherSuite::TlsAes128Ccm8Sha256 => Self::Aes128Ccm8Sha256(KeySchedule::new()),
+            CipherSuite::TlsPskAes128GcmSha256 => return None,
+        })
+    }
+
+    /// Creates an empty key schedule of a server for `cipher_suite`, or `None` if the suite is
+    /// not supported.
+    pub fn new_server(cipher_suite: CipherSuite) -> Option<Self> {
+        let mut key_schedule = Self::new(cipher_suite)?;
+        dispatch!(AnyKeySchedule, &mut key_schedule, key_schedule => key_schedule.server = true);
+        Some(key_schedule)
+    }
+
+    pub fn cipher_suite(&self) -> CipherSuite {
+        match self {
+            Self::Aes128GcmSha256(_) => CipherSuite::TlsAes128GcmSha256,
+            Self::Aes256GcmSha384(_) => CipherSuite::TlsAes256GcmSha384,
+            Self::Chacha20Poly1305Sha256(_) => CipherSuite::TlsChacha20Poly1305Sha256,
+            Self::Aes128CcmSha256(_) => CipherSuite::TlsAes128CcmSha256,
+            Self::Aes128Ccm8Sha256(_) => CipherSuite::TlsAes128Ccm8Sha256,
+        }
+    }
+
+    pub fn split(
+        self,
+    ) -> (
+        AnySharedState<Provider>,
+        AnyWriteKeySchedule<Provider>,
+        AnyReadKeySchedule<Provider>,
+    ) {
+        macro_rules! split {
+            ($variant:ident, $ks:expr) => {{
+                let (shared, write, read) = $ks.split();
+                (
+                    AnySharedState::$variant(shared),
+                    AnyWriteKeySchedule::$variant(write),
+                    AnyReadKeySchedule::$variant(read),
+                )
+            }};
+        }
+
+        match self {
+            Self::Aes128GcmSha256(ks) => split!(Aes128GcmSha256, ks),
+            Self::Aes256GcmSha384(ks) => split!(Aes256GcmSha384, ks),
+            Self::Chacha20Poly1305Sha256(ks) => split!(Chacha20Poly1305Sha256, ks),
+            Self::Aes128CcmSha256(ks) => split!(Aes128CcmSha256, ks),
+            Self::Aes128Ccm8Sha256(ks) => split!(Aes128Ccm8Sha256, ks),
+        }
+    }
+
+    /// Re-creates a key schedule from its split parts. Make sure to only pass in
+    /// parts coming from the same original key schedule.
+    pub fn unsplit(
+        shared: AnySharedState<Provider>,
+        write: AnyWriteKeySchedule<Provider>,
+        read: AnyReadKeySchedule<Provider>,
+    ) -> Self {
+        use AnyReadKeySchedule as R;
+        use AnySharedState as S;
+        use AnyWriteKeySchedule as W;
+
+        match (shared, write, read) {
+            (S::Aes128GcmSha256(s), W::Aes128GcmSha256(w), R::Aes128GcmSha256(r)) => {
+                Self::Aes128GcmSha256(KeySchedule::unsplit(s, w, r))
+            }
+            (S::Aes256GcmSha384(s), W::Aes256GcmSha384(w), R::Aes256GcmSha384(r)) => {
+                Self::Aes256GcmSha384(KeySchedule::unsplit(s, w, r))
+            }
+            (
+                S::Chacha20Poly1305Sha256(s),
+                W::Chacha20Poly1305Sha256(w),
+                R::Chacha20Poly1305Sha256(r),
+            ) => Self::Chacha20Poly1305Sha256(KeySchedule::unsplit(s, w, r)),
+            (S::Aes128CcmSha256(s), W::Aes128CcmSha256(w), R::Aes128CcmSha256(r)) => {
+                Self::Aes128CcmSha256(KeySchedule::unsplit(s, w, r))
+            }
+            (S::Aes128Ccm8Sha256(s), W::Aes128Ccm8Sha256(w), R::Aes128Ccm8Sha256(r)) => {
+                Self::Aes128Ccm8Sha256(KeySchedule::unsplit(s, w, r))
+            }
+            _ => panic!("key schedule parts belong to different cipher suites"),
+        }
+    }
+}
+
+impl<Provider> Default for AnyKeySchedule<Provider>
+where
+    Provider: CryptoProvider,
+{
+    fn default() -> Self {
+        Self::Aes128GcmSha256(KeySchedule::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Aes128GcmSha256;
+
+    #[test]
+    fn test_sequence_number_exhausted() {
+        let mut state = KeyScheduleState::<Aes128GcmSha256>::new();
+        state.counter = u64::MAX - 1;
+        assert!(state.increment_counter().is_ok());
+        assert!(matches!(
+            state.increment_counter(),
+            Err(TlsError::SequenceNumberExhausted)
+        ));
+    }
+}
@@ -20,7 +20,7 @@ async fn main() {
     let mut write_record_buffer = [0; 16384];
     let config = TlsConfig::new()
         .with_server_name("http.sandbox.drogue.cloud");
-    let mut tls: TlsConnection<FromTokio<TcpStream>, Aes128GcmSha256> =
+    let mut tls: TlsConnection<FromTokio<TcpStream>> =
         TlsConnection::new(FromTokio::new(stream), &mut read_record_buffer, &mut write_record_buffer);
 
     // Allows disabling cert verification, in case you are using PSK and don't need it, or are just testing.
@@ -36,6 +36,7 @@ async fn main() {
 pub(crate) mod fmt;
 
 use parse_buffer::ParseError;
+mod acceptor;
 pub mod alert;
 mod application_data;
 pub mod blocking;
@@ -46,15 +47,22 @@ mod common;
 mod config;
 mod connection;
 mod content_types;
-mod crypto_engine;
+mod crypto_provider;
+mod der;
 mod extensions;
 mod handshake;
 mod key_schedule;
+#[cfg(feature = "webpki")]
+mod ocsp;
 mod parse_buffer;
+mod peer_certificates;
+pub mod pin;
 pub mod read_buffer;
 mod record;
 mod record_reader;
+pub mod rpk;
 mod split;
+mod ticket;
 mod write_buffer;
 
 #[cfg(feature = "webpki")]
@@ -90,7 +98,11 @@ pub enum TlsError {
     InvalidKeyShare,
     InvalidCertificate,
     InvalidCertificateEntry,
+    CertificateChainTooLong,
+    CertificateRevoked,
+    SequenceNumberExhausted,
     InvalidCertificateRequest,
+    InvalidPrivateKey,
     UnableToInitializeCryptoEngine,
     ParseError(ParseError),
     OutOfMemory,
@@ -114,11 +126,11 @@ impl embedded_io::Error for TlsError {
 
 #[cfg(feature = "std")]
 mod stdlib {
-    use crate::config::TlsClock;
+    use crate::config::{SystemClock, TlsClock};
 
     use std::time::SystemTime;
-    impl TlsClock for SystemTime {
-        fn now() -> Option<u64> {
+    impl TlsClock for SystemClock {
+        fn now(&self) -> Option<u64> {
             Some(
                 SystemTime::now()
                     .duration_since(SystemTime::UNIX_EPOCH)
@@ -127,4 +139,12 @@ mod stdlib {
             )
         }
     }
+
+    /// Kept for compatibility: reads the current system time like [`SystemClock`], not the time
+    /// held by the value.
+    impl TlsClock for SystemTime {
+        fn now(&self) -> Option<u64> {
+            SystemClock.now()
+        }
+    }
 }
@@ -0,0 +1,393 @@
+//! Checks the revocation status of the server certificate with the OCSP response stapled by
+//! the server (RFC 6960).
+
+use crate::der::{self, X509Certificate};
+use crate::parse_buffer::{ParseBuffer, ParseError};
+use crate::TlsError;
+use ring::{digest, signature};
+
+/// id-pkix-ocsp-basic, 1.3.6.1.5.5.7.48.1.1
+const ID_PKIX_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+/// ecdsa-with-SHA256, 1.2.840.10045.4.3.2
+const ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+/// ecdsa-with-SHA384, 1.2.840.10045.4.3.3
+const ECDSA_WITH_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+/// id-Ed25519, 1.3.101.112
+const ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+/// sha256WithRSAEncryption, 1.2.840.113549.1.1.11
+const SHA256_WITH_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+/// sha384WithRSAEncryption, 1.2.840.113549.1.1.12
+const SHA384_WITH_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+/// sha512WithRSAEncryption, 1.2.840.113549.1.1.13
+const SHA512_WITH_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+/// id-sha1, 1.3.14.3.2.26
+const ID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+/// id-sha256, 2.16.840.1.101.3.4.2.1
+const ID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+/// id-sha384, 2.16.840.1.101.3.4.2.2
+const ID_SHA384: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02];
+/// id-sha512, 2.16.840.1.101.3.4.2.3
+const ID_SHA512: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03];
+/// id-pe-tlsfeature, 1.3.6.1.5.5.7.1.24
+const ID_PE_TLSFEATURE: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x18];
+/// The status_request TLS feature
+const STATUS_REQUEST: &[u8] = &[5];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+struct BasicResponse<'a> {
+    response_data: &'a [u8],
+    signature_algorithm: &'a [u8],
+    signature: &'a [u8],
+}
+
+struct SingleResponse {
+    status: CertStatus,
+    this_update: u64,
+    next_update: Option<u64>,
+}
+
+/// Checks the OCSP response stapled to `leaf`, which must be signed by its issuer.
+///
+/// `issuer_public_key` is the subjectPublicKey of the issuer. Without a response, or with a
+/// response that does not know the certificate, the certificate is only rejected if it
+/// requires a stapled response (RFC 7633).
+pub(crate) fn check_revocation_status(
+    leaf: &X509Certificate,
+    issuer_public_key: &[u8],
+    ocsp_response: Option<&[u8]>,
+    now: u64,
+) -> Result<(), TlsError> {
+    let status = match ocsp_response {
+        Some(response) => parse_response(response, leaf, issuer_public_key, now)?,
+        None => CertStatus::Unknown,
+    };
+
+    match status {
+        CertStatus::Good => Ok(()),
+        CertStatus::Revoked => {
+            warn!("The server certificate is revoked");
+            Err(TlsError::CertificateRevoked)
+        }
+        CertStatus::Unknown => {
+            let must_staple = requires_status_request(leaf).map_err(|_| {
+                warn!("Error parsing the TLS feature extension");
+                TlsError::InvalidCertificate
+            })?;
+            if must_staple {
+                warn!("The server certificate requires a stapled OCSP response");
+                Err(TlsError::InvalidCertificate)
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Whether the certificate has the TLS feature extension with status_request, also known as
+/// OCSP must-staple.
+///
+/// ```text
+/// Features ::= SEQUENCE OF INTEGER
+/// ```
+fn requires_status_request(leaf: &X509Certificate) -> Result<bool, ParseError> {
+    let Some(mut features) = leaf.extension(ID_PE_TLSFEATURE)? else {
+        return Ok(false);
+    };
+    let mut features = der::read_element(&mut features, der::SEQUENCE)?;
+    while !features.is_empty() {
+        if der::read_element(&mut features, der::INTEGER)?.as_slice() == STATUS_REQUEST {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn parse_response(
+    response: &[u8],
+    leaf: &X509Certificate,
+    issuer_public_key: &[u8],
+    now: u64,
+) -> Result<CertStatus, TlsError> {
+    let response = parse_basic_response(response).map_err(|e| {
+        warn!("Error parsing the OCSP response: {:?}", e);
+        TlsError::InvalidCertificate
+    })?;
+
+    // Responses signed by a delegated responder are not supported. A response that cannot be
+    // verified does not tell anything about the certificate.
+    let algorithms = signature_algorithms(response.signature_algorithm);
+    if algorithms.is_empty() {
+        warn!("Unsupported OCSP response signature algorithm");
+        return Ok(CertStatus::Unknown);
+    }
+    let verified = algorithms.iter().any(|algorithm| {
+        signature::UnparsedPublicKey::new(*algorithm, issuer_public_key)
+            .verify(response.response_data, response.signature)
+            .is_ok()
+    });
+    if !verified {
+        warn!("The OCSP response is not signed by the issuer of the certificate");
+        return Err(TlsError::InvalidCertificate);
+    }
+
+    let response = parse_single_response(response.response_data, leaf, issuer_public_key)
+        .map_err(|e| {
+            warn!("Error parsing the OCSP response data: {:?}", e);
+            TlsError::InvalidCertificate
+        })?
+        .ok_or_else(|| {
+            warn!("The OCSP response is not for the server certificate");
+            TlsError::InvalidCertificate
+        })?;
+
+    let expired = response
+        .next_update
+        .is_some_and(|next_update| next_update < now);
+    if response.this_update > now || expired {
+        warn!("The OCSP response is not current");
+        return Err(TlsError::InvalidCertificate);
+    }
+    Ok(response.status)
+}
+
+type Algorithms = &'static [&'static dyn signature::VerificationAlgorithm];
+
+static ECDSA_SHA256: Algorithms = &[
+    &signature::ECDSA_P256_SHA256_ASN1,
+    &signature::ECDSA_P384_SHA256_ASN1,
+];
+static ECDSA_SHA384: Algorithms = &[
+    &signature::ECDSA_P256_SHA384_ASN1,
+    &signature::ECDSA_P384_SHA384_ASN1,
+];
+static EDDSA: Algorithms = &[&signature::ED25519];
+#[cfg(feature = "alloc")]
+static RSA_SHA256: Algorithms = &[&signature::RSA_PKCS1_2048_8192_SHA256];
+#[cfg(feature = "alloc")]
+static RSA_SHA384: Algorithms = &[&signature::RSA_PKCS1_2048_8192_SHA384];
+#[cfg(feature = "alloc")]
+static RSA_SHA512: Algorithms = &[&signature::RSA_PKCS1_2048_8192_SHA512];
+
+/// The algorithms verifying a signature with the given algorithm identifier, the same as the
+/// ones `webpki::CertVerifier` verifies certificates with. ECDSA signatures may be made with a
+/// P-256 or P-384 key, and RSA signatures are only supported with the `alloc` feature.
+fn signature_algorithms(oid: &[u8]) -> Algorithms {
+    match oid {
+        ECDSA_WITH_SHA256 => ECDSA_SHA256,
+        ECDSA_WITH_SHA384 => ECDSA_SHA384,
+        ED25519 => EDDSA,
+        #[cfg(feature = "alloc")]
+        SHA256_WITH_RSA_ENCRYPTION => RSA_SHA256,
+        #[cfg(feature = "alloc")]
+        SHA384_WITH_RSA_ENCRYPTION => RSA_SHA384,
+        #[cfg(feature = "alloc")]
+        SHA512_WITH_RSA_ENCRYPTION => RSA_SHA512,
+        _ => &[],
+    }
+}
+
+/// Parses a successful OCSP response, returning the encoded response data, the signature
+/// algorithm and the signature.
+///
+/// ```text
+/// OCSPResponse ::= SEQUENCE {
+///     responseStatus OCSPResponseStatus,
+///     responseBytes [0] EXPLICIT ResponseBytes OPTIONAL
+/// }
+///
+/// ResponseBytes ::= SEQUENCE {
+///     responseType OBJECT IDENTIFIER,
+///     response OCTET STRING
+/// }
+///
+/// BasicOCSPResponse ::= SEQUENCE {
+///     tbsResponseData ResponseData,
+///     signatureAlgorithm AlgorithmIdentifier,
+///     signature BIT STRING,
+///     certs [0] EXPLICIT SEQUENCE OF Certificate OPTIONAL
+/// }
+/// ```
+fn parse_basic_response(response: &[u8]) -> Result<BasicResponse, ParseError> {
+    let mut buf = ParseBuffer::new(response);
+    let mut response = der::read_element(&mut buf, der::SEQUENCE)?;
+
+    // successful (0)
+    if der::read_element(&mut response, der::ENUMERATED)?.as_slice() != [0] {
+        warn!("The OCSP responder returned an error");
+        return Err(ParseError::InvalidData);
+    }
+
+    let mut response_bytes = der::read_element(&mut response, der::context(0))?;
+    let mut response_bytes = der::read_element(&mut response_bytes, der::SEQUENCE)?;
+    if der::read_element(&mut response_bytes, der::OID)?.as_slice() != ID_PKIX_OCSP_BASIC {
+        return Err(ParseError::InvalidData);
+    }
+    let mut basic_response = der::read_element(&mut response_bytes, der::OCTET_STRING)?;
+    let mut basic_response = der::read_element(&mut basic_response, der::SEQUENCE)?;
+
+    let response_data = der::read_encoded(&mut basic_response, der::SEQUENCE)?;
+    let mut signature_algorithm = der::read_element(&mut basic_response, der::SEQUENCE)?;
+    let signature_algorithm = der::read_element(&mut signature_algorithm, der::OID)?;
+    let signature = der::read_bit_string(&mut basic_response)?;
+
+    Ok(BasicResponse {
+        response_data,
+        signature_algorithm: signature_algorithm.as_slice(),
+        signature,
+    })
+}
+
+/// Returns the response for `leaf`, if any.
+///
+/// The certificate is identified by the hash of its issuer name, the hash of the public key of
+/// its issuer and its serial number. Certificate IDs hashed with an unsupported algorithm are
+/// skipped.
+///
+/// ```text
+/// ResponseData ::= SEQUENCE {
+///     version [0] EXPLICIT Version DEFAULT v1,
+///     responderID ResponderID,
+///     producedAt GeneralizedTime,
+///     responses SEQUENCE OF SingleResponse,
+///     responseExtensions [1] EXPLICIT Extensions OPTIONAL
+/// }
+///
+/// SingleResponse ::= SEQUENCE {
+///     certID CertID,
+///     certStatus CertStatus,
+///     thisUpdate GeneralizedTime,
+///     nextUpdate [0] EXPLICIT GeneralizedTime OPTIONAL,
+///     singleExtensions [1] EXPLICIT Extensions OPTIONAL
+/// }
+///
+/// CertID ::= SEQUENCE {
+///     hashAlgorithm AlgorithmIdentifier,
+///     issuerNameHash OCTET STRING,
+///     issuerKeyHash OCTET STRING,
+///     serialNumber CertificateSerialNumber
+/// }
+///
+/// CertStatus ::= CHOICE {
+///     good [0] IMPLICIT NULL,
+///     revoked [1] IMPLICIT RevokedInfo,
+///     unknown [2] IMPLICIT UnknownInfo
+/// }
+/// ```
+fn parse_single_response(
+    response_data: &[u8],
+    leaf: &X509Certificate,
+    issuer_public_key: &[u8],
+) -> Result<Option<SingleResponse>, ParseError> {
+    let mut buf = ParseBuffer::new(response_data);
+    let mut response_data = der::read_element(&mut buf, der::SEQUENCE)?;
+    der::read_optional_element(&mut response_data, der::context(0))?;
+    der::read_any(&mut response_data)?;
+    der::read_element(&mut response_data, der::GENERALIZED_TIME)?;
+
+    let mut responses = der::read_element(&mut response_data, der::SEQUENCE)?;
+    while !responses.is_empty() {
+        let mut response = der::read_element(&mut responses, der::SEQUENCE)?;
+        let mut cert_id = der::read_element(&mut response, der::SEQUENCE)?;
+        let mut hash_algorithm = der::read_element(&mut cert_id, der::SEQUENCE)?;
+        let hash_algorithm = der::read_element(&mut hash_algorithm, der::OID)?;
+        let issuer_name_hash = der::read_element(&mut cert_id, der::OCTET_STRING)?;
+        let issuer_key_hash = der::read_element(&mut cert_id, der::OCTET_STRING)?;
+        let serial_number = der::read_element(&mut cert_id, der::INTEGER)?;
+
+        let hash_algorithm = match hash_algorithm.as_slice() {
+            ID_SHA1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            ID_SHA256 => &digest::SHA256,
+            ID_SHA384 => &digest::SHA384,
+            ID_SHA512 => &digest::SHA512,
+            _ => continue,
+        };
+        let matches = serial_number.as_slice() == leaf.serial_number
+            && issuer_name_hash.as_slice()
+                == digest::digest(hash_algorithm, leaf.encoded_issuer).as_ref()
+            && issuer_key_hash.as_slice()
+                == digest::digest(hash_algorithm, issuer_public_key).as_ref();
+        if !matches {
+            continue;
+        }
+
+        let status = match der::read_any(&mut response)?.0 {
+            tag if tag == der::context_primitive(0) => CertStatus::Good,
+            tag if tag == der::context(1) => CertStatus::Revoked,
+            tag if tag == der::context_primitive(2) => CertStatus::Unknown,
+            _ => return Err(ParseError::InvalidData),
+        };
+        let this_update = der::read_time(&mut response)?;
+        let next_update = der::read_optional_element(&mut response, der::context(0))?
+            .map(|mut next_update| der::read_time(&mut next_update))
+            .transpose()?;
+        return Ok(Some(SingleResponse {
+            status,
+            this_update,
+            next_update,
+        }));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A time at which the test OCSP responses are current.
+    const NOW: u64 = 1_900_000_000;
+
+    fn issuer_public_key(ca: &[u8]) -> &[u8] {
+        X509Certificate::parse(ca)
+            .unwrap()
+            .subject_public_key()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_cert_id_matches_issuer() {
+        let leaf = pem_parser::pem_to_der(include_str!("../tests/data/server-cert.pem"));
+        let leaf = X509Certificate::parse(&leaf).unwrap();
+        let ca = pem_parser::pem_to_der(include_str!("../tests/data/ca-cert.pem"));
+        let other_ca = pem_parser::pem_to_der(include_str!("../tests/data/rsa-ca-cert.pem"));
+        let response = parse_basic_response(include_bytes!("../tests/data/ocsp-good.der")).unwrap();
+
+        let single = parse_single_response(response.response_data, &leaf, issuer_public_key(&ca));
+        assert!(matches!(
+            single,
+            Ok(Some(SingleResponse {
+                status: CertStatus::Good,
+                ..
+            }))
+        ));
+
+        // The serial number alone does not identify the certificate
+        let single =
+            parse_single_response(response.response_data, &leaf, issuer_public_key(&other_ca));
+        assert!(matches!(single, Ok(None)));
+    }
+
+    #[test]
+    fn test_rsa_signed_response() {
+        let leaf = pem_parser::pem_to_der(include_str!("../tests/data/rsa-leaf-cert.pem"));
+        let leaf = X509Certificate::parse(&leaf).unwrap();
+        let ca = pem_parser::pem_to_der(include_str!("../tests/data/rsa-ca-cert.pem"));
+        let response = include_bytes!("../tests/data/ocsp-rsa-good.der");
+
+        // Without RSA support the response is ignored
+        let expected = if cfg!(feature = "alloc") {
+            CertStatus::Good
+        } else {
+            CertStatus::Unknown
+        };
+        assert_eq!(
+            expected,
+            parse_response(response, &leaf, issuer_public_key(&ca), NOW).unwrap()
+        );
+    }
+}
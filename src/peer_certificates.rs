@@ -0,0 +1,87 @@
+use crate::config::Certificate;
+use crate::handshake::certificate::{CertificateEntryRef, CertificateRef};
+use crate::parse_buffer::ParseBuffer;
+
+const X509: u8 = 0;
+const RAW_PUBLIC_KEY: u8 = 1;
+
+/// Keeps the certificate chain of the server after the handshake, in a buffer provided by the
+/// application.
+///
+/// Each entry is stored as its type, a u24 length and the DER encoded certificate or
+/// SubjectPublicKeyInfo. Entries that do not fit are dropped, so a buffer large enough for the
+/// leaf certificate keeps at least the leaf.
+#[derive(Default)]
+pub(crate) struct PeerCertificateBuffer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> PeerCertificateBuffer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Stores the leading entries of `certificate` that fit in the buffer.
+    pub fn store(&mut self, certificate: &CertificateRef) {
+        self.clear();
+        for entry in certificate.entries.iter() {
+            let (certificate_type, data) = match entry {
+                CertificateEntryRef::X509(data) => (X509, data),
+                CertificateEntryRef::RawPublicKey(data) => (RAW_PUBLIC_KEY, data),
+            };
+            let end = self.len + 4 + data.len();
+            if end > self.buf.len() || data.len() > 0xff_ffff {
+                warn!(
+                    "Peer certificate does not fit in the buffer, dropping the rest of the chain"
+                );
+                break;
+            }
+            let len = (data.len() as u32).to_be_bytes();
+            self.buf[self.len] = certificate_type;
+            self.buf[self.len + 1..self.len + 4].copy_from_slice(&len[1..]);
+            self.buf[self.len + 4..end].copy_from_slice(data);
+            self.len = end;
+        }
+    }
+
+    pub fn iter(&self) -> PeerCertificates<'_> {
+        PeerCertificates {
+            buf: ParseBuffer::new(&self.buf[..self.len]),
+        }
+    }
+}
+
+/// Iterates over the certificate chain sent by the server, starting with the leaf certificate.
+///
+/// Returned by `TlsConnection::peer_certificates`. The items are [`Certificate::X509`] entries,
+/// or a [`Certificate::RawPublicKey`] if raw public keys were negotiated.
+pub struct PeerCertificates<'a> {
+    buf: ParseBuffer<'a>,
+}
+
+impl<'a> PeerCertificates<'a> {
+    pub(crate) fn empty() -> Self {
+        Self {
+            buf: ParseBuffer::new(&[]),
+        }
+    }
+}
+
+impl<'a> Iterator for PeerCertificates<'a> {
+    type Item = Certificate<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let certificate_type = self.buf.read_u8().ok()?;
+        let len = self.buf.read_u24().ok()?;
+        let data = self.buf.slice(len as usize).ok()?.as_slice();
+        match certificate_type {
+            X509 => Some(Certificate::X509(data)),
+            _ => Some(Certificate::RawPublicKey(data)),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::config::{Certificate, NoVerify, TlsClock, TlsVerifier};
+use crate::handshake::{
+    certificate::{x509_subject_public_key_info, CertificateEntryRef, CertificateRef},
+    certificate_verify::CertificateVerify,
+};
+use crate::rpk::PublicKey;
+use crate::TlsError;
+use heapless::Vec;
+use sha2::{Digest, Sha256};
+
+/// Verifies the server by pinning the SHA-256 hash of its public key.
+///
+/// The hash of the DER encoded SubjectPublicKeyInfo of the leaf certificate must match one of
+/// the pins configured with `TlsConfig::with_ca(Certificate::SpkiSha256(..))`, and the
+/// CertificateVerify signature of the server is verified with that key. secp256r1 and
+/// secp384r1 ECDSA keys are supported.
+///
+/// The pin is checked on top of the verification done by `Verifier`. With the default
+/// [`NoVerify`], the pin replaces the validation of the certificate chain, so the validity
+/// period and the server name are not checked. Use `webpki::CertVerifier` to validate the chain
+/// against the X.509 trust anchors as well.
+pub struct PinVerifier<Verifier = NoVerify> {
+    verifier: Verifier,
+    public_key: Option<PublicKey>,
+    certificate_transcript: Option<Vec<u8, 48>>,
+}
+
+impl<'a, Verifier> TlsVerifier<'a> for PinVerifier<Verifier>
+where
+    Verifier: TlsVerifier<'a>,
+{
+    fn new(host: Option<&'a str>, clock: &'a (dyn TlsClock + Sync)) -> Self {
+        Self {
+            verifier: Verifier::new(host, clock),
+            public_key: None,
+            certificate_transcript: None,
+        }
+    }
+
+    fn verify_certificate(
+        &mut self,
+        transcript: &[u8],
+        ca: &[Certificate],
+        crls: &[&[u8]],
+        ocsp_stapling: bool,
+        cert: CertificateRef,
+    ) -> Result<(), TlsError> {
+        let public_key = match cert.entries.first() {
+            Some(CertificateEntryRef::X509(certificate)) => {
+                x509_subject_public_key_info(certificate)?
+            }
+            Some(CertificateEntryRef::RawPublicKey(public_key)) => public_key,
+            None => {
+                warn!("The server did not send a certificate");
+                return Err(TlsError::InvalidCertificate);
+            }
+        };
+
+        let hash: [u8; 32] = Sha256::digest(public_key).into();
+        let pinned = ca.iter().any(|ca| match ca {
+            Certificate::SpkiSha256(pin) => *pin == hash,
+            _ => false,
+        });
+        if !pinned {
+            warn!("The public key of the server is not pinned");
+            return Err(TlsError::InvalidCertificate);
+        }
+        self.public_key
+            .replace(PublicKey::from_subject_public_key_info(public_key)?);
+
+        self.verifier
+            .verify_certificate(transcript, ca, crls, ocsp_stapling, cert)?;
+        self.certificate_transcript
+            .replace(Vec::from_slice(transcript).map_err(|_| TlsError::InternalError)?);
+        Ok(())
+    }
+
+    fn verify_signature(&mut self, verify: CertificateVerify) -> Result<(), TlsError> {
+        let handshake_hash = self
+            .certificate_transcript
+            .take()
+            .ok_or(TlsError::InvalidHandshake)?;
+        let public_key = self.public_key.as_ref().ok_or(TlsError::InvalidHandshake)?;
+
+        let ctx_str = b"TLS 1.3, server CertificateVerify\x00";
+        let mut msg: Vec<u8, 146> = Vec::new();
+        msg.resize(64, 0x20).map_err(|_| TlsError::EncodeError)?;
+        msg.extend_from_slice(ctx_str)
+            .map_err(|_| TlsError::EncodeError)?;
+        msg.extend_from_slice(&handshake_hash)
+            .map_err(|_| TlsError::EncodeError)?;
+
+        public_key.verify(&msg, verify.signature_scheme, verify.signature)?;
+        self.verifier.verify_signature(verify)
+    }
+}
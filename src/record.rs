@@ -1,15 +1,12 @@
 use crate::application_data::ApplicationData;
 use crate::buffer::*;
 use crate::change_cipher_spec::ChangeCipherSpec;
-use crate::config::{TlsCipherSuite, TlsConfig};
+use crate::config::TlsCipherSuite;
 use crate::content_types::ContentType;
-use crate::handshake::client_hello::ClientHello;
 use crate::handshake::{ClientHandshake, ServerHandshake};
-use crate::key_schedule::WriteKeySchedule;
 use crate::TlsError;
 use crate::{alert::*, parse_buffer::ParseBuffer};
 use core::fmt::Debug;
-use rand_core::{CryptoRng, RngCore};
 
 pub type Encrypted = bool;
 
@@ -47,7 +44,7 @@ impl ClientRecordHeader {
     pub fn header_content_type(&self) -> ContentType {
         match self {
             Self::Handshake(false) => ContentType::Handshake,
-            Self::Alert(false) => ContentType::ChangeCipherSpec,
+            Self::Alert(false) => ContentType::Alert,
             Self::ChangeCipherSpec(false) => ContentType::ChangeCipherSpec,
             Self::Handshake(true) => ContentType::ApplicationData,
             Self::Alert(true) => ContentType::ApplicationData,
@@ -103,19 +100,6 @@ where
         }
     }
 
-    pub fn client_hello<RNG>(
-        config: &'config TlsConfig<'config, CipherSuite>,
-        rng: &mut RNG,
-    ) -> Self
-    where
-        RNG: CryptoRng + RngCore,
-    {
-        ClientRecord::Handshake(
-            ClientHandshake::ClientHello(ClientHello::new(config, rng)),
-            false,
-        )
-    }
-
     pub fn close_notify(opened: bool) -> Self {
         ClientRecord::Alert(
             Alert::new(AlertLevel::Warning, AlertDescription::CloseNotify),
@@ -143,12 +127,9 @@ where
         &self,
         buf: &mut CryptoBuffer,
         transcript: &mut CipherSuite::Hash,
-        write_key_schedule: &mut WriteKeySchedule<CipherSuite>,
     ) -> Result<(), TlsError> {
         match self {
-            ClientRecord::Handshake(handshake, false) => {
-                handshake.finalize(buf, transcript, write_key_schedule)
-            }
+            ClientRecord::Handshake(handshake, false) => handshake.finalize(buf, transcript),
             ClientRecord::Handshake(handshake, true) => {
                 handshake.finalize_encrypted(buf, transcript)
             }
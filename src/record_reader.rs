@@ -1,31 +1,30 @@
-use core::marker::PhantomData;
-
 use crate::key_schedule::ReadKeySchedule;
 use embedded_io::{Error, Read as BlockingRead};
 use embedded_io_async::Read as AsyncRead;
 
 use crate::{
-    config::TlsCipherSuite,
+    alert::{AlertDescription, AlertLevel},
+    config::{MaxFragmentLength, TlsCipherSuite},
+    content_types::ContentType,
     record::{RecordHeader, ServerRecord},
+    write_buffer::MAX_PLAINTEXT_LEN,
     TlsError,
 };
 
-pub struct RecordReader<'a, CipherSuite>
-where
-    CipherSuite: TlsCipherSuite,
-{
+/// The maximum expansion of the plaintext by the encryption of a record (Section 5.2)
+const MAX_EXPANSION: usize = 256;
+
+pub struct RecordReader<'a> {
     pub(crate) buf: &'a mut [u8],
     /// The number of decoded bytes in the buffer
     decoded: usize,
     /// The number of read but not yet decoded bytes in the buffer
     pending: usize,
-    cipher_suite: PhantomData<CipherSuite>,
+    /// The maximum plaintext length of a record
+    max_fragment_len: usize,
 }
 
-impl<'a, CipherSuite> RecordReader<'a, CipherSuite>
-where
-    CipherSuite: TlsCipherSuite,
-{
+impl<'a> RecordReader<'a> {
     pub fn new(buf: &'a mut [u8]) -> Self {
         if buf.len() < 16640 {
             warn!("Read buffer is smaller than 16640 bytes, which may cause problems!");
@@ -34,17 +33,47 @@ where
             buf,
             decoded: 0,
             pending: 0,
-            cipher_suite: PhantomData,
+            max_fragment_len: MAX_PLAINTEXT_LEN,
+        }
+    }
+
+    /// Rejects the records read afterwards that exceed the negotiated maximum fragment length.
+    pub(crate) fn set_max_fragment_length(
+        &mut self,
+        max_fragment_length: Option<MaxFragmentLength>,
+    ) {
+        self.max_fragment_len = max_fragment_length.map_or(MAX_PLAINTEXT_LEN, |max| max.size());
+    }
+
+    /// Checks the length of a record before reading it, which for an encrypted record includes
+    /// the content type, padding and authentication tag.
+    fn check_length(&self, header: &RecordHeader) -> Result<(), TlsError> {
+        let max_len = match header.content_type() {
+            ContentType::ApplicationData => self.max_fragment_len + MAX_EXPANSION,
+            _ => self.max_fragment_len,
+        };
+        if header.content_length() > max_len {
+            warn!(
+                "Record of {} bytes exceeds the maximum of {} bytes",
+                header.content_length(),
+                max_len
+            );
+            return Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::RecordOverflow,
+            ));
         }
+        Ok(())
     }
 
-    pub async fn read<'m>(
+    pub async fn read<'m, CipherSuite: TlsCipherSuite>(
         &'m mut self,
         transport: &mut impl AsyncRead,
         key_schedule: &mut ReadKeySchedule<CipherSuite>,
     ) -> Result<ServerRecord<'m, CipherSuite>, TlsError> {
         let header = self.advance(transport, 5).await?;
         let header = RecordHeader::decode(unwrap!(header.try_into().ok()))?;
+        self.check_length(&header)?;
 
         let content_length = header.content_length();
         debug!(
@@ -77,13 +106,14 @@ where
         Ok(self.consume(amount))
     }
 
-    pub fn read_blocking<'m>(
+    pub fn read_blocking<'m, CipherSuite: TlsCipherSuite>(
         &'m mut self,
         transport: &mut impl BlockingRead,
         key_schedule: &mut ReadKeySchedule<CipherSuite>,
     ) -> Result<ServerRecord<'m, CipherSuite>, TlsError> {
         let header = self.advance_blocking(transport, 5)?;
         let header = RecordHeader::decode(unwrap!(header.try_into().ok()))?;
+        self.check_length(&header)?;
 
         let content_length = header.content_length();
         let data = self.advance_blocking(transport, content_length)?;
@@ -141,7 +171,7 @@ mod tests {
     use core::convert::Infallible;
 
     use super::*;
-    use crate::{content_types::ContentType, key_schedule::KeySchedule, Aes128GcmSha256};
+    use crate::{key_schedule::KeySchedule, Aes128GcmSha256};
 
     struct ChunkRead<'a>(&'a [u8], usize);
 
@@ -159,6 +189,41 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_max_fragment_length() {
+        let mut buf = [0; 32];
+        let mut reader = RecordReader::new(&mut buf);
+        let header = |content_type: ContentType, len: u16| {
+            let [upper, lower] = len.to_be_bytes();
+            RecordHeader::decode([content_type as u8, 0x03, 0x03, upper, lower]).unwrap()
+        };
+
+        assert!(reader
+            .check_length(&header(ContentType::ApplicationData, 16384 + 256))
+            .is_ok());
+        assert!(matches!(
+            reader.check_length(&header(ContentType::ApplicationData, 16384 + 257)),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::RecordOverflow
+            ))
+        ));
+
+        reader.set_max_fragment_length(Some(MaxFragmentLength::Bits9));
+        assert!(reader
+            .check_length(&header(ContentType::Handshake, 512))
+            .is_ok());
+        assert!(reader
+            .check_length(&header(ContentType::Handshake, 513))
+            .is_err());
+        assert!(reader
+            .check_length(&header(ContentType::ApplicationData, 512 + 256))
+            .is_ok());
+        assert!(reader
+            .check_length(&header(ContentType::ApplicationData, 512 + 257))
+            .is_err());
+    }
+
     #[test]
     fn can_read_blocking() {
         can_read_blocking_case(1, 0);
@@ -207,7 +272,7 @@ mod tests {
         );
 
         let mut buf = [0; 32];
-        let mut reader = RecordReader::<Aes128GcmSha256>::new(&mut buf);
+        let mut reader = RecordReader::new(&mut buf);
         let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
 
         {
@@ -266,7 +331,7 @@ mod tests {
         .as_slice();
 
         let mut buf = [0; 5]; // This buffer is so small that it cannot contain both the header and data
-        let mut reader = RecordReader::<Aes128GcmSha256>::new(&mut buf);
+        let mut reader = RecordReader::new(&mut buf);
         let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
 
         {
@@ -318,7 +383,7 @@ mod tests {
         .as_slice();
 
         let mut buf = [0; 32];
-        let mut reader = RecordReader::<Aes128GcmSha256>::new(&mut buf);
+        let mut reader = RecordReader::new(&mut buf);
         let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
 
         {
@@ -0,0 +1,117 @@
+use crate::config::{Certificate, TlsClock, TlsVerifier};
+use crate::extensions::extension_data::signature_algorithms::SignatureScheme;
+use crate::handshake::{
+    certificate::{CertificateEntryRef, CertificateRef as ServerCertificate},
+    certificate_verify::CertificateVerify,
+};
+use crate::TlsError;
+use heapless::Vec;
+use p256::ecdsa::signature::Verifier;
+use p256::pkcs8::DecodePublicKey;
+
+/// Verifies a server authenticating with a raw public key (RFC 7250).
+///
+/// The key sent by the server must be identical to one of the DER encoded
+/// SubjectPublicKeyInfos configured with `TlsConfig::with_ca(Certificate::RawPublicKey(..))`,
+/// which also makes the client ask for a raw public key. The server name is not verified, as a raw public key does
+/// not carry one. secp256r1 and secp384r1 ECDSA keys are supported.
+pub struct RpkVerifier {
+    public_key: Option<PublicKey>,
+    certificate_transcript: Option<Vec<u8, 48>>,
+}
+
+pub(crate) enum PublicKey {
+    Secp256r1(p256::ecdsa::VerifyingKey),
+    Secp384r1(p384::ecdsa::VerifyingKey),
+}
+
+impl PublicKey {
+    pub(crate) fn from_subject_public_key_info(der: &[u8]) -> Result<Self, TlsError> {
+        if let Ok(key) = p256::ecdsa::VerifyingKey::from_public_key_der(der) {
+            Ok(Self::Secp256r1(key))
+        } else if let Ok(key) = p384::ecdsa::VerifyingKey::from_public_key_der(der) {
+            Ok(Self::Secp384r1(key))
+        } else {
+            warn!("Unsupported raw public key");
+            Err(TlsError::InvalidCertificate)
+        }
+    }
+
+    pub(crate) fn verify(
+        &self,
+        message: &[u8],
+        signature_scheme: SignatureScheme,
+        signature: &[u8],
+    ) -> Result<(), TlsError> {
+        match (self, signature_scheme) {
+            (Self::Secp256r1(key), SignatureScheme::EcdsaSecp256r1Sha256) => {
+                let signature = p256::ecdsa::DerSignature::from_bytes(signature)
+                    .map_err(|_| TlsError::InvalidSignature)?;
+                key.verify(message, &signature)
+                    .map_err(|_| TlsError::InvalidSignature)
+            }
+            (Self::Secp384r1(key), SignatureScheme::EcdsaSecp384r1Sha384) => {
+                let signature = p384::ecdsa::DerSignature::from_bytes(signature)
+                    .map_err(|_| TlsError::InvalidSignature)?;
+                key.verify(message, &signature)
+                    .map_err(|_| TlsError::InvalidSignature)
+            }
+            _ => Err(TlsError::InvalidSignatureScheme),
+        }
+    }
+}
+
+impl<'a> TlsVerifier<'a> for RpkVerifier {
+    fn new(_host: Option<&'a str>, _clock: &'a (dyn TlsClock + Sync)) -> Self {
+        Self {
+            public_key: None,
+            certificate_transcript: None,
+        }
+    }
+
+    fn verify_certificate(
+        &mut self,
+        transcript: &[u8],
+        ca: &[Certificate],
+        _crls: &[&[u8]],
+        _ocsp_stapling: bool,
+        cert: ServerCertificate,
+    ) -> Result<(), TlsError> {
+        let [CertificateEntryRef::RawPublicKey(public_key)] = cert.entries[..] else {
+            warn!("The server did not send a raw public key");
+            return Err(TlsError::InvalidCertificate);
+        };
+        let trusted = ca.iter().any(|ca| match ca {
+            Certificate::RawPublicKey(expected) => public_key == *expected,
+            _ => false,
+        });
+        if !trusted {
+            warn!("The raw public key of the server is not trusted");
+            return Err(TlsError::InvalidCertificate);
+        }
+
+        self.public_key
+            .replace(PublicKey::from_subject_public_key_info(public_key)?);
+        self.certificate_transcript
+            .replace(Vec::from_slice(transcript).map_err(|_| TlsError::InternalError)?);
+        Ok(())
+    }
+
+    fn verify_signature(&mut self, verify: CertificateVerify) -> Result<(), TlsError> {
+        let handshake_hash = self
+            .certificate_transcript
+            .take()
+            .ok_or(TlsError::InvalidHandshake)?;
+        let public_key = self.public_key.as_ref().ok_or(TlsError::InvalidHandshake)?;
+
+        let ctx_str = b"TLS 1.3, server CertificateVerify\x00";
+        let mut msg: Vec<u8, 146> = Vec::new();
+        msg.resize(64, 0x20).map_err(|_| TlsError::EncodeError)?;
+        msg.extend_from_slice(ctx_str)
+            .map_err(|_| TlsError::EncodeError)?;
+        msg.extend_from_slice(&handshake_hash)
+            .map_err(|_| TlsError::EncodeError)?;
+
+        public_key.verify(&msg, verify.signature_scheme, verify.signature)
+    }
+}
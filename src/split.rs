@@ -4,6 +4,15 @@ pub trait SplitState: Clone {
     fn same(&self, other: &Self) -> bool;
     fn is_open(&self) -> bool;
     fn set_open(&self, open: bool);
+    /// Signals the writer that the server requested a KeyUpdate. The writer answers it with its
+    /// next flush, so a split connection that only reads does not respond.
+    ///
+    /// By default the request is dropped, and the writer never answers it.
+    fn request_key_update(&self) {}
+    /// Returns whether a KeyUpdate was requested since the last call.
+    fn take_key_update_request(&self) -> bool {
+        false
+    }
 }
 
 pub trait SplitStateContainer {
@@ -14,6 +23,7 @@ pub trait SplitStateContainer {
 
 pub struct SplitConnectionState {
     is_open: AtomicBool,
+    key_update_requested: AtomicBool,
 }
 
 impl Default for SplitConnectionState {
@@ -21,6 +31,7 @@ impl Default for SplitConnectionState {
     fn default() -> Self {
         Self {
             is_open: AtomicBool::new(true),
+            key_update_requested: AtomicBool::new(false),
         }
     }
 }
@@ -42,6 +53,14 @@ impl SplitState for &SplitConnectionState {
         self.is_open.store(open, Ordering::Release);
     }
 
+    fn request_key_update(&self) {
+        self.key_update_requested.store(true, Ordering::Release);
+    }
+
+    fn take_key_update_request(&self) -> bool {
+        self.key_update_requested.swap(false, Ordering::AcqRel)
+    }
+
     fn same(&self, other: &Self) -> bool {
         core::ptr::eq(*self, *other)
     }
@@ -80,6 +99,14 @@ mod stdlib {
             self.0.as_ref().set_open(open)
         }
 
+        fn request_key_update(&self) {
+            self.0.as_ref().request_key_update()
+        }
+
+        fn take_key_update_request(&self) -> bool {
+            self.0.as_ref().take_key_update_request()
+        }
+
         fn same(&self, other: &Self) -> bool {
             Arc::ptr_eq(&self.0, &other.0)
         }
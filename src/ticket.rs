@@ -0,0 +1,242 @@
+use heapless::{String, Vec};
+
+use crate::cipher_suites::CipherSuite;
+use crate::config::{NoClock, TlsCipherSuite, TlsClock, TlsConfig};
+use crate::handshake::new_session_ticket::NewSessionTicket;
+use crate::key_schedule::ReadKeySchedule;
+use crate::TlsError;
+
+/// The longest ticket lifetime allowed, 7 days (Section 4.6.1).
+const MAX_TICKET_LIFETIME: u32 = 604800;
+
+/// The largest resumption PSK, the output of SHA-384.
+const MAX_PSK_LEN: usize = 48;
+
+/// The longest DNS name.
+const MAX_SERVER_NAME_LEN: usize = 253;
+
+/// A session ticket sent by the server, which a later connection can resume the session with.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SessionTicket<'a> {
+    /// The cipher suite of the session. Its hash must be used when resuming.
+    pub cipher_suite: CipherSuite,
+    /// The server name of the connection the ticket was received on, if any. The ticket is
+    /// only offered to a server with the same name.
+    pub server_name: Option<&'a str>,
+    /// How long the ticket may be used for, in seconds.
+    pub lifetime: u32,
+    /// Added to the age of the ticket to obfuscate it in the ClientHello.
+    pub age_add: u32,
+    /// The most early data the server accepts when resuming with the ticket, 0 if it does not
+    /// accept early data.
+    pub max_early_data_size: u32,
+    /// The time the ticket was received at, in seconds since the Unix epoch, if the connection
+    /// had a clock.
+    pub received_at: Option<u64>,
+    /// The opaque ticket, sent as the PSK identity.
+    pub ticket: &'a [u8],
+    /// The resumption PSK derived for this ticket. Keep it as secret as a private key.
+    pub psk: &'a [u8],
+}
+
+impl<'a> SessionTicket<'a> {
+    /// Returns the obfuscated age of the ticket in milliseconds, or `None` if the ticket has
+    /// expired. Without a clock the age is assumed to be 0.
+    pub(crate) fn obfuscated_age(&self, now: Option<u64>) -> Option<u32> {
+        let age = match (self.received_at, now) {
+            (Some(received_at), Some(now)) => now.saturating_sub(received_at),
+            _ => 0,
+        };
+        if age > u64::from(self.lifetime) {
+            return None;
+        }
+        // The lifetime is at most 7 days, so the age in milliseconds fits in 32 bits
+        Some((age as u32 * 1000).wrapping_add(self.age_add))
+    }
+}
+
+/// Whether the early data given to `TlsContext::with_early_data` was accepted by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EarlyDataStatus {
+    /// No early data was sent, because there was none, no session ticket to resume or the
+    /// ticket does not allow that much early data.
+    NotSent,
+    /// The server accepted the early data.
+    Accepted,
+    /// The server rejected the early data, so it has to be written again if it is still needed.
+    Rejected,
+}
+
+/// Storage for session tickets, for resuming sessions in later connections.
+///
+/// Set with `TlsConnection::with_ticket_storage`. The ticket returned by [`Self::load`] is
+/// offered when the connection is opened, and tickets received afterwards are passed to
+/// [`Self::store`].
+pub trait TicketStorage {
+    /// Stores a ticket received from the server.
+    fn store(&mut self, ticket: &SessionTicket<'_>);
+
+    /// Returns the ticket to resume the session with, if any.
+    fn load(&self) -> Option<SessionTicket<'_>>;
+}
+
+/// Keeps the most recently received ticket, if it is at most `N` bytes long.
+pub struct SingleTicketStorage<const N: usize> {
+    ticket: Option<StoredTicket<N>>,
+}
+
+struct StoredTicket<const N: usize> {
+    cipher_suite: CipherSuite,
+    server_name: Option<String<MAX_SERVER_NAME_LEN>>,
+    lifetime: u32,
+    age_add: u32,
+    max_early_data_size: u32,
+    received_at: Option<u64>,
+    ticket: Vec<u8, N>,
+    psk: Vec<u8, MAX_PSK_LEN>,
+}
+
+impl<const N: usize> SingleTicketStorage<N> {
+    pub const fn new() -> Self {
+        Self { ticket: None }
+    }
+
+    /// Forgets the stored ticket.
+    pub fn clear(&mut self) {
+        self.ticket = None;
+    }
+}
+
+impl<const N: usize> Default for SingleTicketStorage<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> TicketStorage for SingleTicketStorage<N> {
+    fn store(&mut self, ticket: &SessionTicket<'_>) {
+        let server_name = ticket.server_name.map(String::try_from).transpose();
+        match (
+            server_name,
+            Vec::from_slice(ticket.ticket),
+            Vec::from_slice(ticket.psk),
+        ) {
+            (Ok(server_name), Ok(stored), Ok(psk)) => {
+                self.ticket = Some(StoredTicket {
+                    cipher_suite: ticket.cipher_suite,
+                    server_name,
+                    lifetime: ticket.lifetime,
+                    age_add: ticket.age_add,
+                    max_early_data_size: ticket.max_early_data_size,
+                    received_at: ticket.received_at,
+                    ticket: stored,
+                    psk,
+                });
+            }
+            _ => warn!("Session ticket does not fit in the storage, ignoring it"),
+        }
+    }
+
+    fn load(&self) -> Option<SessionTicket<'_>> {
+        self.ticket.as_ref().map(|stored| SessionTicket {
+            cipher_suite: stored.cipher_suite,
+            server_name: stored.server_name.as_deref(),
+            lifetime: stored.lifetime,
+            age_add: stored.age_add,
+            max_early_data_size: stored.max_early_data_size,
+            received_at: stored.received_at,
+            ticket: &stored.ticket,
+            psk: &stored.psk,
+        })
+    }
+}
+
+/// The ticket storage of a connection, with the clock measuring the age of the tickets and the
+/// server name they are received from.
+pub(crate) struct Tickets<'a> {
+    storage: Option<&'a mut (dyn TicketStorage + Send)>,
+    clock: &'a (dyn TlsClock + Sync),
+    server_name: Option<String<MAX_SERVER_NAME_LEN>>,
+}
+
+impl<'a> Default for Tickets<'a> {
+    fn default() -> Self {
+        Self {
+            storage: None,
+            clock: &NoClock,
+            server_name: None,
+        }
+    }
+}
+
+impl<'a> Tickets<'a> {
+    pub fn new(
+        storage: &'a mut (dyn TicketStorage + Send),
+        clock: &'a (dyn TlsClock + Sync),
+    ) -> Self {
+        Self {
+            storage: Some(storage),
+            clock,
+            server_name: None,
+        }
+    }
+
+    /// Sets the server name of the connection, which tickets are received from.
+    pub fn set_server_name(&mut self, server_name: Option<&str>) {
+        self.server_name = match server_name.map(String::try_from).transpose() {
+            Ok(server_name) => server_name,
+            Err(_) => {
+                warn!("Server name is too long for session tickets, not using them");
+                self.storage = None;
+                None
+            }
+        };
+    }
+
+    /// Returns the stored ticket if it can be used with `config` and has not expired, with its
+    /// obfuscated age.
+    pub fn load(&self, config: &TlsConfig) -> Option<(SessionTicket<'_>, u32)> {
+        let ticket = self.storage.as_ref()?.load()?;
+        if !config.cipher_suites.contains(&ticket.cipher_suite)
+            || ticket.server_name != config.server_name
+        {
+            return None;
+        }
+        let age = ticket.obfuscated_age(self.clock.now())?;
+        Some((ticket, age))
+    }
+
+    /// Derives the PSK of a NewSessionTicket and stores the ticket, if there is a storage.
+    pub fn store<CipherSuite>(
+        &mut self,
+        key_schedule: &ReadKeySchedule<CipherSuite>,
+        ticket: &NewSessionTicket,
+    ) -> Result<(), TlsError>
+    where
+        CipherSuite: TlsCipherSuite,
+    {
+        let Some(storage) = self.storage.as_mut() else {
+            return Ok(());
+        };
+        // Section 4.6.1: a lifetime of zero indicates that the ticket should be discarded
+        if ticket.lifetime() == 0 {
+            return Ok(());
+        }
+        let psk = key_schedule.ticket_psk(ticket.nonce())?;
+        storage.store(&SessionTicket {
+            cipher_suite: CipherSuite::CODE_POINT
+                .try_into()
+                .map_err(|_| TlsError::InvalidCipherSuite)?,
+            server_name: self.server_name.as_deref(),
+            lifetime: ticket.lifetime().min(MAX_TICKET_LIFETIME),
+            age_add: ticket.age_add(),
+            max_early_data_size: ticket.max_early_data_size(),
+            received_at: self.clock.now(),
+            ticket: ticket.ticket(),
+            psk: &psk,
+        });
+        Ok(())
+    }
+}
@@ -1,14 +1,15 @@
-use crate::config::{Certificate, TlsCipherSuite, TlsClock, TlsVerifier};
+use crate::config::{Certificate, TlsClock, TlsVerifier, TrustAnchor, MAX_CRLS, MAX_TRUST_ANCHORS};
+use crate::der::{self, X509Certificate};
 use crate::extensions::extension_data::signature_algorithms::SignatureScheme;
 use crate::handshake::{
     certificate::{
         Certificate as OwnedCertificate, CertificateEntryRef, CertificateRef as ServerCertificate,
+        MAX_CERTIFICATE_CHAIN_LEN,
     },
     certificate_verify::CertificateVerify,
 };
+use crate::ocsp;
 use crate::TlsError;
-use core::marker::PhantomData;
-use digest::Digest;
 use heapless::Vec;
 #[cfg(all(not(feature = "alloc"), feature = "webpki"))]
 impl TryInto<&'static webpki::SignatureAlgorithm> for SignatureScheme {
@@ -89,52 +90,94 @@ static ALL_SIGALGS: &[&webpki::SignatureAlgorithm] = &[
     &webpki::ED25519,
 ];
 
-pub struct CertVerifier<'a, CipherSuite, Clock, const CERT_SIZE: usize>
-where
-    Clock: TlsClock,
-    CipherSuite: TlsCipherSuite,
-{
+impl<'a> TrustAnchor<'a> {
+    /// Parses a DER encoded X.509 CA certificate into a trust anchor.
+    pub fn from_cert_der(cert_der: &'a [u8]) -> Result<Self, TlsError> {
+        let trust = webpki::TrustAnchor::try_from_cert_der(cert_der).map_err(|e| {
+            warn!("Error loading CA: {:?}", e);
+            TlsError::DecodeError
+        })?;
+        Ok(trust.into())
+    }
+}
+
+impl<'a> From<webpki::TrustAnchor<'a>> for TrustAnchor<'a> {
+    fn from(trust: webpki::TrustAnchor<'a>) -> Self {
+        Self {
+            subject: trust.subject,
+            spki: trust.spki,
+            name_constraints: trust.name_constraints,
+        }
+    }
+}
+
+impl<'a> From<&TrustAnchor<'a>> for webpki::TrustAnchor<'a> {
+    fn from(trust: &TrustAnchor<'a>) -> Self {
+        Self {
+            subject: trust.subject,
+            spki: trust.spki,
+            name_constraints: trust.name_constraints,
+        }
+    }
+}
+
+/// Verifies the server certificate chain against the configured CAs, using webpki.
+///
+/// The chain sent by the server, including any intermediate certificates, is kept in a buffer of
+/// `CERT_SIZE` bytes until the signature of the server has been verified. The validity period of
+/// the certificates is checked with the clock set with [`crate::TlsContext::with_clock`], so
+/// validation fails if no clock is set.
+///
+/// With [`crate::TlsConfig::enable_ocsp_stapling`], the OCSP response stapled by the server must
+/// be signed by the issuer of the server certificate, and revoked certificates are rejected with
+/// [`TlsError::CertificateRevoked`]. Certificates requiring a stapled response (OCSP
+/// must-staple) are rejected without one. Without OCSP stapling the revocation status is not
+/// checked, and must-staple certificates are accepted. Responses signed by a delegated OCSP
+/// responder are not supported, and responses signed with RSA are ignored without the `alloc`
+/// feature.
+///
+/// Certificates of the chain revoked by one of the CRLs added with
+/// [`crate::TlsConfig::with_crl`] are rejected with [`TlsError::CertificateRevoked`] as well.
+pub struct CertVerifier<'a, const CERT_SIZE: usize> {
     host: Option<&'a str>,
-    certificate_transcript: Option<CipherSuite::Hash>,
+    clock: &'a (dyn TlsClock + Sync),
+    certificate_transcript: Option<Vec<u8, 48>>,
     certificate: Option<OwnedCertificate<CERT_SIZE>>,
-    _clock: PhantomData<Clock>,
 }
 
-impl<'a, CipherSuite, Clock, const CERT_SIZE: usize> TlsVerifier<'a, CipherSuite>
-    for CertVerifier<'a, CipherSuite, Clock, CERT_SIZE>
-where
-    CipherSuite: TlsCipherSuite,
-    Clock: TlsClock,
-{
-    fn new(host: Option<&'a str>) -> Self {
+impl<'a, const CERT_SIZE: usize> TlsVerifier<'a> for CertVerifier<'a, CERT_SIZE> {
+    fn new(host: Option<&'a str>, clock: &'a (dyn TlsClock + Sync)) -> Self {
         Self {
             host,
+            clock,
             certificate_transcript: None,
             certificate: None,
-            _clock: PhantomData,
         }
     }
 
     fn verify_certificate(
         &mut self,
-        transcript: &CipherSuite::Hash,
-        ca: &Option<Certificate>,
+        transcript: &[u8],
+        ca: &[Certificate],
+        crls: &[&[u8]],
+        ocsp_stapling: bool,
         cert: ServerCertificate,
     ) -> Result<(), TlsError> {
-        verify_certificate(self.host.clone(), ca, &cert, Clock::now())?;
+        verify_certificate(self.host, ca, crls, ocsp_stapling, &cert, self.clock.now())?;
         self.certificate.replace(cert.try_into()?);
-        self.certificate_transcript.replace(transcript.clone());
+        self.certificate_transcript
+            .replace(Vec::from_slice(transcript).map_err(|_| TlsError::InternalError)?);
         Ok(())
     }
 
     fn verify_signature(&mut self, verify: CertificateVerify) -> Result<(), TlsError> {
         let handshake_hash = unwrap!(self.certificate_transcript.take());
         let ctx_str = b"TLS 1.3, server CertificateVerify\x00";
-        let mut msg: Vec<u8, 130> = Vec::new();
+        let mut msg: Vec<u8, 146> = Vec::new();
         msg.resize(64, 0x20).map_err(|_| TlsError::EncodeError)?;
         msg.extend_from_slice(ctx_str)
             .map_err(|_| TlsError::EncodeError)?;
-        msg.extend_from_slice(&handshake_hash.finalize())
+        msg.extend_from_slice(&handshake_hash)
             .map_err(|_| TlsError::EncodeError)?;
 
         let certificate = unwrap!(self.certificate.as_ref()).try_into()?;
@@ -150,7 +193,7 @@ fn verify_signature(
 ) -> Result<(), TlsError> {
     let mut verified = false;
     if !certificate.entries.is_empty() {
-        // TODO: Support intermediates...
+        // The signature is made with the key of the leaf certificate
         if let CertificateEntryRef::X509(certificate) = certificate.entries[0] {
             let cert = webpki::EndEntityCert::try_from(certificate).map_err(|e| {
                 warn!("Error loading cert: {:?}", e);
@@ -181,60 +224,105 @@ fn verify_signature(
 
 fn verify_certificate(
     verify_host: Option<&str>,
-    ca: &Option<Certificate>,
+    ca: &[Certificate],
+    crls: &[&[u8]],
+    ocsp_stapling: bool,
     certificate: &ServerCertificate,
     now: Option<u64>,
 ) -> Result<(), TlsError> {
     let mut verified = false;
     let mut host_verified = false;
-    if let Some(Certificate::X509(ca)) = ca {
-        let trust = webpki::TrustAnchor::try_from_cert_der(ca).map_err(|e| {
-            warn!("Error loading CA: {:?}", e);
+
+    let mut trust_anchors: Vec<webpki::TrustAnchor, MAX_TRUST_ANCHORS> = Vec::new();
+    for ca in ca {
+        let trust = match ca {
+            Certificate::X509(ca) => TrustAnchor::from_cert_der(ca)?,
+            Certificate::TrustAnchor(trust) => *trust,
+            Certificate::RawPublicKey(_) | Certificate::SpkiSha256(_) => continue,
+        };
+        unwrap!(trust_anchors.push((&trust).into()).ok());
+    }
+
+    let mut revocation_lists: Vec<webpki::BorrowedCertRevocationList, MAX_CRLS> = Vec::new();
+    for crl in crls {
+        let crl = webpki::BorrowedCertRevocationList::from_der(crl).map_err(|e| {
+            warn!("Error loading CRL: {:?}", e);
             TlsError::DecodeError
         })?;
+        unwrap!(revocation_lists.push(crl).ok());
+    }
+    let revocation_lists: Vec<&dyn webpki::CertRevocationList, MAX_CRLS> = revocation_lists
+        .iter()
+        .map(|crl| crl as &dyn webpki::CertRevocationList)
+        .collect();
 
+    if !trust_anchors.is_empty() {
         trace!("We got {} certificate entries", certificate.entries.len());
+        let ocsp_response = certificate.ocsp_response();
 
-        if !certificate.entries.is_empty() {
-            // TODO: Support intermediates...
-            if let CertificateEntryRef::X509(certificate) = certificate.entries[0] {
-                let cert = webpki::EndEntityCert::try_from(certificate).map_err(|e| {
-                    warn!("Error loading cert: {:?}", e);
-                    TlsError::DecodeError
-                })?;
-
-                let time = if let Some(now) = now {
-                    webpki::Time::from_seconds_since_unix_epoch(now)
-                } else {
-                    // If no clock is provided, the validity check will fail
-                    webpki::Time::from_seconds_since_unix_epoch(0)
-                };
-                info!("Certificate is loaded!");
-                match cert.verify_for_usage(
-                    ALL_SIGALGS,
-                    &[trust],
-                    &[],
-                    time,
-                    webpki::KeyUsage::server_auth(),
-                    &[],
-                ) {
-                    Ok(_) => verified = true,
-                    Err(e) => {
-                        warn!("Error verifying certificate: {:?}", e);
+        if let Some((CertificateEntryRef::X509(certificate), chain)) =
+            certificate.entries.split_first()
+        {
+            // The leaf is followed by the intermediates certifying it, possibly up to the root
+            let intermediates: Vec<&[u8], { MAX_CERTIFICATE_CHAIN_LEN - 1 }> = chain
+                .iter()
+                .filter_map(|entry| match entry {
+                    CertificateEntryRef::X509(certificate) => Some(*certificate),
+                    CertificateEntryRef::RawPublicKey(_) => None,
+                })
+                .collect();
+
+            let cert = webpki::EndEntityCert::try_from(*certificate).map_err(|e| {
+                warn!("Error loading cert: {:?}", e);
+                TlsError::DecodeError
+            })?;
+
+            let time = if let Some(now) = now {
+                webpki::Time::from_seconds_since_unix_epoch(now)
+            } else {
+                // If no clock is provided, the validity check will fail
+                webpki::Time::from_seconds_since_unix_epoch(0)
+            };
+            info!("Certificate is loaded!");
+            match cert.verify_for_usage(
+                ALL_SIGALGS,
+                &trust_anchors,
+                &intermediates,
+                time,
+                webpki::KeyUsage::server_auth(),
+                &revocation_lists,
+            ) {
+                Ok(_) => {
+                    if ocsp_stapling {
+                        check_revocation_status(
+                            certificate,
+                            &intermediates,
+                            &trust_anchors,
+                            ocsp_response,
+                            now.unwrap_or(0),
+                        )?;
                     }
+                    verified = true;
+                }
+                Err(webpki::Error::CertRevoked) => {
+                    warn!("Certificate is revoked");
+                    return Err(TlsError::CertificateRevoked);
                 }
+                Err(e) => {
+                    warn!("Error verifying certificate: {:?}", e);
+                }
+            }
 
-                if let Some(server_name) = verify_host {
-                    match webpki::SubjectNameRef::try_from_ascii(server_name.as_bytes()) {
-                        Ok(subject) => match cert.verify_is_valid_for_subject_name(subject) {
-                            Ok(_) => host_verified = true,
-                            Err(e) => {
-                                warn!("Error verifying host: {:?}", e);
-                            }
-                        },
+            if let Some(server_name) = verify_host {
+                match webpki::SubjectNameRef::try_from_ascii(server_name.as_bytes()) {
+                    Ok(subject) => match cert.verify_is_valid_for_subject_name(subject) {
+                        Ok(_) => host_verified = true,
                         Err(e) => {
                             warn!("Error verifying host: {:?}", e);
                         }
+                    },
+                    Err(e) => {
+                        warn!("Error verifying host: {:?}", e);
                     }
                 }
             }
@@ -250,3 +338,32 @@ fn verify_certificate(
     }
     Ok(())
 }
+
+/// Checks the revocation status of the leaf certificate of a verified chain, with the OCSP
+/// response stapled by the server.
+fn check_revocation_status(
+    leaf: &[u8],
+    intermediates: &[&[u8]],
+    trust_anchors: &[webpki::TrustAnchor],
+    ocsp_response: Option<&[u8]>,
+    now: u64,
+) -> Result<(), TlsError> {
+    let leaf = X509Certificate::parse(leaf).map_err(|_| TlsError::DecodeError)?;
+
+    // The issuer is one of the intermediates, or a trust anchor if the chain has none
+    let issuer_public_key = intermediates
+        .iter()
+        .filter_map(|intermediate| X509Certificate::parse(intermediate).ok())
+        .find(|intermediate| intermediate.subject == leaf.issuer)
+        .map(|intermediate| intermediate.subject_public_key())
+        .or_else(|| {
+            trust_anchors
+                .iter()
+                .find(|trust| trust.subject == leaf.issuer)
+                .map(|trust| der::subject_public_key(trust.spki))
+        })
+        .ok_or(TlsError::InvalidCertificate)?
+        .map_err(|_| TlsError::DecodeError)?;
+
+    ocsp::check_revocation_status(&leaf, issuer_public_key, ocsp_response, now)
+}
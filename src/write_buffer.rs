@@ -1,16 +1,26 @@
 use crate::{
     buffer::CryptoBuffer,
-    config::{TlsCipherSuite, TLS_RECORD_OVERHEAD},
+    config::{MaxFragmentLength, TlsCipherSuite, TLS_RECORD_OVERHEAD},
     connection::encrypt,
     key_schedule::{ReadKeySchedule, WriteKeySchedule},
     record::{ClientRecord, ClientRecordHeader},
     TlsError,
 };
+use aes_gcm::aead::AeadCore;
+use digest::generic_array::typenum::Unsigned;
+
+/// The maximum plaintext fragment length without the max_fragment_length extension
+/// (Section 5.1).
+pub(crate) const MAX_PLAINTEXT_LEN: usize = 1 << 14;
+
+const HEADER_SIZE: usize = 5;
 
 pub struct WriteBuffer<'a> {
     buffer: &'a mut [u8],
     pos: usize,
     current_header: Option<ClientRecordHeader>,
+    /// The maximum plaintext length of a record
+    max_fragment_len: usize,
 }
 
 impl<'a> WriteBuffer<'a> {
@@ -23,11 +33,24 @@ impl<'a> WriteBuffer<'a> {
             buffer,
             pos: 0,
             current_header: None,
+            max_fragment_len: MAX_PLAINTEXT_LEN,
         }
     }
 
+    /// Limits the plaintext of the records written afterwards to the negotiated maximum
+    /// fragment length.
+    pub(crate) fn set_max_fragment_length(
+        &mut self,
+        max_fragment_length: Option<MaxFragmentLength>,
+    ) {
+        self.max_fragment_len = max_fragment_length.map_or(MAX_PLAINTEXT_LEN, |max| max.size());
+    }
+
     fn max_block_size(&self) -> usize {
-        self.buffer.len() - TLS_RECORD_OVERHEAD
+        usize::min(
+            self.buffer.len() - TLS_RECORD_OVERHEAD,
+            self.max_fragment_len,
+        )
     }
 
     pub fn is_full(&self) -> bool {
@@ -55,6 +78,19 @@ impl<'a> WriteBuffer<'a> {
         self.max_block_size() - self.pos
     }
 
+    /// Returns the last unencrypted record written to the buffer.
+    ///
+    /// The returned slice is only meaningful until a new record is started.
+    pub(crate) fn last_record(&self) -> &[u8] {
+        let len = u16::from_be_bytes([self.buffer[3], self.buffer[4]]) as usize;
+        &self.buffer[..HEADER_SIZE + len]
+    }
+
+    /// Returns the payload of the last unencrypted record written to the buffer.
+    pub(crate) fn last_record_payload(&self) -> &[u8] {
+        &self.last_record()[HEADER_SIZE..]
+    }
+
     pub fn contains(&self, header: ClientRecordHeader) -> bool {
         self.current_header == Some(header)
     }
@@ -94,8 +130,6 @@ impl<'a> WriteBuffer<'a> {
     where
         CipherSuite: TlsCipherSuite,
     {
-        const HEADER_SIZE: usize = 5;
-
         let header = self.current_header.take().unwrap();
         self.with_buffer(|mut buf| {
             if !header.is_encrypted() {
@@ -140,13 +174,143 @@ impl<'a> WriteBuffer<'a> {
             let mut buf = buf.forward();
             record.encode_payload(&mut buf)?;
 
-            let transcript = read_key_schedule
-                .ok_or(TlsError::InternalError)?
-                .transcript_hash();
-
-            record.finish_record(&mut buf, transcript, write_key_schedule)?;
+            // Post-handshake messages are not part of the transcript
+            if let Some(read_key_schedule) = read_key_schedule {
+                record.finish_record(&mut buf, read_key_schedule.transcript_hash())?;
+            }
             Ok(buf.rewind())
         })?;
+        if self.pos - HEADER_SIZE > self.max_fragment_len && record.header().is_encrypted() {
+            return self.close_fragmented_record(write_key_schedule);
+        }
         self.close_record(write_key_schedule)
     }
+
+    /// Splits the payload of the current record into records of at most the maximum fragment
+    /// length, which are encrypted one after the other.
+    ///
+    /// All but the last record are counted here, the last one is counted by the caller once the
+    /// records are sent, like a single record.
+    fn close_fragmented_record<CipherSuite>(
+        &mut self,
+        write_key_schedule: &mut WriteKeySchedule<CipherSuite>,
+    ) -> Result<&[u8], TlsError>
+    where
+        CipherSuite: TlsCipherSuite,
+    {
+        let header = self.current_header.take().unwrap();
+        let payload_len = self.pos - HEADER_SIZE;
+        let records = payload_len.div_ceil(self.max_fragment_len);
+        let record_overhead =
+            HEADER_SIZE + 1 + <CipherSuite::Cipher as AeadCore>::TagSize::to_usize();
+        if payload_len + records * record_overhead > self.buffer.len() {
+            return Err(TlsError::InsufficientSpace);
+        }
+
+        // The payload is moved to the end of the buffer, so that each record is written in
+        // front of the remaining payload
+        let end = self.buffer.len();
+        let mut src = end - payload_len;
+        self.buffer.copy_within(HEADER_SIZE..self.pos, src);
+        let mut dst = 0;
+        while src < end {
+            let len = usize::min(self.max_fragment_len, end - src);
+            self.buffer.copy_within(src..src + len, dst + HEADER_SIZE);
+            src += len;
+
+            let mut buf = CryptoBuffer::wrap(&mut self.buffer[dst..src]);
+            header.encode(&mut buf)?;
+            buf.push_u16(0)?;
+
+            let mut buf =
+                CryptoBuffer::wrap_with_pos(&mut self.buffer[dst..src], HEADER_SIZE + len);
+            buf.push(header.trailer_content_type() as u8)
+                .map_err(|_| TlsError::EncodeError)?;
+            let mut buf = buf.offset(HEADER_SIZE);
+            encrypt(write_key_schedule, &mut buf)?;
+            let record_len = buf.len();
+
+            self.buffer[dst + 3..dst + HEADER_SIZE]
+                .copy_from_slice(&(record_len as u16).to_be_bytes());
+            dst += HEADER_SIZE + record_len;
+            if src < end {
+                write_key_schedule.increment_counter()?;
+            }
+        }
+
+        self.pos = 0;
+        Ok(&self.buffer[..dst])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Certificate;
+    use crate::content_types::ContentType;
+    use crate::handshake::certificate::CertificateRef;
+    use crate::handshake::{ClientHandshake, HandshakeType};
+    use crate::key_schedule::KeySchedule;
+    use crate::Aes128GcmSha256;
+    use aes_gcm::aead::{AeadInPlace, KeyInit};
+
+    #[test]
+    fn test_write_fragmented_record() {
+        let mut client = KeySchedule::<Aes128GcmSha256>::new();
+        let mut server = KeySchedule::<Aes128GcmSha256>::new_server();
+        for key_schedule in [&mut client, &mut server] {
+            key_schedule.initialize_early_secret(None).unwrap();
+            key_schedule
+                .initialize_handshake_secret(&[0x42; 32])
+                .unwrap();
+        }
+
+        let der = [0xaa; 1200];
+        let cert = Certificate::X509(&der);
+        let mut certificate = CertificateRef::with_context(&[]);
+        certificate.add((&cert).try_into().unwrap()).unwrap();
+        let record: ClientRecord<'_, '_, Aes128GcmSha256> =
+            ClientRecord::Handshake(ClientHandshake::Certificate(certificate), true);
+
+        let mut buf = [0; 2048];
+        let mut tx = WriteBuffer::new(&mut buf);
+        tx.set_max_fragment_length(Some(MaxFragmentLength::Bits9));
+        let records = tx
+            .write_record(&record, client.write_state(), None)
+            .unwrap();
+
+        let mut plaintext = [0; 2048];
+        let mut plaintext_len = 0;
+        let mut count = 0;
+        let mut pos = 0;
+        while pos < records.len() {
+            let header: [u8; HEADER_SIZE] = records[pos..pos + HEADER_SIZE].try_into().unwrap();
+            let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+            assert_eq!(ContentType::ApplicationData as u8, header[0]);
+            assert!(len <= 512 + 1 + 16);
+
+            let mut data = [0; 1024];
+            data[..len].copy_from_slice(&records[pos + HEADER_SIZE..pos + HEADER_SIZE + len]);
+            let mut data = CryptoBuffer::wrap_with_pos(&mut data, len);
+            let read_key_schedule = server.read_state();
+            let cipher = aes_gcm::Aes128Gcm::new(&read_key_schedule.get_key().unwrap());
+            cipher
+                .decrypt_in_place(&read_key_schedule.get_nonce().unwrap(), &header, &mut data)
+                .unwrap();
+            read_key_schedule.increment_counter().unwrap();
+
+            let (content_type, fragment) = data.as_slice().split_last().unwrap();
+            assert_eq!(ContentType::Handshake as u8, *content_type);
+            assert!(fragment.len() <= 512);
+            plaintext[plaintext_len..plaintext_len + fragment.len()].copy_from_slice(fragment);
+            plaintext_len += fragment.len();
+            count += 1;
+            pos += HEADER_SIZE + len;
+        }
+
+        // Handshake header, empty context, certificate list with one entry without extensions
+        assert_eq!(4 + 1 + 3 + 3 + 1200 + 2, plaintext_len);
+        assert_eq!(HandshakeType::Certificate as u8, plaintext[0]);
+        assert_eq!(3, count);
+    }
 }
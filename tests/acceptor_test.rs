@@ -0,0 +1,538 @@
+#![macro_use]
+use embedded_io_adapters::tokio_1::FromTokio;
+use embedded_io_async::{Read as _, Write as _};
+use embedded_tls::*;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::ssl;
+use openssl::symm;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Once};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+fn init() {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+}
+
+/// Connects an OpenSSL client verifying the server certificate with the test CA, returning the
+/// application protocol it negotiated once the server echoed a ping.
+fn connect(addr: SocketAddr, alpn: &'static [u8]) -> JoinHandle<Option<Vec<u8>>> {
+    let mut builder = ssl::SslConnector::builder(ssl::SslMethod::tls_client()).unwrap();
+    builder.set_ca_file("tests/data/ca-cert.pem").unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    builder.set_alpn_protos(alpn).unwrap();
+    let connector = builder.build();
+
+    tokio::task::spawn_blocking(move || {
+        let stream = std::net::TcpStream::connect(addr).unwrap();
+        let mut conn = connector.connect("localhost", stream).ok()?;
+        conn.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        conn.read_exact(&mut buf).unwrap();
+        assert_eq!(b"ping", &buf);
+        Some(conn.ssl().selected_alpn_protocol()?.to_vec())
+    })
+}
+
+/// Connects an OpenSSL client that sends the handshake message `message` after the handshake,
+/// protected with its first application traffic key, returning the error of its next read.
+fn connect_sending(addr: SocketAddr, message: &'static [u8]) -> JoinHandle<String> {
+    let secret = Arc::new(Mutex::new(None));
+    let mut builder = ssl::SslConnector::builder(ssl::SslMethod::tls_client()).unwrap();
+    builder.set_ca_file("tests/data/ca-cert.pem").unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    builder.set_ciphersuites("TLS_AES_128_GCM_SHA256").unwrap();
+    let keylog = secret.clone();
+    builder.set_keylog_callback(move |_ssl, line| {
+        if let Some(line) = line.strip_prefix("CLIENT_TRAFFIC_SECRET_0 ") {
+            let hex = line.split(' ').nth(1).unwrap();
+            let bytes: Vec<u8> = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect();
+            keylog.lock().unwrap().replace(bytes);
+        }
+    });
+    let connector = builder.build();
+
+    tokio::task::spawn_blocking(move || {
+        let stream = std::net::TcpStream::connect(addr).unwrap();
+        let mut conn = connector.connect("localhost", stream).unwrap();
+        let secret = secret.lock().unwrap().take().unwrap();
+
+        // Section 5.2: the first record uses the IV as nonce, and the record header as additional
+        // data
+        let key = hkdf_expand_label(&secret, b"key", 16);
+        let iv = hkdf_expand_label(&secret, b"iv", 12);
+        let mut plaintext = message.to_vec();
+        plaintext.push(22);
+        let len = plaintext.len() + 16;
+        let header = [23, 3, 3, (len >> 8) as u8, len as u8];
+        let mut tag = [0; 16];
+        let ciphertext = symm::encrypt_aead(
+            symm::Cipher::aes_128_gcm(),
+            &key,
+            Some(&iv),
+            &header,
+            &plaintext,
+            &mut tag,
+        )
+        .unwrap();
+        let stream = conn.get_mut();
+        stream.write_all(&header).unwrap();
+        stream.write_all(&ciphertext).unwrap();
+        stream.write_all(&tag).unwrap();
+
+        conn.read(&mut [0; 1]).unwrap_err().to_string()
+    })
+}
+
+/// Connects a client that sends `data`, returning the header and the first byte of the record
+/// the server answers with.
+fn connect_raw(addr: SocketAddr, data: Vec<u8>) -> JoinHandle<[u8; 6]> {
+    tokio::task::spawn_blocking(move || {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream.write_all(&data).unwrap();
+        let mut rx = [0; 6];
+        stream.read_exact(&mut rx).unwrap();
+        rx
+    })
+}
+
+/// Encodes a ClientHello record offering TLS_AES_128_GCM_SHA256 with ecdsa_secp256r1_sha256
+/// signatures, and a key share for each of `key_shares`.
+fn client_hello(key_shares: &[(NamedGroup, Vec<u8>)]) -> Vec<u8> {
+    fn with_u16_length(buf: &mut Vec<u8>, data: &[u8]) {
+        buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        buf.extend_from_slice(data);
+    }
+    fn extension(buf: &mut Vec<u8>, extension_type: u16, data: &[u8]) {
+        buf.extend_from_slice(&extension_type.to_be_bytes());
+        with_u16_length(buf, data);
+    }
+
+    let mut groups = Vec::new();
+    let mut shares = Vec::new();
+    for (group, share) in key_shares {
+        groups.extend_from_slice(&(*group as u16).to_be_bytes());
+        shares.extend_from_slice(&(*group as u16).to_be_bytes());
+        with_u16_length(&mut shares, share);
+    }
+    let mut extensions = Vec::new();
+    extension(&mut extensions, 43, &[2, 3, 4]);
+    extension(&mut extensions, 13, &[0, 2, 4, 3]);
+    let mut data = Vec::new();
+    with_u16_length(&mut data, &groups);
+    extension(&mut extensions, 10, &data);
+    let mut data = Vec::new();
+    with_u16_length(&mut data, &shares);
+    extension(&mut extensions, 51, &data);
+
+    let mut body = vec![3, 3];
+    body.extend_from_slice(&[0x5a; 32]);
+    body.extend_from_slice(&[0, 0, 2, 0x13, 0x01, 1, 0]);
+    with_u16_length(&mut body, &extensions);
+
+    let mut handshake = vec![1, 0];
+    with_u16_length(&mut handshake, &body);
+    let mut record = vec![22, 3, 1];
+    with_u16_length(&mut record, &handshake);
+    record
+}
+
+/// HKDF-Expand-Label with SHA-256 and an empty context, for up to 32 bytes.
+fn hkdf_expand_label(secret: &[u8], label: &[u8], len: usize) -> Vec<u8> {
+    let mut info = vec![0, len as u8, 6 + label.len() as u8];
+    info.extend_from_slice(b"tls13 ");
+    info.extend_from_slice(label);
+    info.extend_from_slice(&[0, 1]);
+
+    let key = PKey::hmac(secret).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(&info).unwrap();
+    let mut output = signer.sign_to_vec().unwrap();
+    output.truncate(len);
+    output
+}
+
+/// Accepts a connection and echoes a ping, returning the application protocol selected by the
+/// server.
+async fn accept_ping(
+    listener: &TcpListener,
+    config: &TlsConfig<'_>,
+) -> Result<Option<Vec<u8>>, TlsError> {
+    let (stream, _) = listener.accept().await.unwrap();
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let acceptor: TlsAcceptor<FromTokio<TcpStream>> = TlsAcceptor::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    let mut tls = acceptor
+        .accept(TlsContext::new(config, &mut OsRng))
+        .await
+        .map_err(|(_, e)| e)?;
+    let alpn_protocol = tls.alpn_protocol().map(<[u8]>::to_vec);
+
+    let mut rx = [0; 4];
+    tls.read_exact(&mut rx).await.map_err(|e| match e {
+        embedded_io::ReadExactError::Other(e) => e,
+        embedded_io::ReadExactError::UnexpectedEof => TlsError::ConnectionClosed,
+    })?;
+    tls.write_all(&rx).await?;
+    tls.flush().await?;
+    tls.close().await.map_err(|(_, e)| e)?;
+    Ok(alpn_protocol)
+}
+
+async fn listen() -> (SocketAddr, TcpListener) {
+    init();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    (listener.local_addr().unwrap(), listener)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_accept() {
+    let (addr, listener) = listen().await;
+    timeout(Duration::from_secs(120), async move {
+        let cert = pem_parser::pem_to_der(include_str!("data/server-cert.pem"));
+        let key = pem_parser::pem_to_der(include_str!("data/server-key.pem"));
+        let signer = P256Signer::from_pkcs8_der(&key).unwrap();
+        // OpenSSL sends no key share for secp256r1, so the server asks for one with a
+        // HelloRetryRequest
+        let config = TlsConfig::new()
+            .with_cert(Certificate::X509(&cert))
+            .with_signer(&signer)
+            .with_alpn_protocols(&[b"h2"]);
+
+        let h = connect(addr, b"\x08http/1.1\x02h2");
+        let alpn_protocol = accept_ping(&listener, &config)
+            .await
+            .expect("error accepting TLS connection");
+        assert_eq!(Some(b"h2".to_vec()), alpn_protocol);
+        assert_eq!(Some(b"h2".to_vec()), h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_accept_x25519() {
+    let (addr, listener) = listen().await;
+    timeout(Duration::from_secs(120), async move {
+        let cert = pem_parser::pem_to_der(include_str!("data/server-cert.pem"));
+        let key = pem_parser::pem_to_der(include_str!("data/server-key.pem"));
+        let signer = P256Signer::from_pkcs8_der(&key).unwrap();
+        let config = TlsConfig::new()
+            .with_cert(Certificate::X509(&cert))
+            .with_signer(&signer)
+            .with_named_groups(&[NamedGroup::X25519]);
+
+        let h = connect(addr, b"");
+        accept_ping(&listener, &config)
+            .await
+            .expect("error accepting TLS connection");
+        assert_eq!(None, h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_accept_no_common_alpn_protocol() {
+    let (addr, listener) = listen().await;
+    timeout(Duration::from_secs(120), async move {
+        let cert = pem_parser::pem_to_der(include_str!("data/server-cert.pem"));
+        let key = pem_parser::pem_to_der(include_str!("data/server-key.pem"));
+        let signer = P256Signer::from_pkcs8_der(&key).unwrap();
+        let config = TlsConfig::new()
+            .with_cert(Certificate::X509(&cert))
+            .with_signer(&signer)
+            .with_alpn_protocols(&[b"h2"]);
+
+        let h = connect(addr, b"\x02h3");
+        assert!(matches!(
+            accept_ping(&listener, &config).await,
+            Err(TlsError::AbortHandshake(
+                alert::AlertLevel::Fatal,
+                alert::AlertDescription::NoApplicationProtocol
+            ))
+        ));
+        assert_eq!(None, h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_accept_psk() {
+    let (addr, listener) = listen().await;
+    timeout(Duration::from_secs(120), async move {
+        let server_config = TlsConfig::new().with_psk(&[0xaa, 0xbb, 0xcc, 0xdd], &[b"vader"]);
+        let server = async {
+            accept_ping(&listener, &server_config)
+                .await
+                .expect("error accepting TLS connection");
+        };
+
+        let client = async {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let mut read_record_buffer = [0; 16384];
+            let mut write_record_buffer = [0; 16384];
+            let config = TlsConfig::new()
+                .with_psk(&[0xaa, 0xbb, 0xcc, 0xdd], &[b"vader"])
+                .with_server_name("localhost");
+            let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+                FromTokio::new(stream),
+                &mut read_record_buffer,
+                &mut write_record_buffer,
+            );
+
+            tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+                .await
+                .expect("error establishing TLS connection");
+            tls.write_all(b"ping").await.unwrap();
+            tls.flush().await.unwrap();
+            let mut rx = [0; 4];
+            tls.read_exact(&mut rx).await.unwrap();
+            assert_eq!(b"ping", &rx);
+        };
+
+        tokio::join!(server, client);
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_accept_wrong_psk() {
+    let (addr, listener) = listen().await;
+    timeout(Duration::from_secs(120), async move {
+        let server_config = TlsConfig::new().with_psk(&[0xaa, 0xbb, 0xcc, 0xdd], &[b"vader"]);
+        let server = async {
+            // The binder does not verify with another key
+            assert!(matches!(
+                accept_ping(&listener, &server_config).await,
+                Err(TlsError::AbortHandshake(
+                    alert::AlertLevel::Fatal,
+                    alert::AlertDescription::DecryptError
+                ))
+            ));
+        };
+
+        let client = async {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let mut read_record_buffer = [0; 16384];
+            let mut write_record_buffer = [0; 16384];
+            let config = TlsConfig::new()
+                .with_psk(&[0x11, 0x22, 0x33, 0x44], &[b"vader"])
+                .with_server_name("localhost");
+            let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+                FromTokio::new(stream),
+                &mut read_record_buffer,
+                &mut write_record_buffer,
+            );
+
+            assert!(tls
+                .open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+                .await
+                .is_err());
+        };
+
+        tokio::join!(server, client);
+    })
+    .await
+    .unwrap();
+}
+
+/// Accepts a connection from a client sending `message`, which only a server may send.
+async fn test_accept_unexpected_message(message: &'static [u8]) {
+    let (addr, listener) = listen().await;
+    timeout(Duration::from_secs(120), async move {
+        let cert = pem_parser::pem_to_der(include_str!("data/server-cert.pem"));
+        let key = pem_parser::pem_to_der(include_str!("data/server-key.pem"));
+        let signer = P256Signer::from_pkcs8_der(&key).unwrap();
+        let config = TlsConfig::new()
+            .with_cert(Certificate::X509(&cert))
+            .with_signer(&signer);
+
+        let h = connect_sending(addr, message);
+        assert!(matches!(
+            accept_ping(&listener, &config).await,
+            Err(TlsError::AbortHandshake(
+                alert::AlertLevel::Fatal,
+                alert::AlertDescription::UnexpectedMessage
+            ))
+        ));
+        let error = h.await.unwrap();
+        assert!(error.contains("unexpected message"), "{}", error);
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_accept_new_session_ticket() {
+    // A ticket with a lifetime of an hour, no nonce, a one byte ticket and no extensions
+    test_accept_unexpected_message(&[
+        4, 0, 0, 14, 0, 0, 0x0e, 0x10, 0, 0, 0, 0, 0, 0, 1, 0xaa, 0, 0,
+    ])
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_accept_certificate_request() {
+    // A request without context accepting ecdsa_secp256r1_sha256 signatures
+    test_accept_unexpected_message(&[13, 0, 0, 11, 0, 0, 8, 0, 13, 0, 4, 0, 2, 4, 3]).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_accept_four_key_shares() {
+    let (addr, listener) = listen().await;
+    timeout(Duration::from_secs(120), async move {
+        let cert = pem_parser::pem_to_der(include_str!("data/server-cert.pem"));
+        let key = pem_parser::pem_to_der(include_str!("data/server-key.pem"));
+        let signer = P256Signer::from_pkcs8_der(&key).unwrap();
+        let config = TlsConfig::new()
+            .with_cert(Certificate::X509(&cert))
+            .with_signer(&signer)
+            .with_named_groups(&[NamedGroup::X25519]);
+
+        // The key shares past the first three are ignored
+        let x25519 = PKey::generate_x25519().unwrap();
+        let h = connect_raw(
+            addr,
+            client_hello(&[
+                (NamedGroup::X25519, x25519.raw_public_key().unwrap()),
+                (NamedGroup::Secp256r1, vec![4; 65]),
+                (NamedGroup::Secp384r1, vec![4; 97]),
+                (NamedGroup::X448, vec![0x5a; 56]),
+            ]),
+        );
+        let _ = accept_ping(&listener, &config).await;
+        let rx = h.await.unwrap();
+        // A ServerHello
+        assert_eq!([22, 2], [rx[0], rx[5]]);
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_accept_record_overflow() {
+    let (addr, listener) = listen().await;
+    timeout(Duration::from_secs(120), async move {
+        let cert = pem_parser::pem_to_der(include_str!("data/server-cert.pem"));
+        let key = pem_parser::pem_to_der(include_str!("data/server-key.pem"));
+        let signer = P256Signer::from_pkcs8_der(&key).unwrap();
+        let config = TlsConfig::new()
+            .with_cert(Certificate::X509(&cert))
+            .with_signer(&signer);
+
+        // The header of a handshake record of 2^14 + 1 bytes
+        let h = connect_raw(addr, vec![22, 3, 1, 0x40, 0x01]);
+        assert!(matches!(
+            accept_ping(&listener, &config).await,
+            Err(TlsError::AbortHandshake(
+                alert::AlertLevel::Fatal,
+                alert::AlertDescription::RecordOverflow
+            ))
+        ));
+        // A fatal record_overflow alert
+        let rx = h.await.unwrap();
+        assert_eq!([21, 0, 2, 2], [rx[0], rx[3], rx[4], rx[5]]);
+    })
+    .await
+    .unwrap();
+}
+
+#[test]
+fn test_accept_blocking_split() {
+    use embedded_io_adapters::std::FromStd;
+    use embedded_tls::blocking::*;
+    use std::sync::Arc;
+
+    init();
+
+    #[derive(Clone)]
+    struct Clonable(Arc<std::net::TcpStream>);
+
+    impl embedded_io::ErrorType for Clonable {
+        type Error = std::io::Error;
+    }
+
+    impl embedded_io::Read for Clonable {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            FromStd::new(self.0.as_ref()).read(buf)
+        }
+    }
+
+    impl embedded_io::Write for Clonable {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            FromStd::new(self.0.as_ref()).write(buf)
+        }
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            FromStd::new(self.0.as_ref()).flush()
+        }
+    }
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut builder = ssl::SslConnector::builder(ssl::SslMethod::tls_client()).unwrap();
+    builder.set_ca_file("tests/data/ca-cert.pem").unwrap();
+    let connector = builder.build();
+    let client = std::thread::spawn(move || {
+        let stream = std::net::TcpStream::connect(addr).unwrap();
+        let mut conn = connector.connect("localhost", stream).unwrap();
+        conn.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        conn.read_exact(&mut buf).unwrap();
+        assert_eq!(b"ping", &buf);
+    });
+
+    let cert = pem_parser::pem_to_der(include_str!("data/server-cert.pem"));
+    let key = pem_parser::pem_to_der(include_str!("data/server-key.pem"));
+    let signer = P256Signer::from_pkcs8_der(&key).unwrap();
+    let config = TlsConfig::new()
+        .with_cert(Certificate::X509(&cert))
+        .with_signer(&signer);
+
+    let (stream, _) = listener.accept().unwrap();
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let acceptor: TlsAcceptor<Clonable> = TlsAcceptor::new(
+        Clonable(Arc::new(stream)),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+    let tls = acceptor
+        .accept(TlsContext::new(&config, &mut OsRng))
+        .map_err(|(_, e)| e)
+        .expect("error accepting TLS connection");
+
+    let (mut reader, mut writer) = tls.split();
+    let mut buf = [0; 4];
+    embedded_io::Read::read_exact(&mut reader, &mut buf).expect("Failed to read data");
+    embedded_io::Write::write_all(&mut writer, &buf).expect("Failed to write data");
+    embedded_io::Write::flush(&mut writer).expect("Failed to flush");
+
+    client.join().unwrap();
+}
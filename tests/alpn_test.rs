@@ -0,0 +1,152 @@
+#![macro_use]
+use embedded_io_adapters::{std::FromStd, tokio_1::FromTokio};
+use embedded_tls::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+/// Starts a server selecting the first of `protocols` offered by the client, in the wire format
+/// of `SslContextBuilder::set_alpn_protos`, or no protocol if `protocols` is empty. The server
+/// task returns the protocol it selected.
+fn setup(protocols: &'static [u8]) -> (SocketAddr, JoinHandle<Option<Vec<u8>>>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/server-cert.pem")
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    if !protocols.is_empty() {
+        builder.set_alpn_select_callback(move |_, client| {
+            ssl::select_next_proto(protocols, client).ok_or(ssl::AlpnError::NOACK)
+        });
+    }
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = tokio::task::spawn_blocking(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut conn = acceptor.accept(stream).unwrap();
+
+        let mut buf = [0; 64];
+        let len = conn.read(&mut buf[..]).unwrap();
+        conn.write_all(&buf[..len]).unwrap();
+        conn.ssl().selected_alpn_protocol().map(|p| p.to_vec())
+    });
+    (addr, h)
+}
+
+async fn ping(addr: SocketAddr, protocols: &[&[u8]]) -> Result<Option<Vec<u8>>, TlsError> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_alpn_protocols(protocols);
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .await?;
+    let protocol = tls.alpn_protocol().map(|p| p.to_vec());
+
+    tls.write(b"ping").await?;
+    tls.flush().await?;
+
+    let mut rx = [0; 4];
+    let l = tls.read(&mut rx[..]).await?;
+    assert_eq!(4, l);
+    assert_eq!(b"ping", &rx[..l]);
+    Ok(protocol)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_alpn_protocol_selected() {
+    // The server prefers HTTP/1.1 over HTTP/2
+    let (addr, h) = setup(b"\x08http/1.1\x02h2");
+    timeout(Duration::from_secs(120), async move {
+        let protocol = ping(addr, &[b"h2", b"http/1.1"])
+            .await
+            .expect("error establishing TLS connection");
+        assert_eq!(Some(b"http/1.1".to_vec()), protocol);
+        assert_eq!(Some(b"http/1.1".to_vec()), h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_alpn_no_protocol_selected() {
+    let (addr, h) = setup(b"");
+    timeout(Duration::from_secs(120), async move {
+        let protocol = ping(addr, &[b"x-amzn-mqtt-ca"])
+            .await
+            .expect("error establishing TLS connection");
+        assert_eq!(None, protocol);
+        assert_eq!(None, h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_blocking_alpn() {
+    use embedded_tls::blocking::*;
+
+    let (addr, h) = setup(b"\x0ex-amzn-mqtt-ca");
+    let stream = std::net::TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromStd<std::net::TcpStream>> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_alpn_protocols(&[b"x-amzn-mqtt-ca"]);
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+    assert_eq!(Some(&b"x-amzn-mqtt-ca"[..]), tls.alpn_protocol());
+
+    tls.write(b"ping").expect("error writing data");
+    tls.flush().expect("error flushing data");
+
+    let mut rx_buf = [0; 4];
+    let sz = tls.read(&mut rx_buf).expect("error reading data");
+    assert_eq!(4, sz);
+    assert_eq!(b"ping", &rx_buf[..sz]);
+
+    assert_eq!(Some(b"x-amzn-mqtt-ca".to_vec()), h.await.unwrap());
+}
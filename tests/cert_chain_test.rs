@@ -0,0 +1,292 @@
+#![macro_use]
+use embedded_io_adapters::tokio_1::FromTokio;
+use embedded_tls::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+/// Starts a server sending the certificates of `chain`, leaf first, for the key of
+/// leaf-cert.pem.
+fn setup(chain: &[&str]) -> (SocketAddr, JoinHandle<()>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/leaf-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    let mut certificates = chain.iter().map(|pem| {
+        let pem = std::fs::read(format!("tests/data/{pem}")).unwrap();
+        openssl::x509::X509::from_pem(&pem).unwrap()
+    });
+    builder
+        .set_certificate(&certificates.next().unwrap())
+        .unwrap();
+    for certificate in certificates {
+        builder.add_extra_chain_cert(certificate).unwrap();
+    }
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = tokio::task::spawn_blocking(move || {
+        let (stream, _) = listener.accept().unwrap();
+        if let Ok(mut conn) = acceptor.accept(stream) {
+            let mut buf = [0; 64];
+            let len = conn.read(&mut buf[..]).unwrap();
+            conn.write_all(&buf[..len]).unwrap();
+        }
+    });
+    (addr, h)
+}
+
+async fn ping<'v, Verifier>(
+    addr: SocketAddr,
+    context: TlsContext<'v, OsRng>,
+) -> Result<(), TlsError>
+where
+    Verifier: TlsVerifier<'v>,
+{
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, Verifier>(context).await?;
+
+    tls.write(b"ping").await?;
+    tls.flush().await?;
+
+    let mut rx = [0; 4];
+    let l = tls.read(&mut rx[..]).await?;
+    assert_eq!(4, l);
+    assert_eq!(b"ping", &rx[..l]);
+    Ok(())
+}
+
+#[cfg(feature = "webpki")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_intermediate_certificate() {
+    use embedded_tls::webpki::CertVerifier;
+
+    let (addr, h) = setup(&["leaf-cert.pem", "intermediate-cert.pem"]);
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_server_name("localhost");
+
+        ping::<CertVerifier<4096>>(
+            addr,
+            TlsContext::new(&config, &mut OsRng).with_clock(&SystemClock),
+        )
+        .await
+        .expect("error establishing TLS connection");
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[cfg(feature = "webpki")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_missing_intermediate_certificate() {
+    use embedded_tls::webpki::CertVerifier;
+
+    let (addr, h) = setup(&["leaf-cert.pem"]);
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_server_name("localhost");
+
+        let result = ping::<CertVerifier<4096>>(
+            addr,
+            TlsContext::new(&config, &mut OsRng).with_clock(&SystemClock),
+        )
+        .await;
+        assert!(matches!(result, Err(TlsError::InvalidCertificate)));
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[cfg(feature = "webpki")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_multiple_trust_anchors() {
+    use embedded_tls::webpki::CertVerifier;
+
+    let (addr, h) = setup(&["leaf-cert.pem", "intermediate-cert.pem"]);
+    timeout(Duration::from_secs(120), async move {
+        let other_ca = pem_parser::pem_to_der(include_str!("data/other-ca-cert.pem"));
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&other_ca))
+            .with_ca(Certificate::TrustAnchor(
+                TrustAnchor::from_cert_der(&ca).unwrap(),
+            ))
+            .with_server_name("localhost");
+
+        ping::<CertVerifier<4096>>(
+            addr,
+            TlsContext::new(&config, &mut OsRng).with_clock(&SystemClock),
+        )
+        .await
+        .expect("error establishing TLS connection");
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[cfg(feature = "webpki")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_untrusted_certificate() {
+    use embedded_tls::webpki::CertVerifier;
+
+    let (addr, h) = setup(&["leaf-cert.pem", "intermediate-cert.pem"]);
+    timeout(Duration::from_secs(120), async move {
+        let other_ca = pem_parser::pem_to_der(include_str!("data/other-ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::TrustAnchor(
+                TrustAnchor::from_cert_der(&other_ca).unwrap(),
+            ))
+            .with_server_name("localhost");
+
+        let result = ping::<CertVerifier<4096>>(
+            addr,
+            TlsContext::new(&config, &mut OsRng).with_clock(&SystemClock),
+        )
+        .await;
+        assert!(matches!(result, Err(TlsError::InvalidCertificate)));
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+/// A clock set to a fixed time, like an RTC synchronised once at startup.
+#[cfg(feature = "webpki")]
+struct FixedClock(u64);
+
+#[cfg(feature = "webpki")]
+impl TlsClock for FixedClock {
+    fn now(&self) -> Option<u64> {
+        Some(self.0)
+    }
+}
+
+#[cfg(feature = "webpki")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_clock_instance() {
+    use embedded_tls::webpki::CertVerifier;
+
+    let (addr, h) = setup(&["leaf-cert.pem", "intermediate-cert.pem"]);
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_server_name("localhost");
+
+        // 2030-01-01T00:00:00Z
+        let clock = FixedClock(1_893_456_000);
+        ping::<CertVerifier<4096>>(
+            addr,
+            TlsContext::new(&config, &mut OsRng).with_clock(&clock),
+        )
+        .await
+        .expect("error establishing TLS connection");
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[cfg(feature = "webpki")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_clock_after_expiry() {
+    use embedded_tls::webpki::CertVerifier;
+
+    let (addr, h) = setup(&["leaf-cert.pem", "intermediate-cert.pem"]);
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_server_name("localhost");
+
+        // 2040-01-01T00:00:00Z
+        let clock = FixedClock(2_208_988_800);
+        let result = ping::<CertVerifier<4096>>(
+            addr,
+            TlsContext::new(&config, &mut OsRng).with_clock(&clock),
+        )
+        .await;
+        assert!(matches!(result, Err(TlsError::InvalidCertificate)));
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[cfg(feature = "webpki")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_no_clock() {
+    use embedded_tls::webpki::CertVerifier;
+
+    let (addr, h) = setup(&["leaf-cert.pem", "intermediate-cert.pem"]);
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_server_name("localhost");
+
+        let result = ping::<CertVerifier<4096>>(addr, TlsContext::new(&config, &mut OsRng)).await;
+        assert!(matches!(result, Err(TlsError::InvalidCertificate)));
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_certificate_chain_too_long() {
+    let mut chain = vec!["leaf-cert.pem"];
+    chain.extend(["intermediate-cert.pem"; 16]);
+    let (addr, h) = setup(&chain);
+    timeout(Duration::from_secs(120), async move {
+        let config = TlsConfig::new().with_server_name("localhost");
+
+        let result = ping::<NoVerify>(addr, TlsContext::new(&config, &mut OsRng)).await;
+        assert!(matches!(result, Err(TlsError::CertificateChainTooLong)));
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
@@ -0,0 +1,181 @@
+#![macro_use]
+use embedded_io_adapters::tokio_1::FromTokio;
+use embedded_tls::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+fn setup(ciphersuites: &str) -> (SocketAddr, JoinHandle<()>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/server-cert.pem")
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    builder.set_ciphersuites(ciphersuites).unwrap();
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = tokio::task::spawn_blocking(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut conn = acceptor.accept(stream).unwrap();
+        let mut buf = [0; 64];
+        let len = conn.read(&mut buf[..]).unwrap();
+        conn.write_all(&buf[..len]).unwrap();
+    });
+    (addr, h)
+}
+
+async fn ping(addr: SocketAddr, offered: &[CipherSuite]) -> Result<Option<CipherSuite>, TlsError> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_cipher_suites(offered)
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .await?;
+
+    tls.write(b"ping").await.expect("error writing data");
+    tls.flush().await.expect("error flushing data");
+
+    let mut rx = [0; 4];
+    let l = tls.read(&mut rx[..]).await.expect("error reading data");
+    assert_eq!(4, l);
+    assert_eq!(b"ping", &rx[..l]);
+    Ok(tls.cipher_suite())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_aes128_ccm() {
+    let (addr, h) = setup("TLS_AES_128_CCM_SHA256");
+    timeout(Duration::from_secs(120), async move {
+        let suite = ping(addr, &[CipherSuite::TlsAes128CcmSha256])
+            .await
+            .expect("error establishing TLS connection");
+        assert_eq!(Some(CipherSuite::TlsAes128CcmSha256), suite);
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_aes128_ccm8() {
+    let (addr, h) = setup("TLS_AES_128_CCM_8_SHA256");
+    timeout(Duration::from_secs(120), async move {
+        let suite = ping(addr, &[CipherSuite::TlsAes128Ccm8Sha256])
+            .await
+            .expect("error establishing TLS connection");
+        assert_eq!(Some(CipherSuite::TlsAes128Ccm8Sha256), suite);
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_negotiate_server_choice() {
+    let (addr, h) = setup("TLS_AES_256_GCM_SHA384");
+    timeout(Duration::from_secs(120), async move {
+        // The server only supports a suite with a different hash than the preferred one
+        let suite = ping(
+            addr,
+            &[
+                CipherSuite::TlsAes128GcmSha256,
+                CipherSuite::TlsChacha20Poly1305Sha256,
+                CipherSuite::TlsAes256GcmSha384,
+            ],
+        )
+        .await
+        .expect("error establishing TLS connection");
+        assert_eq!(Some(CipherSuite::TlsAes256GcmSha384), suite);
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_negotiate_default_suites() {
+    let (addr, h) = setup("TLS_CHACHA20_POLY1305_SHA256");
+    timeout(Duration::from_secs(120), async move {
+        let stream = TcpStream::connect(addr)
+            .await
+            .expect("error connecting to server");
+
+        let mut read_record_buffer = [0; 16384];
+        let mut write_record_buffer = [0; 16384];
+        let config = TlsConfig::new().with_server_name("localhost");
+
+        let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+            FromTokio::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        );
+        assert_eq!(None, tls.cipher_suite());
+
+        tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+            .await
+            .expect("error establishing TLS connection");
+        assert_eq!(
+            Some(CipherSuite::TlsChacha20Poly1305Sha256),
+            tls.cipher_suite()
+        );
+
+        tls.write(b"ping").await.expect("error writing data");
+        tls.flush().await.expect("error flushing data");
+        let mut rx = [0; 4];
+        let l = tls.read(&mut rx[..]).await.expect("error reading data");
+        assert_eq!(b"ping", &rx[..l]);
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_negotiate_no_common_suite() {
+    let (addr, h) = setup("TLS_AES_256_GCM_SHA384");
+    timeout(Duration::from_secs(120), async move {
+        let result = ping(addr, &[CipherSuite::TlsAes128GcmSha256]).await;
+        assert!(result.is_err());
+        assert!(h.await.is_err());
+    })
+    .await
+    .unwrap();
+}
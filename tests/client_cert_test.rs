@@ -0,0 +1,161 @@
+#![macro_use]
+use embedded_io_adapters::tokio_1::FromTokio;
+use embedded_tls::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+/// Starts a server that requires a client certificate issued by the test CA, returning the
+/// subject common name of the client certificate once the handshake has completed.
+fn setup() -> (SocketAddr, JoinHandle<Option<String>>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/server-cert.pem")
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    builder.set_ca_file("tests/data/ca-cert.pem").unwrap();
+    builder.set_verify(ssl::SslVerifyMode::PEER | ssl::SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = tokio::task::spawn_blocking(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut conn = acceptor.accept(stream).ok()?;
+        let peer = conn.ssl().peer_certificate()?;
+        let common_name = peer
+            .subject_name()
+            .entries_by_nid(openssl::nid::Nid::COMMONNAME)
+            .next()?
+            .data()
+            .as_utf8()
+            .ok()?
+            .to_string();
+
+        let mut buf = [0; 64];
+        let len = conn.read(&mut buf[..]).unwrap();
+        conn.write_all(&buf[..len]).unwrap();
+        Some(common_name)
+    });
+    (addr, h)
+}
+
+async fn ping(addr: SocketAddr, config: &TlsConfig<'_>) -> Result<(), TlsError> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(config, &mut OsRng))
+        .await?;
+
+    tls.write(b"ping").await?;
+    tls.flush().await?;
+
+    let mut rx = [0; 4];
+    let l = tls.read(&mut rx[..]).await?;
+    assert_eq!(4, l);
+    assert_eq!(b"ping", &rx[..l]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_client_certificate() {
+    let (addr, h) = setup();
+    timeout(Duration::from_secs(120), async move {
+        let cert = pem_parser::pem_to_der(include_str!("data/client-cert.pem"));
+        let key = pem_parser::pem_to_der(include_str!("data/client-key.pem"));
+        let signer = P256Signer::from_pkcs8_der(&key).unwrap();
+        let config = TlsConfig::new()
+            .with_server_name("localhost")
+            .with_cert(Certificate::X509(&cert))
+            .with_signer(&signer);
+
+        ping(addr, &config)
+            .await
+            .expect("error establishing TLS connection");
+        assert_eq!(Some("client"), h.await.unwrap().as_deref());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_client_certificate_without_signer() {
+    let (addr, h) = setup();
+    timeout(Duration::from_secs(120), async move {
+        // Without a signer no certificate is sent, which the server rejects
+        let cert = pem_parser::pem_to_der(include_str!("data/client-cert.pem"));
+        let config = TlsConfig::new()
+            .with_server_name("localhost")
+            .with_cert(Certificate::X509(&cert));
+
+        assert!(ping(addr, &config).await.is_err());
+        assert_eq!(None, h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[test]
+fn test_open_with_signer_is_send() {
+    fn assert_send<T: Send>(_: &T) {}
+
+    let cert = pem_parser::pem_to_der(include_str!("data/client-cert.pem"));
+    let key = pem_parser::pem_to_der(include_str!("data/client-key.pem"));
+    let signer = P256Signer::from_pkcs8_der(&key).unwrap();
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_cert(Certificate::X509(&cert))
+        .with_signer(&signer);
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    stream.set_nonblocking(true).unwrap();
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(TcpStream::from_std(stream).unwrap()),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    // The future is never polled; this only checks that it can be moved to another task
+    let mut rng = OsRng;
+    let open =
+        tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut rng).with_clock(&SystemClock));
+    assert_send(&open);
+}
@@ -55,7 +55,7 @@ async fn test_google() {
     let mut write_record_buffer = [0; 16384];
     let config = TlsConfig::new().with_server_name("google.com");
 
-    let mut tls: TlsConnection<FromTokio<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
         FromTokio::new(stream),
         &mut read_record_buffer,
         &mut write_record_buffer,
@@ -101,7 +101,7 @@ async fn test_ping() {
         .with_ca(Certificate::X509(&der[..]))
         .with_server_name("localhost");
 
-    let mut tls: TlsConnection<FromTokio<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
         FromTokio::new(stream),
         &mut read_record_buffer,
         &mut write_record_buffer,
@@ -168,7 +168,7 @@ async fn test_ping_nocopy() {
         .with_ca(Certificate::X509(&der[..]))
         .with_server_name("localhost");
 
-    let mut tls: TlsConnection<FromTokio<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
         FromTokio::new(stream),
         &mut read_record_buffer,
         &mut write_record_buffer,
@@ -238,7 +238,7 @@ async fn test_ping_nocopy_bufread() {
         .with_ca(Certificate::X509(&der[..]))
         .with_server_name("localhost");
 
-    let mut tls: TlsConnection<FromTokio<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
         FromTokio::new(stream),
         &mut read_record_buffer,
         &mut write_record_buffer,
@@ -283,7 +283,7 @@ fn test_blocking_ping() {
         .with_ca(Certificate::X509(&der[..]))
         .with_server_name("localhost");
 
-    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+    let mut tls: TlsConnection<FromStd<TcpStream>> = TlsConnection::new(
         FromStd::new(stream),
         &mut read_record_buffer,
         &mut write_record_buffer,
@@ -334,7 +334,7 @@ fn test_blocking_ping_nocopy() {
         .with_ca(Certificate::X509(&der[..]))
         .with_server_name("localhost");
 
-    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+    let mut tls: TlsConnection<FromStd<TcpStream>> = TlsConnection::new(
         FromStd::new(stream),
         &mut read_record_buffer,
         &mut write_record_buffer,
@@ -379,7 +379,7 @@ fn test_blocking_ping_nocopy_bufread() {
         .with_ca(Certificate::X509(&der[..]))
         .with_server_name("localhost");
 
-    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+    let mut tls: TlsConnection<FromStd<TcpStream>> = TlsConnection::new(
         FromStd::new(stream),
         &mut read_record_buffer,
         &mut write_record_buffer,
@@ -404,3 +404,97 @@ fn test_blocking_ping_nocopy_bufread() {
         .map_err(|(_, e)| e)
         .expect("error closing session");
 }
+
+#[tokio::test]
+async fn test_ping_chacha20poly1305() {
+    use embedded_tls::*;
+    use tokio::net::TcpStream;
+    let addr = setup();
+    let pem = include_str!("data/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    log::info!("Connected");
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_cipher_suites(&[CipherSuite::TlsChacha20Poly1305Sha256])
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .await
+        .expect("error establishing TLS connection");
+    log::info!("Established");
+    assert_eq!(
+        Some(CipherSuite::TlsChacha20Poly1305Sha256),
+        tls.cipher_suite()
+    );
+
+    tls.write(b"ping").await.expect("error writing data");
+    tls.flush().await.expect("error flushing data");
+
+    let mut rx_buf = [0; 4096];
+    let sz = tls.read(&mut rx_buf).await.expect("error reading data");
+    assert_eq!(4, sz);
+    assert_eq!(b"ping", &rx_buf[..sz]);
+
+    tls.close()
+        .await
+        .map_err(|(_, e)| e)
+        .expect("error closing session");
+}
+
+#[test]
+fn test_blocking_ping_chacha20poly1305() {
+    use embedded_tls::blocking::*;
+    use std::net::TcpStream;
+
+    let addr = setup();
+    let pem = include_str!("data/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    log::info!("Connected");
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_cipher_suites(&[CipherSuite::TlsChacha20Poly1305Sha256])
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+    log::info!("Established");
+    assert_eq!(
+        Some(CipherSuite::TlsChacha20Poly1305Sha256),
+        tls.cipher_suite()
+    );
+
+    tls.write(b"ping").expect("error writing data");
+    tls.flush().expect("error flushing data");
+
+    let mut rx_buf = [0; 4096];
+    let sz = tls.read(&mut rx_buf).expect("error reading data");
+    assert_eq!(4, sz);
+    assert_eq!(b"ping", &rx_buf[..sz]);
+
+    tls.close()
+        .map_err(|(_, e)| e)
+        .expect("error closing session");
+}
@@ -0,0 +1,170 @@
+#![macro_use]
+#![cfg(feature = "webpki")]
+use embedded_io_adapters::tokio_1::FromTokio;
+use embedded_tls::webpki::CertVerifier;
+use embedded_tls::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+/// Starts a server sending the certificate chain in `chain`, leaf first. The server task
+/// returns the error of the handshake, if it failed.
+fn setup(key: &str, chain: &[&str]) -> (SocketAddr, JoinHandle<Option<String>>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file(format!("tests/data/{key}"), ssl::SslFiletype::PEM)
+        .unwrap();
+    let mut certificates = chain.iter().map(|pem| {
+        let pem = std::fs::read(format!("tests/data/{pem}")).unwrap();
+        openssl::x509::X509::from_pem(&pem).unwrap()
+    });
+    builder
+        .set_certificate(&certificates.next().unwrap())
+        .unwrap();
+    for certificate in certificates {
+        builder.add_extra_chain_cert(certificate).unwrap();
+    }
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = tokio::task::spawn_blocking(move || {
+        let (stream, _) = listener.accept().unwrap();
+        match acceptor.accept(stream) {
+            Ok(mut conn) => {
+                let mut buf = [0; 64];
+                let len = conn.read(&mut buf[..]).unwrap();
+                conn.write_all(&buf[..len]).unwrap();
+                None
+            }
+            Err(e) => Some(e.to_string()),
+        }
+    });
+    (addr, h)
+}
+
+async fn ping(addr: SocketAddr, config: &TlsConfig<'_>) -> Result<(), TlsError> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, CertVerifier<4096>>(
+        TlsContext::new(config, &mut OsRng).with_clock(&SystemClock),
+    )
+    .await?;
+
+    tls.write(b"ping").await?;
+    tls.flush().await?;
+
+    let mut rx = [0; 4];
+    let l = tls.read(&mut rx[..]).await?;
+    assert_eq!(4, l);
+    assert_eq!(b"ping", &rx[..l]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_crl_not_revoked() {
+    let (addr, h) = setup("server-key.pem", &["server-cert.pem"]);
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_crl(include_bytes!("data/crl-empty.der"))
+            .with_crl(include_bytes!("data/crl-intermediate-revoked.der"))
+            .with_server_name("localhost");
+
+        ping(addr, &config)
+            .await
+            .expect("error establishing TLS connection");
+        assert_eq!(None, h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_crl_revoked() {
+    let (addr, h) = setup("server-key.pem", &["server-cert.pem"]);
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_crl(include_bytes!("data/crl-server-revoked.der"))
+            .with_server_name("localhost");
+
+        let result = ping(addr, &config).await;
+        assert!(matches!(result, Err(TlsError::CertificateRevoked)));
+
+        let error = h.await.unwrap().expect("handshake should fail");
+        assert!(error.contains("certificate revoked"), "{error}");
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_crl_intermediate_revoked() {
+    let (addr, h) = setup("leaf-key.pem", &["leaf-cert.pem", "intermediate-cert.pem"]);
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_crl(include_bytes!("data/crl-intermediate-revoked.der"))
+            .with_server_name("localhost");
+
+        let result = ping(addr, &config).await;
+        assert!(matches!(result, Err(TlsError::CertificateRevoked)));
+        assert!(h.await.unwrap().is_some());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_crl_invalid() {
+    let (addr, h) = setup("server-key.pem", &["server-cert.pem"]);
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_crl(&[0x30, 0x03, 0x02, 0x01, 0x01])
+            .with_server_name("localhost");
+
+        let result = ping(addr, &config).await;
+        assert!(matches!(result, Err(TlsError::DecodeError)));
+        assert!(h.await.unwrap().is_some());
+    })
+    .await
+    .unwrap();
+}
@@ -0,0 +1,207 @@
+#![macro_use]
+use aes_gcm::aead::consts::{U0, U12, U16};
+use aes_gcm::aead::{AeadCore, AeadInPlace, KeyInit, KeySizeUser};
+use aes_gcm::{Aes128Gcm, Key, Nonce, Tag};
+use embedded_io_adapters::tokio_1::FromTokio;
+use embedded_tls::*;
+use hmac::SimpleHmac;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use rand_core::{CryptoRng, RngCore};
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+fn setup(groups: &str) -> (SocketAddr, JoinHandle<()>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/server-cert.pem")
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    builder.set_groups_list(groups).unwrap();
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = tokio::task::spawn_blocking(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut conn = acceptor.accept(stream).unwrap();
+        let mut buf = [0; 64];
+        let len = conn.read(&mut buf[..]).unwrap();
+        conn.write_all(&buf[..len]).unwrap();
+    });
+    (addr, h)
+}
+
+static ENCRYPTIONS: AtomicUsize = AtomicUsize::new(0);
+static DECRYPTIONS: AtomicUsize = AtomicUsize::new(0);
+static KEY_EXCHANGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Stands in for a hardware accelerated AES-GCM implementation.
+struct CountingAes128Gcm(Aes128Gcm);
+
+impl KeySizeUser for CountingAes128Gcm {
+    type KeySize = U16;
+}
+
+impl KeyInit for CountingAes128Gcm {
+    fn new(key: &Key<Aes128Gcm>) -> Self {
+        Self(Aes128Gcm::new(key))
+    }
+}
+
+impl AeadCore for CountingAes128Gcm {
+    type NonceSize = U12;
+    type TagSize = U16;
+    type CiphertextOverhead = U0;
+}
+
+impl AeadInPlace for CountingAes128Gcm {
+    fn encrypt_in_place_detached(
+        &self,
+        nonce: &Nonce<U12>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+    ) -> aes_gcm::aead::Result<Tag> {
+        ENCRYPTIONS.fetch_add(1, Ordering::SeqCst);
+        self.0
+            .encrypt_in_place_detached(nonce, associated_data, buffer)
+    }
+
+    fn decrypt_in_place_detached(
+        &self,
+        nonce: &Nonce<U12>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+        tag: &Tag,
+    ) -> aes_gcm::aead::Result<()> {
+        DECRYPTIONS.fetch_add(1, Ordering::SeqCst);
+        self.0
+            .decrypt_in_place_detached(nonce, associated_data, buffer, tag)
+    }
+}
+
+struct CountingAes128GcmSha256;
+
+impl TlsCipherSuite for CountingAes128GcmSha256 {
+    const CODE_POINT: u16 = CipherSuite::TlsAes128GcmSha256 as u16;
+    type Cipher = CountingAes128Gcm;
+    type KeyLen = U16;
+    type IvLen = U12;
+
+    type Hash = Sha256;
+    type Hmac = SimpleHmac<Sha256>;
+    type LabelBufferSize = <Aes128GcmSha256 as TlsCipherSuite>::LabelBufferSize;
+}
+
+/// Stands in for a key exchange implementation that only supports x25519.
+struct CountingKeyExchange(RustCryptoKeyExchange);
+
+impl TlsKeyExchange for CountingKeyExchange {
+    type SharedSecret = RustCryptoSharedSecret;
+
+    fn is_supported(group: NamedGroup) -> bool {
+        group == NamedGroup::X25519
+    }
+
+    fn generate<RNG>(group: NamedGroup, rng: &mut RNG) -> Result<Self, TlsError>
+    where
+        RNG: CryptoRng + RngCore,
+    {
+        assert_eq!(NamedGroup::X25519, group);
+        KEY_EXCHANGES.fetch_add(1, Ordering::SeqCst);
+        RustCryptoKeyExchange::generate(group, rng).map(Self)
+    }
+
+    fn group(&self) -> NamedGroup {
+        self.0.group()
+    }
+
+    fn public_key(&self) -> &[u8] {
+        self.0.public_key()
+    }
+
+    fn complete(self, peer_public_key: &[u8]) -> Result<Self::SharedSecret, TlsError> {
+        self.0.complete(peer_public_key)
+    }
+}
+
+struct CountingProvider;
+
+impl CryptoProvider for CountingProvider {
+    type Aes128GcmSha256 = CountingAes128GcmSha256;
+    type Aes256GcmSha384 = Aes256GcmSha384;
+    type Chacha20Poly1305Sha256 = Chacha20Poly1305Sha256;
+    type Aes128CcmSha256 = Aes128CcmSha256;
+    type Aes128Ccm8Sha256 = Aes128Ccm8Sha256;
+
+    type KeyExchange = CountingKeyExchange;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_custom_provider() {
+    // The server prefers secp256r1, which the provider does not support
+    let (addr, h) = setup("P-256:X25519");
+    timeout(Duration::from_secs(120), async move {
+        let stream = TcpStream::connect(addr)
+            .await
+            .expect("error connecting to server");
+
+        let mut read_record_buffer = [0; 16384];
+        let mut write_record_buffer = [0; 16384];
+        let config = TlsConfig::new()
+            .with_cipher_suites(&[CipherSuite::TlsAes128GcmSha256])
+            .with_named_groups(&[NamedGroup::Secp256r1, NamedGroup::X25519])
+            .with_server_name("localhost");
+
+        let mut tls: TlsConnection<FromTokio<TcpStream>, CountingProvider> = TlsConnection::new(
+            FromTokio::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        );
+
+        tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+            .await
+            .expect("error establishing TLS connection");
+        assert_eq!(Some(CipherSuite::TlsAes128GcmSha256), tls.cipher_suite());
+
+        tls.write(b"ping").await.expect("error writing data");
+        tls.flush().await.expect("error flushing data");
+
+        let mut rx = [0; 4];
+        let l = tls.read(&mut rx[..]).await.expect("error reading data");
+        assert_eq!(4, l);
+        assert_eq!(b"ping", &rx[..l]);
+
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(1, KEY_EXCHANGES.load(Ordering::SeqCst));
+    assert!(ENCRYPTIONS.load(Ordering::SeqCst) > 0);
+    assert!(DECRYPTIONS.load(Ordering::SeqCst) > 0);
+}
@@ -0,0 +1,263 @@
+#![macro_use]
+use embedded_io_adapters::tokio_1::FromTokio;
+use embedded_tls::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+/// Starts a server accepting `connections` connections. If `stateless`, it always answers the
+/// first ClientHello with a HelloRetryRequest carrying a cookie. `configure` sets up anything
+/// else.
+fn setup<F>(connections: usize, stateless: bool, configure: F) -> (SocketAddr, JoinHandle<()>)
+where
+    F: FnOnce(&mut ssl::SslAcceptorBuilder),
+{
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/server-cert.pem")
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    builder.set_stateless_cookie_generate_cb(|_ssl, cookie| {
+        cookie[..6].copy_from_slice(b"cookie");
+        Ok(6)
+    });
+    builder.set_stateless_cookie_verify_cb(|_ssl, cookie| cookie == b"cookie");
+    configure(&mut builder);
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = tokio::task::spawn_blocking(move || {
+        for _ in 0..connections {
+            let (stream, _) = listener.accept().unwrap();
+            let ssl = ssl::Ssl::new(acceptor.context()).unwrap();
+            let mut conn = ssl::SslStream::new(ssl, stream).unwrap();
+            if stateless {
+                // The first call sends the HelloRetryRequest, the second one verifies the cookie
+                assert!(!conn.stateless().unwrap());
+                assert!(conn.stateless().unwrap());
+            }
+            conn.accept().unwrap();
+
+            let mut buf = [0; 64];
+            let len = conn.read(&mut buf[..]).unwrap();
+            conn.write_all(&buf[..len]).unwrap();
+        }
+    });
+    (addr, h)
+}
+
+async fn ping(addr: SocketAddr, config: &TlsConfig<'_>) -> Option<CipherSuite> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(config, &mut OsRng))
+        .await
+        .expect("error establishing TLS connection");
+
+    tls.write(b"ping").await.expect("error writing data");
+    tls.flush().await.expect("error flushing data");
+
+    let mut rx = [0; 4];
+    let l = tls.read(&mut rx[..]).await.expect("error reading data");
+    assert_eq!(4, l);
+    assert_eq!(b"ping", &rx[..l]);
+    tls.cipher_suite()
+}
+
+#[cfg(feature = "mlkem")]
+async fn ping_early_data(
+    addr: SocketAddr,
+    config: &TlsConfig<'_>,
+    storage: &mut (dyn TicketStorage + Send),
+    early_data: &[u8],
+) -> EarlyDataStatus {
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    )
+    .with_ticket_storage(storage, &SystemClock);
+
+    tls.open::<OsRng, NoVerify>(
+        TlsContext::new(config, &mut OsRng)
+            .with_clock(&SystemClock)
+            .with_early_data(early_data),
+    )
+    .await
+    .expect("error establishing TLS connection");
+    let status = tls.early_data_status();
+
+    tls.write(b"ping").await.expect("error writing data");
+    tls.flush().await.expect("error flushing data");
+
+    let mut rx = [0; 4];
+    let l = tls.read(&mut rx[..]).await.expect("error reading data");
+    assert_eq!(4, l);
+    assert_eq!(b"ping", &rx[..l]);
+    status
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hello_retry_request_cookie() {
+    let (addr, h) = setup(1, true, |builder| {
+        builder.set_ciphersuites("TLS_AES_128_GCM_SHA256").unwrap();
+    });
+    timeout(Duration::from_secs(120), async move {
+        let config = TlsConfig::new().with_server_name("localhost");
+        assert_eq!(
+            Some(CipherSuite::TlsAes128GcmSha256),
+            ping(addr, &config).await
+        );
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hello_retry_request_cipher_suite() {
+    // The retry selects a cipher suite with a different hash than the preferred one
+    let (addr, h) = setup(1, true, |builder| {
+        builder.set_ciphersuites("TLS_AES_256_GCM_SHA384").unwrap();
+    });
+    timeout(Duration::from_secs(120), async move {
+        let config = TlsConfig::new().with_server_name("localhost");
+        assert_eq!(
+            Some(CipherSuite::TlsAes256GcmSha384),
+            ping(addr, &config).await
+        );
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+/// The groups of a client that only sends key shares for the first three, so that a server
+/// supporting only the last one asks for another key share.
+#[cfg(feature = "mlkem")]
+const RETRY_GROUPS: &[NamedGroup] = &[
+    NamedGroup::X25519MlKem768,
+    NamedGroup::Secp256r1,
+    NamedGroup::X25519,
+    NamedGroup::Secp384r1,
+];
+
+#[cfg(feature = "mlkem")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hello_retry_request_named_group() {
+    let (addr, h) = setup(1, false, |builder| {
+        builder.set_groups_list("P-384").unwrap();
+    });
+    timeout(Duration::from_secs(120), async move {
+        let config = TlsConfig::new()
+            .with_named_groups(RETRY_GROUPS)
+            .with_server_name("localhost");
+        assert_eq!(
+            Some(CipherSuite::TlsAes128GcmSha256),
+            ping(addr, &config).await
+        );
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[cfg(feature = "mlkem")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hello_retry_request_psk() {
+    // The binder of the second ClientHello covers the HelloRetryRequest
+    let (addr, h) = setup(1, false, |builder| {
+        builder.set_groups_list("P-384").unwrap();
+        builder.set_psk_server_callback(move |_ssl, identity, secret_mut| {
+            if let Some(b"vader") = identity {
+                secret_mut[..4].copy_from_slice(&[0xaa, 0xbb, 0xcc, 0xdd]);
+                Ok(4)
+            } else {
+                Ok(0)
+            }
+        });
+    });
+    timeout(Duration::from_secs(120), async move {
+        let config = TlsConfig::new()
+            .with_named_groups(RETRY_GROUPS)
+            .with_psk(&[0xaa, 0xbb, 0xcc, 0xdd], &[b"vader"])
+            .with_server_name("localhost");
+        assert_eq!(
+            Some(CipherSuite::TlsAes128GcmSha256),
+            ping(addr, &config).await
+        );
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[cfg(feature = "mlkem")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hello_retry_request_early_data() {
+    // The early data is written after the first ClientHello, and rejected by the retry
+    let (addr, h) = setup(2, false, |builder| {
+        builder.set_groups_list("P-384").unwrap();
+        builder.set_max_early_data(1024).unwrap();
+    });
+    timeout(Duration::from_secs(120), async move {
+        let config = TlsConfig::new()
+            .with_named_groups(RETRY_GROUPS)
+            .with_server_name("localhost");
+        let mut storage = SingleTicketStorage::<1024>::new();
+
+        assert_eq!(
+            EarlyDataStatus::NotSent,
+            ping_early_data(addr, &config, &mut storage, b"hello").await
+        );
+        assert!(storage.load().is_some(), "no ticket received");
+
+        assert_eq!(
+            EarlyDataStatus::Rejected,
+            ping_early_data(addr, &config, &mut storage, b"hello").await
+        );
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
@@ -0,0 +1,253 @@
+#![macro_use]
+use embedded_io_adapters::tokio_1::FromTokio;
+use embedded_tls::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+fn setup(groups: &str) -> (SocketAddr, JoinHandle<()>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/server-cert.pem")
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    builder.set_groups_list(groups).unwrap();
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = tokio::task::spawn_blocking(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut conn = acceptor.accept(stream).unwrap();
+        let mut buf = [0; 64];
+        let len = conn.read(&mut buf[..]).unwrap();
+        conn.write_all(&buf[..len]).unwrap();
+    });
+    (addr, h)
+}
+
+/// Starts an `openssl s_server` with `groups` for a single connection, which echoes a ping.
+///
+/// The `openssl` command is used instead of the library linked to the tests, as X25519MLKEM768
+/// requires OpenSSL 3.5 or later.
+#[cfg(feature = "mlkem")]
+fn setup_s_server(groups: &str) -> (SocketAddr, JoinHandle<()>) {
+    use std::io::BufRead;
+    use std::process::{Command, Stdio};
+
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    // The output is only flushed with line buffering
+    let mut child = Command::new("stdbuf")
+        .args([
+            "-oL",
+            "openssl",
+            "s_server",
+            "-accept",
+            "127.0.0.1:0",
+            "-naccept",
+            "1",
+            "-tls1_3",
+            "-groups",
+            groups,
+            "-key",
+            "tests/data/server-key.pem",
+            "-cert",
+            "tests/data/server-cert.pem",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("error starting openssl s_server");
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = std::io::BufReader::new(child.stdout.take().unwrap());
+
+    let mut line = String::new();
+    let addr = loop {
+        line.clear();
+        if stdout.read_line(&mut line).unwrap() == 0 {
+            panic!("openssl s_server does not support {}", groups);
+        }
+        if let Some(addr) = line.trim().strip_prefix("ACCEPT ") {
+            break addr.parse().unwrap();
+        }
+    };
+
+    let h = tokio::task::spawn_blocking(move || {
+        // The ping is printed without a newline
+        let mut received = std::vec::Vec::new();
+        while !received.ends_with(b"ping") {
+            let buf = stdout.fill_buf().unwrap();
+            assert!(!buf.is_empty(), "server exited");
+            received.extend_from_slice(buf);
+            let len = buf.len();
+            stdout.consume(len);
+        }
+        stdin.write_all(b"ping\n").unwrap();
+        // The server exits once the client closed the connection
+        std::io::copy(&mut stdout, &mut std::io::sink()).unwrap();
+        child.wait().unwrap();
+    });
+    (addr, h)
+}
+
+async fn ping(addr: SocketAddr, groups: &[NamedGroup]) -> Result<(), TlsError> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_named_groups(groups)
+        .with_server_name("localhost");
+    let mut write_record_buffer = vec![0; config.min_write_buffer_len()];
+
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .await?;
+
+    tls.write(b"ping").await.expect("error writing data");
+    tls.flush().await.expect("error flushing data");
+
+    let mut rx = [0; 4];
+    let l = tls.read(&mut rx[..]).await.expect("error reading data");
+    assert_eq!(4, l);
+    assert_eq!(b"ping", &rx[..l]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_x25519() {
+    let (addr, h) = setup("X25519");
+    timeout(Duration::from_secs(120), async move {
+        ping(addr, &[NamedGroup::X25519])
+            .await
+            .expect("error establishing TLS connection");
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_secp256r1() {
+    let (addr, h) = setup("P-256");
+    timeout(Duration::from_secs(120), async move {
+        ping(addr, &[NamedGroup::Secp256r1])
+            .await
+            .expect("error establishing TLS connection");
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_secp384r1() {
+    let (addr, h) = setup("P-384");
+    timeout(Duration::from_secs(120), async move {
+        ping(addr, &[NamedGroup::Secp384r1])
+            .await
+            .expect("error establishing TLS connection");
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_multiple_groups() {
+    // The server picks the key share of the group it supports, even if it is not the
+    // client's preferred one.
+    for (server, client) in [
+        ("X25519", &[NamedGroup::Secp256r1, NamedGroup::X25519][..]),
+        ("P-256", &[NamedGroup::X25519, NamedGroup::Secp256r1]),
+        ("P-384", &[NamedGroup::Secp256r1, NamedGroup::Secp384r1]),
+        (
+            "P-256",
+            &[
+                NamedGroup::Secp384r1,
+                NamedGroup::X25519,
+                NamedGroup::Secp256r1,
+            ],
+        ),
+    ] {
+        let (addr, h) = setup(server);
+        timeout(Duration::from_secs(120), async move {
+            ping(addr, client)
+                .await
+                .expect("error establishing TLS connection");
+            h.await.unwrap();
+        })
+        .await
+        .unwrap();
+    }
+}
+
+#[cfg(feature = "mlkem")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_x25519_mlkem768() {
+    let (addr, h) = setup_s_server("X25519MLKEM768");
+    timeout(Duration::from_secs(120), async move {
+        ping(addr, &[NamedGroup::X25519MlKem768])
+            .await
+            .expect("error establishing TLS connection");
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[cfg(feature = "mlkem")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_x25519_mlkem768_fallback() {
+    for (server, client) in [
+        (
+            "X25519MLKEM768",
+            &[NamedGroup::X25519, NamedGroup::X25519MlKem768][..],
+        ),
+        ("X25519", &[NamedGroup::X25519MlKem768, NamedGroup::X25519]),
+    ] {
+        let (addr, h) = setup_s_server(server);
+        timeout(Duration::from_secs(120), async move {
+            ping(addr, client)
+                .await
+                .expect("error establishing TLS connection");
+            h.await.unwrap();
+        })
+        .await
+        .unwrap();
+    }
+}
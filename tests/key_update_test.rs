@@ -0,0 +1,298 @@
+#![macro_use]
+use embedded_io::{Read as _, Write as _};
+use embedded_io_adapters::std::FromStd;
+use embedded_io_adapters::tokio_1::FromTokio;
+use embedded_tls::*;
+use rand::rngs::OsRng;
+use std::io::{BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+/// An `openssl s_server` for a single connection. Lines written to its stdin are sent to the
+/// client, and a line of just `K` makes it send a KeyUpdate requesting a response. Its stdout
+/// has the data it received, and with `-msg` the handshake messages as well.
+struct Server {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Server {
+    fn start() -> (SocketAddr, Self) {
+        INIT.call_once(|| {
+            env_logger::init();
+        });
+
+        // The messages printed with `-msg` are only flushed with line buffering
+        let mut child = Command::new("stdbuf")
+            .args([
+                "-oL",
+                "openssl",
+                "s_server",
+                "-accept",
+                "127.0.0.1:0",
+                "-naccept",
+                "1",
+                "-tls1_3",
+                "-msg",
+                "-key",
+                "tests/data/server-key.pem",
+                "-cert",
+                "tests/data/server-cert.pem",
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("error starting openssl s_server");
+
+        let stdin = child.stdin.take().unwrap();
+        let mut server = Self {
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            stdin,
+            child,
+        };
+        let addr = server.wait_for(|line| {
+            line.strip_prefix("ACCEPT ")
+                .map(|addr| addr.parse().unwrap())
+        });
+        (addr, server)
+    }
+
+    fn send(&mut self, line: &str) {
+        // The server only recognizes commands read at once
+        self.stdin
+            .write_all(format!("{}\n", line).as_bytes())
+            .unwrap();
+    }
+
+    /// Reads the output of the server until `f` returns a value for a line.
+    fn wait_for<T>(&mut self, f: impl Fn(&str) -> Option<T>) -> T {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            assert_ne!(
+                0,
+                self.stdout.read_line(&mut line).unwrap(),
+                "server exited"
+            );
+            if let Some(value) = f(line.trim()) {
+                return value;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: &str) {
+        self.wait_for(|line| (line == expected).then_some(()))
+    }
+
+    /// Makes the server update its keys and request the client to update its keys too.
+    fn key_update(&mut self) {
+        self.send("K");
+        self.wait_for(|line| line.ends_with("KeyUpdate").then_some(()));
+    }
+
+    /// Waits until the server received a KeyUpdate from the client.
+    fn expect_key_update(&mut self) {
+        self.wait_for(|line| (line.starts_with("<<<") && line.ends_with("KeyUpdate")).then_some(()))
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_key_update() {
+    let (addr, mut server) = Server::start();
+    timeout(Duration::from_secs(120), async move {
+        let stream = TcpStream::connect(addr)
+            .await
+            .expect("error connecting to server");
+
+        let mut read_record_buffer = [0; 16384];
+        let mut write_record_buffer = [0; 16384];
+        let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+            FromTokio::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        );
+
+        let config = TlsConfig::new().with_server_name("localhost");
+        tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+            .await
+            .expect("error establishing TLS connection");
+
+        tls.write(b"ping\n").await.expect("error writing data");
+        tls.flush().await.expect("error flushing data");
+        server.expect("ping");
+
+        // The server sends the KeyUpdate before the data, protected with its next keys
+        for _ in 0..2 {
+            server.key_update();
+            server.send("pong");
+
+            let mut rx = [0; 5];
+            let mut len = 0;
+            while len < rx.len() {
+                len += tls.read(&mut rx[len..]).await.expect("error reading data");
+            }
+            assert_eq!(b"pong\n", &rx);
+
+            // The response was sent when reading, the data is protected with the next keys
+            tls.write(b"ping\n").await.expect("error writing data");
+            tls.flush().await.expect("error flushing data");
+            server.expect_key_update();
+            server.expect("ping");
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_key_update_read_only() {
+    let (addr, mut server) = Server::start();
+    timeout(Duration::from_secs(120), async move {
+        let stream = TcpStream::connect(addr)
+            .await
+            .expect("error connecting to server");
+
+        let mut read_record_buffer = [0; 16384];
+        let mut write_record_buffer = [0; 16384];
+        let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+            FromTokio::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        );
+
+        let config = TlsConfig::new().with_server_name("localhost");
+        tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+            .await
+            .expect("error establishing TLS connection");
+
+        // The client responds to the KeyUpdate without writing anything itself
+        server.key_update();
+        server.send("pong");
+
+        let mut rx = [0; 5];
+        let mut len = 0;
+        while len < rx.len() {
+            len += tls.read(&mut rx[len..]).await.expect("error reading data");
+        }
+        assert_eq!(b"pong\n", &rx);
+
+        // Closing the connection makes the server exit if it did not receive the response
+        drop(tls);
+        server.expect_key_update();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_key_update_threshold() {
+    let (addr, mut server) = Server::start();
+    timeout(Duration::from_secs(120), async move {
+        let stream = TcpStream::connect(addr)
+            .await
+            .expect("error connecting to server");
+
+        let mut read_record_buffer = [0; 16384];
+        let mut write_record_buffer = [0; 16384];
+        let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+            FromTokio::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        );
+
+        let config = TlsConfig::new()
+            .with_server_name("localhost")
+            .with_key_update_threshold(2);
+        tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+            .await
+            .expect("error establishing TLS connection");
+
+        // The keys are updated after every second record
+        for _ in 0..3 {
+            for _ in 0..2 {
+                tls.write(b"ping\n").await.expect("error writing data");
+                tls.flush().await.expect("error flushing data");
+                server.expect("ping");
+            }
+            server.expect_key_update();
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[test]
+fn test_blocking_split_key_update() {
+    use embedded_tls::blocking::*;
+    use std::net::TcpStream;
+    use std::sync::Arc;
+
+    let (addr, mut server) = Server::start();
+    let stream = Arc::new(TcpStream::connect(addr).expect("error connecting to server"));
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<Clonable> = TlsConnection::new(
+        Clonable(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    let config = TlsConfig::new().with_server_name("localhost");
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    let mut state = SplitConnectionState::default();
+    let (mut reader, mut writer) = tls.split_with(&mut state);
+
+    server.key_update();
+    server.send("pong");
+
+    // The reader receives the KeyUpdate, and the writer responds to it
+    let mut rx = [0; 5];
+    reader.read_exact(&mut rx).expect("error reading data");
+    assert_eq!(b"pong\n", &rx);
+
+    writer.write_all(b"ping\n").expect("error writing data");
+    writer.flush().expect("error flushing data");
+    server.expect_key_update();
+    server.expect("ping");
+}
+
+#[derive(Clone)]
+struct Clonable(std::sync::Arc<std::net::TcpStream>);
+
+impl embedded_io::ErrorType for Clonable {
+    type Error = std::io::Error;
+}
+
+impl embedded_io::Read for Clonable {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        FromStd::new(self.0.as_ref()).read(buf)
+    }
+}
+
+impl embedded_io::Write for Clonable {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        FromStd::new(self.0.as_ref()).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        FromStd::new(self.0.as_ref()).flush()
+    }
+}
@@ -0,0 +1,326 @@
+#![macro_use]
+use embedded_io_adapters::tokio_1::FromTokio;
+use embedded_io_async::{Read as _, Write as _};
+use embedded_tls::*;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::ssl;
+use openssl::symm;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, Once};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+const PAYLOAD_LEN: usize = 2000;
+
+/// Builds a PSK server, passing the lines of its key log to `keylog`.
+fn acceptor(keylog: impl Fn(&str) + Send + Sync + 'static) -> ssl::SslAcceptor {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    builder.set_ciphersuites("TLS_AES_128_GCM_SHA256").unwrap();
+    builder.set_psk_server_callback(move |_ssl, identity, secret_mut| {
+        if let Some(b"vader") = identity {
+            secret_mut[..4].copy_from_slice(&[0xaa, 0xbb, 0xcc, 0xdd]);
+            Ok(4)
+        } else {
+            Ok(0)
+        }
+    });
+    builder.set_keylog_callback(move |_ssl, line| keylog(line));
+    builder.build()
+}
+
+fn listen() -> (SocketAddr, TcpListener) {
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+    (addr, listener)
+}
+
+/// Starts a PSK server that echoes a payload. OpenSSL rejects records exceeding the negotiated
+/// maximum fragment length, and fragments the echo accordingly.
+fn setup() -> (SocketAddr, JoinHandle<()>) {
+    let acceptor = acceptor(|_| {});
+    let (addr, listener) = listen();
+
+    let h = tokio::task::spawn_blocking(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut conn = acceptor.accept(stream).unwrap();
+        let mut buf = [0; PAYLOAD_LEN];
+        conn.read_exact(&mut buf).unwrap();
+        conn.write_all(&buf).unwrap();
+    });
+    (addr, h)
+}
+
+/// Starts a PSK server behind a proxy that replaces the maximum fragment length it echoes with
+/// 2^10 bytes, by decrypting its first encrypted record with the key from its key log. Returns
+/// the handshake error of the server.
+fn setup_echoing_other_length() -> (SocketAddr, JoinHandle<String>) {
+    let secret = Arc::new(Mutex::new(None));
+    let keylog = secret.clone();
+    let acceptor = acceptor(move |line| {
+        if let Some(line) = line.strip_prefix("SERVER_HANDSHAKE_TRAFFIC_SECRET ") {
+            keylog
+                .lock()
+                .unwrap()
+                .replace(from_hex(line.split(' ').nth(1).unwrap()));
+        }
+    });
+    let (server_addr, server_listener) = listen();
+    let (addr, listener) = listen();
+
+    std::thread::spawn(move || {
+        let (mut client, _) = listener.accept().unwrap();
+        let mut server = std::net::TcpStream::connect(server_addr).unwrap();
+        let mut upstream = server.try_clone().unwrap();
+        let mut downstream = client.try_clone().unwrap();
+        std::thread::spawn(move || std::io::copy(&mut downstream, &mut upstream));
+
+        loop {
+            let mut header = [0; 5];
+            if server.read_exact(&mut header).is_err() {
+                break;
+            }
+            let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+            let mut record = vec![0; len];
+            server.read_exact(&mut record).unwrap();
+            if header[0] == 23 {
+                let secret = secret.lock().unwrap().take().unwrap();
+                record = replace_max_fragment_length(&secret, &header, &record);
+            }
+            client.write_all(&header).unwrap();
+            client.write_all(&record).unwrap();
+            if header[0] == 23 {
+                break;
+            }
+        }
+        std::io::copy(&mut server, &mut client)
+    });
+
+    let h = tokio::task::spawn_blocking(move || {
+        let (stream, _) = server_listener.accept().unwrap();
+        acceptor.accept(stream).unwrap_err().to_string()
+    });
+    (addr, h)
+}
+
+/// Replaces the maximum fragment length extension in the EncryptedExtensions starting the first
+/// encrypted record, which uses the IV as nonce and the record header as additional data.
+fn replace_max_fragment_length(secret: &[u8], header: &[u8], record: &[u8]) -> Vec<u8> {
+    let key = hkdf_expand_label(secret, b"key", 16);
+    let iv = hkdf_expand_label(secret, b"iv", 12);
+    let (ciphertext, tag) = record.split_at(record.len() - 16);
+    let mut plaintext = symm::decrypt_aead(
+        symm::Cipher::aes_128_gcm(),
+        &key,
+        Some(&iv),
+        header,
+        ciphertext,
+        tag,
+    )
+    .unwrap();
+
+    assert_eq!(8, plaintext[0]);
+    let mut offset = 6;
+    loop {
+        let extension_type = u16::from_be_bytes([plaintext[offset], plaintext[offset + 1]]);
+        let len = u16::from_be_bytes([plaintext[offset + 2], plaintext[offset + 3]]) as usize;
+        if extension_type == 1 {
+            plaintext[offset + 4] = 2;
+            break;
+        }
+        offset += 4 + len;
+    }
+
+    let mut tag = [0; 16];
+    let mut record = symm::encrypt_aead(
+        symm::Cipher::aes_128_gcm(),
+        &key,
+        Some(&iv),
+        header,
+        &plaintext,
+        &mut tag,
+    )
+    .unwrap();
+    record.extend_from_slice(&tag);
+    record
+}
+
+/// Starts a PSK server that sends the header of a record exceeding 2^9 bytes, and more than
+/// its encryption can add, after the handshake. Returns the error of its next read.
+fn setup_sending_overflow() -> (SocketAddr, JoinHandle<String>) {
+    let acceptor = acceptor(|_| {});
+    let (addr, listener) = listen();
+
+    let h = tokio::task::spawn_blocking(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut conn = acceptor.accept(stream).unwrap();
+        let len: u16 = 512 + 256 + 1;
+        let [high, low] = len.to_be_bytes();
+        let stream = conn.get_mut();
+        stream.write_all(&[23, 3, 3, high, low]).unwrap();
+        stream.write_all(&vec![0; len as usize]).unwrap();
+        conn.read(&mut [0; 1]).unwrap_err().to_string()
+    });
+    (addr, h)
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+/// HKDF-Expand-Label with SHA-256 and an empty context, for up to 32 bytes.
+fn hkdf_expand_label(secret: &[u8], label: &[u8], len: usize) -> Vec<u8> {
+    let mut info = vec![0, len as u8, 6 + label.len() as u8];
+    info.extend_from_slice(b"tls13 ");
+    info.extend_from_slice(label);
+    info.extend_from_slice(&[0, 1]);
+
+    let key = PKey::hmac(secret).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(&info).unwrap();
+    let mut output = signer.sign_to_vec().unwrap();
+    output.truncate(len);
+    output
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_max_fragment_length() {
+    let (addr, h) = setup();
+    timeout(Duration::from_secs(120), async move {
+        let stream = TcpStream::connect(addr)
+            .await
+            .expect("error connecting to server");
+
+        // Records of 512 bytes of plaintext fit in buffers much smaller than 16 KiB
+        let mut read_record_buffer = [0; 1024];
+        let mut write_record_buffer = [0; 1024];
+        let config = TlsConfig::new()
+            .with_psk(&[0xaa, 0xbb, 0xcc, 0xdd], &[b"vader"])
+            .with_max_fragment_length(MaxFragmentLength::Bits9)
+            .with_server_name("localhost");
+
+        let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+            FromTokio::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        );
+
+        tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+            .await
+            .expect("error establishing TLS connection");
+
+        let payload: Vec<u8> = (0..PAYLOAD_LEN).map(|i| i as u8).collect();
+        tls.write_all(&payload).await.unwrap();
+        tls.flush().await.unwrap();
+
+        let mut rx = [0; PAYLOAD_LEN];
+        tls.read_exact(&mut rx).await.unwrap();
+        assert_eq!(&payload[..], &rx[..]);
+
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_max_fragment_length_other_echo() {
+    let (addr, h) = setup_echoing_other_length();
+    timeout(Duration::from_secs(120), async move {
+        let stream = TcpStream::connect(addr)
+            .await
+            .expect("error connecting to server");
+
+        let mut read_record_buffer = [0; 16384];
+        let mut write_record_buffer = [0; 16384];
+        let config = TlsConfig::new()
+            .with_psk(&[0xaa, 0xbb, 0xcc, 0xdd], &[b"vader"])
+            .with_max_fragment_length(MaxFragmentLength::Bits9)
+            .with_server_name("localhost");
+
+        let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+            FromTokio::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        );
+
+        assert!(matches!(
+            tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+                .await,
+            Err(TlsError::AbortHandshake(
+                alert::AlertLevel::Fatal,
+                alert::AlertDescription::IllegalParameter
+            ))
+        ));
+        drop(tls);
+
+        let error = h.await.unwrap();
+        assert!(error.contains("illegal parameter"), "{}", error);
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_max_fragment_length_record_overflow() {
+    let (addr, h) = setup_sending_overflow();
+    timeout(Duration::from_secs(120), async move {
+        let stream = TcpStream::connect(addr)
+            .await
+            .expect("error connecting to server");
+
+        let mut read_record_buffer = [0; 1024];
+        let mut write_record_buffer = [0; 1024];
+        let config = TlsConfig::new()
+            .with_psk(&[0xaa, 0xbb, 0xcc, 0xdd], &[b"vader"])
+            .with_max_fragment_length(MaxFragmentLength::Bits9)
+            .with_server_name("localhost");
+
+        let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+            FromTokio::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        );
+
+        tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+            .await
+            .expect("error establishing TLS connection");
+
+        assert!(matches!(
+            tls.read(&mut [0; 1]).await,
+            Err(TlsError::AbortHandshake(
+                alert::AlertLevel::Fatal,
+                alert::AlertDescription::RecordOverflow
+            ))
+        ));
+        drop(tls);
+
+        let error = h.await.unwrap();
+        assert!(error.contains("record overflow"), "{}", error);
+    })
+    .await
+    .unwrap();
+}
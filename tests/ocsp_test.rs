@@ -0,0 +1,237 @@
+#![macro_use]
+#![cfg(feature = "webpki")]
+use embedded_io_adapters::tokio_1::FromTokio;
+use embedded_tls::webpki::CertVerifier;
+use embedded_tls::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+/// Starts a server with the certificate in `cert`, stapling `ocsp_response` when the client
+/// asks for it.
+fn setup(cert: &str, ocsp_response: Option<&'static [u8]>) -> (SocketAddr, JoinHandle<()>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file(format!("tests/data/{cert}"))
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    if let Some(ocsp_response) = ocsp_response {
+        builder
+            .set_status_callback(move |ssl| {
+                ssl.set_ocsp_status(ocsp_response)?;
+                Ok(true)
+            })
+            .unwrap();
+    }
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = tokio::task::spawn_blocking(move || {
+        let (stream, _) = listener.accept().unwrap();
+        if let Ok(mut conn) = acceptor.accept(stream) {
+            let mut buf = [0; 64];
+            let len = conn.read(&mut buf[..]).unwrap();
+            conn.write_all(&buf[..len]).unwrap();
+        }
+    });
+    (addr, h)
+}
+
+async fn ping(addr: SocketAddr, config: &TlsConfig<'_>) -> Result<(), TlsError> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, CertVerifier<4096>>(
+        TlsContext::new(config, &mut OsRng).with_clock(&SystemClock),
+    )
+    .await?;
+
+    tls.write(b"ping").await?;
+    tls.flush().await?;
+
+    let mut rx = [0; 4];
+    let l = tls.read(&mut rx[..]).await?;
+    assert_eq!(4, l);
+    assert_eq!(b"ping", &rx[..l]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_ocsp_good() {
+    let (addr, h) = setup(
+        "server-cert.pem",
+        Some(include_bytes!("data/ocsp-good.der")),
+    );
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_server_name("localhost")
+            .enable_ocsp_stapling();
+
+        ping(addr, &config)
+            .await
+            .expect("error establishing TLS connection");
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_ocsp_revoked() {
+    let (addr, h) = setup(
+        "server-cert.pem",
+        Some(include_bytes!("data/ocsp-revoked.der")),
+    );
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_server_name("localhost")
+            .enable_ocsp_stapling();
+
+        let result = ping(addr, &config).await;
+        assert!(matches!(result, Err(TlsError::CertificateRevoked)));
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_ocsp_not_requested() {
+    // The server only staples the response when the client asks for it
+    let (addr, h) = setup(
+        "server-cert.pem",
+        Some(include_bytes!("data/ocsp-revoked.der")),
+    );
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_server_name("localhost");
+
+        ping(addr, &config)
+            .await
+            .expect("error establishing TLS connection");
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_ocsp_wrong_certificate() {
+    // The response is for the certificate of the server, not the must-staple one
+    let (addr, h) = setup(
+        "must-staple-cert.pem",
+        Some(include_bytes!("data/ocsp-good.der")),
+    );
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_server_name("localhost")
+            .enable_ocsp_stapling();
+
+        let result = ping(addr, &config).await;
+        assert!(matches!(result, Err(TlsError::InvalidCertificate)));
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_must_staple() {
+    let (addr, h) = setup(
+        "must-staple-cert.pem",
+        Some(include_bytes!("data/ocsp-must-staple-good.der")),
+    );
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_server_name("localhost")
+            .enable_ocsp_stapling();
+
+        ping(addr, &config)
+            .await
+            .expect("error establishing TLS connection");
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_must_staple_without_response() {
+    let (addr, h) = setup("must-staple-cert.pem", None);
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_server_name("localhost")
+            .enable_ocsp_stapling();
+
+        let result = ping(addr, &config).await;
+        assert!(matches!(result, Err(TlsError::InvalidCertificate)));
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_must_staple_not_requested() {
+    // Without OCSP stapling a must-staple certificate is verified like any other
+    let (addr, h) = setup("must-staple-cert.pem", None);
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_server_name("localhost");
+
+        ping(addr, &config)
+            .await
+            .expect("error establishing TLS connection");
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
@@ -0,0 +1,203 @@
+#![macro_use]
+use embedded_io_adapters::{std::FromStd, tokio_1::FromTokio};
+use embedded_tls::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+/// Starts a server sending leaf-cert.pem and intermediate-cert.pem.
+fn setup() -> (SocketAddr, JoinHandle<()>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/leaf-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate(&certificate("leaf-cert.pem"))
+        .unwrap();
+    builder
+        .add_extra_chain_cert(certificate("intermediate-cert.pem"))
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = tokio::task::spawn_blocking(move || {
+        let (stream, _) = listener.accept().unwrap();
+        if let Ok(mut conn) = acceptor.accept(stream) {
+            let mut buf = [0; 64];
+            let len = conn.read(&mut buf[..]).unwrap();
+            conn.write_all(&buf[..len]).unwrap();
+        }
+    });
+    (addr, h)
+}
+
+fn certificate(pem: &str) -> openssl::x509::X509 {
+    let pem = std::fs::read(format!("tests/data/{pem}")).unwrap();
+    openssl::x509::X509::from_pem(&pem).unwrap()
+}
+
+fn der(certificate: Certificate) -> Vec<u8> {
+    match certificate {
+        Certificate::X509(der) => der.to_vec(),
+        _ => panic!("unexpected certificate type"),
+    }
+}
+
+async fn ping(
+    addr: SocketAddr,
+    peer_certificates_buffer: Option<&mut [u8]>,
+) -> Result<Vec<Vec<u8>>, TlsError> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+    if let Some(buf) = peer_certificates_buffer {
+        tls = tls.with_peer_certificates_buffer(buf);
+    }
+    assert_eq!(0, tls.peer_certificates().count());
+
+    let config = TlsConfig::new().with_server_name("localhost");
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .await?;
+    let peer_certificates = tls.peer_certificates().map(der).collect();
+
+    tls.write(b"ping").await?;
+    tls.flush().await?;
+
+    let mut rx = [0; 4];
+    let l = tls.read(&mut rx[..]).await?;
+    assert_eq!(4, l);
+    assert_eq!(b"ping", &rx[..l]);
+    Ok(peer_certificates)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_peer_certificates() {
+    let (addr, h) = setup();
+    timeout(Duration::from_secs(120), async move {
+        let mut buf = [0; 4096];
+        let peer_certificates = ping(addr, Some(&mut buf))
+            .await
+            .expect("error establishing TLS connection");
+
+        assert_eq!(
+            vec![
+                certificate("leaf-cert.pem").to_der().unwrap(),
+                certificate("intermediate-cert.pem").to_der().unwrap(),
+            ],
+            peer_certificates
+        );
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_peer_certificates_leaf_only() {
+    let (addr, h) = setup();
+    timeout(Duration::from_secs(120), async move {
+        let leaf = certificate("leaf-cert.pem").to_der().unwrap();
+        let mut buf = vec![0; leaf.len() + 4];
+        let peer_certificates = ping(addr, Some(&mut buf))
+            .await
+            .expect("error establishing TLS connection");
+
+        assert_eq!(vec![leaf], peer_certificates);
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_peer_certificates_without_buffer() {
+    let (addr, h) = setup();
+    timeout(Duration::from_secs(120), async move {
+        let peer_certificates = ping(addr, None)
+            .await
+            .expect("error establishing TLS connection");
+
+        assert!(peer_certificates.is_empty());
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_blocking_peer_certificates() {
+    use embedded_tls::blocking::*;
+
+    let (addr, h) = setup();
+    let stream = std::net::TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut peer_certificates_buffer = [0; 4096];
+    let config = TlsConfig::new().with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<std::net::TcpStream>> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    )
+    .with_peer_certificates_buffer(&mut peer_certificates_buffer);
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    let mut peer_certificates = tls.peer_certificates();
+    assert_eq!(
+        certificate("leaf-cert.pem").to_der().unwrap(),
+        der(peer_certificates.next().unwrap())
+    );
+    assert_eq!(
+        certificate("intermediate-cert.pem").to_der().unwrap(),
+        der(peer_certificates.next().unwrap())
+    );
+    assert!(peer_certificates.next().is_none());
+
+    tls.write(b"ping").expect("error writing data");
+    tls.flush().expect("error flushing data");
+
+    let mut rx_buf = [0; 4];
+    let sz = tls.read(&mut rx_buf).expect("error reading data");
+    assert_eq!(4, sz);
+    assert_eq!(b"ping", &rx_buf[..sz]);
+
+    tls.close()
+        .map_err(|(_, e)| e)
+        .expect("error closing session");
+    h.await.unwrap();
+}
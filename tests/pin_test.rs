@@ -0,0 +1,184 @@
+#![macro_use]
+use embedded_io_adapters::tokio_1::FromTokio;
+use embedded_tls::pin::PinVerifier;
+use embedded_tls::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+fn setup() -> (SocketAddr, JoinHandle<()>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/server-cert.pem")
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = tokio::task::spawn_blocking(move || {
+        let (stream, _) = listener.accept().unwrap();
+        if let Ok(mut conn) = acceptor.accept(stream) {
+            let mut buf = [0; 64];
+            let len = conn.read(&mut buf[..]).unwrap();
+            conn.write_all(&buf[..len]).unwrap();
+        }
+    });
+    (addr, h)
+}
+
+/// The SHA-256 hash of the SubjectPublicKeyInfo of a PEM certificate.
+fn spki_sha256(pem: &str) -> [u8; 32] {
+    let certificate = openssl::x509::X509::from_pem(pem.as_bytes()).unwrap();
+    let spki = certificate
+        .public_key()
+        .unwrap()
+        .public_key_to_der()
+        .unwrap();
+    openssl::sha::sha256(&spki)
+}
+
+async fn ping<'v, Verifier>(
+    addr: SocketAddr,
+    context: TlsContext<'v, OsRng>,
+) -> Result<(), TlsError>
+where
+    Verifier: TlsVerifier<'v>,
+{
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, Verifier>(context).await?;
+
+    tls.write(b"ping").await?;
+    tls.flush().await?;
+
+    let mut rx = [0; 4];
+    let l = tls.read(&mut rx[..]).await?;
+    assert_eq!(4, l);
+    assert_eq!(b"ping", &rx[..l]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pinned_public_key() {
+    let (addr, h) = setup();
+    timeout(Duration::from_secs(120), async move {
+        let config = TlsConfig::new()
+            .with_ca(Certificate::SpkiSha256(spki_sha256(include_str!(
+                "data/client-cert.pem"
+            ))))
+            .with_ca(Certificate::SpkiSha256(spki_sha256(include_str!(
+                "data/server-cert.pem"
+            ))));
+
+        ping::<PinVerifier>(addr, TlsContext::new(&config, &mut OsRng))
+            .await
+            .expect("error establishing TLS connection");
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_unpinned_public_key() {
+    let (addr, h) = setup();
+    timeout(Duration::from_secs(120), async move {
+        let config = TlsConfig::new().with_ca(Certificate::SpkiSha256(spki_sha256(include_str!(
+            "data/client-cert.pem"
+        ))));
+
+        let result = ping::<PinVerifier>(addr, TlsContext::new(&config, &mut OsRng)).await;
+        assert!(matches!(result, Err(TlsError::InvalidCertificate)));
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[cfg(feature = "webpki")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pinned_public_key_with_ca() {
+    use embedded_tls::webpki::CertVerifier;
+
+    let (addr, h) = setup();
+    timeout(Duration::from_secs(120), async move {
+        let ca = pem_parser::pem_to_der(include_str!("data/ca-cert.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::X509(&ca))
+            .with_ca(Certificate::SpkiSha256(spki_sha256(include_str!(
+                "data/server-cert.pem"
+            ))))
+            .with_server_name("localhost");
+
+        ping::<PinVerifier<CertVerifier<4096>>>(
+            addr,
+            TlsContext::new(&config, &mut OsRng).with_clock(&SystemClock),
+        )
+        .await
+        .expect("error establishing TLS connection");
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[cfg(feature = "webpki")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pinned_public_key_without_ca() {
+    use embedded_tls::webpki::CertVerifier;
+
+    let (addr, h) = setup();
+    timeout(Duration::from_secs(120), async move {
+        // The pin alone is not enough when the certificate chain is validated as well
+        let config = TlsConfig::new()
+            .with_ca(Certificate::SpkiSha256(spki_sha256(include_str!(
+                "data/server-cert.pem"
+            ))))
+            .with_server_name("localhost");
+
+        let result = ping::<PinVerifier<CertVerifier<4096>>>(
+            addr,
+            TlsContext::new(&config, &mut OsRng).with_clock(&SystemClock),
+        )
+        .await;
+        assert!(matches!(result, Err(TlsError::InvalidCertificate)));
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
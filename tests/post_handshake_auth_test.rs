@@ -0,0 +1,280 @@
+#![macro_use]
+use embedded_io::{Read as _, Write as _};
+use embedded_io_adapters::std::FromStd;
+use embedded_io_adapters::tokio_1::FromTokio;
+use embedded_tls::*;
+use rand::rngs::OsRng;
+use std::io::{BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+/// An `openssl s_server` for a single connection, which verifies client certificates with the
+/// test CA. Lines written to its stdin are sent to the client, and a line of just `c` makes it
+/// request a client certificate. Its stdout has the data it received, and with `-msg` the
+/// handshake messages as well.
+struct Server {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Server {
+    fn start() -> (SocketAddr, Self) {
+        INIT.call_once(|| {
+            env_logger::init();
+        });
+
+        // The messages printed with `-msg` are only flushed with line buffering
+        let mut child = Command::new("stdbuf")
+            .args([
+                "-oL",
+                "openssl",
+                "s_server",
+                "-accept",
+                "127.0.0.1:0",
+                "-naccept",
+                "1",
+                "-tls1_3",
+                "-msg",
+                "-key",
+                "tests/data/server-key.pem",
+                "-cert",
+                "tests/data/server-cert.pem",
+                "-CAfile",
+                "tests/data/ca-cert.pem",
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("error starting openssl s_server");
+
+        let stdin = child.stdin.take().unwrap();
+        let mut server = Self {
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            stdin,
+            child,
+        };
+        let addr = server.wait_for(|line| {
+            line.strip_prefix("ACCEPT ")
+                .map(|addr| addr.parse().unwrap())
+        });
+        (addr, server)
+    }
+
+    fn send(&mut self, line: &str) {
+        // The server only recognizes commands read at once
+        self.stdin
+            .write_all(format!("{}\n", line).as_bytes())
+            .unwrap();
+    }
+
+    /// Reads the output of the server until `f` returns a value for a line.
+    fn wait_for<T>(&mut self, f: impl Fn(&str) -> Option<T>) -> T {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            assert_ne!(
+                0,
+                self.stdout.read_line(&mut line).unwrap(),
+                "server exited"
+            );
+            if let Some(value) = f(line.trim()) {
+                return value;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: &str) {
+        self.wait_for(|line| (line == expected).then_some(()))
+    }
+
+    /// Makes the server send a CertificateRequest.
+    fn request_certificate(&mut self) {
+        self.send("c");
+        self.wait_for(|line| {
+            (line.starts_with(">>>") && line.ends_with("CertificateRequest")).then_some(())
+        });
+    }
+
+    /// Waits until the server received the answer to its CertificateRequest, returning whether
+    /// it contained a CertificateVerify, i.e. a certificate.
+    fn expect_certificate(&mut self) -> bool {
+        let mut certificate_verify = false;
+        loop {
+            let message = self.wait_for(|line| {
+                line.strip_prefix("<<< ")
+                    .and_then(|line| line.rsplit_once(", "))
+                    .map(|(_, message)| message.to_string())
+            });
+            match message.as_str() {
+                "CertificateVerify" => certificate_verify = true,
+                "Finished" => return certificate_verify,
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_post_handshake_auth() {
+    let (addr, mut server) = Server::start();
+    timeout(Duration::from_secs(120), async move {
+        let cert = pem_parser::pem_to_der(include_str!("data/client-cert.pem"));
+        let key = pem_parser::pem_to_der(include_str!("data/client-key.pem"));
+        let signer = P256Signer::from_pkcs8_der(&key).unwrap();
+
+        let stream = TcpStream::connect(addr)
+            .await
+            .expect("error connecting to server");
+
+        let mut read_record_buffer = [0; 16384];
+        let mut write_record_buffer = [0; 16384];
+        let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+            FromTokio::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        )
+        .with_post_handshake_auth(Certificate::X509(&cert), &signer);
+
+        let config = TlsConfig::new()
+            .with_server_name("localhost")
+            .with_cert(Certificate::X509(&cert))
+            .with_signer(&signer);
+        tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+            .await
+            .expect("error establishing TLS connection");
+
+        // The request is answered while reading the data sent after it
+        for _ in 0..2 {
+            server.request_certificate();
+            server.send("pong");
+
+            let mut rx = [0; 5];
+            let mut len = 0;
+            while len < rx.len() {
+                len += tls.read(&mut rx[len..]).await.expect("error reading data");
+            }
+            assert_eq!(b"pong\n", &rx);
+            assert!(server.expect_certificate());
+
+            tls.write(b"ping\n").await.expect("error writing data");
+            tls.flush().await.expect("error flushing data");
+            server.expect("ping");
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[test]
+fn test_blocking_post_handshake_auth_without_certificate() {
+    use embedded_tls::blocking::*;
+
+    let cert = pem_parser::pem_to_der(include_str!("data/client-cert.pem"));
+    let key = pem_parser::pem_to_der(include_str!("data/client-key.pem"));
+    let signer = P256Signer::from_pkcs8_der(&key).unwrap();
+
+    let (addr, mut server) = Server::start();
+    let stream = std::net::TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromStd<std::net::TcpStream>> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    // The certificate is only offered, so the request is answered without it
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_cert(Certificate::X509(&cert))
+        .with_signer(&signer);
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    server.request_certificate();
+    server.send("pong");
+
+    let mut rx = [0; 5];
+    tls.read_exact(&mut rx).expect("error reading data");
+    assert_eq!(b"pong\n", &rx);
+    assert!(!server.expect_certificate());
+
+    tls.write_all(b"ping\n").expect("error writing data");
+    tls.flush().expect("error flushing data");
+    server.expect("ping");
+}
+
+#[test]
+fn test_split_post_handshake_auth() {
+    use embedded_tls::blocking::*;
+
+    let cert = pem_parser::pem_to_der(include_str!("data/client-cert.pem"));
+    let key = pem_parser::pem_to_der(include_str!("data/client-key.pem"));
+    let signer = P256Signer::from_pkcs8_der(&key).unwrap();
+
+    let (addr, mut server) = Server::start();
+    let stream = std::net::TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<Clonable> = TlsConnection::new(
+        Clonable(std::sync::Arc::new(stream)),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    )
+    .with_post_handshake_auth(Certificate::X509(&cert), &signer);
+
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_cert(Certificate::X509(&cert))
+        .with_signer(&signer);
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    let mut state = SplitConnectionState::default();
+    let (mut reader, _writer) = tls.split_with(&mut state);
+
+    // The reader cannot answer the request without the writer
+    server.request_certificate();
+    let mut rx = [0; 1];
+    assert!(matches!(reader.read(&mut rx), Err(TlsError::Unimplemented)));
+}
+
+#[derive(Clone)]
+struct Clonable(std::sync::Arc<std::net::TcpStream>);
+
+impl embedded_io::ErrorType for Clonable {
+    type Error = std::io::Error;
+}
+
+impl embedded_io::Read for Clonable {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        FromStd::new(self.0.as_ref()).read(buf)
+    }
+}
+
+impl embedded_io::Write for Clonable {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        FromStd::new(self.0.as_ref()).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        FromStd::new(self.0.as_ref()).flush()
+    }
+}
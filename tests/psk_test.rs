@@ -54,7 +54,7 @@ fn setup() -> (SocketAddr, JoinHandle<()>) {
         let mut conn = acceptor.accept(stream).unwrap();
         let mut buf = [0; 64];
         let len = conn.read(&mut buf[..]).unwrap();
-        conn.write(&buf[..len]).unwrap();
+        conn.write_all(&buf[..len]).unwrap();
     });
     (addr, h)
 }
@@ -75,7 +75,7 @@ async fn test_psk_open() {
             .with_psk(&[0xaa, 0xbb, 0xcc, 0xdd], &[b"vader"])
             .with_server_name("localhost");
 
-        let mut tls: TlsConnection<FromTokio<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+        let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
             FromTokio::new(stream),
             &mut read_record_buffer,
             &mut write_record_buffer,
@@ -104,3 +104,42 @@ async fn test_psk_open() {
     .await
     .unwrap();
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_psk_open_chacha20poly1305() {
+    let (addr, h) = setup();
+    timeout(Duration::from_secs(120), async move {
+        let stream = TcpStream::connect(addr)
+            .await
+            .expect("error connecting to server");
+
+        let mut read_record_buffer = [0; 16384];
+        let mut write_record_buffer = [0; 16384];
+        let config = TlsConfig::new()
+            .with_psk(&[0xaa, 0xbb, 0xcc, 0xdd], &[b"vader"])
+            .with_cipher_suites(&[CipherSuite::TlsChacha20Poly1305Sha256])
+            .with_server_name("localhost");
+
+        let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+            FromTokio::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        );
+
+        tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+            .await
+            .expect("error establishing TLS connection");
+
+        tls.write(b"ping").await.unwrap();
+        tls.flush().await.unwrap();
+
+        let mut rx = [0; 4];
+        let l = tls.read(&mut rx[..]).await.unwrap();
+        assert_eq!(4, l);
+        assert_eq!(b"ping", &rx[..l]);
+
+        h.await.unwrap();
+    })
+    .await
+    .unwrap();
+}
@@ -0,0 +1,195 @@
+#![macro_use]
+use embedded_io_adapters::tokio_1::FromTokio;
+use embedded_tls::rpk::RpkVerifier;
+use embedded_tls::*;
+use openssl::pkey::PKey;
+use rand::rngs::OsRng;
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+/// Raw public keys are supported by OpenSSL 3.2 and later, which the openssl crate does not
+/// expose yet, so the tests run the `openssl s_server` command instead.
+fn openssl_supports_rpk() -> bool {
+    let help = Command::new("openssl")
+        .args(["s_server", "-help"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stderr).contains("-enable_server_rpk"))
+        .unwrap_or(false);
+    if !help {
+        println!("skipping, the openssl command does not support raw public keys");
+    }
+    help
+}
+
+/// Starts an echo server for a single connection, which sends its raw public key.
+fn setup(args: &[&str]) -> (SocketAddr, Child) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut server = Command::new("openssl")
+        .args([
+            "s_server",
+            "-accept",
+            "127.0.0.1:0",
+            "-naccept",
+            "1",
+            "-tls1_3",
+            "-rev",
+            "-key",
+            "tests/data/server-key.pem",
+            "-cert",
+            "tests/data/server-cert.pem",
+            "-enable_server_rpk",
+        ])
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("error starting openssl s_server");
+
+    let mut stdout = BufReader::new(server.stdout.take().unwrap());
+    let mut line = String::new();
+    let addr = loop {
+        line.clear();
+        assert_ne!(0, stdout.read_line(&mut line).unwrap());
+        if let Some(addr) = line.trim().strip_prefix("ACCEPT ") {
+            break addr.parse().unwrap();
+        }
+    };
+    server.stdout.replace(stdout.into_inner());
+    (addr, server)
+}
+
+/// The DER encoded SubjectPublicKeyInfo of a PEM private key.
+fn public_key(pem: &str) -> Vec<u8> {
+    PKey::private_key_from_pem(pem.as_bytes())
+        .unwrap()
+        .public_key_to_der()
+        .unwrap()
+}
+
+async fn ping(addr: SocketAddr, config: &TlsConfig<'_>) -> Result<(), TlsError> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, RpkVerifier>(TlsContext::new(config, &mut OsRng))
+        .await?;
+
+    tls.write(b"ping\n").await?;
+    tls.flush().await?;
+
+    // The server echoes the line reversed
+    let mut rx = [0; 5];
+    let mut len = 0;
+    while len < rx.len() {
+        len += tls.read(&mut rx[len..]).await?;
+    }
+    assert_eq!(b"gnip\n", &rx);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_server_raw_public_key() {
+    if !openssl_supports_rpk() {
+        return;
+    }
+    let (addr, mut server) = setup(&[]);
+    timeout(Duration::from_secs(120), async move {
+        let server_key = public_key(include_str!("data/server-key.pem"));
+        let config = TlsConfig::new().with_ca(Certificate::RawPublicKey(&server_key));
+
+        ping(addr, &config)
+            .await
+            .expect("error establishing TLS connection");
+    })
+    .await
+    .unwrap();
+    server.wait().unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_multiple_server_raw_public_keys() {
+    if !openssl_supports_rpk() {
+        return;
+    }
+    let (addr, mut server) = setup(&[]);
+    timeout(Duration::from_secs(120), async move {
+        let other_key = public_key(include_str!("data/client-key.pem"));
+        let server_key = public_key(include_str!("data/server-key.pem"));
+        let config = TlsConfig::new()
+            .with_ca(Certificate::RawPublicKey(&other_key))
+            .with_ca(Certificate::RawPublicKey(&server_key));
+
+        ping(addr, &config)
+            .await
+            .expect("error establishing TLS connection");
+    })
+    .await
+    .unwrap();
+    server.wait().unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_untrusted_server_raw_public_key() {
+    if !openssl_supports_rpk() {
+        return;
+    }
+    let (addr, mut server) = setup(&[]);
+    timeout(Duration::from_secs(120), async move {
+        let other_key = public_key(include_str!("data/client-key.pem"));
+        let config = TlsConfig::new().with_ca(Certificate::RawPublicKey(&other_key));
+
+        let result = ping(addr, &config).await;
+        assert!(matches!(result, Err(TlsError::InvalidCertificate)));
+    })
+    .await
+    .unwrap();
+    server.kill().unwrap();
+    server.wait().unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_client_raw_public_key() {
+    if !openssl_supports_rpk() {
+        return;
+    }
+    // The server fails the handshake unless the client authenticates
+    let (addr, mut server) = setup(&["-enable_client_rpk", "-Verify", "1"]);
+    timeout(Duration::from_secs(120), async move {
+        let server_key = public_key(include_str!("data/server-key.pem"));
+        let client_key = public_key(include_str!("data/client-key.pem"));
+        let signer = P256Signer::from_pkcs8_der(&pem_parser::pem_to_der(include_str!(
+            "data/client-key.pem"
+        )))
+        .unwrap();
+        let config = TlsConfig::new()
+            .with_ca(Certificate::RawPublicKey(&server_key))
+            .with_cert(Certificate::RawPublicKey(&client_key))
+            .with_signer(&signer);
+
+        ping(addr, &config)
+            .await
+            .expect("error establishing TLS connection");
+    })
+    .await
+    .unwrap();
+
+    server.wait().unwrap();
+}
@@ -0,0 +1,292 @@
+#![macro_use]
+use embedded_io_adapters::{std::FromStd, tokio_1::FromTokio};
+use embedded_tls::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+/// Starts a server accepting `connections` connections, which share the session ticket keys.
+/// The server task returns whether each connection resumed a session.
+fn setup(connections: usize) -> (SocketAddr, JoinHandle<Vec<bool>>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/server-cert.pem")
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = tokio::task::spawn_blocking(move || {
+        (0..connections)
+            .map(|_| {
+                let (stream, _) = listener.accept().unwrap();
+                let mut conn = acceptor.accept(stream).unwrap();
+                let mut buf = [0; 64];
+                let len = conn.read(&mut buf[..]).unwrap();
+                conn.write_all(&buf[..len]).unwrap();
+                conn.ssl().session_reused()
+            })
+            .collect()
+    });
+    (addr, h)
+}
+
+struct FixedClock(u64);
+
+impl TlsClock for FixedClock {
+    fn now(&self) -> Option<u64> {
+        Some(self.0)
+    }
+}
+
+async fn ping(
+    addr: SocketAddr,
+    storage: &mut (dyn TicketStorage + Send),
+    clock: &(dyn TlsClock + Sync),
+) -> Result<(), TlsError> {
+    ping_server(addr, storage, clock, "localhost").await
+}
+
+async fn ping_server(
+    addr: SocketAddr,
+    storage: &mut (dyn TicketStorage + Send),
+    clock: &(dyn TlsClock + Sync),
+    server_name: &str,
+) -> Result<(), TlsError> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    )
+    .with_ticket_storage(storage, clock);
+
+    let config = TlsConfig::new().with_server_name(server_name);
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng).with_clock(clock))
+        .await?;
+
+    tls.write(b"ping").await?;
+    tls.flush().await?;
+
+    // The server sends its tickets before the response
+    let mut rx = [0; 4];
+    let l = tls.read(&mut rx[..]).await?;
+    assert_eq!(4, l);
+    assert_eq!(b"ping", &rx[..l]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_resumption() {
+    let (addr, h) = setup(2);
+    timeout(Duration::from_secs(120), async move {
+        let mut storage = SingleTicketStorage::<1024>::new();
+        assert!(storage.load().is_none());
+
+        ping(addr, &mut storage, &SystemClock)
+            .await
+            .expect("error establishing TLS connection");
+        let ticket = storage.load().expect("no ticket received");
+        assert_eq!(CipherSuite::TlsAes128GcmSha256, ticket.cipher_suite);
+        assert_eq!(32, ticket.psk.len());
+        assert!(ticket.lifetime > 0);
+        assert!(ticket.received_at.is_some());
+
+        ping(addr, &mut storage, &SystemClock)
+            .await
+            .expect("error resuming TLS connection");
+
+        assert_eq!(vec![false, true], h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_resumption_expired_ticket() {
+    let (addr, h) = setup(2);
+    timeout(Duration::from_secs(120), async move {
+        let mut storage = SingleTicketStorage::<1024>::new();
+
+        ping(addr, &mut storage, &FixedClock(1_700_000_000))
+            .await
+            .expect("error establishing TLS connection");
+        let lifetime = storage.load().expect("no ticket received").lifetime;
+
+        // The expired ticket is not offered, so a full handshake is made
+        ping(
+            addr,
+            &mut storage,
+            &FixedClock(1_700_000_001 + u64::from(lifetime)),
+        )
+        .await
+        .expect("error establishing TLS connection");
+
+        assert_eq!(vec![false, false], h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_resumption_other_server_name() {
+    let (addr, h) = setup(2);
+    timeout(Duration::from_secs(120), async move {
+        let mut storage = SingleTicketStorage::<1024>::new();
+
+        ping_server(addr, &mut storage, &SystemClock, "localhost")
+            .await
+            .expect("error establishing TLS connection");
+        let ticket = storage.load().expect("no ticket received");
+        assert_eq!(Some("localhost"), ticket.server_name);
+
+        // The ticket is not offered to another server
+        ping_server(addr, &mut storage, &SystemClock, "example.com")
+            .await
+            .expect("error establishing TLS connection");
+
+        assert_eq!(vec![false, false], h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_ticket_received_at() {
+    struct SettableClock(AtomicU64);
+
+    impl TlsClock for SettableClock {
+        fn now(&self) -> Option<u64> {
+            Some(self.0.load(Ordering::Relaxed))
+        }
+    }
+
+    let (addr, h) = setup(1);
+    timeout(Duration::from_secs(120), async move {
+        let clock = SettableClock(AtomicU64::new(1_700_000_000));
+        let mut storage = SingleTicketStorage::<1024>::new();
+        let stream = TcpStream::connect(addr)
+            .await
+            .expect("error connecting to server");
+
+        let mut read_record_buffer = [0; 16384];
+        let mut write_record_buffer = [0; 16384];
+        let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+            FromTokio::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        )
+        .with_ticket_storage(&mut storage, &clock);
+
+        let config = TlsConfig::new().with_server_name("localhost");
+        tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng).with_clock(&clock))
+            .await
+            .expect("error establishing TLS connection");
+        clock.0.store(1_700_000_100, Ordering::Relaxed);
+
+        tls.write(b"ping").await.unwrap();
+        tls.flush().await.unwrap();
+        let mut rx = [0; 4];
+        tls.read(&mut rx[..]).await.unwrap();
+        drop(tls);
+
+        // The age of the ticket is measured from when it is read, not from the handshake
+        let ticket = storage.load().expect("no ticket received");
+        assert_eq!(Some(1_700_000_100), ticket.received_at);
+        assert_eq!(vec![false], h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_resumption_unsupported_ticket() {
+    let (addr, h) = setup(2);
+    timeout(Duration::from_secs(120), async move {
+        let mut storage = SingleTicketStorage::<16>::new();
+
+        // The tickets sent by OpenSSL do not fit in the storage
+        ping(addr, &mut storage, &SystemClock)
+            .await
+            .expect("error establishing TLS connection");
+        assert!(storage.load().is_none());
+
+        ping(addr, &mut storage, &SystemClock)
+            .await
+            .expect("error establishing TLS connection");
+
+        assert_eq!(vec![false, false], h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_blocking_resumption() {
+    use embedded_tls::blocking::*;
+
+    let (addr, h) = setup(2);
+    let mut storage = SingleTicketStorage::<1024>::new();
+    let config = TlsConfig::new().with_server_name("localhost");
+
+    for _ in 0..2 {
+        let stream = std::net::TcpStream::connect(addr).expect("error connecting to server");
+
+        let mut read_record_buffer = [0; 16384];
+        let mut write_record_buffer = [0; 16384];
+        let mut tls: TlsConnection<FromStd<std::net::TcpStream>> = TlsConnection::new(
+            FromStd::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        )
+        .with_ticket_storage(&mut storage, &SystemClock);
+
+        tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng).with_clock(&SystemClock))
+            .expect("error establishing TLS connection");
+
+        tls.write(b"ping").expect("error writing data");
+        tls.flush().expect("error flushing data");
+
+        let mut rx_buf = [0; 4];
+        let sz = tls.read(&mut rx_buf).expect("error reading data");
+        assert_eq!(4, sz);
+        assert_eq!(b"ping", &rx_buf[..sz]);
+
+        tls.close()
+            .map_err(|(_, e)| e)
+            .expect("error closing session");
+    }
+
+    assert_eq!(vec![false, true], h.await.unwrap());
+}
@@ -78,7 +78,7 @@ fn test_blocking_borrowed() {
         .with_ca(Certificate::X509(&der[..]))
         .with_server_name("localhost");
 
-    let mut tls: TlsConnection<Clonable<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+    let mut tls: TlsConnection<Clonable<TcpStream>> = TlsConnection::new(
         Clonable(Arc::new(stream)),
         &mut read_record_buffer,
         &mut write_record_buffer,
@@ -126,7 +126,7 @@ fn test_blocking_managed() {
         .with_ca(Certificate::X509(&der[..]))
         .with_server_name("localhost");
 
-    let mut tls: TlsConnection<Clonable<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+    let mut tls: TlsConnection<Clonable<TcpStream>> = TlsConnection::new(
         Clonable(Arc::new(stream)),
         &mut read_record_buffer,
         &mut write_record_buffer,
@@ -154,3 +154,52 @@ fn test_blocking_managed() {
         .map_err(|(_, e)| e)
         .expect("error closing session");
 }
+
+#[test]
+fn test_blocking_split_chacha20poly1305() {
+    use embedded_tls::blocking::*;
+    use std::net::TcpStream;
+    use std::sync::Arc;
+    let addr = setup();
+    let pem = include_str!("data/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    log::info!("Connected");
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_cipher_suites(&[CipherSuite::TlsChacha20Poly1305Sha256])
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<Clonable<TcpStream>> = TlsConnection::new(
+        Clonable(Arc::new(stream)),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    let (mut reader, mut writer) = tls.split();
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut buffer = [0; 4];
+            reader.read_exact(&mut buffer).expect("Failed to read data");
+            assert_eq!(b"ping", &buffer);
+        });
+        scope.spawn(|| {
+            writer.write(b"ping").expect("Failed to write data");
+            writer.flush().expect("Failed to flush");
+        });
+    });
+
+    let tls = TlsConnection::unsplit(reader, writer);
+
+    tls.close()
+        .map_err(|(_, e)| e)
+        .expect("error closing session");
+}
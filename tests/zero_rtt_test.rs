@@ -0,0 +1,287 @@
+#![macro_use]
+use embedded_io_adapters::{std::FromStd, tokio_1::FromTokio};
+use embedded_tls::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::Once;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio::time::Duration;
+
+static INIT: Once = Once::new();
+
+/// Whether a connection resumed a session, and the early data the server received.
+type Received = (bool, Vec<u8>);
+
+/// Creates a server accepting up to 1024 bytes of early data, with the given TLS 1.3 cipher
+/// suites if any.
+fn acceptor(ciphersuites: Option<&str>) -> ssl::SslAcceptor {
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/server-cert.pem")
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    if let Some(ciphersuites) = ciphersuites {
+        builder.set_ciphersuites(ciphersuites).unwrap();
+    }
+    builder.set_max_early_data(1024).unwrap();
+    builder.build()
+}
+
+/// Starts a server accepting `connections` connections, which share the session ticket keys.
+/// The server task returns what it received on each connection.
+fn setup(connections: usize) -> (SocketAddr, JoinHandle<Vec<Received>>) {
+    setup_acceptors(vec![acceptor(None); connections])
+}
+
+/// Starts a server accepting a connection with each of `acceptors`.
+fn setup_acceptors(acceptors: Vec<ssl::SslAcceptor>) -> (SocketAddr, JoinHandle<Vec<Received>>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = tokio::task::spawn_blocking(move || {
+        acceptors
+            .into_iter()
+            .map(|acceptor| {
+                let (stream, _) = listener.accept().unwrap();
+                let ssl = ssl::Ssl::new(acceptor.context()).unwrap();
+                let mut conn = ssl::SslStream::new(ssl, stream).unwrap();
+
+                let mut early_data = Vec::new();
+                let mut buf = [0; 64];
+                loop {
+                    let len = conn.read_early_data(&mut buf).unwrap();
+                    if len == 0 {
+                        break;
+                    }
+                    early_data.extend_from_slice(&buf[..len]);
+                }
+                conn.accept().unwrap();
+
+                let len = conn.read(&mut buf[..]).unwrap();
+                conn.write_all(&buf[..len]).unwrap();
+                // OpenSSL removes the session from its cache if the connection is not shut down
+                let _ = conn.shutdown();
+                (conn.ssl().session_reused(), early_data)
+            })
+            .collect()
+    });
+    (addr, h)
+}
+
+struct FixedClock(u64);
+
+impl TlsClock for FixedClock {
+    fn now(&self) -> Option<u64> {
+        Some(self.0)
+    }
+}
+
+async fn ping(
+    addr: SocketAddr,
+    storage: &mut (dyn TicketStorage + Send),
+    clock: &(dyn TlsClock + Sync),
+    early_data: &[u8],
+) -> Result<EarlyDataStatus, TlsError> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<FromTokio<TcpStream>> = TlsConnection::new(
+        FromTokio::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    )
+    .with_ticket_storage(storage, clock);
+
+    let config = TlsConfig::new().with_server_name("localhost");
+    tls.open::<OsRng, NoVerify>(
+        TlsContext::new(&config, &mut OsRng)
+            .with_clock(clock)
+            .with_early_data(early_data),
+    )
+    .await?;
+    let status = tls.early_data_status();
+
+    tls.write(b"ping").await?;
+    tls.flush().await?;
+
+    let mut rx = [0; 4];
+    let l = tls.read(&mut rx[..]).await?;
+    assert_eq!(4, l);
+    assert_eq!(b"ping", &rx[..l]);
+    Ok(status)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_early_data_accepted() {
+    let (addr, h) = setup(2);
+    timeout(Duration::from_secs(120), async move {
+        let mut storage = SingleTicketStorage::<1024>::new();
+
+        // There is no ticket to send early data with yet
+        let status = ping(addr, &mut storage, &SystemClock, b"hello")
+            .await
+            .expect("error establishing TLS connection");
+        assert_eq!(EarlyDataStatus::NotSent, status);
+        let ticket = storage.load().expect("no ticket received");
+        assert_eq!(1024, ticket.max_early_data_size);
+
+        let status = ping(addr, &mut storage, &SystemClock, b"hello")
+            .await
+            .expect("error resuming TLS connection");
+        assert_eq!(EarlyDataStatus::Accepted, status);
+
+        assert_eq!(
+            vec![(false, vec![]), (true, b"hello".to_vec())],
+            h.await.unwrap()
+        );
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_early_data_rejected() {
+    let (addr, h) = setup(2);
+    timeout(Duration::from_secs(120), async move {
+        let mut storage = SingleTicketStorage::<1024>::new();
+
+        ping(addr, &mut storage, &FixedClock(1_700_000_000), b"hello")
+            .await
+            .expect("error establishing TLS connection");
+
+        // The server resumes the session, but rejects the early data because the ticket age
+        // does not match the time it issued the ticket at
+        let status = ping(addr, &mut storage, &FixedClock(1_700_000_100), b"hello")
+            .await
+            .expect("error resuming TLS connection");
+        assert_eq!(EarlyDataStatus::Rejected, status);
+
+        assert_eq!(vec![(false, vec![]), (true, vec![])], h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_early_data_other_cipher_suite() {
+    // The ticket is for a cipher suite with another hash than the one the server selects
+    // afterwards, so the transcript starts over after the early data was written
+    let (addr, h) = setup_acceptors(vec![
+        acceptor(Some("TLS_AES_256_GCM_SHA384")),
+        acceptor(Some("TLS_AES_128_GCM_SHA256")),
+    ]);
+    timeout(Duration::from_secs(120), async move {
+        let mut storage = SingleTicketStorage::<1024>::new();
+
+        ping(addr, &mut storage, &SystemClock, b"hello")
+            .await
+            .expect("error establishing TLS connection");
+        let ticket = storage.load().expect("no ticket received");
+        assert_eq!(CipherSuite::TlsAes256GcmSha384, ticket.cipher_suite);
+
+        let status = ping(addr, &mut storage, &SystemClock, b"hello")
+            .await
+            .expect("error establishing TLS connection");
+        assert_eq!(EarlyDataStatus::Rejected, status);
+
+        assert_eq!(vec![(false, vec![]), (false, vec![])], h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_early_data_too_large() {
+    let (addr, h) = setup(2);
+    timeout(Duration::from_secs(120), async move {
+        let mut storage = SingleTicketStorage::<1024>::new();
+
+        ping(addr, &mut storage, &SystemClock, &[])
+            .await
+            .expect("error establishing TLS connection");
+
+        // The ticket allows at most 1024 bytes of early data
+        let status = ping(addr, &mut storage, &SystemClock, &[0; 1025])
+            .await
+            .expect("error resuming TLS connection");
+        assert_eq!(EarlyDataStatus::NotSent, status);
+
+        assert_eq!(vec![(false, vec![]), (true, vec![])], h.await.unwrap());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_blocking_early_data() {
+    use embedded_tls::blocking::*;
+
+    let (addr, h) = setup(2);
+    let mut storage = SingleTicketStorage::<1024>::new();
+    let config = TlsConfig::new().with_server_name("localhost");
+    let mut statuses = Vec::new();
+
+    for _ in 0..2 {
+        let stream = std::net::TcpStream::connect(addr).expect("error connecting to server");
+
+        let mut read_record_buffer = [0; 16384];
+        let mut write_record_buffer = [0; 16384];
+        let mut tls: TlsConnection<FromStd<std::net::TcpStream>> = TlsConnection::new(
+            FromStd::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        )
+        .with_ticket_storage(&mut storage, &SystemClock);
+
+        tls.open::<OsRng, NoVerify>(
+            TlsContext::new(&config, &mut OsRng)
+                .with_clock(&SystemClock)
+                .with_early_data(b"hello"),
+        )
+        .expect("error establishing TLS connection");
+        statuses.push(tls.early_data_status());
+
+        tls.write(b"ping").expect("error writing data");
+        tls.flush().expect("error flushing data");
+
+        let mut rx_buf = [0; 4];
+        let sz = tls.read(&mut rx_buf).expect("error reading data");
+        assert_eq!(4, sz);
+        assert_eq!(b"ping", &rx_buf[..sz]);
+
+        tls.close()
+            .map_err(|(_, e)| e)
+            .expect("error closing session");
+    }
+
+    assert_eq!(
+        vec![EarlyDataStatus::NotSent, EarlyDataStatus::Accepted],
+        statuses
+    );
+    assert_eq!(
+        vec![(false, vec![]), (true, b"hello".to_vec())],
+        h.await.unwrap()
+    );
+}